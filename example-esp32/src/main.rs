@@ -0,0 +1,83 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ *
+ * Example for I2S playback with oggopus-embedded and opus-embedded crates on ESP32.
+ */
+
+//! Plays a tone over I2S on ESP32 with ESP-IDF.
+//!
+//! Connect the i2s DAC (MAX38357A) as follows:
+//!   bclk : GPIO 26
+//!   lrc  : GPIO 25
+//!   din  : GPIO 22
+
+use esp_idf_svc::hal::delay::BLOCK;
+use esp_idf_svc::hal::gpio::AnyIOPin;
+use esp_idf_svc::hal::i2s::config::{DataBitWidth, StdConfig};
+use esp_idf_svc::hal::i2s::I2sDriver;
+use esp_idf_svc::hal::peripherals::Peripherals;
+use log::info;
+use oggopus_embedded::prelude::*;
+use opus_embedded::prelude::*;
+
+const SAMPLING_RATE: SamplingRate = SamplingRate::F48k;
+
+// The same sample as in rp2040 example
+const SAMPLE: &[u8] = include_bytes!("../../example-rp2040/src/tone_440_32k.opus");
+
+fn main() -> Result<(), Box<dyn core::error::Error>> {
+    esp_idf_svc::sys::link_patches();
+    esp_idf_svc::log::EspLogger::initialize_default();
+
+    let peripherals = Peripherals::take()?;
+    let config = StdConfig::philips(SAMPLING_RATE as u32, DataBitWidth::Bits16);
+    let mut i2s = I2sDriver::new_std_tx(
+        peripherals.i2s0,
+        &config,
+        peripherals.pins.gpio26,
+        peripherals.pins.gpio22,
+        Option::<AnyIOPin>::None,
+        peripherals.pins.gpio25,
+    )?;
+    i2s.tx_enable()?;
+
+    // Decode to stereo so that mono samples are written to both slots of I2S
+    let mut decoder = Decoder::new(SAMPLING_RATE, Channels::Stereo)?;
+    let mut output = Vec::default();
+    let mut bytes = Vec::default();
+
+    loop {
+        let stream = Bitstream::new(SAMPLE);
+        let (reader, header) = stream.reader().read_header()?;
+        let Either::Continued(mut reader) = reader else {
+            return Err("Stream ended after header or comments packet".into());
+        };
+        let mut pre_skip = header.pre_skip as usize * 2;
+        decoder.reset()?;
+
+        info!("Playing the sample");
+        loop {
+            let (new_reader, mut packets) = reader.next_packets::<1024>()?;
+            while let Some(packet) = packets.next() {
+                output.resize(decoder.get_nb_samples_total(packet.data)?, 0i16);
+                let output = decoder.decode(packet.data, output.as_mut_slice())?;
+                let skipped = pre_skip.min(output.len());
+                pre_skip -= skipped;
+
+                // I2S driver takes bytes, and blocks until they fit to its DMA buffers
+                bytes.clear();
+                bytes.extend(
+                    output[skipped..]
+                        .iter()
+                        .flat_map(|sample| sample.to_le_bytes()),
+                );
+                i2s.write_all(&bytes, BLOCK)?;
+            }
+            match new_reader {
+                Either::Ended(_) => break,
+                Either::Continued(new_reader) => reader = new_reader,
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Estimation of bitrate from bytes read and granule positions.
+
+use super::{container::RawPage, info::NO_PACKET_ENDS, time::GRANULE_RATE};
+
+/// The number of pages with granule position that the instantaneous bitrate is calculated over.
+pub const WINDOW: usize = 8;
+
+/// Bitrate of a stream in bits per second including ogg overhead.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Bitrate {
+    /// Mean bitrate of everything that has been counted.
+    pub mean: u32,
+    /// Bitrate of the last [`WINDOW`] pages.
+    pub instantaneous: u32,
+}
+
+/**
+ * Estimates bitrate from the sizes and granule positions of pages.
+ *
+ * Bytes of each page are counted against the progress of granule position since the previous
+ * page. Bytes of pages on which no packet ends are counted with the next page that has a granule
+ * position. This needs no knowledge of the length of the stream, so it works for live streams as
+ * well as for files, e.g. for deciding how much to buffer over a slow link.
+ *
+ * ```rust
+ * # use oggopus_embedded::{bitrate::BitrateMeter, Pages};
+ * # let data = include_bytes!("test/offset.opus");
+ * let mut meter = BitrateMeter::new();
+ * // Audio begins at granule position 48 000
+ * meter.start_at(48_000);
+ * for page in Pages::new(&data[136..]) {
+ *     meter.page(&page.unwrap());
+ * }
+ * println!("Mean bitrate is {} bit/s", meter.bitrate().unwrap().mean);
+ * ```
+ */
+#[derive(Clone, Debug, Default)]
+pub struct BitrateMeter {
+    total_bytes: u64,
+    total_granules: u64,
+    /// Bytes of pages without granule position since the last page with one.
+    pending: u64,
+    /// Granule position of the last page with one.
+    position: Option<u64>,
+    /// Bytes and progress of granule position of the last pages, oldest first after `index`.
+    recent: [(u64, u64); WINDOW],
+    index: usize,
+}
+
+impl BitrateMeter {
+    /// Construct [`BitrateMeter`] that has not counted anything.
+    pub const fn new() -> Self {
+        Self {
+            total_bytes: 0,
+            total_granules: 0,
+            pending: 0,
+            position: None,
+            recent: [(0, 0); WINDOW],
+            index: 0,
+        }
+    }
+
+    /**
+     * Set granule position where the next page begins if no page has been counted yet.
+     *
+     * Without this the first page with granule position is only taken as the starting point and
+     * its bytes are not counted.
+     */
+    pub fn start_at(&mut self, granule_position: u64) {
+        if self.position.is_none() {
+            self.position = Some(granule_position);
+        }
+    }
+
+    /// Count page.
+    pub fn page(&mut self, page: &RawPage<'_>) {
+        self.add(page.as_bytes().len(), page.granule_position());
+    }
+
+    /**
+     * Count `bytes` of data that end at granule position.
+     *
+     * Granule position of `u64::MAX` means that the position is not known yet as on pages on
+     * which no packet ends. Data that moves granule position backwards is taken as a jump, e.g.
+     * after seeking, and it is not counted.
+     */
+    pub fn add(&mut self, bytes: usize, granule_position: u64) {
+        let bytes = self.pending.saturating_add(bytes as u64);
+        if granule_position == NO_PACKET_ENDS {
+            self.pending = bytes;
+            return;
+        }
+        self.pending = 0;
+        match self.position.replace(granule_position) {
+            Some(previous) if previous <= granule_position => {
+                let granules = granule_position - previous;
+                self.total_bytes = self.total_bytes.saturating_add(bytes);
+                self.total_granules = self.total_granules.saturating_add(granules);
+                self.recent[self.index] = (bytes, granules);
+                self.index = (self.index + 1) % WINDOW;
+            }
+            _ => {}
+        }
+    }
+
+    /**
+     * Forget the granule position of the last page, e.g. after seeking.
+     *
+     * The next page with granule position becomes the new starting point. Counted data is kept.
+     */
+    pub fn jump(&mut self) {
+        self.position = None;
+        self.pending = 0;
+    }
+
+    /// Returns the bitrate, or [`None`] if granule position has not moved forward yet.
+    pub fn bitrate(&self) -> Option<Bitrate> {
+        let (bytes, granules) = self
+            .recent
+            .iter()
+            .fold((0u64, 0u64), |(bytes, granules), recent| {
+                (bytes.saturating_add(recent.0), granules + recent.1)
+            });
+        Some(Bitrate {
+            mean: rate(self.total_bytes, self.total_granules)?,
+            instantaneous: rate(bytes, granules)?,
+        })
+    }
+}
+
+/// Returns bits per second of bytes over granule positions.
+fn rate(bytes: u64, granules: u64) -> Option<u32> {
+    if granules == 0 {
+        return None;
+    }
+    let bits = u128::from(bytes) * 8 * u128::from(GRANULE_RATE);
+    Some((bits / u128::from(granules)).min(u128::from(u32::MAX)) as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Pages;
+
+    #[test]
+    fn count_pages() {
+        let data = include_bytes!("test/offset.opus");
+        let mut meter = BitrateMeter::new();
+        assert_eq!(meter.bitrate(), None);
+        meter.start_at(48_000);
+        for page in Pages::new(&data[136..]) {
+            meter.page(&page.unwrap());
+        }
+        let expected = ((data.len() - 136) as u64 * 8 * 48_000 / (7 * 960)) as u32;
+        let bitrate = meter.bitrate().unwrap();
+        assert_eq!(bitrate.mean, expected);
+        assert_eq!(bitrate.instantaneous, expected);
+    }
+
+    #[test]
+    fn window() {
+        let mut meter = BitrateMeter::new();
+        // Starting point only
+        meter.add(1_000, 0);
+        assert_eq!(meter.bitrate(), None);
+        for page in 1..=WINDOW as u64 {
+            meter.add(120, page * 960);
+        }
+        // 120 bytes per 20 ms
+        assert_eq!(meter.bitrate().unwrap().instantaneous, 48_000);
+        // Page without granule position is counted with the next one
+        meter.add(240, NO_PACKET_ENDS);
+        for page in 1..=WINDOW as u64 {
+            meter.add(240, (WINDOW as u64 + page) * 960);
+        }
+        let bitrate = meter.bitrate().unwrap();
+        assert_eq!(bitrate.instantaneous, 108_000);
+        assert_eq!(bitrate.mean, 78_000);
+        // Jumping back is not counted
+        meter.add(10_000, 960);
+        assert_eq!(meter.bitrate(), Some(bitrate));
+        meter.jump();
+        meter.add(10_000, 100 * 960);
+        assert_eq!(meter.bitrate(), Some(bitrate));
+    }
+}
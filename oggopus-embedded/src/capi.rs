@@ -0,0 +1,208 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * C compatible versions of parsed header data.
+ *
+ * Types in this module have `#[repr(C)]` layout so that they can be passed to C code as they are.
+ * They are plain copies of the data and do not borrow from the stream. A C header with these
+ * types can be generated with [cbindgen] using `cbindgen.toml` from the root of this crate:
+ *
+ * ```sh
+ * cbindgen --config cbindgen.toml --output oggopus_embedded.h
+ * ```
+ *
+ * [cbindgen]: https://github.com/mozilla/cbindgen
+ */
+
+use crate::opus::OpusHeader;
+use crate::{Bitstream, ProbeInfo};
+
+/// The maximum number of channels in an opus stream.
+pub const MAX_CHANNELS: usize = 255;
+
+/**
+ * C compatible version of [`OpusHeader`].
+ *
+ * The mapping table is always included, also for family 0 which has implicit mapping. Entries
+ * after `channel_count` are zero.
+ */
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct COpusHeader {
+    /// Opus version.
+    pub version: u8,
+    /// The number of output channels.
+    pub channel_count: u8,
+    /// The number of samples to skip in the beginning of the stream.
+    pub pre_skip: u16,
+    /// Sample rate used for the original audio.
+    pub sample_rate: u32,
+    /// Output gain in Q7.8 format.
+    pub output_gain: i16,
+    /// Channel mapping family number.
+    pub mapping_family: u8,
+    /// The number of total streams encoded in each Ogg packet.
+    pub stream_count: u8,
+    /// The number of stereo decoders needed.
+    pub coupled_count: u8,
+    /// Channel mapping table.
+    pub mapping: [u8; MAX_CHANNELS],
+}
+
+impl From<&OpusHeader> for COpusHeader {
+    fn from(header: &OpusHeader) -> Self {
+        let channels = &header.channels;
+        let channel_count = channels.get_channel_count();
+        let mut mapping = [0; MAX_CHANNELS];
+        match channels.get_mapping_table() {
+            Some(table) => mapping[..table.len()].copy_from_slice(table),
+            None => (0..channel_count).for_each(|i| mapping[usize::from(i)] = i),
+        }
+        Self {
+            version: header.version,
+            channel_count,
+            pre_skip: header.pre_skip,
+            sample_rate: header.sample_rate,
+            output_gain: header.output_gain,
+            mapping_family: channels.get_family(),
+            stream_count: channels.get_stream_count(),
+            coupled_count: channels.get_coupled_stream_count(),
+            mapping,
+        }
+    }
+}
+
+impl From<OpusHeader> for COpusHeader {
+    fn from(header: OpusHeader) -> Self {
+        Self::from(&header)
+    }
+}
+
+/**
+ * C compatible summary of [`ProbeInfo`].
+ *
+ * Contains the header fields needed for setting up playback and the length of the stream, so
+ * that C code can get them with one call to [`CStreamInfo::probe`].
+ */
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CStreamInfo {
+    /// The number of output channels.
+    pub channel_count: u8,
+    /// Channel mapping family number.
+    pub mapping_family: u8,
+    /// The number of samples to skip in the beginning of the stream.
+    pub pre_skip: u16,
+    /// Sample rate used for the original audio.
+    pub sample_rate: u32,
+    /// Output gain in Q7.8 format.
+    pub output_gain: i16,
+    /// Granule position of the first sample in the first audio packet.
+    pub first_granule_position: u64,
+    /// Granule position at the end of the last audio packet.
+    pub last_granule_position: u64,
+    /**
+     * The number of samples at 48 kHz without pre-skip, see
+     * [`StreamInfo::total_samples`][`crate::StreamInfo::total_samples`].
+     */
+    pub total_samples: u64,
+}
+
+impl CStreamInfo {
+    /**
+     * Probe the first stream in data and fill [`CStreamInfo`] from it.
+     *
+     * See [`Bitstream::probe`].
+     *
+     * ```rust
+     * # use oggopus_embedded::capi::CStreamInfo;
+     * # let data = include_bytes!("test/mono.opus");
+     * let info = CStreamInfo::probe(data).unwrap();
+     * assert_eq!(info.channel_count, 1);
+     * ```
+     */
+    pub fn probe(data: &[u8]) -> crate::Result<'_, Self> {
+        Bitstream::new(data).probe().map(|info| Self::from(&info))
+    }
+}
+
+impl From<&ProbeInfo> for CStreamInfo {
+    fn from(info: &ProbeInfo) -> Self {
+        let header = &info.header;
+        Self {
+            channel_count: header.channels.get_channel_count(),
+            mapping_family: header.channels.get_family(),
+            pre_skip: header.pre_skip,
+            sample_rate: header.sample_rate,
+            output_gain: header.output_gain,
+            first_granule_position: info.stream.first_granule_position,
+            last_granule_position: info.stream.last_granule_position,
+            total_samples: info.stream.total_samples(),
+        }
+    }
+}
+
+impl From<ProbeInfo> for CStreamInfo {
+    fn from(info: ProbeInfo) -> Self {
+        Self::from(&info)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn convert_family_0() {
+        let data = include_bytes!("test/opus.data");
+        let header = COpusHeader::from(OpusHeader::parse(data).unwrap());
+        assert_eq!(header.version, 1);
+        assert_eq!(header.channel_count, 1);
+        assert_eq!(header.pre_skip, 312);
+        assert_eq!(header.sample_rate, 8_000);
+        assert_eq!(header.output_gain, 0);
+        assert_eq!(header.mapping_family, 0);
+        assert_eq!(header.stream_count, 1);
+        assert_eq!(header.coupled_count, 0);
+        assert!(header.mapping.iter().all(|&index| index == 0));
+    }
+
+    #[test]
+    fn convert_family_1() {
+        let data = [
+            b'O', b'p', b'u', b's', b'H', b'e', b'a', b'd', 0x01, 0x03, 0x38, 0x01, 0x80, 0xBB,
+            0x00, 0x00, 0x00, 0xFF, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01,
+        ];
+        let header = COpusHeader::from(&OpusHeader::parse(&data).unwrap());
+        assert_eq!(header.channel_count, 3);
+        assert_eq!(header.pre_skip, 312);
+        assert_eq!(header.sample_rate, 48_000);
+        assert_eq!(header.output_gain, -256);
+        assert_eq!(header.mapping_family, 1);
+        assert_eq!(header.stream_count, 2);
+        assert_eq!(header.coupled_count, 1);
+        assert_eq!(header.mapping[..4], [0, 2, 1, 0]);
+    }
+
+    #[test]
+    fn stream_info() {
+        let data = include_bytes!("test/stereo.opus");
+        let info = CStreamInfo::probe(data).unwrap();
+        let probed = Bitstream::new(data).probe().unwrap();
+        assert_eq!(info.channel_count, 2);
+        assert_eq!(info.mapping_family, 0);
+        assert_eq!(info.pre_skip, probed.header.pre_skip);
+        assert_eq!(info.sample_rate, probed.header.sample_rate);
+        assert_eq!(info.output_gain, probed.header.output_gain);
+        assert_eq!(
+            info.last_granule_position,
+            probed.stream.last_granule_position
+        );
+        assert_eq!(info.total_samples, probed.stream.total_samples());
+        assert!(CStreamInfo::probe(b"not ogg").is_err());
+    }
+}
@@ -0,0 +1,455 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Checking that streams follow the requirements of RFC7845.
+ *
+ * Readers accept streams that can be played even if they bend the rules of
+ * [RFC7845](https://datatracker.ietf.org/doc/html/rfc7845). [`Checker`] reports where a stream
+ * breaks them, e.g. for validating the output of an encoder in CI. Readers of [`Bitstream`] check
+ * the same rules when [strict mode][`Bitstream::with_strict`] is turned on. Conversely, they
+ * tolerate more in [lenient mode][`Bitstream::with_lenient`] and tell which [`Deviations`] they
+ * met.
+ *
+ * ```rust
+ * # use oggopus_embedded::{conformance::Checker, Pages};
+ * # let data = include_bytes!("test/mono.opus");
+ * let mut checker = Checker::new();
+ * for page in Pages::new(data) {
+ *     if let Err(violation) = checker.page(&page.unwrap()) {
+ *         println!("{}", violation);
+ *     }
+ * }
+ * assert_eq!(checker.finish(), Ok(()));
+ * ```
+ *
+ * [`Bitstream`]: crate::Bitstream
+ * [`Bitstream::with_strict`]: crate::Bitstream::with_strict
+ * [`Bitstream::with_lenient`]: crate::Bitstream::with_lenient
+ */
+
+use super::{
+    container::RawPage,
+    info::{StartPosition, NO_PACKET_ENDS},
+    opus::{ErrorValues, OpusError, OpusHeader},
+};
+use bitflags::bitflags;
+
+/// Requirement of RFC7845 that the stream does not meet.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Violation {
+    /// The first page of the stream does not have the beginning of stream flag.
+    MissingBeginOfStream(u32),
+    /// The identification header is not alone on the first page.
+    HeaderNotAlone(u32),
+    /// The identification header cannot be parsed.
+    InvalidHeader(OpusError),
+    /// The version of the identification header is not 1.
+    HeaderVersion(u8),
+    /**
+     * Channel mapping family is reserved.
+     *
+     * Families 2 and 3 for ambisonics from
+     * [RFC8486](https://datatracker.ietf.org/doc/html/rfc8486) are not reserved, but their
+     * channel counts are checked.
+     */
+    ReservedMappingFamily(u8),
+    /// The comment header does not begin on the second page.
+    MissingCommentHeader(u32),
+    /// Audio begins on the last page of the comment header.
+    AudioOnHeaderPage(u32),
+    /// Granule position of a header page on which a packet ends is not zero.
+    HeaderGranulePosition(u32),
+    /// Page has a granule position but no packet ends on it or the other way around.
+    GranulePositionMismatch(u32),
+    /// Granule position is smaller than on the previous page.
+    GranulePositionDecreased(u32),
+    /// Granule position of the first audio page is smaller than the samples that end on it.
+    StartBeforeZero(u32),
+    /// Page of the stream comes after its last page.
+    PageAfterEnd(u32),
+    /// The stream ended without a page with the end of stream flag.
+    MissingEndOfStream,
+}
+
+impl core::fmt::Display for Violation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use Violation::*;
+        match self {
+            MissingBeginOfStream(page) => f.write_fmt(format_args!(
+                "first page {} does not begin the stream",
+                page
+            )),
+            HeaderNotAlone(page) => f.write_fmt(format_args!(
+                "identification header is not alone on page {}",
+                page
+            )),
+            InvalidHeader(error) => {
+                f.write_str("invalid identification header: ")?;
+                error.fmt(f)
+            }
+            HeaderVersion(version) => {
+                f.write_fmt(format_args!("header version is {} instead of 1", version))
+            }
+            ReservedMappingFamily(family) => f.write_fmt(format_args!(
+                "channel mapping family {} is reserved",
+                family
+            )),
+            MissingCommentHeader(page) => f.write_fmt(format_args!(
+                "comment header does not begin on page {}",
+                page
+            )),
+            AudioOnHeaderPage(page) => {
+                f.write_fmt(format_args!("audio begins on comment header page {}", page))
+            }
+            HeaderGranulePosition(page) => f.write_fmt(format_args!(
+                "granule position of header page {} is not zero",
+                page
+            )),
+            GranulePositionMismatch(page) => f.write_fmt(format_args!(
+                "granule position of page {} does not match its packets",
+                page
+            )),
+            GranulePositionDecreased(page) => {
+                f.write_fmt(format_args!("granule position decreased on page {}", page))
+            }
+            StartBeforeZero(page) => f.write_fmt(format_args!(
+                "granule position of page {} is smaller than its samples",
+                page
+            )),
+            PageAfterEnd(page) => {
+                f.write_fmt(format_args!("page {} is after the end of stream", page))
+            }
+            MissingEndOfStream => f.write_str("stream ended without end of stream flag"),
+        }
+    }
+}
+
+impl core::error::Error for Violation {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        None
+    }
+}
+
+bitflags! {
+    /**
+     * Deviations from RFC7845 that were tolerated in lenient mode.
+     *
+     * See [`Bitstream::with_lenient`][`crate::Bitstream::with_lenient`].
+     */
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    pub struct Deviations: u8 {
+        /**
+         * Granule position of the first audio page is smaller than the samples that end on it,
+         * e.g. because the muxer subtracted pre-skip from granule positions. The stream is taken
+         * to start from zero.
+         */
+        const StartBeforeZero = 0b0001;
+        /// The data ended without a page with the end of stream flag, or in the middle of a page.
+        const MissingEndOfStream = 0b0010;
+        /// The vendor string of the comment header is not valid UTF-8.
+        const InvalidVendor = 0b0100;
+        /**
+         * The stream ends before its pre-skip does, e.g. because the muxer of a very short stream
+         * wrote a slightly wrong pre-skip. Nothing of it is played.
+         */
+        const PreSkipPastEnd = 0b1000;
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Deviations {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "Deviations({=u8:#06b})", self.bits())
+    }
+}
+
+/// Part of the stream that the next page belongs to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    Identification,
+    Comments { first: bool },
+    Audio,
+    Ended,
+}
+
+/**
+ * Checks pages of an opus stream against RFC7845.
+ *
+ * Pages are given in the order they are in the stream. Pages of other logical streams than the
+ * first one are ignored. Checking continues after a violation, so all of them can be reported.
+ */
+#[derive(Debug)]
+pub struct Checker {
+    bitstream_serial_number: Option<u32>,
+    stage: Stage,
+    granule_position: Option<u64>,
+    start: StartPosition,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checker {
+    /// Construct [`Checker`] for the beginning of a stream.
+    pub fn new() -> Self {
+        Self {
+            bitstream_serial_number: None,
+            stage: Stage::Identification,
+            granule_position: None,
+            start: StartPosition::default(),
+        }
+    }
+
+    /**
+     * Check the next page. Returns the first violation on the page.
+     *
+     * The rest of the page is still taken into account for checking the following pages.
+     */
+    pub fn page(&mut self, page: &RawPage<'_>) -> Result<(), Violation> {
+        use Violation::*;
+        let serial_number = *self
+            .bitstream_serial_number
+            .get_or_insert(page.bitstream_serial_number());
+        if page.bitstream_serial_number() != serial_number {
+            return Ok(());
+        }
+        let sequence_number = page.page_sequence_number();
+        let mut result = Ok(());
+        let mut fail = |violation| {
+            if result.is_ok() {
+                result = Err(violation);
+            }
+        };
+        let granule_position = page.granule_position();
+        let packet_ends = page.packets().any(|(_, ended)| ended);
+        if packet_ends == (granule_position == NO_PACKET_ENDS) {
+            fail(GranulePositionMismatch(sequence_number));
+        }
+        match self.stage {
+            Stage::Identification => {
+                if !page.begin_of_stream() {
+                    fail(MissingBeginOfStream(sequence_number));
+                }
+                let mut packets = page.packets();
+                match (packets.next(), packets.next()) {
+                    (Some((packet, true)), None) => match OpusHeader::parse(packet) {
+                        Ok(header) => {
+                            if header.version != 1 {
+                                fail(HeaderVersion(header.version));
+                            }
+                            let family = header.channels.get_family();
+                            let channels = header.channels.get_channel_count();
+                            if (4..=254).contains(&family) {
+                                fail(ReservedMappingFamily(family));
+                            } else if (2..=3).contains(&family) && !is_ambisonic(channels) {
+                                fail(InvalidHeader(OpusError::InvalidStream(
+                                    ErrorValues::BadNumberOfChannels(family, channels),
+                                )));
+                            }
+                        }
+                        Err(error) => fail(InvalidHeader(error)),
+                    },
+                    _ => fail(HeaderNotAlone(sequence_number)),
+                }
+                if packet_ends && granule_position != 0 {
+                    fail(HeaderGranulePosition(sequence_number));
+                }
+                self.stage = Stage::Comments { first: true };
+            }
+            Stage::Comments { first } => {
+                if first && (page.continued() || !page.data().starts_with(b"OpusTags")) {
+                    fail(MissingCommentHeader(sequence_number));
+                }
+                let mut packets = page.packets();
+                if packets.any(|(_, ended)| ended) {
+                    if packets.next().is_some() {
+                        fail(AudioOnHeaderPage(sequence_number));
+                    }
+                    if granule_position != 0 {
+                        fail(HeaderGranulePosition(sequence_number));
+                    }
+                    self.stage = Stage::Audio;
+                } else {
+                    self.stage = Stage::Comments { first: false };
+                }
+            }
+            Stage::Audio => {
+                if granule_position != NO_PACKET_ENDS {
+                    if self
+                        .granule_position
+                        .is_some_and(|last| granule_position < last)
+                    {
+                        fail(GranulePositionDecreased(sequence_number));
+                    }
+                    self.granule_position = Some(granule_position);
+                }
+                if self.start.position().is_none() && self.start.page(page).is_err() {
+                    fail(StartBeforeZero(sequence_number));
+                }
+            }
+            Stage::Ended => fail(PageAfterEnd(sequence_number)),
+        }
+        if page.end_of_stream() {
+            self.stage = Stage::Ended;
+        }
+        result
+    }
+
+    /**
+     * Forget the granule position of the last audio page, e.g. after seeking.
+     *
+     * The next audio page is not compared to the pages before it. Audio pages may follow even if
+     * the last page ended the stream.
+     */
+    pub fn jump(&mut self) {
+        self.granule_position = None;
+        if self.stage == Stage::Ended {
+            self.stage = Stage::Audio;
+        }
+    }
+
+    /// Check that the stream has ended after the last page has been checked.
+    pub fn finish(&self) -> Result<(), Violation> {
+        if self.stage == Stage::Ended {
+            Ok(())
+        } else {
+            Err(Violation::MissingEndOfStream)
+        }
+    }
+}
+
+/**
+ * Returns whether channel count is valid for ambisonics, i.e. mapping families 2 and 3.
+ *
+ * The channels are ambisonic channels of order from 0 to 14 and optionally two non-diegetic
+ * stereo channels as specified in [RFC8486](https://datatracker.ietf.org/doc/html/rfc8486).
+ */
+fn is_ambisonic(channels: u8) -> bool {
+    (1..=15u16).any(|order| {
+        let ambisonic = order * order;
+        u16::from(channels) == ambisonic || u16::from(channels) == ambisonic + 2
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::{crc, Pages};
+
+    /// Check all pages and collect the violations.
+    fn check(data: &[u8]) -> Vec<Violation> {
+        let mut checker = Checker::new();
+        let mut violations: Vec<_> = Pages::new(data)
+            .filter_map(|page| checker.page(&page.unwrap()).err())
+            .collect();
+        violations.extend(checker.finish().err());
+        violations
+    }
+
+    /// Update the checksum of the page at offset after modifying it.
+    pub(crate) fn update_checksum(data: &mut [u8], offset: usize, length: usize) {
+        data[offset + 22..offset + 26].fill(0);
+        let checksum = crc::page_checksum(&crc::SoftwareCrc, &data[offset..offset + length]);
+        data[offset + 22..offset + 26].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn conforming_streams() {
+        for data in [
+            include_bytes!("test/mono.opus").as_slice(),
+            include_bytes!("test/stereo.opus"),
+            include_bytes!("test/offset.opus"),
+            include_bytes!("test/trimmed.opus"),
+        ] {
+            assert_eq!(check(data), []);
+        }
+    }
+
+    #[test]
+    fn violations() {
+        let mut data = Vec::from(include_bytes!("test/offset.opus"));
+        // Header version 2 and nonzero granule position of the comment header page
+        data[28 + 8] = 2;
+        update_checksum(&mut data, 0, 47);
+        data[47 + 6] = 1;
+        update_checksum(&mut data, 47, 136 - 47);
+        // Granule position of the second audio page before the first one
+        data[205 + 6..205 + 14].copy_from_slice(&1_000u64.to_le_bytes());
+        data[205 + 5] = 0;
+        let length = data.len() - 205;
+        update_checksum(&mut data, 205, length);
+        assert_eq!(
+            check(&data),
+            [
+                Violation::HeaderVersion(2),
+                Violation::HeaderGranulePosition(1),
+                Violation::GranulePositionDecreased(3),
+                Violation::MissingEndOfStream,
+            ]
+        );
+        assert_eq!(
+            Violation::GranulePositionDecreased(3).to_string(),
+            "granule position decreased on page 3"
+        );
+    }
+
+    /// Replace the identification header of the stream.
+    #[cfg(feature = "family255")]
+    fn with_header(data: &[u8], header: &[u8]) -> Vec<u8> {
+        let mut stream = Vec::from(&data[..26]);
+        stream.push(1);
+        stream.push(header.len() as u8);
+        stream.extend_from_slice(header);
+        let length = stream.len();
+        update_checksum(&mut stream, 0, length);
+        stream.extend_from_slice(&data[47..]);
+        stream
+    }
+
+    #[test]
+    #[cfg(feature = "family255")]
+    fn ambisonics() {
+        let data = include_bytes!("test/mono.opus");
+        let head = |channels, family, table: &[u8]| {
+            let mut header = Vec::from(&data[28..46]);
+            header[9] = channels;
+            header.push(family);
+            header.extend_from_slice(table);
+            header
+        };
+        // First order ambisonics with and without non-diegetic stereo channels
+        let header = head(4, 2, &[4, 0, 0, 1, 2, 3]);
+        assert_eq!(check(&with_header(data, &header)), []);
+        let header = head(6, 2, &[5, 1, 0, 1, 2, 3, 4, 5]);
+        assert_eq!(check(&with_header(data, &header)), []);
+        let header = head(5, 2, &[5, 0, 0, 1, 2, 3, 4]);
+        assert_eq!(
+            check(&with_header(data, &header)),
+            [Violation::InvalidHeader(OpusError::InvalidStream(
+                ErrorValues::BadNumberOfChannels(2, 5)
+            ))]
+        );
+        // Demixing matrix for 4 channels from two coupled streams
+        let header = head(4, 3, &[[2, 2].as_slice(), &[0; 2 * 4 * 4]].concat());
+        assert_eq!(check(&with_header(data, &header)), []);
+        let header = head(4, 3, &[2, 2, 0, 1, 2, 3]);
+        assert_eq!(
+            check(&with_header(data, &header)),
+            [Violation::InvalidHeader(OpusError::InvalidStream(
+                ErrorValues::BadTableLength(4, 4)
+            ))]
+        );
+        let header = head(4, 4, &[4, 0, 0, 1, 2, 3]);
+        assert_eq!(
+            check(&with_header(data, &header)),
+            [Violation::ReservedMappingFamily(4)]
+        );
+    }
+}
@@ -4,7 +4,10 @@
  */
 //! Ogg parsing code.
 
-use super::ErrorValues;
+use super::{
+    crc::{self, Crc32Provider, SoftwareCrc},
+    ErrorValues,
+};
 use bitflags::bitflags;
 use core::num::NonZeroUsize;
 use nom::{
@@ -22,13 +25,66 @@ bitflags! {
     }
 }
 
+/**
+ * The largest possible ogg page size.
+ *
+ * This is the size of page header with 255 segments that are all 255 bytes long.
+ */
+pub const MAX_PAGE_SIZE: usize = HEADER_SIZE + 255 + 255 * 255;
+
+/// Size of ogg page header without segment table.
+pub(crate) const HEADER_SIZE: usize = 27;
+
+/// Limits and checks for parsing pages.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ParseOptions {
+    /// Maximum size of pages including their headers.
+    pub max_page_size: usize,
+    /// Maximum size of packets that are reassembled from pages.
+    pub max_packet_size: usize,
+    /// Provider for checking CRC checksums of pages, or [`None`] to not check them.
+    pub crc: Option<&'static dyn Crc32Provider>,
+    /// Whether streams are checked against RFC7845.
+    pub strict: bool,
+    /// Whether common deviations from RFC7845 are tolerated.
+    pub lenient: bool,
+}
+
+impl ParseOptions {
+    /// Construct [`ParseOptions`] that allow all valid pages and check their checksums.
+    pub(crate) const fn new() -> Self {
+        Self {
+            max_page_size: MAX_PAGE_SIZE,
+            max_packet_size: usize::MAX,
+            crc: Some(&SoftwareCrc),
+            strict: false,
+            lenient: false,
+        }
+    }
+}
+
+impl PartialEq for ParseOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.max_page_size == other.max_page_size
+            && self.max_packet_size == other.max_packet_size
+            && self.strict == other.strict
+            && self.lenient == other.lenient
+            && match (self.crc, other.crc) {
+                (Some(crc), Some(other)) => core::ptr::addr_eq(crc, other),
+                (crc, other) => crc.is_none() && other.is_none(),
+            }
+    }
+}
+
 /// Error from parsing ogg container.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum OggError {
     /// Unsupported ogg version.
     UnsupportedVersion(u8),
     /// Parsing error from nom library.
-    ParsingError(ErrorKind),
+    ParsingError(#[cfg_attr(feature = "defmt", defmt(Debug2Format))] ErrorKind),
     /// Stream ended abruptly.
     EndOfStreamError(Option<NonZeroUsize>),
     /// Stream did not validate as ogg stream.
@@ -43,6 +99,57 @@ pub enum OggError {
      * Contains size of the buffer and how many bytes would have been actually needed.
      */
     BufferTooSmallError(usize, usize),
+    /**
+     * Page was larger than allowed.
+     *
+     * Contains the maximum page size and the size of the page claimed by its segment table.
+     */
+    PageTooLargeError(usize, usize),
+    /**
+     * CRC checksum of page did not match its contents.
+     *
+     * Contains the page sequence number claimed by the page.
+     */
+    CrcMismatch(u32),
+    /**
+     * Data did not continue with a page where one was expected.
+     *
+     * Contains the number of bytes that were skipped to find the next page.
+     */
+    LostSync(usize),
+    /**
+     * Packet was larger than the maximum packet size.
+     *
+     * Contains the size of the packet.
+     */
+    PacketTooLarge(usize),
+}
+
+impl OggError {
+    /// Returns the layer of the stream where the error comes from, which is always the container.
+    pub fn layer(&self) -> crate::ErrorLayer {
+        crate::ErrorLayer::Container
+    }
+
+    /**
+     * Returns whether the stream may continue after the page or packet that caused the error.
+     *
+     * Lost pages and pages or packets that are too large for the configured limits affect only a
+     * part of the stream. Other errors mean that the stream cannot be read further.
+     */
+    pub fn is_recoverable(&self) -> bool {
+        use OggError::*;
+        matches!(
+            self,
+            InvalidStream(
+                ErrorValues::SequenceNumberMismatch(..) | ErrorValues::MissingContinuation(..)
+            ) | BufferTooSmallError(..)
+                | PageTooLargeError(..)
+                | CrcMismatch(..)
+                | LostSync(..)
+                | PacketTooLarge(..)
+        )
+    }
 }
 
 impl core::fmt::Display for OggError {
@@ -73,6 +180,22 @@ impl core::fmt::Display for OggError {
                 "buffer is too small: got {} but needed {}",
                 got, needed
             ))?,
+            PageTooLargeError(max, size) => f.write_fmt(format_args!(
+                "page is too large: maximum is {} but page has {} bytes",
+                max, size
+            ))?,
+            CrcMismatch(page_sequence_number) => f.write_fmt(format_args!(
+                "checksum mismatch on page {}",
+                page_sequence_number
+            ))?,
+            LostSync(skipped) => f.write_fmt(format_args!(
+                "lost synchronization, skipped {} bytes to the next page",
+                skipped
+            ))?,
+            PacketTooLarge(size) => f.write_fmt(format_args!(
+                "packet is too large: {} bytes is more than allowed",
+                size
+            ))?,
         };
         Ok(())
     }
@@ -156,14 +279,15 @@ impl Iterator for SegmentTableIterator<'_> {
 struct PageHeader<'data> {
     version: u8,
     header_type: HeaderFlags,
-    _granule_position: u64,
+    granule_position: u64,
     bitstream_serial_number: u32,
     page_sequence_number: u32,
+    checksum: u32,
     segment_table: &'data [u8],
 }
 
 impl PageHeader<'_> {
-    fn parse(input: &[u8]) -> Result<PageHeader<'_>> {
+    fn parse(input: &[u8]) -> Result<'_, PageHeader<'_>> {
         use OggError::*;
         let (input, _) = tag(b"OggS".as_slice())(input)
             .map_err(|_: nom::Err<(&[u8], ErrorKind)>| NotOggStream)?;
@@ -174,7 +298,7 @@ impl PageHeader<'_> {
         let (input, granule_position) = number::le_u64().parse(input)?;
         let (input, bitstream_serial_number) = number::le_u32().parse(input)?;
         let (input, page_sequence_number) = number::le_u32().parse(input)?;
-        let (input, _crc_checksum) = number::le_u32().parse(input)?;
+        let (input, checksum) = number::le_u32().parse(input)?;
         let (input, count) = number::u8().parse(input)?;
         let (input, segment_table) = take(count)(input)?;
         Ok((
@@ -182,9 +306,10 @@ impl PageHeader<'_> {
             PageHeader {
                 version,
                 header_type,
-                _granule_position: granule_position,
+                granule_position,
                 bitstream_serial_number,
                 page_sequence_number,
+                checksum,
                 segment_table,
             },
         ))
@@ -206,19 +331,87 @@ pub(crate) struct Page<'data> {
 }
 
 impl Page<'_> {
+    /// Parse page that has been checked already.
     fn parse(input: &[u8]) -> Result<'_, Page<'_>> {
+        Self::parse_with(
+            input,
+            &ParseOptions {
+                crc: None,
+                ..ParseOptions::new()
+            },
+        )
+    }
+
+    /**
+     * Parse page with the limits and checks of options.
+     *
+     * The size is checked from the segment table before the page data is taken.
+     */
+    fn parse_with<'input>(
+        input: &'input [u8],
+        options: &ParseOptions,
+    ) -> Result<'input, Page<'input>> {
         use OggError::*;
         let (data, header) = PageHeader::parse(input)?;
         if header.version != 0 {
             return Err(UnsupportedVersion(header.version));
         }
         let size: usize = header.segment_table.iter().map(|x| usize::from(*x)).sum();
+        let page_size = input.len() - data.len() + size;
+        if page_size > options.max_page_size {
+            return Err(PageTooLargeError(options.max_page_size, page_size));
+        }
         let (remaining, data) = take(size)(data)?;
+        if let Some(provider) = options.crc {
+            if crc::page_checksum(provider, &input[..page_size]) != header.checksum {
+                return Err(CrcMismatch(header.page_sequence_number));
+            }
+        }
         Ok((remaining, Page { header, data }))
     }
 
+    /**
+     * Returns whether the last packet continues on the next page.
+     *
+     * Pages without segments pass the packet through if they continue it.
+     */
     fn last_packet_continues(&self) -> bool {
-        *self.header.segment_table.last().unwrap() == 255
+        match self.header.segment_table.last() {
+            Some(size) => *size == 255,
+            None => self.continued(),
+        }
+    }
+
+    /// Returns whether the page continues a packet from the previous page.
+    fn continued(&self) -> bool {
+        self.header.header_type.contains(HeaderFlags::Continuation)
+    }
+
+    /// Parse the page that continues the packet of this page.
+    fn parse_continuation<'input>(
+        &self,
+        input: &'input [u8],
+        options: &ParseOptions,
+    ) -> Result<'input, Page<'input>> {
+        use OggError::*;
+        let (remaining, page) = Self::parse_with(input, options)?;
+        if page.page_sequence_number() != self.page_sequence_number().wrapping_add(1) {
+            return Err(InvalidStream(ErrorValues::SequenceNumberMismatch(
+                self.page_sequence_number(),
+                page.page_sequence_number(),
+            )));
+        }
+        if page.bitstream_serial_number() != self.bitstream_serial_number() {
+            return Err(UnsupportedStream(
+                "bitstream serial number changed unexpectedly",
+            ));
+        }
+        if !page.continued() {
+            return Err(InvalidStream(ErrorValues::MissingContinuation(
+                page.page_sequence_number(),
+            )));
+        }
+        Ok((remaining, page))
     }
 
     fn max_segment_size(&self, old_max: usize, accumulated: usize) -> (usize, usize) {
@@ -250,33 +443,271 @@ impl Page<'_> {
         self.header.page_sequence_number
     }
 
+    /// Granule position for the page.
+    pub fn granule_position(&self) -> u64 {
+        self.header.granule_position
+    }
+
     /**
      * Parse pages from data until end of page at packet boundary.
      *
      * Useful for skipping comment headers. Returns the last page which is useful for validating
      * the stream.
      */
-    pub(crate) fn skip(data: &[u8]) -> Result<'_, Page> {
-        use OggError::*;
-        let (mut remaining, mut page) = Self::parse(data)?;
-        let mut page_sequence_number = page.page_sequence_number();
-        let bitstream_serial_number = page.bitstream_serial_number();
+    pub(crate) fn skip<'input>(
+        data: &'input [u8],
+        options: &ParseOptions,
+    ) -> Result<'input, Page<'input>> {
+        let (mut remaining, mut page) = Self::parse_with(data, options)?;
         while page.last_packet_continues() {
-            (remaining, page) = Self::parse(remaining)?;
-            if page.page_sequence_number() != page_sequence_number + 1 {
-                return Err(InvalidStream(ErrorValues::SequenceNumberMismatch(
-                    page_sequence_number,
-                    page.page_sequence_number(),
-                )));
+            (remaining, page) = page.parse_continuation(remaining, options)?;
+        }
+        Ok((remaining, page))
+    }
+
+    /**
+     * Skip the page at the beginning of data with the pages that continue its last packet.
+     *
+     * Pages are not checked, so this can skip pages that were rejected. Returns page sequence
+     * number of the last skipped page.
+     */
+    pub(crate) fn skip_rejected(data: &[u8]) -> Result<'_, u32> {
+        let (mut remaining, mut page) = Page::parse(data)?;
+        while page.last_packet_continues() {
+            match Page::parse(remaining) {
+                Ok((next_remaining, next))
+                    if next.continued()
+                        && next.page_sequence_number()
+                            == page.page_sequence_number().wrapping_add(1) =>
+                {
+                    (remaining, page) = (next_remaining, next);
+                }
+                _ => break,
             }
-            page_sequence_number = page.page_sequence_number();
-            if page.bitstream_serial_number() != bitstream_serial_number {
-                return Err(UnsupportedStream(
-                    "bitstream serial number changed unexpectedly",
-                ));
+        }
+        Ok((remaining, page.page_sequence_number()))
+    }
+
+    /// Returns the offset of the next capture pattern after the first byte, or the data length.
+    pub(crate) fn find_next(data: &[u8]) -> usize {
+        (1..data.len())
+            .find(|&index| data[index..].starts_with(b"OggS"))
+            .unwrap_or(data.len())
+    }
+
+    /**
+     * Iterate over raw pages in data that has been parsed already.
+     *
+     * Returns page sequence number and all bytes of the page including its header.
+     */
+    pub(crate) fn iter_raw(mut data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+        core::iter::from_fn(move || {
+            if data.is_empty() {
+                None
+            } else {
+                // These have been parsed already, we can expect them to succeed
+                let (remaining, page) = Page::parse(data).unwrap();
+                let raw = &data[..data.len() - remaining.len()];
+                data = remaining;
+                Some((page.page_sequence_number(), raw))
+            }
+        })
+    }
+}
+
+/**
+ * Ogg page with its header parsed.
+ *
+ * Gives access to all bytes of the page so that it can be written out as is.
+ */
+#[derive(Debug, PartialEq)]
+pub struct RawPage<'data> {
+    raw: &'data [u8],
+    page: Page<'data>,
+}
+
+impl<'data> RawPage<'data> {
+    /// Parse page from the beginning of input.
+    pub(crate) fn parse(input: &'data [u8]) -> core::result::Result<Self, OggError> {
+        let (remaining, page) = Page::parse(input)?;
+        Ok(Self {
+            raw: &input[..input.len() - remaining.len()],
+            page,
+        })
+    }
+
+    /// Parse page from the beginning of input with the limits and checks of options.
+    pub(crate) fn parse_with(
+        input: &'data [u8],
+        options: &ParseOptions,
+    ) -> core::result::Result<Self, OggError> {
+        let (remaining, page) = Page::parse_with(input, options)?;
+        Ok(Self {
+            raw: &input[..input.len() - remaining.len()],
+            page,
+        })
+    }
+
+    /// Returns the parsed page.
+    pub(crate) fn page(&self) -> &Page<'data> {
+        &self.page
+    }
+
+    /// Returns all bytes of the page including its header.
+    pub fn as_bytes(&self) -> &'data [u8] {
+        self.raw
+    }
+
+    /// Returns the data of the page without its header.
+    pub fn data(&self) -> &'data [u8] {
+        self.page.data
+    }
+
+    /// Returns the segment table of the page.
+    pub fn segment_table(&self) -> &'data [u8] {
+        self.page.header.segment_table
+    }
+
+    /// Bitstream serial number for the page.
+    pub fn bitstream_serial_number(&self) -> u32 {
+        self.page.bitstream_serial_number()
+    }
+
+    /// Page sequence number for the page.
+    pub fn page_sequence_number(&self) -> u32 {
+        self.page.page_sequence_number()
+    }
+
+    /// Granule position for the page.
+    pub fn granule_position(&self) -> u64 {
+        self.page.granule_position()
+    }
+
+    /// Returns whether the page continues a packet from the previous page.
+    pub fn continued(&self) -> bool {
+        self.page
+            .header
+            .header_type
+            .contains(HeaderFlags::Continuation)
+    }
+
+    /// Returns whether the page is the first page of its stream.
+    pub fn begin_of_stream(&self) -> bool {
+        self.page
+            .header
+            .header_type
+            .contains(HeaderFlags::BeginOfStream)
+    }
+
+    /// Returns whether the page is the last page of its stream.
+    pub fn end_of_stream(&self) -> bool {
+        self.page
+            .header
+            .header_type
+            .contains(HeaderFlags::EndOfStream)
+    }
+
+    /// Returns the header type flags of the page as they are, including any unknown flags.
+    pub fn header_type(&self) -> u8 {
+        self.page.header.header_type.bits()
+    }
+
+    /// Returns the CRC checksum of the page as it is in its header.
+    pub fn checksum(&self) -> u32 {
+        self.page.header.checksum
+    }
+
+    /**
+     * Returns the parts of packets on the page with whether each of them ends on this page.
+     *
+     * The first part continues a packet of the previous page if the page is
+     * [`continued`][`RawPage::continued`] and the last part continues on the next page if it
+     * does not end here. Parts are found from the segment table, so pages without packets have
+     * none.
+     */
+    pub fn packets(&self) -> impl Iterator<Item = (&'data [u8], bool)> {
+        let mut segments = self.segment_table().iter();
+        let mut data = self.data();
+        core::iter::from_fn(move || {
+            let mut length = 0;
+            for &size in segments.by_ref() {
+                length += usize::from(size);
+                if size < 255 {
+                    let (packet, remaining) = data.split_at(length);
+                    data = remaining;
+                    return Some((packet, true));
+                }
+            }
+            if length > 0 {
+                let (packet, remaining) = data.split_at(length);
+                data = remaining;
+                return Some((packet, false));
+            }
+            None
+        })
+    }
+}
+
+/**
+ * Iterator over raw ogg pages.
+ *
+ * Pages are not combined into packets and their contents are not interpreted. Iteration stops
+ * after the first error, except that pages with a checksum mismatch are skipped.
+ */
+#[derive(Debug)]
+pub struct Pages<'data> {
+    data: &'data [u8],
+    options: ParseOptions,
+}
+
+impl<'data> Pages<'data> {
+    /**
+     * Construct [`Pages`] for any ogg data.
+     *
+     * Pages of all logical streams are returned in the order they are in the data and their
+     * checksums are checked. Unlike [`Bitstream::pages`][`crate::Bitstream::pages`] this does
+     * not require the data to be an opus stream, so it can be used for analyzing, remuxing or
+     * repairing any ogg files.
+     */
+    pub const fn new(data: &'data [u8]) -> Self {
+        Self::with_options(data, ParseOptions::new())
+    }
+
+    /// Construct [`Pages`] for data that is parsed with options.
+    pub(crate) const fn with_options(data: &'data [u8], options: ParseOptions) -> Self {
+        Self { data, options }
+    }
+
+    /// Returns the data that has not been iterated over yet.
+    pub fn remaining(&self) -> &'data [u8] {
+        self.data
+    }
+}
+
+impl<'data> Iterator for Pages<'data> {
+    type Item = core::result::Result<RawPage<'data>, OggError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        match Page::parse_with(self.data, &self.options) {
+            Ok((remaining, page)) => {
+                let raw = &self.data[..self.data.len() - remaining.len()];
+                self.data = remaining;
+                Some(Ok(RawPage { raw, page }))
+            }
+            Err(error @ OggError::CrcMismatch(_)) => {
+                // Parses successfully without checking as only the checksum was wrong
+                let (remaining, _) = Page::parse(self.data).ok()?;
+                self.data = remaining;
+                Some(Err(error))
+            }
+            Err(error) => {
+                self.data = &self.data[self.data.len()..];
+                Some(Err(error))
             }
         }
-        Ok((remaining, page))
     }
 }
 
@@ -294,35 +725,78 @@ pub struct Packets<'data, const BUFFER_SIZE: usize> {
     buffer: [u8; BUFFER_SIZE],
 }
 
-/// Ogg packet.
+/**
+ * Ogg packet.
+ *
+ * Packets that are read from ogg pages also tell where they are in the stream, so that timing and
+ * seeking can be done without parsing the pages again. Packets from other containers have only
+ * their data, except that MP4 packets have granule positions from their sample timestamps.
+ */
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Packet<'buffer> {
     /// Data in ogg packet.
     pub data: &'buffer [u8],
+    /**
+     * Granule position at the end of the packet if it is the last packet that ends on its page.
+     *
+     * Ogg gives granule positions only per page, so this is [`None`] for the other packets.
+     */
+    pub granule_position: Option<u64>,
+    /// Whether the packet is the last packet that ends on its page.
+    pub ends_page: bool,
+    /// Whether the packet ends on the first page of its stream.
+    pub begin_of_stream: bool,
+    /// Whether the packet is the last packet of its stream.
+    pub end_of_stream: bool,
+}
+
+impl<'buffer> Packet<'buffer> {
+    /// Construct [`Packet`] with only data, e.g. for packets that do not come from ogg pages.
+    pub const fn new(data: &'buffer [u8]) -> Self {
+        Self {
+            data,
+            granule_position: None,
+            ends_page: false,
+            begin_of_stream: false,
+            end_of_stream: false,
+        }
+    }
+
+    /// Construct [`Packet`] that ends on page before the segments in `remaining`.
+    pub(crate) fn on_page(data: &'buffer [u8], page: &Page<'_>, remaining: &[u8]) -> Self {
+        let ends_page = remaining.iter().all(|&size| size == 255);
+        let flags = &page.header.header_type;
+        Self {
+            data,
+            granule_position: ends_page.then(|| page.granule_position()),
+            ends_page,
+            begin_of_stream: flags.contains(HeaderFlags::BeginOfStream),
+            end_of_stream: ends_page && flags.contains(HeaderFlags::EndOfStream),
+        }
+    }
 }
 
 impl<const BUFFER_SIZE: usize> Packets<'_, BUFFER_SIZE> {
-    /// Parses input data for pages until a page that ends at packet boundary.
-    pub(crate) fn parse(data: &[u8]) -> Result<'_, Packets<'_, BUFFER_SIZE>> {
+    /**
+     * Parses input data for pages until a page that ends at packet boundary.
+     *
+     * Packets that continue over several pages are reassembled into the buffer. Pages and
+     * packets that are larger than allowed by options or the buffer are rejected.
+     */
+    pub(crate) fn parse<'input>(
+        data: &'input [u8],
+        options: &ParseOptions,
+    ) -> Result<'input, Packets<'input, BUFFER_SIZE>> {
         use OggError::*;
-        let (mut remaining, mut page) = Page::parse(data)?;
+        let (mut remaining, mut page) = Page::parse_with(data, options)?;
         let (mut max_segment, mut acc) = page.max_segment_size(0, 0);
-        let mut page_sequence_number = page.page_sequence_number();
-        let bitstream_serial_number = page.bitstream_serial_number();
         while page.last_packet_continues() {
-            (remaining, page) = Page::parse(remaining)?;
+            (remaining, page) = page.parse_continuation(remaining, options)?;
             (max_segment, acc) = page.max_segment_size(max_segment, acc);
-            if page.page_sequence_number() != page_sequence_number + 1 {
-                return Err(InvalidStream(ErrorValues::SequenceNumberMismatch(
-                    page_sequence_number,
-                    page.page_sequence_number(),
-                )));
-            }
-            page_sequence_number = page.page_sequence_number();
-            if page.bitstream_serial_number() != bitstream_serial_number {
-                return Err(UnsupportedStream(
-                    "bitstream serial number changed unexpectedly",
-                ));
-            }
+        }
+        if max_segment > options.max_packet_size {
+            return Err(PacketTooLarge(max_segment));
         }
         if max_segment > BUFFER_SIZE {
             return Err(BufferTooSmallError(BUFFER_SIZE, max_segment));
@@ -359,6 +833,24 @@ impl<const BUFFER_SIZE: usize> Packets<'_, BUFFER_SIZE> {
         }
     }
 
+    /**
+     * Returns granule position of the last page.
+     *
+     * This is the granule position at the end of the last packet.
+     */
+    pub fn last_granule_position(&self) -> u64 {
+        if self.data.is_empty() {
+            self.page.granule_position()
+        } else {
+            // These have been parsed already, we can expect them to succeed
+            let (mut remaining, mut page) = Page::parse(self.data).unwrap();
+            while page.last_packet_continues() {
+                (remaining, page) = Page::parse(remaining).unwrap();
+            }
+            page.granule_position()
+        }
+    }
+
     /// Returns bitstream serial number for the page being read.
     pub fn bitstream_serial_number(&self) -> u32 {
         self.page.bitstream_serial_number()
@@ -372,6 +864,16 @@ impl<const BUFFER_SIZE: usize> Packets<'_, BUFFER_SIZE> {
             .contains(HeaderFlags::EndOfStream)
     }
 
+    /// Skip the packet that continues from the previous page and return whether there was one.
+    pub(crate) fn skip_continued(&mut self) -> bool {
+        self.page.continued() && self.next().is_some()
+    }
+
+    /// Returns whether [`next`][`Packets::next`] would return another packet.
+    pub(crate) fn has_next(&self) -> bool {
+        !self.segments.table.is_empty() || self.page.last_packet_continues()
+    }
+
     /// Iterates to the next packet and returns it, or [`None`] if the last packet has been read.
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<Packet<'_>> {
@@ -387,9 +889,11 @@ impl<const BUFFER_SIZE: usize> Packets<'_, BUFFER_SIZE> {
                     .copy_from_slice(&self.page.data[before..before + size]);
                 buf += size;
                 if complete {
-                    return Some(Packet {
-                        data: &self.buffer[0..buf],
-                    });
+                    return Some(Packet::on_page(
+                        &self.buffer[0..buf],
+                        &self.page,
+                        self.segments.table,
+                    ));
                 }
             } else if self.page.last_packet_continues() {
                 assert!(!self.data.is_empty());
@@ -413,6 +917,14 @@ mod test {
     use super::*;
     use core::error::Error;
 
+    const OPTIONS: ParseOptions = ParseOptions::new();
+
+    /// Options for data that has been modified without updating its checksums.
+    const UNCHECKED: ParseOptions = ParseOptions {
+        crc: None,
+        ..ParseOptions::new()
+    };
+
     #[test]
     fn parse_empty_page() {
         let data = include_bytes!("test/empty.ogg");
@@ -421,7 +933,7 @@ mod test {
         assert_eq!(page.data.len(), 0);
         assert_eq!(page.header.version, 0);
         assert_eq!(page.header.header_type, HeaderFlags::BeginOfStream);
-        assert_eq!(page.header._granule_position, 0);
+        assert_eq!(page.header.granule_position, 0);
         assert_eq!(page.header.bitstream_serial_number, 2132339074);
         assert_eq!(page.header.page_sequence_number, 0);
         assert_eq!(page.header.segment_table, &[0]);
@@ -435,7 +947,7 @@ mod test {
         assert_eq!(page.data.len(), 0x13);
         assert_eq!(page.header.version, 0);
         assert_eq!(page.header.header_type, HeaderFlags::BeginOfStream);
-        assert_eq!(page.header._granule_position, 0);
+        assert_eq!(page.header.granule_position, 0);
         assert_eq!(page.header.bitstream_serial_number, 2132339074);
         assert_eq!(page.header.page_sequence_number, 0);
         assert_eq!(page.header.segment_table, &[0x13]);
@@ -445,9 +957,10 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn parse_packet() -> core::result::Result<(), String> {
         let data = include_bytes!("test/split.ogg");
-        let (remaining, mut packets) = Packets::<512>::parse(data).unwrap();
+        let (remaining, mut packets) = Packets::<512>::parse(data, &OPTIONS).unwrap();
         assert_eq!(remaining.len(), 0);
         assert_eq!(packets.last_page_sequence_number(), 17);
         let packet = packets.next().unwrap();
@@ -478,6 +991,158 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn packet_positions() {
+        let data = include_bytes!("test/mono.opus");
+        let (_, mut packets) = Packets::<512>::parse(&data[136..], &OPTIONS).unwrap();
+        let packet = packets.next().unwrap();
+        assert_eq!(packet.granule_position, None);
+        assert!(!packet.ends_page && !packet.begin_of_stream && !packet.end_of_stream);
+        let mut last = None;
+        while let Some(packet) = packets.next() {
+            last = Some((
+                packet.granule_position,
+                packet.ends_page,
+                packet.end_of_stream,
+            ));
+        }
+        assert_eq!(last, Some((Some(7992), true, true)));
+        let (_, mut packets) = Packets::<512>::parse(data, &OPTIONS).unwrap();
+        let packet = packets.next().unwrap();
+        assert_eq!(packet.granule_position, Some(0));
+        assert!(packet.ends_page && packet.begin_of_stream && !packet.end_of_stream);
+        assert_eq!(Packet::new(&[1]).granule_position, None);
+    }
+
+    #[test]
+    fn page_too_large() {
+        let data = include_bytes!("test/split.ogg");
+        let result = Page::parse_with(
+            data,
+            &ParseOptions {
+                max_page_size: 27 + 1 + 254,
+                ..OPTIONS
+            },
+        );
+        assert_eq!(result, Err(OggError::PageTooLargeError(282, 283)));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "page is too large: maximum is 282 but page has 283 bytes"
+        );
+        assert!(Page::parse_with(
+            data,
+            &ParseOptions {
+                max_page_size: 27 + 1 + 255,
+                ..OPTIONS
+            }
+        )
+        .is_ok());
+        let result = Packets::<512>::parse(
+            data,
+            &ParseOptions {
+                max_page_size: 100,
+                ..OPTIONS
+            },
+        );
+        assert_eq!(result, Err(OggError::PageTooLargeError(100, 283)));
+    }
+
+    #[test]
+    fn raw_pages() {
+        let data = include_bytes!("test/mono.opus");
+        let pages = Pages::new(data).collect::<core::result::Result<Vec<_>, _>>();
+        let pages = pages.unwrap();
+        assert_eq!(pages.len(), 3);
+        assert!(pages[0].begin_of_stream());
+        assert_eq!(&pages[0].data()[..8], b"OpusHead");
+        assert_eq!(&pages[1].data()[..8], b"OpusTags");
+        assert!(pages[2].end_of_stream());
+        assert!(!pages[2].continued());
+        assert_eq!(pages[2].granule_position(), 7992);
+        assert_eq!(pages[2].segment_table().len(), 7);
+        for (index, page) in pages.iter().enumerate() {
+            assert_eq!(page.page_sequence_number(), index as u32);
+            assert_eq!(
+                page.bitstream_serial_number(),
+                pages[0].bitstream_serial_number()
+            );
+        }
+        let bytes: Vec<u8> = pages
+            .iter()
+            .flat_map(|page| page.as_bytes())
+            .copied()
+            .collect();
+        assert_eq!(bytes, data);
+    }
+
+    #[test]
+    fn page_packets() {
+        let data = include_bytes!("test/mono.opus");
+        let pages: Vec<_> = Pages::new(data).map(|page| page.unwrap()).collect();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].header_type(), 0b010);
+        assert_eq!(pages[2].header_type(), 0b100);
+        assert_eq!(
+            pages[0].checksum(),
+            u32::from_le_bytes(data[22..26].try_into().unwrap())
+        );
+        let packets: Vec<_> = pages[0].packets().collect();
+        assert_eq!(packets, [(pages[0].data(), true)]);
+        let sizes: Vec<_> = pages[2]
+            .packets()
+            .map(|(packet, ended)| (packet.len(), ended))
+            .collect();
+        assert_eq!(sizes, [13, 13, 13, 13, 14, 16, 14].map(|size| (size, true)));
+        // Packet that continues on the next page
+        let mut page = Vec::from(&data[..HEADER_SIZE]);
+        page[HEADER_SIZE - 1] = 3;
+        page.extend_from_slice(&[10, 255, 255]);
+        page.extend_from_slice(&[0; 10 + 2 * 255]);
+        let page = RawPage::parse_with(&page, &UNCHECKED).unwrap();
+        let sizes: Vec<_> = page
+            .packets()
+            .map(|(packet, ended)| (packet.len(), ended))
+            .collect();
+        assert_eq!(sizes, [(10, true), (510, false)]);
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut data = Vec::from(include_bytes!("test/mono.opus"));
+        // Corrupt the vendor string of the comment header
+        data[47 + 40] ^= 1;
+        let mut pages = Pages::new(&data);
+        assert!(pages.next().unwrap().is_ok());
+        let result = pages.next().unwrap();
+        assert_eq!(result, Err(OggError::CrcMismatch(1)));
+        let error = result.unwrap_err();
+        assert!(error.is_recoverable());
+        assert_eq!(error.to_string(), "checksum mismatch on page 1");
+        // Iteration continues after the page
+        assert_eq!(pages.next().unwrap().unwrap().page_sequence_number(), 2);
+        assert_eq!(pages.next(), None);
+        let result = Page::skip(&data[47..], &OPTIONS);
+        assert_eq!(result, Err(OggError::CrcMismatch(1)));
+        assert!(Page::skip(&data[47..], &UNCHECKED).is_ok());
+        assert_eq!(
+            Pages::with_options(&data, UNCHECKED)
+                .filter(|page| page.is_ok())
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn raw_pages_error() {
+        let data = include_bytes!("test/mono.opus");
+        let mut pages = Pages::new(&data[..data.len() - 1]);
+        assert!(pages.next().unwrap().is_ok());
+        assert!(pages.next().unwrap().is_ok());
+        assert_eq!(pages.next(), Some(Err(OggError::EndOfStreamError(None))));
+        assert_eq!(pages.next(), None);
+        assert!(pages.remaining().is_empty());
+    }
+
     #[test]
     fn incomplete_page() {
         let data = include_bytes!("test/single.ogg");
@@ -489,12 +1154,12 @@ mod test {
     #[test]
     fn incomplete_packet() {
         let data = include_bytes!("test/split.ogg");
-        let result = Packets::<512>::parse(&data[..350]);
+        let result = Packets::<512>::parse(&data[..350], &OPTIONS);
         assert_eq!(result, Err(OggError::EndOfStreamError(None)));
         let error = result.unwrap_err();
         assert!(error.source().is_none());
         assert_eq!(error.to_string(), "ogg stream ended abruptly");
-        let result = Packets::<512>::parse(&data[..300]);
+        let result = Packets::<512>::parse(&data[..300], &OPTIONS);
         assert_eq!(
             result,
             Err(OggError::EndOfStreamError(Some(1.try_into().unwrap())))
@@ -521,12 +1186,12 @@ mod test {
     #[test]
     fn test_skip() {
         let data = include_bytes!("test/split.ogg");
-        let (remaining, page) = Page::skip(data).unwrap();
+        let (remaining, page) = Page::skip(data, &OPTIONS).unwrap();
         assert_eq!(remaining.len(), 0);
         assert_eq!(page.data.len(), 45);
         assert_eq!(page.header.version, 0);
         assert_eq!(page.header.header_type, HeaderFlags::Continuation);
-        assert_eq!(page.header._granule_position, 0);
+        assert_eq!(page.header.granule_position, 0);
         assert_eq!(page.header.bitstream_serial_number, 2132339074);
         assert_eq!(page.header.page_sequence_number, 17);
         assert_eq!(page.header.segment_table, &[45]);
@@ -536,7 +1201,7 @@ mod test {
     fn bad_sequence() {
         let mut data = Vec::from(include_bytes!("test/split.ogg"));
         data[0x12d] = 9;
-        let result = Page::skip(&data);
+        let result = Page::skip(&data, &UNCHECKED);
         assert_eq!(
             result,
             Err(OggError::InvalidStream(
@@ -549,7 +1214,7 @@ mod test {
             error.to_string(),
             "invalid stream: page sequence numbers are not sequential, previous: 16, current: 9"
         );
-        let result = Packets::<512>::parse(&data);
+        let result = Packets::<512>::parse(&data, &UNCHECKED);
         assert_eq!(
             result,
             Err(OggError::InvalidStream(
@@ -558,6 +1223,7 @@ mod test {
         );
         let error = result.unwrap_err();
         assert!(error.source().is_none());
+        assert!(error.is_recoverable());
         assert_eq!(
             error.to_string(),
             "invalid stream: page sequence numbers are not sequential, previous: 16, current: 9"
@@ -568,7 +1234,7 @@ mod test {
     fn bitstream_changed() {
         let mut data = Vec::from(include_bytes!("test/split.ogg"));
         data[0x129] = 0x81;
-        let result = Page::skip(&data);
+        let result = Page::skip(&data, &UNCHECKED);
         assert_eq!(
             result,
             Err(OggError::UnsupportedStream(
@@ -581,7 +1247,7 @@ mod test {
             error.to_string(),
             "unsupported stream: bitstream serial number changed unexpectedly"
         );
-        let result = Packets::<512>::parse(&data);
+        let result = Packets::<512>::parse(&data, &UNCHECKED);
         assert_eq!(
             result,
             Err(OggError::UnsupportedStream(
@@ -590,6 +1256,7 @@ mod test {
         );
         let error = result.unwrap_err();
         assert!(error.source().is_none());
+        assert!(!error.is_recoverable());
         assert_eq!(
             error.to_string(),
             "unsupported stream: bitstream serial number changed unexpectedly"
@@ -599,13 +1266,136 @@ mod test {
     #[test]
     fn too_small_buffer() {
         let data = include_bytes!("test/split.ogg");
-        let result = Packets::<64>::parse(data);
+        let result = Packets::<64>::parse(data, &OPTIONS);
         assert_eq!(result, Err(OggError::BufferTooSmallError(64, 300)));
         let error = result.unwrap_err();
         assert!(error.source().is_none());
+        assert!(error.is_recoverable());
         assert_eq!(
             error.to_string(),
             "buffer is too small: got 64 but needed 300"
         );
     }
+
+    #[test]
+    fn too_large_packet() {
+        let data = include_bytes!("test/split.ogg");
+        let result = Packets::<512>::parse(
+            data,
+            &ParseOptions {
+                max_packet_size: 299,
+                ..OPTIONS
+            },
+        );
+        assert_eq!(result, Err(OggError::PacketTooLarge(300)));
+        let error = result.unwrap_err();
+        assert!(error.is_recoverable());
+        assert_eq!(
+            error.to_string(),
+            "packet is too large: 300 bytes is more than allowed"
+        );
+        assert!(Packets::<512>::parse(
+            data,
+            &ParseOptions {
+                max_packet_size: 300,
+                ..OPTIONS
+            }
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn missing_continuation() {
+        let mut data = Vec::from(include_bytes!("test/split.ogg"));
+        data[283 + 5] = 0;
+        let result = Page::skip(&data, &UNCHECKED);
+        assert_eq!(
+            result,
+            Err(OggError::InvalidStream(ErrorValues::MissingContinuation(
+                17
+            )))
+        );
+        let result = Packets::<512>::parse(&data, &UNCHECKED);
+        assert_eq!(
+            result,
+            Err(OggError::InvalidStream(ErrorValues::MissingContinuation(
+                17
+            )))
+        );
+        let error = result.unwrap_err();
+        assert!(error.is_recoverable());
+        assert_eq!(
+            error.to_string(),
+            "invalid stream: page 17 does not continue the packet of the previous page"
+        );
+    }
+
+    #[test]
+    fn pages_without_segments() {
+        let data = include_bytes!("test/split.ogg");
+        // Insert a page without segments in the middle of the packet
+        let mut empty = Vec::from(&data[283..283 + 27]);
+        empty[26] = 0;
+        let mut last = Vec::from(&data[283..]);
+        last[18] = 18;
+        let split = [&data[..283], &empty, &last].concat();
+        let (remaining, mut packets) = Packets::<512>::parse(&split, &UNCHECKED).unwrap();
+        assert_eq!(remaining.len(), 0);
+        assert_eq!(packets.last_page_sequence_number(), 18);
+        let packet = packets.next().unwrap().data.to_vec();
+        assert_eq!(packets.next(), None);
+        let (_, mut expected) = Packets::<512>::parse(data, &UNCHECKED).unwrap();
+        assert_eq!(packet, expected.next().unwrap().data);
+        // Page without segments between packets has no packets
+        empty[5] = 0;
+        let (remaining, mut packets) = Packets::<512>::parse(&empty, &UNCHECKED).unwrap();
+        assert_eq!(remaining.len(), 0);
+        assert_eq!(packets.next(), None);
+        assert!(!packets.has_next());
+    }
+
+    /// Build page of split.ogg stream with segment table and data filled with sequence number.
+    fn build_page(page_sequence_number: u32, continued: bool, segment_table: &[u8]) -> Vec<u8> {
+        let data = include_bytes!("test/split.ogg");
+        let mut page = Vec::from(&data[..26]);
+        page[5] = u8::from(continued);
+        page[18..22].copy_from_slice(&page_sequence_number.to_le_bytes());
+        page.push(segment_table.len() as u8);
+        page.extend_from_slice(segment_table);
+        let size: usize = segment_table.iter().map(|size| usize::from(*size)).sum();
+        page.resize(page.len() + size, page_sequence_number as u8);
+        let checksum = crc::page_checksum(&SoftwareCrc, &page);
+        page[22..26].copy_from_slice(&checksum.to_le_bytes());
+        page
+    }
+
+    #[test]
+    fn packet_over_full_pages() {
+        // Both pages are full of 255 lacing values
+        let data = [
+            build_page(0, false, &[255; 255]),
+            build_page(1, true, &[255; 255]),
+            build_page(2, true, &[10, 20]),
+        ]
+        .concat();
+        let (remaining, mut packets) = Packets::<131_000>::parse(&data, &OPTIONS).unwrap();
+        assert_eq!(remaining.len(), 0);
+        assert_eq!(packets.last_page_sequence_number(), 2);
+        let packet = packets.next().unwrap();
+        assert_eq!(packet.data.len(), 2 * 255 * 255 + 10);
+        assert!(packet.data[..255 * 255].iter().all(|value| *value == 0));
+        assert!(packet.data[255 * 255..]
+            .iter()
+            .all(|value| *value == 1 || *value == 2));
+        assert_eq!(packets.next().unwrap().data, &[2; 20]);
+        assert_eq!(packets.next(), None);
+        let result = Packets::<131_000>::parse(
+            &data,
+            &ParseOptions {
+                max_packet_size: 100_000,
+                ..OPTIONS
+            },
+        );
+        assert_eq!(result, Err(OggError::PacketTooLarge(2 * 255 * 255 + 10)));
+    }
 }
@@ -0,0 +1,167 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * CRC-32 as used by ogg.
+ *
+ * Checksums of pages are calculated with [`Crc32Provider`] which is [`SoftwareCrc`] by default.
+ * Firmware can implement it with a hardware CRC peripheral instead, e.g. the one found in STM32
+ * and nRF chips, to save CPU time. The peripheral must be configured for polynomial
+ * `0x04C11DB7` without reflection of input or output and without final xor. The provider is
+ * given as a static reference so that it can be shared by all readers.
+ *
+ * ```rust
+ * # use oggopus_embedded::{crc::{Crc32Provider, SoftwareCrc}, Bitstream};
+ * # struct Peripheral;
+ * # impl Peripheral {
+ * #     fn set_initial_value(&self, _: u32) {}
+ * #     fn feed(&self, _: &[u8]) {}
+ * #     fn result(&self) -> u32 { 0 }
+ * # }
+ * struct HardwareCrc(Peripheral);
+ *
+ * impl Crc32Provider for HardwareCrc {
+ *     fn update(&self, crc: u32, data: &[u8]) -> u32 {
+ *         self.0.set_initial_value(crc);
+ *         self.0.feed(data);
+ *         self.0.result()
+ *     }
+ * }
+ *
+ * static CRC: HardwareCrc = HardwareCrc(Peripheral);
+ *
+ * # let data = include_bytes!("test/mono.opus");
+ * let stream = Bitstream::new(data).with_crc_provider(&CRC);
+ * ```
+ */
+
+/// Generator polynomial of ogg CRC-32.
+const POLYNOMIAL: u32 = 0x04C1_1DB7;
+
+/// Lookup table for calculating CRC-32 one byte at a time.
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = (index as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ POLYNOMIAL
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+/// Calculates CRC-32 checksums for validating ogg pages.
+pub trait Crc32Provider {
+    /**
+     * Continue calculating CRC-32 over data.
+     *
+     * Calculation starts with 0 and the previous result is passed to continue over more data.
+     * This is the checksum that ogg uses, i.e. no reflection nor final xor.
+     */
+    fn update(&self, crc: u32, data: &[u8]) -> u32;
+}
+
+impl core::fmt::Debug for dyn Crc32Provider {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Crc32Provider")
+    }
+}
+
+/// [`Crc32Provider`] that calculates in software with a lookup table.
+#[derive(Debug, Default, PartialEq)]
+pub struct SoftwareCrc;
+
+impl Crc32Provider for SoftwareCrc {
+    fn update(&self, crc: u32, data: &[u8]) -> u32 {
+        update(crc, data)
+    }
+}
+
+/// Continue calculating CRC-32 over data in software, see [`Crc32Provider::update`].
+pub(crate) fn update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, byte| {
+        (crc << 8) ^ TABLE[usize::from((crc >> 24) as u8 ^ byte)]
+    })
+}
+
+/**
+ * Calculate CRC-32 checksum of raw ogg page with provider.
+ *
+ * The checksum field of the header is taken as zero as it is when the checksum is calculated.
+ */
+pub(crate) fn page_checksum(provider: &dyn Crc32Provider, page: &[u8]) -> u32 {
+    let crc = provider.update(provider.update(0, &page[..22]), &[0; 4]);
+    provider.update(crc, &page[26..])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Bitstream, BitstreamError, OggError, OggPusher, PacketSource};
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn page_checksum() {
+        let data = include_bytes!("test/mono.opus");
+        let mut page = Vec::from(&data[..47]);
+        let expected = u32::from_le_bytes(page[22..26].try_into().unwrap());
+        page[22..26].fill(0);
+        assert_eq!(update(0, &page), expected);
+        // Calculating in parts gives the same result
+        assert_eq!(update(update(0, &page[..10]), &page[10..]), expected);
+        assert_eq!(super::page_checksum(&SoftwareCrc, &data[..47]), expected);
+    }
+
+    /// Counts the pages that were checked.
+    struct CountingCrc(AtomicU32);
+
+    impl Crc32Provider for CountingCrc {
+        fn update(&self, crc: u32, data: &[u8]) -> u32 {
+            if crc == 0 {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+            SoftwareCrc.update(crc, data)
+        }
+    }
+
+    /// Fails all checks.
+    struct BrokenCrc;
+
+    impl Crc32Provider for BrokenCrc {
+        fn update(&self, _crc: u32, _data: &[u8]) -> u32 {
+            0
+        }
+    }
+
+    #[test]
+    fn custom_provider() {
+        static CRC: CountingCrc = CountingCrc(AtomicU32::new(0));
+        let data = include_bytes!("test/mono.opus");
+        let bitstream = Bitstream::new(data).with_crc_provider(&CRC);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        while reader.next_packet().unwrap().is_some() {}
+        assert_eq!(CRC.0.load(Ordering::Relaxed), 3);
+        let mut pusher = OggPusher::<512, 512>::new().with_crc_provider(&BrokenCrc);
+        assert_eq!(pusher.push(data), Err(OggError::CrcMismatch(0)));
+        let bitstream = Bitstream::new(data).with_crc_provider(&BrokenCrc);
+        assert_eq!(
+            bitstream.packet_reader::<512>().unwrap_err(),
+            BitstreamError::OggError(OggError::CrcMismatch(0))
+        );
+        // Turning checks on keeps the provider
+        let bitstream = bitstream.with_crc_check(true);
+        assert!(bitstream.packet_reader::<512>().is_err());
+        let bitstream = bitstream.with_crc_check(false);
+        assert!(bitstream.packet_reader::<512>().is_ok());
+    }
+}
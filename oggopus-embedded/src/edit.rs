@@ -0,0 +1,377 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Editing gains of ogg opus streams in place.
+ *
+ * Loudness scanning tools need to tag files with their gain. [`set_output_gain`] and
+ * [`set_r128_gain`] patch the bytes of the header pages and recompute their checksums, so the
+ * rest of the stream is not rewritten and no memory is needed for a copy of it.
+ *
+ * Lengths of pages cannot change, so an R128 gain comment must already exist with room for the
+ * new value. The value is padded with leading zeros, so e.g. `R128_TRACK_GAIN=-00000` that an
+ * encoder wrote as a placeholder fits any gain.
+ *
+ * ```rust
+ * # use oggopus_embedded::{edit, Bitstream};
+ * # let mut data = include_bytes!("test/offset.opus").to_vec();
+ * // Make the stream 3 dB quieter
+ * edit::set_output_gain(&mut data, -3 * 256).unwrap();
+ * assert_eq!(Bitstream::new(&data).info().unwrap().output_gain, -768);
+ * ```
+ */
+
+use super::{
+    crc::{self, SoftwareCrc},
+    tags::{gain_digits, Normalization},
+    BitstreamError, OggError, Pages, RawPage,
+};
+use core::ops::Range;
+
+/// Error from editing stream.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum EditError {
+    /// Error from parsing the stream.
+    BitstreamError(BitstreamError),
+    /// The comment to edit is not in the comment header.
+    MissingComment,
+    /**
+     * Value of the comment is too short for the new value.
+     *
+     * Contains the length of the value and how many bytes would have been needed.
+     */
+    ValueTooShort(usize, usize),
+}
+
+impl core::fmt::Display for EditError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use EditError::*;
+        match self {
+            BitstreamError(error) => error.fmt(f),
+            MissingComment => f.write_str("comment is missing"),
+            ValueTooShort(got, needed) => f.write_fmt(format_args!(
+                "comment value is too short: got {} but needed {}",
+                got, needed
+            )),
+        }
+    }
+}
+
+impl core::error::Error for EditError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::BitstreamError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<BitstreamError> for EditError {
+    fn from(error: BitstreamError) -> Self {
+        Self::BitstreamError(error)
+    }
+}
+
+impl From<OggError> for EditError {
+    fn from(error: OggError) -> Self {
+        Self::BitstreamError(error.into())
+    }
+}
+
+/// Result for editing streams.
+pub type Result<O> = core::result::Result<O, EditError>;
+
+/**
+ * Set output gain in Q7.8 decibels in the identification header of the first opus stream.
+ *
+ * R128 gains in the comment header are relative to the output gain, so they should be updated
+ * too with [`set_r128_gain`] if there are any.
+ */
+pub fn set_output_gain(data: &mut [u8], gain: i16) -> Result<()> {
+    let (page, header, _) = find_header(data)?;
+    data[header + 16..header + 18].copy_from_slice(&gain.to_le_bytes());
+    update_checksum(&mut data[page]);
+    Ok(())
+}
+
+/**
+ * Set R128 gain in Q7.8 decibels for normalization mode in the comment header of the first opus
+ * stream.
+ *
+ * The first comment with the key of the gain is edited. Returns
+ * [`MissingComment`][`EditError::MissingComment`] if there is none and
+ * [`ValueTooShort`][`EditError::ValueTooShort`] if the gain does not fit in its value.
+ */
+pub fn set_r128_gain(data: &mut [u8], normalization: Normalization, gain: i16) -> Result<()> {
+    let (page, _, serial_number) = find_header(data)?;
+    let start = page.end;
+    let (value, length) = find_value(&data[start..], serial_number, normalization.key())?;
+    let (digits, first) = gain_digits(gain);
+    let digits = &digits[first..];
+    let sign = usize::from(gain < 0);
+    if length < sign + digits.len() {
+        return Err(EditError::ValueTooShort(length, sign + digits.len()));
+    }
+    let byte = |index: usize| match (index + digits.len()).checked_sub(length) {
+        Some(digit) => digits[digit],
+        None if index < sign => b'-',
+        None => b'0',
+    };
+    let mut offset = start;
+    let mut position = 0;
+    while position < value + length {
+        let page = RawPage::parse(&data[offset..])?;
+        let size = page.as_bytes().len();
+        let header = size - page.data().len();
+        if page.bitstream_serial_number() == serial_number {
+            let begin = position;
+            position += page.data().len();
+            let range = value.max(begin)..(value + length).min(position);
+            if !range.is_empty() {
+                for index in range {
+                    data[offset + header + index - begin] = byte(index - value);
+                }
+                update_checksum(&mut data[offset..offset + size]);
+            }
+        }
+        offset += size;
+    }
+    Ok(())
+}
+
+/**
+ * Find the first page of the first opus stream.
+ *
+ * Returns the range of the page, the offset of the header on it and the serial number of the
+ * stream.
+ */
+fn find_header(data: &[u8]) -> Result<(Range<usize>, usize, u32)> {
+    let mut pages = Pages::new(data);
+    while let Some(page) = pages.next() {
+        let page = page?;
+        if !page.begin_of_stream() {
+            break;
+        }
+        if page.data().starts_with(b"OpusHead") && page.data().len() >= 19 {
+            let end = data.len() - pages.remaining().len();
+            let start = end - page.as_bytes().len();
+            let header = end - page.data().len();
+            return Ok((start..end, header, page.bitstream_serial_number()));
+        }
+    }
+    Err(BitstreamError::NotOpusStream.into())
+}
+
+/**
+ * Find the value of the first comment with key in the comment header that begins the data.
+ *
+ * Returns the offset of the value in the comment header packet and its length.
+ */
+fn find_value(data: &[u8], serial_number: u32, key: &str) -> Result<(usize, usize)> {
+    let mut reader = PacketBytes {
+        pages: Pages::new(data),
+        serial_number,
+        data: &[],
+        position: 0,
+    };
+    let mut magic = [0; 8];
+    for byte in &mut magic {
+        *byte = reader.byte()?;
+    }
+    if &magic != b"OpusTags" {
+        return Err(BitstreamError::NotOpusStream.into());
+    }
+    let vendor = reader.length()?;
+    reader.skip(vendor)?;
+    for _ in 0..reader.length()? {
+        let length = reader.length()?;
+        if length <= key.len() {
+            reader.skip(length)?;
+            continue;
+        }
+        let mut matches = true;
+        for expected in key.bytes() {
+            matches &= reader.byte()?.eq_ignore_ascii_case(&expected);
+        }
+        matches &= reader.byte()? == b'=';
+        let length = length - key.len() - 1;
+        if matches {
+            return Ok((reader.position, length));
+        }
+        reader.skip(length)?;
+    }
+    Err(EditError::MissingComment)
+}
+
+/// Recompute the checksum of page after it was modified.
+fn update_checksum(page: &mut [u8]) {
+    let checksum = crc::page_checksum(&SoftwareCrc, page);
+    page[22..26].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Reads bytes of a packet that continues over pages of one stream.
+struct PacketBytes<'data> {
+    pages: Pages<'data>,
+    serial_number: u32,
+    /// Data of the current page that has not been read yet.
+    data: &'data [u8],
+    /// The number of bytes read from the packet.
+    position: usize,
+}
+
+impl PacketBytes<'_> {
+    /// Move to the next page of the stream if the current page has been read.
+    fn fill(&mut self) -> Result<()> {
+        while self.data.is_empty() {
+            let page = self
+                .pages
+                .next()
+                .ok_or(OggError::EndOfStreamError(None))??;
+            if page.bitstream_serial_number() == self.serial_number {
+                self.data = page.data();
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the next byte.
+    fn byte(&mut self) -> Result<u8> {
+        self.fill()?;
+        let byte = self.data[0];
+        self.data = &self.data[1..];
+        self.position += 1;
+        Ok(byte)
+    }
+
+    /// Read little-endian 32-bit length.
+    fn length(&mut self) -> Result<usize> {
+        let mut bytes = [0; 4];
+        for byte in &mut bytes {
+            *byte = self.byte()?;
+        }
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    /// Skip bytes.
+    fn skip(&mut self, mut count: usize) -> Result<()> {
+        while count > 0 {
+            self.fill()?;
+            let skipped = count.min(self.data.len());
+            self.data = &self.data[skipped..];
+            self.position += skipped;
+            count -= skipped;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{tags::TagsBuilder, Bitstream};
+
+    /// Replace the comment header of offset.opus with comments.
+    fn with_comments(comments: &[(&str, &str)]) -> Vec<u8> {
+        let original = include_bytes!("test/offset.opus");
+        let mut buffer = [0; 200];
+        let mut builder = TagsBuilder::new(&mut buffer, "v").unwrap();
+        for (key, value) in comments {
+            builder.push(key, value).unwrap();
+        }
+        let packet = builder.as_bytes();
+        let mut data = original[..47].to_vec();
+        data.extend_from_slice(&original[47..47 + 26]);
+        data.push(1);
+        data.push(packet.len() as u8);
+        data.extend_from_slice(packet);
+        update_checksum(&mut data[47..]);
+        data.extend_from_slice(&original[136..]);
+        data
+    }
+
+    /// Check that all pages are valid and return the comment header.
+    fn gains(data: &[u8]) -> (Option<i16>, Option<i16>, i16) {
+        assert!(Pages::new(data).all(|page| page.is_ok()));
+        let bitstream = Bitstream::new(data);
+        let tags = bitstream.tags().unwrap();
+        (
+            tags.r128_gain(Normalization::Track),
+            tags.r128_gain(Normalization::Album),
+            bitstream.info().unwrap().output_gain,
+        )
+    }
+
+    #[test]
+    fn output_gain() {
+        let mut data = include_bytes!("test/offset.opus").to_vec();
+        set_output_gain(&mut data, -1_000).unwrap();
+        assert_eq!(gains(&data), (None, None, -1_000));
+        assert_eq!(data[47..], include_bytes!("test/offset.opus")[47..]);
+        assert_eq!(
+            set_output_gain(&mut data[47..], 0),
+            Err(EditError::BitstreamError(BitstreamError::NotOpusStream))
+        );
+    }
+
+    #[test]
+    fn r128_gain() {
+        let mut data = with_comments(&[
+            ("TITLE", "T"),
+            ("R128_ALBUM_GAIN", "0"),
+            ("r128_track_gain", "-00000"),
+        ]);
+        set_r128_gain(&mut data, Normalization::Track, -1_280).unwrap();
+        assert_eq!(gains(&data), (Some(-1_280), Some(0), 0));
+        set_r128_gain(&mut data, Normalization::Track, 32_767).unwrap();
+        assert_eq!(gains(&data), (Some(32_767), Some(0), 0));
+        set_r128_gain(&mut data, Normalization::Album, 9).unwrap();
+        assert_eq!(gains(&data), (Some(32_767), Some(9), 0));
+        assert_eq!(
+            set_r128_gain(&mut data, Normalization::Album, -9),
+            Err(EditError::ValueTooShort(1, 2))
+        );
+        let mut data = with_comments(&[("R128_TRACK_GAIN_X", "0")]);
+        assert_eq!(
+            set_r128_gain(&mut data, Normalization::Track, 0),
+            Err(EditError::MissingComment)
+        );
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn continued_comment_header() {
+        use crate::{opus::OpusHeader, writer::OggWriter};
+        let header = OpusHeader::parse(&include_bytes!("test/offset.opus")[28..47]).unwrap();
+        let mut tags = [0; 400];
+        let mut builder = TagsBuilder::new(&mut tags, "test").unwrap();
+        builder.push("TITLE", &"x".repeat(203)).unwrap();
+        builder.push("R128_TRACK_GAIN", "+0000").unwrap();
+        let mut buffer = [0; 1_024];
+        let mut output = &mut buffer[..];
+        let writer =
+            OggWriter::<_, 256>::new_with_tags(&mut output, 7, &header, builder.as_bytes())
+                .unwrap();
+        writer.finish().unwrap();
+        let length = 1_024 - output.len();
+        let data = &mut buffer[..length];
+        // Value continues over two pages
+        assert_eq!(Pages::new(data).count(), 3);
+        set_r128_gain(data, Normalization::Track, -300).unwrap();
+        assert!(Pages::new(data).all(|page| page.is_ok()));
+        let mut reader = PacketBytes {
+            pages: Pages::new(data),
+            serial_number: 7,
+            data: &[],
+            position: 0,
+        };
+        reader.skip(19 + builder.as_bytes().len() - 5).unwrap();
+        let mut value = [0; 5];
+        for byte in &mut value {
+            *byte = reader.byte().unwrap();
+        }
+        assert_eq!(&value, b"-0300");
+    }
+}
@@ -0,0 +1,200 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Owned ogg opus files for hosted tools.
+
+use super::{
+    container::Pages,
+    opus::{OpusError, OpusHeader},
+    tags::OpusTags,
+    Bitstream, BitstreamError, Decode, FramesError, IoReaderError,
+};
+use std::collections::HashMap;
+use std::path::Path;
+
+/**
+ * Ogg opus file that is kept in memory with its parsed headers.
+ *
+ * This is a convenience for desktop tools that share code with devices using the rest of the
+ * crate. Headers and comments of the first logical stream are parsed when the file is opened.
+ *
+ * ```rust
+ * # use oggopus_embedded::OggOpusFile;
+ * # let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/test/mono.opus");
+ * let file = OggOpusFile::open(path).unwrap();
+ * println!("Sample rate is {} Hz", file.header().sample_rate);
+ * for (key, values) in file.tags() {
+ *     println!("{key}: {}", values.join(", "));
+ * }
+ * ```
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Debug)]
+pub struct OggOpusFile {
+    data: Vec<u8>,
+    header: OpusHeader,
+    vendor: String,
+    tags: HashMap<String, Vec<String>>,
+}
+
+impl OggOpusFile {
+    /// Read the whole file at path and parse its headers.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, IoReaderError<std::io::Error>> {
+        let data = std::fs::read(path).map_err(IoReaderError::IoError)?;
+        Ok(Self::new(data)?)
+    }
+
+    /// Construct [`OggOpusFile`] from data and parse its headers.
+    pub fn new(data: Vec<u8>) -> crate::Result<'static, Self> {
+        let (_, header) = Bitstream::new(&data).reader().read_header()?;
+        let comments = comment_header(&data)?;
+        let (vendor, tags) = parse_comments(&comments)?;
+        Ok(Self {
+            data,
+            header,
+            vendor,
+            tags,
+        })
+    }
+
+    /// Returns the opus header of the file.
+    pub fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    /// Returns the vendor string of the comment header.
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+
+    /**
+     * Returns the comments of the file.
+     *
+     * Keys are in upper case as comment field names are case insensitive. A key may have several
+     * values, e.g. for several artists. Invalid UTF-8 is replaced.
+     */
+    pub fn tags(&self) -> &HashMap<String, Vec<String>> {
+        &self.tags
+    }
+
+    /// Returns [`Bitstream`] for reading the file with the rest of the crate.
+    pub fn bitstream(&self) -> Bitstream<'_> {
+        Bitstream::new(&self.data)
+    }
+
+    /// Deconstruct [`OggOpusFile`] into the data of the file.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    /**
+     * Decode the first logical stream with decoder into interleaved samples.
+     *
+     * See [`Bitstream::decode_all`].
+     */
+    pub fn decode_all<D: Decode>(
+        &self,
+        decoder: D,
+    ) -> Result<Vec<i16>, FramesError<BitstreamError, D::Error>> {
+        self.bitstream().decode_all(decoder)
+    }
+}
+
+/// Collect the second packet of the first logical stream.
+fn comment_header(data: &[u8]) -> crate::Result<'static, Vec<u8>> {
+    let mut packets = 0;
+    let mut packet = Vec::new();
+    let mut serial_number = None;
+    for page in Pages::new(data) {
+        let page = page?;
+        if *serial_number.get_or_insert(page.bitstream_serial_number())
+            != page.bitstream_serial_number()
+        {
+            continue;
+        }
+        let mut offset = 0;
+        for size in page.segment_table() {
+            let size = usize::from(*size);
+            if packets == 1 {
+                packet.extend_from_slice(&page.data()[offset..offset + size]);
+            }
+            offset += size;
+            if size < 255 {
+                packets += 1;
+                if packets == 2 {
+                    return Ok(packet);
+                }
+            }
+        }
+    }
+    Err(BitstreamError::InvalidOpusStream("missing comment header"))
+}
+
+/// Parse vendor string and comments from comment header.
+fn parse_comments(input: &[u8]) -> crate::Result<'_, (String, HashMap<String, Vec<String>>)> {
+    let comments = OpusTags::parse(input).map_err(|error| match error {
+        OpusError::NotOpusStream => BitstreamError::InvalidOpusStream("missing comment header"),
+        error => error.into(),
+    })?;
+    let mut tags = HashMap::<_, Vec<_>>::new();
+    for (key, value) in comments.comments() {
+        let value = value.to_string_lossy();
+        tags.entry(key.to_uppercase()).or_default().push(value);
+    }
+    Ok((comments.vendor().to_string_lossy(), tags))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{frames::test::CountingDecoder, Frames};
+
+    #[test]
+    fn open_file() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/test/mono.opus");
+        let file = OggOpusFile::open(path).unwrap();
+        assert_eq!(file.header().sample_rate, 8_000);
+        assert_eq!(file.vendor(), "Lavf61.7.100");
+        assert_eq!(file.tags().len(), 1);
+        assert_eq!(file.tags()["ENCODER"], ["Lavc61.19.100 libopus"]);
+        assert_eq!(file.into_inner(), include_bytes!("test/mono.opus"));
+        let missing = OggOpusFile::open(concat!(env!("CARGO_MANIFEST_DIR"), "/missing.opus"));
+        assert!(matches!(missing, Err(IoReaderError::IoError(_))));
+    }
+
+    #[test]
+    fn decode_file() {
+        let data = include_bytes!("test/trimmed.opus");
+        let file = OggOpusFile::new(data.to_vec()).unwrap();
+        let samples = file.decode_all(CountingDecoder::default()).unwrap();
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 960>::new(reader, CountingDecoder::default());
+        let mut expected = Vec::new();
+        while let Some(frame) = frames.next().unwrap() {
+            expected.extend_from_slice(frame);
+        }
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn repeated_tags() {
+        let mut comments = b"OpusTags\x01\x00\x00\x00v\x04\x00\x00\x00".to_vec();
+        for comment in [&b"Artist=A"[..], b"ARTIST=B", b"title=T=1", b"broken"] {
+            comments.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            comments.extend_from_slice(comment);
+        }
+        let (vendor, tags) = parse_comments(&comments).unwrap();
+        assert_eq!(vendor, "v");
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags["ARTIST"], ["A", "B"]);
+        assert_eq!(tags["TITLE"], ["T=1"]);
+        let result = parse_comments(&comments[..comments.len() - 1]);
+        assert!(matches!(result, Err(BitstreamError::OpusError(_))));
+        let result = parse_comments(b"OpusHead");
+        assert_eq!(
+            result,
+            Err(BitstreamError::InvalidOpusStream("missing comment header"))
+        );
+    }
+}
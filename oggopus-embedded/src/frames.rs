@@ -0,0 +1,1742 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Decoding of opus packets into frames of audio.
+
+#[cfg(feature = "async")]
+use super::AsyncPacketSource;
+use super::{
+    opus::{packet_samples, OpusHeader},
+    seek::{self, SeekEntry, SeekablePacketSource, PRE_ROLL},
+    tags::{Normalization, OpusTags},
+    time::{GranulePos, GRANULE_RATE},
+    PacketSource,
+};
+use core::time::Duration;
+
+/**
+ * Opus decoder.
+ *
+ * This is implemented by opus-embedded's decoder when its `ogg` feature is enabled, but any
+ * decoder can be used with [`Frames`].
+ */
+pub trait Decode {
+    /// Error returned when decoding fails.
+    type Error;
+
+    /// Returns the sample rate of decoded audio in Hz.
+    fn sample_rate(&self) -> u32;
+
+    /// Returns the number of interleaved channels in decoded audio.
+    fn channels(&self) -> u8;
+
+    /**
+     * Decode opus packet into output buffer.
+     *
+     * Empty packet means that the packet was lost. Returns the decoded frame which must be stored
+     * at the beginning of output buffer.
+     */
+    fn decode<'output>(
+        &mut self,
+        packet: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], Self::Error>;
+
+    /**
+     * Recover a lost packet from forward error correction data of the packet after it.
+     *
+     * Output buffer is exactly as long as the lost packet. The packet itself is decoded with
+     * [`decode`][`Decode::decode`] after this. The default implementation conceals the lost
+     * packet as if it had no FEC data.
+     */
+    fn decode_fec<'output>(
+        &mut self,
+        _packet: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], Self::Error> {
+        self.decode(&[], output)
+    }
+
+    /**
+     * Reset decoder state so that the next packet is decoded as if it was the first one.
+     *
+     * This is called when seeking. The default implementation does nothing.
+     */
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/**
+ * Decoders in heap memory, e.g. `Box<dyn Decode<Error = E>>` for choosing the decoder at runtime.
+ *
+ * Real decoders have large state, which may be better kept out of the stack.
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[cfg(feature = "alloc")]
+impl<D: Decode + ?Sized> Decode for alloc::boxed::Box<D> {
+    type Error = D::Error;
+
+    fn sample_rate(&self) -> u32 {
+        (**self).sample_rate()
+    }
+
+    fn channels(&self) -> u8 {
+        (**self).channels()
+    }
+
+    fn decode<'output>(
+        &mut self,
+        packet: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], Self::Error> {
+        (**self).decode(packet, output)
+    }
+
+    fn decode_fec<'output>(
+        &mut self,
+        packet: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], Self::Error> {
+        (**self).decode_fec(packet, output)
+    }
+
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        (**self).reset()
+    }
+}
+
+/// Error from decoding frames.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum FramesError<S, D> {
+    /// Error from reading packets from [`PacketSource`].
+    SourceError(S),
+    /// Error from decoding a packet with [`Decode`].
+    DecoderError(D),
+}
+
+impl<D> FramesError<crate::BitstreamError, D> {
+    /// Returns the layer of the stream where the error comes from.
+    pub fn layer(&self) -> crate::ErrorLayer {
+        match self {
+            Self::SourceError(error) => error.layer(),
+            Self::DecoderError(_) => crate::ErrorLayer::Decoder,
+        }
+    }
+}
+
+impl<S: core::fmt::Display, D: core::fmt::Display> core::fmt::Display for FramesError<S, D> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use FramesError::*;
+        match self {
+            SourceError(error) => f.write_fmt(format_args!("failed to read packet: {}", error)),
+            DecoderError(error) => f.write_fmt(format_args!("failed to decode packet: {}", error)),
+        }
+    }
+}
+
+impl<S, D> core::error::Error for FramesError<S, D>
+where
+    S: core::error::Error + 'static,
+    D: core::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        use FramesError::*;
+        match self {
+            SourceError(error) => Some(error),
+            DecoderError(error) => Some(error),
+        }
+    }
+}
+
+/// Result for decoding frames.
+type FrameResult<'frame, S, D> =
+    Result<Option<&'frame [i16]>, FramesError<<S as PacketSource>::Error, <D as Decode>::Error>>;
+
+/// Result for decoding frames into planar buffers.
+type PlanarResult<S, D> =
+    Result<Option<usize>, FramesError<<S as PacketSource>::Error, <D as Decode>::Error>>;
+
+/// [`SeekablePacketSource::seek_granule`] of a source.
+type SeekGranule<S> = fn(&mut S, u64) -> Result<u64, <S as PacketSource>::Error>;
+
+/**
+ * Iterator for decoded frames of audio.
+ *
+ * Reads packets from [`PacketSource`], decodes them with [`Decode`] and returns the decoded
+ * frames. Pre-skip samples are discarded from the beginning of the stream and the last frame is
+ * trimmed to the final granule position if the source knows it. Granule positions are counted
+ * from the start of the stream, so streams that do not start from zero are trimmed too. Frames
+ * are interleaved if there are multiple channels.
+ *
+ * `BUFFER_SIZE` is the number of samples in decoding buffer and it must fit the largest frame in
+ * the stream. For 120 ms frames of stereo audio at 48 kHz that is 11 520 samples.
+ *
+ * Note that this does not implement [`Iterator`] trait because it is not possible to borrow from
+ * iterator in [`Item`][`Iterator::Item`].
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, Decode, Frames};
+ * # struct Decoder;
+ * # impl Decode for Decoder {
+ * #     type Error = ();
+ * #     fn sample_rate(&self) -> u32 { 8_000 }
+ * #     fn channels(&self) -> u8 { 1 }
+ * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+ * #         Ok(&o[..160])
+ * #     }
+ * # }
+ * # let decoder = Decoder;
+ * # let data = include_bytes!("test/mono.opus");
+ * let stream = Bitstream::new(data);
+ * let reader = stream.packet_reader::<512>().unwrap();
+ * let mut frames = Frames::<_, _, 960>::new(reader, decoder);
+ * while let Some(frame) = frames.next().unwrap() {
+ *     println!("Got {} samples of audio", frame.len());
+ * }
+ * ```
+ */
+pub struct Frames<S: PacketSource, D: Decode, const BUFFER_SIZE: usize> {
+    source: S,
+    core: Core<D, BUFFER_SIZE>,
+    /// [`SeekablePacketSource::seek_granule`] of the source when looping is set.
+    seek_granule: Option<SeekGranule<S>>,
+}
+
+impl<S, D, const BUFFER_SIZE: usize> core::fmt::Debug for Frames<S, D, BUFFER_SIZE>
+where
+    S: PacketSource + core::fmt::Debug,
+    D: Decode + core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Frames")
+            .field("source", &self.source)
+            .field("core", &self.core)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: PacketSource, D: Decode, const BUFFER_SIZE: usize> Frames<S, D, BUFFER_SIZE> {
+    /// Construct [`Frames`] that decodes packets from source with decoder.
+    pub fn new(source: S, decoder: D) -> Self {
+        Self {
+            core: Core::new(decoder, source.header()),
+            source,
+            seek_granule: None,
+        }
+    }
+
+    /**
+     * Set whether pre-skip samples are discarded from the beginning of the stream.
+     *
+     * Defaults to `true`. Turn this off if the caller trims the beginning of the stream itself.
+     * The last frame is still trimmed to the final granule position, which then counts the
+     * pre-skip samples too. Must be set before the first frame is decoded.
+     */
+    pub fn with_pre_skip(mut self, discard: bool) -> Self {
+        self.core.set_pre_skip(discard, self.source.header());
+        self
+    }
+
+    /// Returns whether the stream or a region of it is played in a loop.
+    pub fn is_looping(&self) -> bool {
+        self.core.looping.is_some()
+    }
+
+    /**
+     * Set whether output gain of the opus header is applied to decoded samples.
+     *
+     * Defaults to `true` so that streams play at the level they were mastered for. Turn this off
+     * if the decoder applies the gain itself. Samples that would clip are saturated.
+     */
+    pub fn with_header_gain(mut self, apply: bool) -> Self {
+        self.core.set_header_gain(apply, self.source.header());
+        self
+    }
+
+    /**
+     * Set loudness normalization with R128 gain from the comment header.
+     *
+     * The gain of [`OpusTags`] for the mode is added to the output gain of the opus header,
+     * which normalizes the loudness to EBU R128 reference level as described in
+     * [RFC7845](https://datatracker.ietf.org/doc/html/rfc7845#section-5.2.1). Nothing is added if
+     * the comment header does not have the gain, e.g. album gain is usually in the output gain
+     * already. The gain is applied even if [header gain][`Self::with_header_gain`] is not.
+     */
+    pub fn with_normalization(mut self, tags: &OpusTags<'_>, normalization: Normalization) -> Self {
+        self.core.set_normalization(tags, normalization);
+        self
+    }
+
+    /// Returns the packet source.
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Returns the decoder.
+    pub fn decoder(&self) -> &D {
+        &self.core.decoder
+    }
+
+    /// Deconstruct [`Frames`] into the packet source and the decoder.
+    pub fn into_inner(self) -> (S, D) {
+        (self.source, self.core.decoder)
+    }
+
+    /// Decode the rest of the stream into interleaved samples.
+    #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+    #[cfg(feature = "alloc")]
+    pub fn decode_all(mut self) -> Result<alloc::vec::Vec<i16>, FramesError<S::Error, D::Error>> {
+        let mut samples = alloc::vec::Vec::new();
+        while let Some(frame) = self.next()? {
+            samples.extend_from_slice(frame);
+        }
+        Ok(samples)
+    }
+
+    /**
+     * Give back samples from the end of the last frame.
+     *
+     * The samples are returned again by the next call to [`next`][`Frames::next`]. This is
+     * useful when only a part of a frame could be used, e.g. when output buffer became full.
+     *
+     * # Panics
+     * Panics if there are not that many samples left in the last frame.
+     */
+    pub fn unread(&mut self, samples: usize) {
+        self.core.unread(samples);
+    }
+
+    /// Returns the number of samples given back with [`unread`][`Frames::unread`].
+    pub fn unread_samples(&self) -> usize {
+        self.core.unread
+    }
+
+    /**
+     * Returns the number of samples per channel returned so far at the decoder sample rate.
+     *
+     * This is the playback position counted from the first sample after pre-skip, or from the
+     * first sample of the stream if pre-skip is not discarded. Seeking moves the position to the
+     * target and samples given back with [`unread`][`Frames::unread`] are not counted. Frames
+     * decoded from lost packets are counted like others.
+     */
+    pub fn position(&self) -> u64 {
+        self.core.position()
+    }
+
+    /// Returns the playback position as time. See [`position`][`Frames::position`].
+    pub fn elapsed(&self) -> Duration {
+        self.core.elapsed()
+    }
+
+    /**
+     * Decodes the next frame and returns it, or [`None`] if the stream has ended.
+     *
+     * Frames that are discarded completely are not returned, so the returned frames are never
+     * empty. If samples were given back with [`unread`][`Frames::unread`], those are returned
+     * before decoding more.
+     */
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> FrameResult<'_, S, D> {
+        if self.core.begin_next() {
+            return Ok(Some(self.core.frame()));
+        }
+        let mut looped = false;
+        loop {
+            // Packets after the end of the region are not read
+            let packet = if self.core.region_ended() {
+                None
+            } else {
+                self.source
+                    .next_packet()
+                    .map_err(FramesError::SourceError)?
+            };
+            let Some(packet) = packet else {
+                match (self.core.looping, self.seek_granule) {
+                    // Stop if the region did not have any samples
+                    (Some(looping), Some(seek_granule)) if !looped => {
+                        let position =
+                            seek_granule(&mut self.source, looping.start.saturating_sub(PRE_ROLL))
+                                .map_err(FramesError::SourceError)?;
+                        self.core.restart(looping.start, position)?;
+                        looped = true;
+                        continue;
+                    }
+                    _ => return Ok(None),
+                }
+            };
+            let samples = self.core.decode(packet.data)?;
+            if self.core.trim(
+                samples,
+                self.source.start_granule_position(),
+                self.source.end_granule_position(),
+            ) {
+                return Ok(Some(self.core.frame()));
+            }
+        }
+    }
+
+    /**
+     * Skip forward to time from the beginning of the stream by reading and discarding packets.
+     *
+     * This works with sources that cannot rewind, e.g. streams that are received over UART.
+     * Packets before the target are not decoded. If `pre_roll` is set, the packets of 80 ms
+     * before the target are decoded so that the decoder has converged when output resumes,
+     * otherwise decoding starts from the packet that contains the target. The decoder is reset
+     * either way and the next frame begins exactly at the target. Time is counted like with
+     * [`seek_to`][`Frames::seek_to`]. Nothing is skipped if the target is not ahead of the
+     * current position.
+     */
+    pub fn skip_to(
+        &mut self,
+        time: Duration,
+        pre_roll: bool,
+    ) -> Result<(), FramesError<S::Error, D::Error>> {
+        let Some(skip) = self.core.begin_skip(time, pre_roll) else {
+            return Ok(());
+        };
+        while let Some(packet) = self
+            .source
+            .next_packet()
+            .map_err(FramesError::SourceError)?
+        {
+            if let Some(samples) = self.core.skip(skip, packet.data)? {
+                self.core.trim_skipped(
+                    samples,
+                    self.source.start_granule_position(),
+                    self.source.end_granule_position(),
+                );
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Decodes the next frame into separate left and right channel buffers.
+     *
+     * Samples are de-interleaved while they are copied, so the frame does not need to be
+     * traversed again. Mono audio is copied to both buffers. Samples that do not fit in the
+     * buffers are returned first by the next call. Returns the number of samples written to
+     * each buffer, or [`None`] if the stream has ended.
+     *
+     * # Panics
+     * Panics if the buffers have different lengths.
+     */
+    pub fn next_planar(&mut self, left: &mut [i16], right: &mut [i16]) -> PlanarResult<S, D> {
+        assert_eq!(left.len(), right.len(), "Buffers must be equally long");
+        let channels = usize::from(self.core.decoder.channels());
+        let Some(frame) = self.next()? else {
+            return Ok(None);
+        };
+        let samples = frame.len();
+        let mut written = 0;
+        for ((samples, left), right) in frame
+            .chunks_exact(channels)
+            .zip(left.iter_mut())
+            .zip(right.iter_mut())
+        {
+            *left = samples[0];
+            *right = samples[channels.min(2) - 1];
+            written += 1;
+        }
+        if written * channels < samples {
+            self.unread(samples - written * channels);
+        }
+        Ok(Some(written))
+    }
+}
+
+impl<S: SeekablePacketSource, D: Decode, const BUFFER_SIZE: usize> Frames<S, D, BUFFER_SIZE> {
+    /**
+     * Seek to time from the beginning of the stream.
+     *
+     * Time is counted from the first sample after pre-skip, or from the first sample of the
+     * stream if pre-skip is not discarded. The page to continue from is found by bisecting
+     * granule positions and the decoder is reset. Decoding restarts 80 ms before the target so
+     * that the decoder has converged, and the samples before the target are discarded, so the
+     * next frame begins exactly at it. Seeking past the end ends the stream.
+     *
+     * ```rust
+     * # use oggopus_embedded::{Bitstream, Decode, Frames};
+     * # struct Decoder;
+     * # impl Decode for Decoder {
+     * #     type Error = ();
+     * #     fn sample_rate(&self) -> u32 { 8_000 }
+     * #     fn channels(&self) -> u8 { 1 }
+     * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+     * #         Ok(&o[..160])
+     * #     }
+     * # }
+     * # let data = include_bytes!("test/mono.opus");
+     * let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+     * let mut frames = Frames::<_, _, 960>::new(reader, Decoder);
+     * frames.seek_to(core::time::Duration::from_millis(100)).unwrap();
+     * let frame = frames.next().unwrap().unwrap();
+     * // 100 ms after 52 samples of pre-skip is in the middle of the sixth frame
+     * assert_eq!(frame.len(), 6 * 160 - 52 - 800);
+     * ```
+     */
+    pub fn seek_to(&mut self, time: Duration) -> Result<(), FramesError<S::Error, D::Error>> {
+        let target = self.core.target(time);
+        let position = self
+            .source
+            .seek_granule(target.saturating_sub(PRE_ROLL))
+            .map_err(FramesError::SourceError)?;
+        self.core.restart(target, position)
+    }
+
+    /**
+     * Seek to time from the beginning of the stream with entries of [`SeekIndex`].
+     *
+     * This is like [`seek_to`][`Frames::seek_to`] but jumps directly to the page of the entry
+     * before the pre-roll, so that only one page needs to be found on slow media. Bisection is
+     * used if there is no entry before the pre-roll. The entries must be built for the same
+     * stream, e.g. with [`PacketReader::seek_index`][`crate::PacketReader::seek_index`].
+     *
+     * [`SeekIndex`]: crate::SeekIndex
+     */
+    pub fn seek_indexed(
+        &mut self,
+        time: Duration,
+        entries: &[SeekEntry],
+    ) -> Result<(), FramesError<S::Error, D::Error>> {
+        let target = self.core.target(time);
+        let pre_roll = target.saturating_sub(PRE_ROLL);
+        let position = match seek::lookup(entries, pre_roll) {
+            Some(entry) => self.source.seek_entry(entry),
+            None => self.source.seek_granule(pre_roll),
+        }
+        .map_err(FramesError::SourceError)?;
+        self.core.restart(target, position)
+    }
+
+    /**
+     * Set whether the whole stream is played in a loop.
+     *
+     * When the end of the stream is reached, decoding continues from the beginning as if
+     * [`seek_to`][`Frames::seek_to`] had been called, so the first frame of the next round
+     * begins exactly at the first sample after the last one. Turning looping off also clears the
+     * region set with [`loop_region`][`Frames::loop_region`].
+     */
+    pub fn set_looping(&mut self, looping: bool) {
+        self.core.set_looping(looping);
+        self.seek_granule = Some(S::seek_granule);
+    }
+
+    /**
+     * Play the region between `start` and `end` in a loop.
+     *
+     * Time is counted like with [`seek_to`][`Frames::seek_to`]. When the end of the region is
+     * reached, the last frame is trimmed to it and decoding restarts 80 ms before the start with
+     * the decoder reset, and the samples before the start are discarded. The loop joins sample
+     * exactly without clicks if the audio is made to loop, e.g. background music of a game. If
+     * playback is before the region, it continues until the end of the region and if it is after
+     * the region, it jumps to the start on the next frame. The end of the stream ends the region
+     * if it comes first.
+     *
+     * ```rust
+     * # use oggopus_embedded::{Bitstream, Decode, Frames};
+     * # struct Decoder;
+     * # impl Decode for Decoder {
+     * #     type Error = ();
+     * #     fn sample_rate(&self) -> u32 { 8_000 }
+     * #     fn channels(&self) -> u8 { 1 }
+     * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+     * #         Ok(&o[..160])
+     * #     }
+     * # }
+     * # let data = include_bytes!("test/mono.opus");
+     * use core::time::Duration;
+     * let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+     * let mut frames = Frames::<_, _, 960>::new(reader, Decoder);
+     * frames.loop_region(Duration::from_millis(20), Duration::from_millis(80));
+     * // The region is played until looping is turned off
+     * for _ in 0..100 {
+     *     assert!(frames.next().unwrap().is_some());
+     * }
+     * ```
+     *
+     * # Panics
+     * Panics if the region ends before it starts.
+     */
+    pub fn loop_region(&mut self, start: Duration, end: Duration) {
+        self.core.loop_region(start, end);
+        self.seek_granule = Some(S::seek_granule);
+    }
+}
+
+/**
+ * Asynchronous stream of decoded frames of audio.
+ *
+ * This is the asynchronous counterpart of [`Frames`] for [`AsyncPacketSource`]. Reading packets
+ * is awaited but decoding is not as it does not wait for anything.
+ *
+ * ```rust
+ * # use oggopus_embedded::{AsyncFrames, AsyncIoPacketReader, Decode, MAX_PAGE_SIZE};
+ * # struct Decoder;
+ * # impl Decode for Decoder {
+ * #     type Error = ();
+ * #     fn sample_rate(&self) -> u32 { 8_000 }
+ * #     fn channels(&self) -> u8 { 1 }
+ * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+ * #         Ok(&o[..160])
+ * #     }
+ * # }
+ * async fn play(file: &[u8], decoder: Decoder) {
+ *     let reader = AsyncIoPacketReader::<_, MAX_PAGE_SIZE, 1_024>::new(file).await.unwrap();
+ *     let mut frames = AsyncFrames::<_, _, 960>::new(reader, decoder);
+ *     while let Some(frame) = frames.next().await.unwrap() {
+ *         println!("Got {} samples of audio", frame.len());
+ *     }
+ * }
+ * ```
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncFrames<S: AsyncPacketSource, D: Decode, const BUFFER_SIZE: usize> {
+    source: S,
+    core: Core<D, BUFFER_SIZE>,
+}
+
+#[cfg(feature = "async")]
+impl<S: AsyncPacketSource, D: Decode, const BUFFER_SIZE: usize> AsyncFrames<S, D, BUFFER_SIZE> {
+    /// Construct [`AsyncFrames`] that decodes packets from source with decoder.
+    pub fn new(source: S, decoder: D) -> Self {
+        Self {
+            core: Core::new(decoder, source.header()),
+            source,
+        }
+    }
+
+    /**
+     * Set whether pre-skip samples are discarded from the beginning of the stream.
+     *
+     * See [`Frames::with_pre_skip`].
+     */
+    pub fn with_pre_skip(mut self, discard: bool) -> Self {
+        self.core.set_pre_skip(discard, self.source.header());
+        self
+    }
+
+    /**
+     * Set whether output gain of the opus header is applied to decoded samples.
+     *
+     * See [`Frames::with_header_gain`].
+     */
+    pub fn with_header_gain(mut self, apply: bool) -> Self {
+        self.core.set_header_gain(apply, self.source.header());
+        self
+    }
+
+    /**
+     * Set loudness normalization with R128 gain from the comment header.
+     *
+     * The gain of [`OpusTags`] for the mode is added to the output gain of the opus header,
+     * which normalizes the loudness to EBU R128 reference level as described in
+     * [RFC7845](https://datatracker.ietf.org/doc/html/rfc7845#section-5.2.1). Nothing is added if
+     * the comment header does not have the gain, e.g. album gain is usually in the output gain
+     * already. The gain is applied even if [header gain][`Self::with_header_gain`] is not.
+     */
+    pub fn with_normalization(mut self, tags: &OpusTags<'_>, normalization: Normalization) -> Self {
+        self.core.set_normalization(tags, normalization);
+        self
+    }
+
+    /// Returns the packet source.
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Returns the decoder.
+    pub fn decoder(&self) -> &D {
+        &self.core.decoder
+    }
+
+    /// Deconstruct [`AsyncFrames`] into the packet source and the decoder.
+    pub fn into_inner(self) -> (S, D) {
+        (self.source, self.core.decoder)
+    }
+
+    /**
+     * Give back samples from the end of the last frame.
+     *
+     * See [`Frames::unread`].
+     *
+     * # Panics
+     * Panics if there are not that many samples left in the last frame.
+     */
+    pub fn unread(&mut self, samples: usize) {
+        self.core.unread(samples);
+    }
+
+    /// Returns the number of samples given back with [`unread`][`AsyncFrames::unread`].
+    pub fn unread_samples(&self) -> usize {
+        self.core.unread
+    }
+
+    /// Returns the number of samples per channel returned so far. See [`Frames::position`].
+    pub fn position(&self) -> u64 {
+        self.core.position()
+    }
+
+    /// Returns the playback position as time. See [`Frames::position`].
+    pub fn elapsed(&self) -> Duration {
+        self.core.elapsed()
+    }
+
+    /// Returns whether the stream or a region of it is played in a loop.
+    pub fn is_looping(&self) -> bool {
+        self.core.looping.is_some()
+    }
+
+    /**
+     * Set whether the whole stream is played in a loop.
+     *
+     * See [`Frames::set_looping`]. The source is moved back with
+     * [`AsyncPacketSource::seek_granule`], and the stream ends as usual if the source cannot
+     * seek.
+     */
+    pub fn set_looping(&mut self, looping: bool) {
+        self.core.set_looping(looping);
+    }
+
+    /**
+     * Play the region between `start` and `end` in a loop.
+     *
+     * See [`Frames::loop_region`] and [`set_looping`][`AsyncFrames::set_looping`].
+     *
+     * # Panics
+     * Panics if the region ends before it starts.
+     */
+    pub fn loop_region(&mut self, start: Duration, end: Duration) {
+        self.core.loop_region(start, end);
+    }
+
+    /**
+     * Skip forward to time from the beginning of the stream by reading and discarding packets.
+     *
+     * See [`Frames::skip_to`].
+     */
+    pub async fn skip_to(
+        &mut self,
+        time: Duration,
+        pre_roll: bool,
+    ) -> Result<(), FramesError<S::Error, D::Error>> {
+        let Some(skip) = self.core.begin_skip(time, pre_roll) else {
+            return Ok(());
+        };
+        while let Some(packet) = self
+            .source
+            .next_packet()
+            .await
+            .map_err(FramesError::SourceError)?
+        {
+            if let Some(samples) = self.core.skip(skip, packet.data)? {
+                self.core.trim_skipped(
+                    samples,
+                    self.source.start_granule_position(),
+                    self.source.end_granule_position(),
+                );
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Decodes the next frame and returns it, or [`None`] if the stream has ended.
+     *
+     * See [`Frames::next`].
+     */
+    pub async fn next(&mut self) -> Result<Option<&[i16]>, FramesError<S::Error, D::Error>> {
+        if self.core.begin_next() {
+            return Ok(Some(self.core.frame()));
+        }
+        let mut looped = false;
+        loop {
+            // Packets after the end of the region are not read
+            let packet = if self.core.region_ended() {
+                None
+            } else {
+                self.source
+                    .next_packet()
+                    .await
+                    .map_err(FramesError::SourceError)?
+            };
+            let Some(packet) = packet else {
+                let Some(looping) = self.core.looping.filter(|_| !looped) else {
+                    return Ok(None);
+                };
+                let position = self
+                    .source
+                    .seek_granule(looping.start.saturating_sub(PRE_ROLL))
+                    .await
+                    .map_err(FramesError::SourceError)?;
+                // Stop if the source cannot seek
+                let Some(position) = position else {
+                    return Ok(None);
+                };
+                self.core.restart(looping.start, position)?;
+                looped = true;
+                continue;
+            };
+            let samples = self.core.decode(packet.data)?;
+            if self.core.trim(
+                samples,
+                self.source.start_granule_position(),
+                self.source.end_granule_position(),
+            ) {
+                return Ok(Some(self.core.frame()));
+            }
+        }
+    }
+}
+
+/// Region of the stream that is played again when its end is reached.
+#[derive(Clone, Copy, Debug)]
+struct Looping {
+    /// Granule position where the region begins, counted from the start.
+    start: u64,
+    /// End of the region in samples per channel after pre-skip or [`None`] for the end of the
+    /// stream.
+    end: Option<u64>,
+}
+
+/// Target of [`Frames::skip_to`] as granule positions counted from the start.
+#[derive(Clone, Copy, Debug)]
+struct Skip {
+    /// Granule position where the next frame begins.
+    target: u64,
+    /// Granule position from which packets are decoded.
+    decode_from: u64,
+}
+
+/**
+ * Decoding state that is shared by [`Frames`] and `AsyncFrames`.
+ *
+ * The wrappers read packets from their sources and pass them here, so that decoding, trimming,
+ * gain and looping work the same way whether reading is awaited or not.
+ */
+#[derive(Debug)]
+struct Core<D: Decode, const BUFFER_SIZE: usize> {
+    decoder: D,
+    buffer: [i16; BUFFER_SIZE],
+    frame: core::ops::Range<usize>,
+    unread: usize,
+    trim: Trim,
+    gain: Gain,
+    /// Granule position at the end of the last packet read, counted from the start.
+    packet_position: u64,
+    looping: Option<Looping>,
+}
+
+impl<D: Decode, const BUFFER_SIZE: usize> Core<D, BUFFER_SIZE> {
+    /// Construct [`Core`] for stream with header.
+    fn new(decoder: D, header: &OpusHeader) -> Self {
+        Self {
+            trim: Trim::new(header.pre_skip, decoder.sample_rate()),
+            gain: Gain::new(header.output_gain),
+            decoder,
+            buffer: [0; BUFFER_SIZE],
+            frame: 0..0,
+            unread: 0,
+            packet_position: 0,
+            looping: None,
+        }
+    }
+
+    /// Set whether pre-skip samples of header are discarded.
+    fn set_pre_skip(&mut self, discard: bool, header: &OpusHeader) {
+        let pre_skip = if discard { header.pre_skip } else { 0 };
+        self.trim = Trim::new(pre_skip, self.decoder.sample_rate());
+    }
+
+    /// Set whether output gain of header is applied.
+    fn set_header_gain(&mut self, apply: bool, header: &OpusHeader) {
+        let output_gain = if apply { header.output_gain } else { 0 };
+        self.gain = Gain::with_normalization(output_gain, self.gain.normalization);
+    }
+
+    /// Set loudness normalization with R128 gain of tags.
+    fn set_normalization(&mut self, tags: &OpusTags<'_>, normalization: Normalization) {
+        let gain = tags.r128_gain(normalization).unwrap_or(0);
+        self.gain = Gain::with_normalization(self.gain.output_gain, gain);
+    }
+
+    /// Set whether the whole stream is played in a loop.
+    fn set_looping(&mut self, looping: bool) {
+        self.looping = looping.then(|| Looping {
+            start: u64::from(self.trim.pre_skip),
+            end: None,
+        });
+    }
+
+    /// Play the region between `start` and `end` in a loop.
+    fn loop_region(&mut self, start: Duration, end: Duration) {
+        assert!(start < end, "Loop region must not be empty");
+        self.looping = Some(Looping {
+            start: self.target(start),
+            end: Some(GranulePos::from_duration(end, 0).samples(self.decoder.sample_rate(), 0)),
+        });
+    }
+
+    /// Returns the granule position of time counted from the start.
+    fn target(&self, time: Duration) -> u64 {
+        GranulePos::from_duration(time, self.trim.pre_skip).get()
+    }
+
+    /// Give back samples from the end of the last frame.
+    fn unread(&mut self, samples: usize) {
+        assert!(
+            self.unread + samples <= self.frame.len(),
+            "Cannot unread more than the last frame"
+        );
+        self.unread += samples;
+    }
+
+    /// Returns the number of samples per channel returned so far.
+    fn position(&self) -> u64 {
+        let channels = usize::from(self.decoder.channels());
+        self.trim.position - (self.unread / channels) as u64
+    }
+
+    /// Returns the playback position as time.
+    fn elapsed(&self) -> Duration {
+        let sample_rate = self.decoder.sample_rate();
+        GranulePos::from_samples(self.position(), sample_rate, 0).duration(0)
+    }
+
+    /// Returns the frame that was returned last.
+    fn frame(&self) -> &[i16] {
+        &self.buffer[self.frame.clone()]
+    }
+
+    /**
+     * Prepare for returning the next frame.
+     *
+     * Returns `true` if samples that were given back are returned as the frame, otherwise the
+     * next frame must be decoded.
+     */
+    fn begin_next(&mut self) -> bool {
+        if self.unread > 0 {
+            self.frame.start = self.frame.end - self.unread;
+            self.unread = 0;
+            return true;
+        }
+        self.frame = 0..0;
+        false
+    }
+
+    /// Returns whether playback has reached the end of the loop region.
+    fn region_ended(&self) -> bool {
+        let loop_end = self.looping.and_then(|looping| looping.end);
+        loop_end.is_some_and(|loop_end| self.trim.position >= loop_end)
+    }
+
+    /// Decode packet and return the number of decoded samples per channel.
+    fn decode<E>(&mut self, packet: &[u8]) -> Result<usize, FramesError<E, D::Error>> {
+        self.packet_position += u64::from(packet_samples(packet).unwrap_or(0));
+        let frame = self
+            .decoder
+            .decode(packet, &mut self.buffer)
+            .map_err(FramesError::DecoderError)?;
+        Ok(frame.len() / usize::from(self.decoder.channels()))
+    }
+
+    /**
+     * Trim decoded samples to get the frame.
+     *
+     * Returns `true` if samples were kept, in which case they are the frame. Granule positions
+     * are those of the source.
+     */
+    fn trim(
+        &mut self,
+        samples: usize,
+        start_granule_position: Option<u64>,
+        end_granule_position: Option<u64>,
+    ) -> bool {
+        let channels = usize::from(self.decoder.channels());
+        let end_position = self.trim.end_position(
+            start_granule_position,
+            end_granule_position,
+            self.decoder.sample_rate(),
+        );
+        let loop_end = self.looping.and_then(|looping| looping.end);
+        let end_position = end_position.into_iter().chain(loop_end).min();
+        let Some(frame) = self.trim.trim(samples, end_position) else {
+            return false;
+        };
+        self.frame = frame.start * channels..frame.end * channels;
+        self.gain.apply(&mut self.buffer[self.frame.clone()]);
+        true
+    }
+
+    /// Returns the target for skipping to time, or [`None`] if it is not ahead.
+    fn begin_skip(&mut self, time: Duration, pre_roll: bool) -> Option<Skip> {
+        let target = self.target(time);
+        if target <= self.trim.next_position(self.decoder.sample_rate()) {
+            return None;
+        }
+        self.frame = 0..0;
+        self.unread = 0;
+        Some(Skip {
+            target,
+            decode_from: target.saturating_sub(if pre_roll { PRE_ROLL } else { 0 }),
+        })
+    }
+
+    /**
+     * Skip over packet or decode it if it is not before the target.
+     *
+     * Returns the number of decoded samples per channel when skipping is done, and those must
+     * be passed to [`trim_skipped`][`Core::trim_skipped`].
+     */
+    fn skip<E>(
+        &mut self,
+        skip: Skip,
+        packet: &[u8],
+    ) -> Result<Option<usize>, FramesError<E, D::Error>> {
+        let position = self.packet_position;
+        let end = position + u64::from(packet_samples(packet).unwrap_or(0));
+        if end <= skip.decode_from {
+            self.packet_position = end;
+            return Ok(None);
+        }
+        self.decoder.reset().map_err(FramesError::DecoderError)?;
+        self.trim
+            .seek(skip.target, position, self.decoder.sample_rate());
+        self.decode(packet).map(Some)
+    }
+
+    /// Trim samples decoded by [`skip`][`Core::skip`] so that they are returned by the next frame.
+    fn trim_skipped(
+        &mut self,
+        samples: usize,
+        start_granule_position: Option<u64>,
+        end_granule_position: Option<u64>,
+    ) {
+        if self.trim(samples, start_granule_position, end_granule_position) {
+            self.unread = self.frame.len();
+        }
+    }
+
+    /// Restart decoding from granule position `position` so that output begins at `target`.
+    fn restart<E>(&mut self, target: u64, position: u64) -> Result<(), FramesError<E, D::Error>> {
+        self.decoder.reset().map_err(FramesError::DecoderError)?;
+        self.trim.seek(target, position, self.decoder.sample_rate());
+        self.frame = 0..0;
+        self.unread = 0;
+        self.packet_position = position;
+        Ok(())
+    }
+}
+
+/// Discards pre-skip samples and samples after the end position.
+#[derive(Debug)]
+pub(crate) struct Trim {
+    pre_skip: u16,
+    skip: u64,
+    position: u64,
+}
+
+impl Trim {
+    /// Construct [`Trim`] for decoded audio at sample rate.
+    pub(crate) fn new(pre_skip: u16, sample_rate: u32) -> Self {
+        Self {
+            pre_skip,
+            skip: u64::from(pre_skip) * u64::from(sample_rate) / u64::from(GRANULE_RATE),
+            position: 0,
+        }
+    }
+
+    /**
+     * Trim the next decoded frame of `samples` per channel.
+     *
+     * Returns the range of samples per channel to keep, or [`None`] if all are discarded.
+     */
+    pub(crate) fn trim(
+        &mut self,
+        samples: usize,
+        end_position: Option<u64>,
+    ) -> Option<core::ops::Range<usize>> {
+        let samples = samples as u64;
+        let start = samples.min(self.skip);
+        self.skip -= start;
+        let mut end = samples;
+        if let Some(end_position) = end_position {
+            let remaining = end_position.saturating_sub(self.position);
+            end = end.min(start + remaining);
+        }
+        if start < end {
+            self.position += end - start;
+            Some(start as usize..end as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the granule position of the next sample to keep, counted from the start.
+    pub(crate) fn next_position(&self, sample_rate: u32) -> u64 {
+        u64::from(self.pre_skip) + self.position * u64::from(GRANULE_RATE) / u64::from(sample_rate)
+    }
+
+    /**
+     * Continue trimming after seeking to `target` when decoding continues from `position`.
+     *
+     * Granule positions are counted from the start of the stream. Samples before the target are
+     * discarded.
+     */
+    pub(crate) fn seek(&mut self, target: u64, position: u64, sample_rate: u32) {
+        let to_samples =
+            |granules: u64| granules * u64::from(sample_rate) / u64::from(GRANULE_RATE);
+        self.skip = to_samples(target.saturating_sub(position));
+        self.position = to_samples(
+            target
+                .max(position)
+                .saturating_sub(u64::from(self.pre_skip)),
+        );
+    }
+
+    /**
+     * Returns the end position of the stream in samples per channel after pre-skip, if known.
+     *
+     * Granule positions are counted from the start position, which is zero if it is not known.
+     */
+    pub(crate) fn end_position(
+        &self,
+        start_granule_position: Option<u64>,
+        end_granule_position: Option<u64>,
+        sample_rate: u32,
+    ) -> Option<u64> {
+        end_granule_position.map(|granule_position| {
+            granule_position
+                .saturating_sub(start_granule_position.unwrap_or(0))
+                .saturating_sub(u64::from(self.pre_skip))
+                * u64::from(sample_rate)
+                / u64::from(GRANULE_RATE)
+        })
+    }
+}
+
+/**
+ * Amplifies decoded samples by output gain and normalization gain.
+ *
+ * The factor is in Q16 fixed point so that no floating point math is needed.
+ */
+#[derive(Debug)]
+pub(crate) struct Gain {
+    output_gain: i16,
+    normalization: i16,
+    factor: i64,
+}
+
+impl Gain {
+    /// Construct [`Gain`] for output gain in Q7.8 decibels.
+    pub(crate) fn new(output_gain: i16) -> Self {
+        Self::with_normalization(output_gain, 0)
+    }
+
+    /// Construct [`Gain`] for the sum of output gain and normalization gain in Q7.8 decibels.
+    pub(crate) fn with_normalization(output_gain: i16, normalization: i16) -> Self {
+        let gain = i64::from(output_gain) + i64::from(normalization);
+        // Factor is 10^(gain / 20) which is 2^(gain / 20 * log2(10)), exponent is in Q16
+        let exponent = (gain * 2_786_635) >> 16;
+        let fraction = exponent & 0xffff;
+        // Cubic approximation of 2^fraction - 1
+        let power = [14_873, 45_576]
+            .into_iter()
+            .fold(5_071, |power, term| ((power * fraction) >> 16) + term);
+        let power = (power * fraction) >> 16;
+        let mantissa = (1 << 16) + power;
+        // Gains above about 180 dB clamp any non-zero sample anyway, so cap the factor to 2^47 to
+        // keep amplified samples within i64
+        let shift = (exponent >> 16).min(30);
+        let factor = if shift >= 0 {
+            mantissa << shift
+        } else {
+            mantissa >> -shift
+        };
+        Self {
+            output_gain,
+            normalization,
+            factor,
+        }
+    }
+
+    /// Apply gain to samples.
+    pub(crate) fn apply(&self, samples: &mut [i16]) {
+        if self.factor == 1 << 16 {
+            return;
+        }
+        for sample in samples {
+            let amplified = (i64::from(*sample) * self.factor + (1 << 15)) >> 16;
+            *sample = amplified.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::Bitstream;
+
+    /// Produces 20 ms frames of mono audio at 8 kHz that count samples.
+    #[derive(Default)]
+    pub(crate) struct CountingDecoder {
+        pub count: i16,
+    }
+
+    impl Decode for CountingDecoder {
+        type Error = ();
+
+        fn sample_rate(&self) -> u32 {
+            8_000
+        }
+
+        fn channels(&self) -> u8 {
+            1
+        }
+
+        fn decode<'output>(
+            &mut self,
+            _packet: &[u8],
+            output: &'output mut [i16],
+        ) -> Result<&'output [i16], ()> {
+            for sample in &mut output[..160] {
+                *sample = self.count;
+                self.count += 1;
+            }
+            Ok(&output[..160])
+        }
+
+        fn reset(&mut self) -> Result<(), ()> {
+            self.count = 0;
+            Ok(())
+        }
+    }
+
+    /// Poll future to completion, which must not wait for anything.
+    #[cfg(feature = "async")]
+    pub(crate) fn block_on<F: core::future::Future>(future: F) -> F::Output {
+        let mut context = core::task::Context::from_waker(core::task::Waker::noop());
+        let mut future = core::pin::pin!(future);
+        loop {
+            if let core::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    /// Asynchronous source that reads packets from memory and can seek.
+    #[cfg(feature = "async")]
+    struct AsyncReader<'data>(crate::PacketReader<'data, 512>);
+
+    #[cfg(feature = "async")]
+    impl AsyncPacketSource for AsyncReader<'_> {
+        type Error = crate::BitstreamError;
+
+        fn header(&self) -> &OpusHeader {
+            self.0.header()
+        }
+
+        async fn next_packet(&mut self) -> Result<Option<crate::Packet<'_>>, Self::Error> {
+            self.0.next_packet()
+        }
+
+        fn end_granule_position(&self) -> Option<u64> {
+            self.0.end_granule_position()
+        }
+
+        async fn seek_granule(
+            &mut self,
+            granule_position: u64,
+        ) -> Result<Option<u64>, Self::Error> {
+            self.0.seek_granule(granule_position).map(Some)
+        }
+    }
+
+    fn collect(data: &[u8]) -> (usize, i16, i16) {
+        let bitstream = Bitstream::new(data);
+        let reader = bitstream.packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        let mut total = 0;
+        let mut first = None;
+        let mut last = 0;
+        while let Some(frame) = frames.next().unwrap() {
+            assert!(!frame.is_empty());
+            first.get_or_insert(frame[0]);
+            last = *frame.last().unwrap();
+            total += frame.len();
+        }
+        assert_eq!(frames.next(), Ok(None));
+        (total, first.unwrap(), last)
+    }
+
+    #[test]
+    fn pre_skip() {
+        // 312 samples at 48 kHz is 52 samples at 8 kHz
+        let (total, first, last) = collect(include_bytes!("test/mono.opus"));
+        assert_eq!(total, 7 * 160 - 52);
+        assert_eq!(first, 52);
+        assert_eq!(last, 7 * 160 - 1);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn boxed_decoder() {
+        let data = include_bytes!("test/trimmed.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let decoder: Box<dyn Decode<Error = ()>> = Box::new(CountingDecoder::default());
+        let samples = Frames::<_, _, 160>::new(reader, decoder)
+            .decode_all()
+            .unwrap();
+        assert_eq!(samples, (52..1052).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn end_trim() {
+        // Final granule position is 6312, i.e. 1000 samples at 8 kHz after pre-skip
+        let (total, first, last) = collect(include_bytes!("test/trimmed.opus"));
+        assert_eq!(total, 1000);
+        assert_eq!(first, 52);
+        assert_eq!(last, 1051);
+    }
+
+    #[test]
+    fn pre_skip_over_packets() {
+        let mut trim = Trim::new(312, 8_000);
+        assert_eq!(trim.trim(20, None), None);
+        assert_eq!(trim.trim(20, None), None);
+        assert_eq!(trim.trim(20, Some(100)), Some(12..20));
+        assert_eq!(trim.trim(100, Some(100)), Some(0..92));
+        assert_eq!(trim.trim(100, Some(100)), None);
+    }
+
+    #[test]
+    fn pre_skip_disabled() {
+        let data = include_bytes!("test/trimmed.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut frames =
+            Frames::<_, _, 160>::new(reader, CountingDecoder::default()).with_pre_skip(false);
+        let mut samples = Vec::new();
+        while let Some(frame) = frames.next().unwrap() {
+            samples.extend_from_slice(frame);
+        }
+        // The end is trimmed to the same sample as with pre-skip
+        assert_eq!(samples.len(), 52 + 1000);
+        assert_eq!(samples[0], 0);
+        assert_eq!(samples.last(), Some(&1051));
+    }
+
+    #[test]
+    fn end_trim_late_start() {
+        // Trim the stream that starts at 48 000 to 1000 samples at 8 kHz after pre-skip
+        let mut data = Vec::from(include_bytes!("test/offset.opus"));
+        let last_page = 205;
+        data[last_page + 6..last_page + 14].copy_from_slice(&(48_000u64 + 6_312).to_le_bytes());
+        let checksum = crate::crc::page_checksum(&crate::crc::SoftwareCrc, &data[last_page..]);
+        data[last_page + 22..last_page + 26].copy_from_slice(&checksum.to_le_bytes());
+        let (total, first, last) = collect(&data);
+        assert_eq!(total, 1000);
+        assert_eq!(first, 52);
+        assert_eq!(last, 1051);
+    }
+
+    #[test]
+    fn seek() {
+        let data = include_bytes!("test/offset.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        // 100 ms is 852 samples from the start with pre-skip and pre-roll begins from the start
+        frames.seek_to(Duration::from_millis(100)).unwrap();
+        let mut samples = Vec::new();
+        while let Some(frame) = frames.next().unwrap() {
+            samples.extend_from_slice(frame);
+        }
+        assert_eq!(samples.len(), 7 * 160 - 852);
+        assert_eq!(samples[0], 852);
+        // Seeking back to the beginning matches reading from the start
+        frames.seek_to(Duration::ZERO).unwrap();
+        assert_eq!(frames.next().unwrap().unwrap()[0], 52);
+        frames.seek_to(Duration::from_secs(1)).unwrap();
+        assert_eq!(frames.next(), Ok(None));
+    }
+
+    #[test]
+    fn seek_indexed() {
+        let data = include_bytes!("test/offset.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let index = reader.seek_index::<4>(Duration::ZERO).unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        // Pre-roll of 130 ms begins on the first audio page
+        let time = Duration::from_millis(130);
+        frames.seek_indexed(time, index.entries()).unwrap();
+        assert_eq!(frames.next().unwrap().unwrap()[0], 52 + 1_040);
+        // Bisection is used without entries
+        frames.seek_indexed(Duration::ZERO, &[]).unwrap();
+        let mut total = 0;
+        while let Some(frame) = frames.next().unwrap() {
+            total += frame.len();
+        }
+        assert_eq!(total, collect(data).0);
+    }
+
+    #[test]
+    fn skip() {
+        let data = include_bytes!("test/offset.opus");
+        let collect = |pre_roll| {
+            let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+            let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+            frames
+                .skip_to(Duration::from_millis(100), pre_roll)
+                .unwrap();
+            // Skipping backwards does nothing
+            frames.skip_to(Duration::from_millis(50), pre_roll).unwrap();
+            let mut samples = Vec::new();
+            while let Some(frame) = frames.next().unwrap() {
+                samples.extend_from_slice(frame);
+            }
+            samples
+        };
+        // Decoding starts from the sixth packet that contains 100 ms after pre-skip
+        let samples = collect(false);
+        assert_eq!(samples.len(), 7 * 160 - 852);
+        assert_eq!(samples[0], 852 - 5 * 160);
+        // 80 ms of pre-roll begins in the second packet
+        let samples = collect(true);
+        assert_eq!(samples.len(), 7 * 160 - 852);
+        assert_eq!(samples[0], 852 - 160);
+    }
+
+    #[test]
+    fn looping() {
+        let data = include_bytes!("test/offset.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        let read = |frames: &mut Frames<_, _, 160>, count| {
+            let mut samples = Vec::new();
+            while samples.len() < count {
+                samples.extend_from_slice(frames.next().unwrap().unwrap());
+            }
+            samples
+        };
+        frames.set_looping(true);
+        assert!(frames.is_looping());
+        let samples = read(&mut frames, 2 * (7 * 160 - 52));
+        let first: Vec<i16> = (52..7 * 160).collect();
+        assert_eq!(samples[..first.len()], first);
+        assert_eq!(samples[first.len()..], first);
+        // The region from 40 ms to 100 ms is from 372 to 852 with pre-skip
+        frames.loop_region(Duration::from_millis(40), Duration::from_millis(100));
+        let samples = read(&mut frames, 3 * 480);
+        let region: Vec<i16> = (372..852).collect();
+        assert_eq!(samples.len(), 3 * 480);
+        for round in samples.chunks(480) {
+            assert_eq!(round, region);
+        }
+        assert_eq!(frames.position(), 800);
+        // Playback continues to the end of the stream when looping is turned off
+        assert_eq!(frames.next().unwrap().unwrap()[0], 372);
+        frames.set_looping(false);
+        assert!(!frames.is_looping());
+        let mut last = 0;
+        while let Some(frame) = frames.next().unwrap() {
+            last = *frame.last().unwrap();
+        }
+        assert_eq!(last, 7 * 160 - 1);
+        assert_eq!(frames.position(), 7 * 160 - 52);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn looping_async() {
+        let data = include_bytes!("test/offset.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut expected = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        let reader = AsyncReader(Bitstream::new(data).packet_reader::<512>().unwrap());
+        let mut frames = AsyncFrames::<_, _, 160>::new(reader, CountingDecoder::default());
+        block_on(async {
+            frames.set_looping(true);
+            expected.set_looping(true);
+            assert!(frames.is_looping());
+            for _ in 0..20 {
+                assert_eq!(frames.next().await.unwrap(), expected.next().unwrap());
+            }
+            frames.loop_region(Duration::from_millis(40), Duration::from_millis(100));
+            expected.loop_region(Duration::from_millis(40), Duration::from_millis(100));
+            for _ in 0..20 {
+                assert_eq!(frames.next().await.unwrap(), expected.next().unwrap());
+            }
+            assert_eq!(frames.position(), expected.position());
+            frames.set_looping(false);
+            expected.set_looping(false);
+            while let Some(frame) = frames.next().await.unwrap() {
+                assert_eq!(Some(frame), expected.next().unwrap());
+            }
+            assert_eq!(expected.next(), Ok(None));
+        });
+        // Sources that cannot seek end the stream
+        let reader = crate::AsyncIoPacketReader::<_, 512, 512>::new(&data[..]);
+        let reader = block_on(reader).unwrap();
+        let mut frames = AsyncFrames::<_, _, 160>::new(reader, CountingDecoder::default());
+        frames.set_looping(true);
+        block_on(async {
+            let mut total = 0;
+            while let Some(frame) = frames.next().await.unwrap() {
+                total += frame.len();
+            }
+            assert_eq!(total, 7 * 160 - 52);
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_loop_region() {
+        let data = include_bytes!("test/offset.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        frames.loop_region(Duration::from_millis(40), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn seek_trim() {
+        let mut trim = Trim::new(312, 8_000);
+        // Decoding continues from 960 before target
+        trim.seek(312 + 4_800, 312 + 3_840, 8_000);
+        assert_eq!(trim.trim(160, Some(1_000)), None);
+        assert_eq!(trim.trim(160, Some(1_000)), Some(0..160));
+        assert_eq!(trim.position, 800 + 160);
+    }
+
+    #[test]
+    fn header_gain() {
+        assert_eq!(Gain::new(0).factor, 1 << 16);
+        let mut samples = [1_000, -1_000, 20_000, i16::MIN];
+        Gain::new(-20 * 256).apply(&mut samples);
+        assert_eq!(samples, [100, -100, 2_000, -3_276]);
+        let mut samples = [1_000, -1_000, 20_000, i16::MIN];
+        Gain::new(6 * 256).apply(&mut samples);
+        assert_eq!(samples, [1_995, -1_995, i16::MAX, i16::MIN]);
+        let mut samples = [i16::MAX, i16::MIN];
+        Gain::new(i16::MIN).apply(&mut samples);
+        assert_eq!(samples, [0, 0]);
+        Gain::new(i16::MAX).apply(&mut samples);
+        assert_eq!(samples, [0, 0]);
+        // Normalization is added to output gain even beyond the range of either
+        let gain = Gain::with_normalization(-3 * 256, 9 * 256);
+        assert_eq!(gain.factor, Gain::new(6 * 256).factor);
+        let gain = Gain::with_normalization(i16::MAX, i16::MAX);
+        let mut samples = [1, -1, 0, i16::MAX, i16::MIN];
+        gain.apply(&mut samples);
+        assert_eq!(samples, [i16::MAX, i16::MIN, 0, i16::MAX, i16::MIN]);
+        // Set -1 dB gain to the header
+        let mut data = Vec::from(include_bytes!("test/mono.opus"));
+        data[28 + 16..28 + 18].copy_from_slice(&(-256i16).to_le_bytes());
+        let checksum = crate::crc::page_checksum(&crate::crc::SoftwareCrc, &data[..47]);
+        data[22..26].copy_from_slice(&checksum.to_le_bytes());
+        let reader = Bitstream::new(&data).packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        assert_eq!(&frames.next().unwrap().unwrap()[..3], [46, 47, 48]);
+        let reader = Bitstream::new(&data).packet_reader::<512>().unwrap();
+        let mut frames =
+            Frames::<_, _, 160>::new(reader, CountingDecoder::default()).with_header_gain(false);
+        assert_eq!(&frames.next().unwrap().unwrap()[..3], [52, 53, 54]);
+    }
+
+    #[test]
+    fn normalization() {
+        let mut tags = b"OpusTags\x00\x00\x00\x00\x02\x00\x00\x00".to_vec();
+        for comment in [&b"R128_TRACK_GAIN=-512"[..], b"R128_ALBUM_GAIN=256"] {
+            tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            tags.extend_from_slice(comment);
+        }
+        let tags = OpusTags::parse(&tags).unwrap();
+        let data = include_bytes!("test/mono.opus");
+        let frames = |normalization| {
+            let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+            let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default())
+                .with_normalization(&tags, normalization)
+                .with_header_gain(false);
+            frames.next().unwrap().unwrap()[..3].to_vec()
+        };
+        // -2 dB and +1 dB
+        assert_eq!(frames(Normalization::Track), [41, 42, 43]);
+        assert_eq!(frames(Normalization::Album), [58, 59, 61]);
+    }
+
+    #[test]
+    fn chain_links() {
+        let data = [
+            &include_bytes!("test/mono.opus")[..],
+            include_bytes!("test/trimmed.opus"),
+        ]
+        .concat();
+        let bitstream = Bitstream::new(&data);
+        let reader = bitstream.packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 960>::new(reader, CountingDecoder::default());
+        let mut samples = Vec::new();
+        loop {
+            while let Some(frame) = frames.next().unwrap() {
+                samples.extend_from_slice(frame);
+            }
+            // Keep the decoder for the next link
+            let (reader, decoder) = frames.into_inner();
+            let Some(reader) = reader.next_link() else {
+                break;
+            };
+            frames = Frames::new(reader.unwrap(), decoder);
+        }
+        // Pre-skip is discarded from both links and the end of the second link is trimmed
+        assert_eq!(samples.len(), 7 * 160 - 52 + 1000);
+        assert_eq!(samples[7 * 160 - 52 - 1], 7 * 160 - 1);
+        assert_eq!(samples[7 * 160 - 52], 7 * 160 + 52);
+    }
+
+    #[test]
+    fn unread() {
+        let bitstream = Bitstream::new(include_bytes!("test/mono.opus"));
+        let reader = bitstream.packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        assert_eq!(frames.next().unwrap().unwrap().len(), 108);
+        frames.unread(8);
+        frames.unread(2);
+        assert_eq!(frames.unread_samples(), 10);
+        let frame = frames.next().unwrap().unwrap();
+        assert_eq!(frame, (150..160).collect::<Vec<_>>());
+        assert_eq!(frames.unread_samples(), 0);
+        assert_eq!(frames.next().unwrap().unwrap()[0], 160);
+    }
+
+    #[test]
+    fn position() {
+        let data = include_bytes!("test/offset.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        assert_eq!(frames.position(), 0);
+        assert_eq!(frames.next().unwrap().unwrap().len(), 108);
+        frames.unread(8);
+        assert_eq!(frames.position(), 100);
+        assert_eq!(frames.elapsed(), Duration::from_micros(12_500));
+        frames.seek_to(Duration::from_millis(100)).unwrap();
+        assert_eq!(frames.position(), 800);
+        frames.next().unwrap();
+        assert_eq!(frames.elapsed(), Duration::from_micros(113_500));
+        frames.skip_to(Duration::from_millis(130), false).unwrap();
+        assert_eq!(frames.elapsed(), Duration::from_millis(130));
+        while frames.next().unwrap().is_some() {}
+        assert_eq!(frames.position(), 7 * 160 - 52);
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut frames =
+            Frames::<_, _, 160>::new(reader, CountingDecoder::default()).with_pre_skip(false);
+        frames.next().unwrap();
+        assert_eq!(frames.position(), 160);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unread_too_much() {
+        let bitstream = Bitstream::new(include_bytes!("test/mono.opus"));
+        let reader = bitstream.packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        assert!(frames.next().unwrap().is_some());
+        frames.unread(109);
+    }
+
+    /// Produces 10 ms frames of stereo audio at 8 kHz with negated right channel.
+    #[derive(Default)]
+    struct StereoDecoder {
+        count: i16,
+    }
+
+    impl Decode for StereoDecoder {
+        type Error = ();
+
+        fn sample_rate(&self) -> u32 {
+            8_000
+        }
+
+        fn channels(&self) -> u8 {
+            2
+        }
+
+        fn decode<'output>(
+            &mut self,
+            _packet: &[u8],
+            output: &'output mut [i16],
+        ) -> Result<&'output [i16], ()> {
+            for samples in output[..160].chunks_exact_mut(2) {
+                samples[0] = self.count;
+                samples[1] = -self.count;
+                self.count += 1;
+            }
+            Ok(&output[..160])
+        }
+    }
+
+    #[test]
+    fn planar() {
+        let bitstream = Bitstream::new(include_bytes!("test/mono.opus"));
+        let reader = bitstream.packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, StereoDecoder::default());
+        let mut left = [0; 20];
+        let mut right = [0; 20];
+        // 52 samples are skipped from the first frame of 80 samples
+        assert_eq!(frames.next_planar(&mut left, &mut right), Ok(Some(20)));
+        assert_eq!(left[..], (52..72).collect::<Vec<_>>());
+        assert_eq!(right[..], (-71..=-52).rev().collect::<Vec<_>>());
+        assert_eq!(frames.unread_samples(), 16);
+        assert_eq!(frames.next_planar(&mut left, &mut right), Ok(Some(8)));
+        assert_eq!(left[..8], (72..80).collect::<Vec<_>>());
+        assert_eq!(frames.next_planar(&mut left, &mut right), Ok(Some(20)));
+        assert_eq!((left[0], right[0]), (80, -80));
+    }
+
+    #[test]
+    fn planar_mono() {
+        let bitstream = Bitstream::new(include_bytes!("test/mono.opus"));
+        let reader = bitstream.packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        let mut left = [0; 200];
+        let mut right = [0; 200];
+        assert_eq!(frames.next_planar(&mut left, &mut right), Ok(Some(108)));
+        assert_eq!(left, right);
+        assert_eq!(left[..108], (52..160).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn decoder_error() {
+        struct FailingDecoder;
+
+        impl Decode for FailingDecoder {
+            type Error = &'static str;
+
+            fn sample_rate(&self) -> u32 {
+                48_000
+            }
+
+            fn channels(&self) -> u8 {
+                2
+            }
+
+            fn decode<'output>(
+                &mut self,
+                _packet: &[u8],
+                _output: &'output mut [i16],
+            ) -> Result<&'output [i16], &'static str> {
+                Err("corrupted")
+            }
+        }
+
+        let bitstream = Bitstream::new(include_bytes!("test/mono.opus"));
+        let reader = bitstream.packet_reader::<512>().unwrap();
+        let mut frames = Frames::<_, _, 160>::new(reader, FailingDecoder);
+        let error = frames.next().unwrap_err();
+        assert_eq!(error, FramesError::DecoderError("corrupted"));
+        assert_eq!(error.to_string(), "failed to decode packet: corrupted");
+    }
+}
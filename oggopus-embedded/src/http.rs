@@ -0,0 +1,549 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Reading ogg opus streams over HTTP.
+ *
+ * [`HttpReader`] implements [`Read`] and [`Seek`] with HTTP range requests, so that
+ * [`StdPacketReader`][`crate::StdPacketReader`] can read and seek a file on a web server without
+ * downloading it first. Seeking bisects pages by requesting small ranges of the file. Data is read
+ * ahead in chunks to keep the number of requests low when reading sequentially.
+ *
+ * Only plain `http` URLs are supported as there is no TLS implementation here. Every request
+ * opens a new connection.
+ *
+ * ```rust,no_run
+ * # use oggopus_embedded::{http::HttpReader, PacketSource, StdPacketReader, MAX_PAGE_SIZE};
+ * let reader = HttpReader::new("http://192.168.1.2:8000/audio.opus").unwrap();
+ * let mut reader = StdPacketReader::<_, MAX_PAGE_SIZE, 1_024>::new(reader).unwrap();
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * ```
+ */
+
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Default number of bytes to request at a time.
+pub const DEFAULT_READ_AHEAD: usize = 64 * 1024;
+
+/// Default timeout for connecting and for each read and write.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of redirects followed for one request.
+const MAX_REDIRECTS: usize = 5;
+
+/// Location of resource parsed from URL.
+#[derive(Clone, Debug, PartialEq)]
+struct Location {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Location {
+    /// Parse absolute `http` URL.
+    fn parse(url: &str) -> Result<Self, Error> {
+        let invalid = |error: &str| Error::new(ErrorKind::InvalidInput, error.to_string());
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("only http URLs are supported"))?;
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid("invalid port"))?)
+            }
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        let path = if path.starts_with('?') {
+            format!("/{}", path)
+        } else {
+            path.to_string()
+        };
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// Returns value for host header.
+    fn host_header(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Response to a range request.
+struct Response {
+    /// Offset of body in the resource.
+    start: u64,
+    body: Vec<u8>,
+    /// Length of the whole resource if it is known.
+    length: Option<u64>,
+}
+
+/**
+ * [`Read`] and [`Seek`] for a file on HTTP server.
+ *
+ * The length of the file is found from the response to the first request. Servers that do not
+ * support range requests work too but then each request transfers the file from its beginning.
+ * If the server does not tell the length, seeking from the end fails with
+ * [`Unsupported`][`ErrorKind::Unsupported`].
+ */
+#[derive(Debug)]
+pub struct HttpReader {
+    location: Location,
+    read_ahead: usize,
+    timeout: Duration,
+    position: u64,
+    length: Option<u64>,
+    /// Offset of buffer in the file.
+    buffer_start: u64,
+    buffer: Vec<u8>,
+    requests: u32,
+}
+
+impl HttpReader {
+    /**
+     * Construct [`HttpReader`] for URL.
+     *
+     * Nothing is requested until the reader is used.
+     */
+    pub fn new(url: &str) -> Result<Self, Error> {
+        Ok(Self {
+            location: Location::parse(url)?,
+            read_ahead: DEFAULT_READ_AHEAD,
+            timeout: DEFAULT_TIMEOUT,
+            position: 0,
+            length: None,
+            buffer_start: 0,
+            buffer: Vec::new(),
+            requests: 0,
+        })
+    }
+
+    /// Set the number of bytes to request at a time.
+    pub fn with_read_ahead(mut self, read_ahead: usize) -> Self {
+        self.read_ahead = read_ahead.max(1);
+        self
+    }
+
+    /// Set timeout for connecting and for each read and write.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the length of the file if it is known already.
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// Returns the number of requests made so far.
+    pub fn requests(&self) -> u32 {
+        self.requests
+    }
+
+    /// Fill buffer from position.
+    fn fill(&mut self) -> Result<(), Error> {
+        let response = self.request(self.position)?;
+        let end = response.start + response.body.len() as u64;
+        if response.length.is_some() {
+            self.length = response.length;
+        } else if response.body.is_empty() {
+            self.length = Some(end);
+        }
+        // Skip the data before position if the server ignored the range
+        let skip = usize::try_from(self.position.saturating_sub(response.start)).unwrap_or(0);
+        let mut body = response.body;
+        body.drain(..skip.min(body.len()));
+        body.truncate(self.read_ahead);
+        self.buffer_start = self.position.min(end);
+        self.buffer = body;
+        Ok(())
+    }
+
+    /// Request range beginning from start, following redirects.
+    fn request(&mut self, start: u64) -> Result<Response, Error> {
+        let end = start.saturating_add(self.read_ahead as u64 - 1);
+        let mut location = self.location.clone();
+        for _ in 0..=MAX_REDIRECTS {
+            self.requests = self.requests.wrapping_add(1);
+            match self.request_once(&location, start, end)? {
+                Ok(response) => return Ok(response),
+                Err(redirect) => location = redirect,
+            }
+        }
+        Err(Error::other("too many redirects"))
+    }
+
+    /// Make one request and return the response or redirected location.
+    fn request_once(
+        &self,
+        location: &Location,
+        start: u64,
+        end: u64,
+    ) -> Result<Result<Response, Location>, Error> {
+        let host = location.host.trim_start_matches('[').trim_end_matches(']');
+        let address = (host, location.port);
+        let mut stream = std::net::ToSocketAddrs::to_socket_addrs(&address)?
+            .find_map(|address| TcpStream::connect_timeout(&address, self.timeout).ok())
+            .ok_or_else(|| Error::new(ErrorKind::ConnectionRefused, "could not connect"))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\n\
+            Host: {}\r\n\
+            Range: bytes={}-{}\r\n\
+            User-Agent: {}/{}\r\n\
+            Connection: close\r\n\r\n",
+            location.path,
+            location.host_header(),
+            start,
+            end,
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        )?;
+        let mut stream = BufReader::new(stream);
+        let invalid = |error: &str| Error::new(ErrorKind::InvalidData, error.to_string());
+        let mut line = String::new();
+        stream.read_line(&mut line)?;
+        let status: u16 = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| invalid("invalid status line"))?;
+        let mut content_length = None;
+        let mut content_range = None;
+        let mut chunked = false;
+        let mut redirect = None;
+        loop {
+            line.clear();
+            if stream.read_line(&mut line)? == 0 {
+                return Err(Error::from(ErrorKind::UnexpectedEof));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse::<u64>().ok(),
+                "content-range" => content_range = Some(parse_content_range(value)?),
+                "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+                "location" => redirect = Some(value.to_string()),
+                _ => (),
+            }
+        }
+        match status {
+            301 | 302 | 303 | 307 | 308 => {
+                let redirect = redirect.ok_or_else(|| invalid("redirect without location"))?;
+                return Ok(Err(if redirect.starts_with('/') {
+                    Location {
+                        path: redirect,
+                        ..location.clone()
+                    }
+                } else {
+                    Location::parse(&redirect)?
+                }));
+            }
+            // Range begins after the end of the file
+            416 => {
+                let length = content_range.and_then(|(_, length)| length);
+                return Ok(Ok(Response {
+                    start,
+                    body: Vec::new(),
+                    length: Some(length.unwrap_or(start)),
+                }));
+            }
+            200 | 206 => (),
+            status => return Err(Error::other(format!("HTTP status {}", status))),
+        }
+        let mut body = Vec::new();
+        if chunked {
+            read_chunked(&mut stream, &mut body)?;
+        } else if let Some(length) = content_length {
+            stream.take(length).read_to_end(&mut body)?;
+            if (body.len() as u64) < length {
+                return Err(Error::from(ErrorKind::UnexpectedEof));
+            }
+        } else {
+            stream.read_to_end(&mut body)?;
+        }
+        Ok(Ok(match (status, content_range) {
+            (206, Some((range_start, length))) => Response {
+                start: range_start,
+                body,
+                length,
+            },
+            (206, None) => return Err(invalid("partial content without content range")),
+            _ => Response {
+                start: 0,
+                length: Some(body.len() as u64),
+                body,
+            },
+        }))
+    }
+}
+
+/// Parse start and complete length from content range header.
+fn parse_content_range(value: &str) -> Result<(u64, Option<u64>), Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "invalid content range");
+    let (range, length) = value
+        .strip_prefix("bytes ")
+        .and_then(|value| value.split_once('/'))
+        .ok_or_else(invalid)?;
+    let length = match length {
+        "*" => None,
+        length => Some(length.parse().map_err(|_| invalid())?),
+    };
+    let start = match range.split_once('-') {
+        Some((start, _)) => start.parse().map_err(|_| invalid())?,
+        None if range == "*" => 0,
+        None => return Err(invalid()),
+    };
+    Ok((start, length))
+}
+
+/// Read body with chunked transfer encoding.
+fn read_chunked<R: BufRead>(stream: &mut R, body: &mut Vec<u8>) -> Result<(), Error> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        stream.read_line(&mut line)?;
+        let size = line.trim_end().split(';').next().unwrap_or_default();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid chunk size"))?;
+        if size == 0 {
+            return Ok(());
+        }
+        let start = body.len();
+        stream.by_ref().take(size).read_to_end(body)?;
+        if ((body.len() - start) as u64) < size {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        line.clear();
+        stream.read_line(&mut line)?;
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() || self.length.is_some_and(|length| self.position >= length) {
+            return Ok(0);
+        }
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+        if self.position < self.buffer_start || self.position >= buffer_end {
+            self.fill()?;
+        }
+        let offset = usize::try_from(self.position - self.buffer_start).unwrap_or(usize::MAX);
+        let available = self.buffer.get(offset..).unwrap_or_default();
+        let size = available.len().min(buf.len());
+        buf[..size].copy_from_slice(&available[..size]);
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl Seek for HttpReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                if self.length.is_none() {
+                    self.fill()?;
+                }
+                // Server may not tell the length even if it supports ranges
+                let length = self.length.ok_or_else(|| {
+                    Error::new(ErrorKind::Unsupported, "length of the file is not known")
+                })?;
+                length.checked_add_signed(offset)
+            }
+        };
+        self.position = position
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "seek to a negative position"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{seek::SeekablePacketSource, Bitstream, PacketSource, StdPacketReader};
+    use std::net::TcpListener;
+
+    const DATA: &[u8] = include_bytes!("test/mono.opus");
+
+    /// Serve DATA from a local server and return its URL.
+    fn serve(ranges: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = BufReader::new(stream.try_clone().unwrap());
+                let mut path = String::new();
+                let mut range = None;
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 2 {
+                    if let Some(value) = line.strip_prefix("Range: bytes=") {
+                        let (start, end) = value.trim_end().split_once('-').unwrap();
+                        let start: usize = start.parse().unwrap();
+                        let end: usize = end.parse().unwrap();
+                        range = Some((start, end.min(DATA.len() - 1)));
+                    } else if let Some(value) = line.strip_prefix("GET ") {
+                        path = value.split(' ').next().unwrap().to_string();
+                    }
+                    line.clear();
+                }
+                if path == "/redirect" {
+                    write!(stream, "HTTP/1.1 302 Found\r\nLocation: /mono.opus\r\n\r\n").unwrap();
+                    continue;
+                }
+                match range.filter(|_| ranges) {
+                    Some((start, _)) if start >= DATA.len() => {
+                        write!(
+                            stream,
+                            "HTTP/1.1 416 Range Not Satisfiable\r\n\
+                            Content-Range: bytes */{}\r\n\r\n",
+                            DATA.len()
+                        )
+                        .unwrap();
+                    }
+                    Some((start, end)) => {
+                        // Length is not told for /unknown
+                        let length = match path.as_str() {
+                            "/unknown" => "*".to_string(),
+                            _ => DATA.len().to_string(),
+                        };
+                        write!(
+                            stream,
+                            "HTTP/1.1 206 Partial Content\r\n\
+                            Content-Range: bytes {}-{}/{}\r\n\
+                            Content-Length: {}\r\n\r\n",
+                            start,
+                            end,
+                            length,
+                            end + 1 - start
+                        )
+                        .unwrap();
+                        stream.write_all(&DATA[start..=end]).unwrap();
+                    }
+                    None => {
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"
+                        )
+                        .unwrap();
+                        for chunk in DATA.chunks(1_000) {
+                            write!(stream, "{:x}\r\n", chunk.len()).unwrap();
+                            stream.write_all(chunk).unwrap();
+                            write!(stream, "\r\n").unwrap();
+                        }
+                        write!(stream, "0\r\n\r\n").unwrap();
+                    }
+                }
+            }
+        });
+        format!("http://{}/mono.opus", address)
+    }
+
+    fn check_packets<R: Read + Seek>(reader: R) {
+        let mut reader = StdPacketReader::<_, 4_096, 512>::new(reader).unwrap();
+        let bitstream = Bitstream::new(DATA);
+        let mut expected = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(reader.header(), expected.header());
+        while let Some(packet) = expected.next_packet().unwrap() {
+            assert_eq!(reader.next_packet().unwrap().unwrap().data, packet.data);
+        }
+        assert!(reader.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_location() {
+        assert_eq!(
+            Location::parse("http://example.com:8080/a/b.opus?x=1").unwrap(),
+            Location {
+                host: "example.com".to_string(),
+                port: 8080,
+                path: "/a/b.opus?x=1".to_string(),
+            }
+        );
+        let location = Location::parse("http://[::1]?x").unwrap();
+        assert_eq!((location.port, location.path.as_str()), (80, "/?x"));
+        assert_eq!(location.host_header(), "[::1]");
+        let error = Location::parse("https://example.com/").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(
+            parse_content_range("bytes 10-19/100").unwrap(),
+            (10, Some(100))
+        );
+        assert_eq!(parse_content_range("bytes */100").unwrap(), (0, Some(100)));
+    }
+
+    #[test]
+    fn read_ranges() {
+        let mut reader = HttpReader::new(&serve(true))
+            .unwrap()
+            .with_read_ahead(1_000);
+        assert_eq!(reader.length(), None);
+        assert_eq!(
+            reader.seek(SeekFrom::End(-4)).unwrap(),
+            DATA.len() as u64 - 4
+        );
+        assert_eq!(reader.length(), Some(DATA.len() as u64));
+        let mut end = Vec::new();
+        reader.read_to_end(&mut end).unwrap();
+        assert_eq!(end, DATA[DATA.len() - 4..]);
+        reader.rewind().unwrap();
+        check_packets(reader);
+    }
+
+    #[test]
+    fn unknown_length() {
+        let url = serve(true).replace("/mono.opus", "/unknown");
+        let mut reader = HttpReader::new(&url).unwrap().with_read_ahead(1_000);
+        let error = reader.seek(SeekFrom::End(-4)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        assert_eq!(reader.length(), None);
+        // Reading from the start still works
+        let mut start = [0; 4];
+        reader.read_exact(&mut start).unwrap();
+        assert_eq!(&start, b"OggS");
+    }
+
+    #[test]
+    fn seek() {
+        let reader = HttpReader::new(&serve(true)).unwrap().with_read_ahead(512);
+        let mut reader = StdPacketReader::<_, 4_096, 512>::new(reader).unwrap();
+        reader.seek_granule(3_000).unwrap();
+        assert!(reader.next_packet().unwrap().is_some());
+        let requests = reader.into_inner().requests();
+        assert!(requests < 20, "too many requests: {}", requests);
+    }
+
+    #[test]
+    fn no_ranges() {
+        let url = serve(false).replace("mono.opus", "redirect");
+        let reader = HttpReader::new(&url).unwrap().with_read_ahead(1_000);
+        check_packets(reader);
+    }
+}
@@ -0,0 +1,511 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Information about the whole stream.
+
+use super::{
+    container::RawPage,
+    opus::{packet_samples, OpusHeader},
+    tags::CommentValue,
+    time::GRANULE_RATE,
+    Bitstream, BitstreamError, Pages,
+};
+use crate::{states::Either, time::GranulePos};
+use core::time::Duration;
+
+/// Granule position of a page on which no packet ends.
+pub(crate) const NO_PACKET_ENDS: u64 = u64::MAX;
+
+/// Error of [`StartPosition`] when granule position of the first audio page is smaller than its samples.
+pub(crate) const START_BEFORE_ZERO: BitstreamError = BitstreamError::InvalidOpusStream(
+    "granule position is smaller than the number of samples on the page",
+);
+
+/**
+ * Information about opus stream that is collected by scanning it.
+ *
+ * Granule positions are in samples at 48 kHz as in the ogg stream.
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StreamInfo {
+    /// The number of samples to skip in the beginning of the stream.
+    pub pre_skip: u16,
+    /// Granule position of the first sample in the first audio packet.
+    pub first_granule_position: u64,
+    /// Granule position at the end of the last audio packet.
+    pub last_granule_position: u64,
+}
+
+impl StreamInfo {
+    /**
+     * Scan the first stream in bitstream.
+     *
+     * Reads every page of the stream but does not decode packets.
+     */
+    pub(crate) fn scan<'data>(bitstream: &Bitstream<'data>) -> crate::Result<'data, Self> {
+        Self::read(bitstream, false).map(|(info, ..)| info)
+    }
+
+    /**
+     * Probe the first stream in bitstream.
+     *
+     * Reads the headers and the first audio page and looks up the last page from the end of the
+     * data. Falls back to reading every page if the last page is not found.
+     */
+    pub(crate) fn probe<'data>(
+        bitstream: &Bitstream<'data>,
+    ) -> crate::Result<'data, (Self, OpusHeader)> {
+        Self::read(bitstream, true).map(|(info, header, _)| (info, header))
+    }
+
+    /// Read the first stream in bitstream. Returns also the size of its audio pages in bytes.
+    fn read<'data>(
+        bitstream: &Bitstream<'data>,
+        quick: bool,
+    ) -> crate::Result<'data, (Self, OpusHeader, usize)> {
+        use BitstreamError::*;
+        let (reader, header) = bitstream.reader().read_header()?;
+        let Either::Continued(reader) = reader else {
+            return Err(InvalidOpusStream("missing audio data"));
+        };
+        let mut first_granule_position = None;
+        let mut last_granule_position = 0;
+        let mut start = StartPosition::default();
+        let audio_offset = bitstream.data.len() - reader.remaining.len();
+        let mut audio_size = 0;
+        for page in Pages::with_options(reader.remaining, bitstream.options) {
+            let page = page?;
+            if page.bitstream_serial_number() != reader.marker.bitstream_serial_number {
+                return Err(UnsupportedStream(
+                    "bitstream serial number changed unexpectedly",
+                ));
+            }
+            audio_size += page.as_bytes().len();
+            if first_granule_position.is_none() {
+                first_granule_position = match start.page(&page) {
+                    Err(error) if bitstream.options.lenient && error == START_BEFORE_ZERO => {
+                        Some(0)
+                    }
+                    result => result?,
+                };
+                if first_granule_position.is_some() && quick && !page.end_of_stream() {
+                    let serial_number = reader.marker.bitstream_serial_number;
+                    if let Some((last, end)) = find_last_page(bitstream, serial_number) {
+                        last_granule_position = last;
+                        audio_size = end.saturating_sub(audio_offset);
+                        break;
+                    }
+                }
+            }
+            if page.granule_position() != NO_PACKET_ENDS {
+                last_granule_position = page.granule_position();
+            }
+            if page.end_of_stream() {
+                break;
+            }
+        }
+        let info = Self {
+            pre_skip: header.pre_skip,
+            first_granule_position: first_granule_position.unwrap_or(0),
+            last_granule_position,
+        };
+        Ok((info, header, audio_size))
+    }
+
+    /**
+     * Returns the number of playable samples per channel at 48 kHz.
+     *
+     * This is the number of samples between the first and the last granule position without
+     * pre-skip as specified in [RFC7845](https://datatracker.ietf.org/doc/html/rfc7845#section-4).
+     *
+     * ```rust
+     * # use oggopus_embedded::Bitstream;
+     * # let data = include_bytes!("test/mono.opus");
+     * let info = Bitstream::new(data).stream_info().unwrap();
+     * let milliseconds = info.total_samples() / 48;
+     * assert_eq!(milliseconds, 133);
+     * ```
+     */
+    pub fn total_samples(&self) -> u64 {
+        self.last_granule_position
+            .saturating_sub(self.first_granule_position)
+            .saturating_sub(u64::from(self.pre_skip))
+    }
+
+    /**
+     * Returns the playing time of the stream.
+     *
+     * ```rust
+     * # use oggopus_embedded::Bitstream;
+     * # let data = include_bytes!("test/mono.opus");
+     * let info = Bitstream::new(data).stream_info().unwrap();
+     * assert_eq!(info.duration().as_micros(), 133_500);
+     * ```
+     */
+    pub fn duration(&self) -> Duration {
+        GranulePos::new(self.total_samples()).duration(0)
+    }
+}
+
+/**
+ * Find the last page of a stream by searching backwards from the end.
+ *
+ * Only the last page sized area of data is searched. Returns the granule position of the page and
+ * the offset where it ends.
+ */
+fn find_last_page(bitstream: &Bitstream<'_>, serial_number: u32) -> Option<(u64, usize)> {
+    let data = bitstream.data;
+    let start = data.len().saturating_sub(bitstream.options.max_page_size);
+    let (index, page) = last_page(&data[start..], serial_number)?;
+    Some((
+        page.granule_position(),
+        start + index + page.as_bytes().len(),
+    ))
+}
+
+/**
+ * Find the last page of a stream on which a packet ends and that starts within data.
+ *
+ * Returns the offset of the page with the page.
+ */
+pub(crate) fn last_page(data: &[u8], serial_number: u32) -> Option<(usize, RawPage<'_>)> {
+    (0..data.len())
+        .rev()
+        .filter(|&index| data[index..].starts_with(b"OggS"))
+        .filter_map(|index| Some((index, RawPage::parse(&data[index..]).ok()?)))
+        .find(|(_, page)| {
+            page.bitstream_serial_number() == serial_number
+                && page.granule_position() != NO_PACKET_ENDS
+        })
+}
+
+/**
+ * Summary of opus stream for showing it to the user, e.g. in a file browser.
+ *
+ * This collects the fields of the identification header, the vendor string of the comment header
+ * and the length of the stream in one place. The stream is probed like with
+ * [`Bitstream::probe`], so the pages in between are not read.
+ *
+ * ```rust
+ * # use oggopus_embedded::Bitstream;
+ * # let data = include_bytes!("test/mono.opus");
+ * let info = Bitstream::new(data).info().unwrap();
+ * assert_eq!(info.channels, 1);
+ * assert_eq!(info.vendor.and_then(|vendor| vendor.as_str()), Some("Lavf61.7.100"));
+ * println!("{:?} at {} bit/s", info.duration.unwrap(), info.bitrate.unwrap());
+ * ```
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Summary<'data> {
+    /// The number of output channels.
+    pub channels: u8,
+    /// The number of samples to skip in the beginning of the stream.
+    pub pre_skip: u16,
+    /// Sample rate of the original input, which is only informative.
+    pub input_sample_rate: u32,
+    /// Channel mapping family.
+    pub mapping_family: u8,
+    /// Output gain of the header in Q7.8 dB.
+    pub output_gain: i16,
+    /// Vendor string of the comment header, or [`None`] if it could not be read.
+    pub vendor: Option<CommentValue<'data>>,
+    /// Playing time of the stream, or [`None`] if it could not be found.
+    pub duration: Option<Duration>,
+    /// Mean bitrate in bits per second including ogg overhead of audio pages, or [`None`] if
+    /// the stream has no playable samples.
+    pub bitrate: Option<u32>,
+}
+
+impl<'data> Summary<'data> {
+    /**
+     * Summarize the first stream in bitstream.
+     *
+     * Fails only if the identification header is invalid. Comment header and audio that cannot
+     * be read are left out of the summary.
+     */
+    pub(crate) fn read(bitstream: &Bitstream<'data>) -> crate::Result<'data, Self> {
+        let (_, header) = bitstream.reader().read_header()?;
+        let vendor = bitstream.tags().ok().map(|tags| tags.vendor());
+        let stream = StreamInfo::read(bitstream, true).ok();
+        let bitrate = stream.as_ref().and_then(|(info, _, size)| {
+            let samples = info.total_samples();
+            let bits = *size as u64 * 8 * u64::from(GRANULE_RATE);
+            (samples > 0).then(|| (bits / samples).min(u64::from(u32::MAX)) as u32)
+        });
+        Ok(Self {
+            channels: header.channels.get_channel_count(),
+            pre_skip: header.pre_skip,
+            input_sample_rate: header.sample_rate,
+            mapping_family: header.channels.get_family(),
+            output_gain: header.output_gain,
+            vendor,
+            duration: stream.map(|(info, ..)| info.duration()),
+            bitrate,
+        })
+    }
+}
+
+/// Header and length of a stream found with [`scan_library`].
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProbeInfo {
+    /// Opus header of the stream.
+    pub header: OpusHeader,
+    /// Length information of the stream.
+    pub stream: StreamInfo,
+}
+
+/**
+ * Probe many streams for their headers and lengths.
+ *
+ * Each item is tagged with a key, e.g. a file name, which is returned with the result. Streams
+ * are probed one at a time when the returned iterator is advanced so memory use does not grow with
+ * the number of streams. Probing reads the headers and the first and the last page of each stream
+ * and does not decode anything.
+ *
+ * ```rust
+ * # use oggopus_embedded::scan_library;
+ * # let (first, second) = (include_bytes!("test/mono.opus"), include_bytes!("test/stereo.opus"));
+ * let files = [("first.opus", &first[..]), ("second.opus", &second[..])];
+ * for (name, info) in scan_library(files) {
+ *     match info {
+ *         Ok(info) => println!("{}: {} samples", name, info.stream.total_samples()),
+ *         Err(error) => println!("{}: {}", name, error),
+ *     }
+ * }
+ * ```
+ */
+pub fn scan_library<'data, K, I>(
+    sources: I,
+) -> impl Iterator<Item = (K, crate::Result<'data, ProbeInfo>)>
+where
+    I: IntoIterator<Item = (K, &'data [u8])>,
+{
+    sources
+        .into_iter()
+        .map(|(key, data)| (key, Bitstream::new(data).probe()))
+}
+
+/**
+ * Finds the granule position of the first sample of a stream from its first audio pages.
+ *
+ * That is the granule position of the first page on which a packet ends minus the number of
+ * samples in the packets that end on the pages up to it.
+ */
+#[derive(Debug, Default)]
+pub(crate) struct StartPosition {
+    head: PacketHead,
+    samples: u64,
+    position: Option<u64>,
+}
+
+impl StartPosition {
+    /**
+     * Count samples on the next audio page. Returns the start position once it is known.
+     *
+     * Pages after the start position was found are ignored. On error the stream is taken to
+     * start from zero.
+     */
+    pub(crate) fn page(&mut self, page: &RawPage<'_>) -> Result<Option<u64>, BitstreamError> {
+        if self.position.is_some() {
+            return Ok(self.position);
+        }
+        let samples = self.head.count_samples(page.segment_table(), page.data());
+        self.samples += samples.inspect_err(|_| self.position = Some(0))?;
+        if page.granule_position() != NO_PACKET_ENDS {
+            if page.granule_position() >= self.samples {
+                self.position = Some(page.granule_position() - self.samples);
+            } else {
+                self.position = Some(0);
+                // The end may be trimmed from the only page of audio
+                if !page.end_of_stream() {
+                    return Err(START_BEFORE_ZERO);
+                }
+            }
+        }
+        Ok(self.position)
+    }
+
+    /// Construct [`StartPosition`] that has been found already.
+    pub(crate) fn found(position: u64) -> Self {
+        Self {
+            position: Some(position),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the start position if it has been found.
+    pub(crate) fn position(&self) -> Option<u64> {
+        self.position
+    }
+}
+
+/**
+ * Returns the granule position where the first packet that begins on page begins.
+ *
+ * The packet that continues from the previous page is not counted. Returns [`None`] if no packet
+ * ends on the page or if a packet is invalid.
+ */
+pub(crate) fn first_packet_position(page: &RawPage<'_>) -> Option<u64> {
+    if page.granule_position() == NO_PACKET_ENDS {
+        return None;
+    }
+    let mut table = page.segment_table();
+    let mut data = page.data();
+    if page.continued() {
+        let count = table.iter().position(|size| *size < 255)? + 1;
+        let size = table[..count].iter().map(|size| usize::from(*size)).sum();
+        (table, data) = (&table[count..], &data[size..]);
+    }
+    let samples = PacketHead::default().count_samples(table, data).ok()?;
+    Some(page.granule_position().saturating_sub(samples))
+}
+
+/// The beginning of a packet that may continue over page boundary.
+#[derive(Debug, Default)]
+struct PacketHead {
+    head: [u8; 2],
+    length: usize,
+}
+
+impl PacketHead {
+    /// Count the number of samples in packets that end in segments of a page.
+    fn count_samples(&mut self, segment_table: &[u8], data: &[u8]) -> Result<u64, BitstreamError> {
+        let mut samples = 0;
+        let mut offset = 0;
+        for size in segment_table.iter().map(|size| usize::from(*size)) {
+            let count = size.min(self.head.len() - self.length);
+            self.head[self.length..self.length + count]
+                .copy_from_slice(&data[offset..offset + count]);
+            self.length += count;
+            offset += size;
+            if size < 255 {
+                let packet = &self.head[..self.length];
+                self.length = 0;
+                samples += u64::from(
+                    packet_samples(packet)
+                        .ok_or(BitstreamError::InvalidOpusStream("packet is too short"))?,
+                );
+            }
+        }
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn single_audio_page() {
+        let data = include_bytes!("test/mono.opus");
+        let info = Bitstream::new(data).stream_info().unwrap();
+        // Seven 20 ms packets on the only audio page
+        assert_eq!(info.first_granule_position, 7_992 - 7 * 960);
+        assert_eq!(info.last_granule_position, 7_992);
+        assert_eq!(info.total_samples(), 7 * 960 - 312);
+    }
+
+    #[test]
+    fn end_trimmed() {
+        let data = include_bytes!("test/trimmed.opus");
+        let info = Bitstream::new(data).stream_info().unwrap();
+        assert_eq!(info.first_granule_position, 0);
+        assert_eq!(info.total_samples(), 6_312 - 312);
+    }
+
+    #[test]
+    fn starts_late() {
+        // Audio is split to two pages and starts at granule position 48 000
+        let data = include_bytes!("test/offset.opus");
+        let info = Bitstream::new(data).stream_info().unwrap();
+        assert_eq!(info.first_granule_position, 48_000);
+        assert_eq!(info.last_granule_position, 48_000 + 7 * 960);
+        assert_eq!(info.total_samples(), 7 * 960 - 312);
+    }
+
+    #[test]
+    fn probe_matches_scan() {
+        let files = [
+            include_bytes!("test/mono.opus").as_slice(),
+            include_bytes!("test/trimmed.opus"),
+            include_bytes!("test/offset.opus"),
+            include_bytes!("test/stereo.opus"),
+        ];
+        for data in files {
+            let bitstream = Bitstream::new(data);
+            let (info, header) = StreamInfo::probe(&bitstream).unwrap();
+            assert_eq!(info, StreamInfo::scan(&bitstream).unwrap());
+            assert_eq!(header.pre_skip, info.pre_skip);
+        }
+    }
+
+    #[test]
+    fn probe_with_trailing_data() {
+        let mut data = Vec::from(include_bytes!("test/offset.opus"));
+        data.extend_from_slice(b"OggS but not a page");
+        let bitstream = Bitstream::new(&data);
+        let (info, _) = StreamInfo::probe(&bitstream).unwrap();
+        assert_eq!(info.last_granule_position, 48_000 + 7 * 960);
+    }
+
+    #[test]
+    fn library() {
+        let files = [
+            (1, include_bytes!("test/offset.opus").as_slice()),
+            (2, include_bytes!("test/vorbis.ogg")),
+            (3, include_bytes!("test/stereo.opus")),
+        ];
+        let results: Vec<_> = scan_library(files).collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].0, 1);
+        let info = results[0].1.as_ref().unwrap();
+        assert_eq!(info.header.channels.get_channel_count(), 1);
+        assert_eq!(info.stream.total_samples(), 7 * 960 - 312);
+        assert_eq!(results[1], (2, Err(BitstreamError::NotOpusStream)));
+        let info = results[2].1.as_ref().unwrap();
+        assert_eq!(info.header.channels.get_channel_count(), 2);
+    }
+
+    #[test]
+    fn summary() {
+        let data = include_bytes!("test/offset.opus");
+        let info = Bitstream::new(data).info().unwrap();
+        assert_eq!(info.channels, 1);
+        assert_eq!(info.pre_skip, 312);
+        assert_eq!(info.mapping_family, 0);
+        assert_eq!(info.duration, Some(Duration::from_micros(133_500)));
+        // Both audio pages in 133.5 ms
+        let bits = (data.len() - 136) as u64 * 8;
+        assert_eq!(info.bitrate, Some((bits * 48_000 / (7 * 960 - 312)) as u32));
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        assert_eq!(reader.info(), Ok(info));
+        // Headers without audio
+        let info = Bitstream::new(&data[..136]).info().unwrap();
+        assert!(info.vendor.is_some());
+        assert_eq!((info.duration, info.bitrate), (Some(Duration::ZERO), None));
+        let vorbis = include_bytes!("test/vorbis.ogg");
+        assert_eq!(
+            Bitstream::new(vorbis).info(),
+            Err(BitstreamError::NotOpusStream)
+        );
+    }
+
+    #[test]
+    fn granule_position_too_small() {
+        let mut data = Vec::from(include_bytes!("test/offset.opus"));
+        // Set granule position of the first audio page to 0
+        let first_audio_page = 28 + 19 + 28 + 61;
+        data[first_audio_page + 6..first_audio_page + 14].fill(0);
+        let result = Bitstream::new(&data).with_crc_check(false).stream_info();
+        assert_eq!(
+            result,
+            Err(BitstreamError::InvalidOpusStream(
+                "granule position is smaller than the number of samples on the page"
+            ))
+        );
+    }
+}
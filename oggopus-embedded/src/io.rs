@@ -0,0 +1,1641 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Reading ogg opus streams from embedded-io and std readers.
+
+#[cfg(feature = "async")]
+use super::AsyncPacketSource;
+use super::{
+    bitrate::{Bitrate, BitrateMeter},
+    conformance::{Checker, Deviations, Violation},
+    info::{StartPosition, START_BEFORE_ZERO},
+    opus::OpusHeader,
+    tags, BitstreamError, OggError, OggPusher, Packet, PacketSource,
+};
+#[cfg(feature = "std")]
+use super::{
+    container::ParseOptions,
+    info::first_packet_position,
+    seek::{self, SeekEntry, SeekIndex, SeekPoint, SeekablePacketSource},
+    time::GranulePos,
+    RawPage, StreamInfo,
+};
+#[cfg(feature = "std")]
+use core::time::Duration;
+
+/// Error from reading ogg opus stream from a reader.
+#[cfg_attr(docsrs, doc(cfg(any(feature = "embedded-io", feature = "std"))))]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum IoReaderError<E> {
+    /// Error from the reader.
+    IoError(E),
+    /// Error from parsing the data that was read.
+    BitstreamError(BitstreamError),
+}
+
+#[cfg(feature = "embedded-io")]
+impl<E: embedded_io::Error> IoReaderError<E> {
+    /**
+     * Returns whether reading may continue after the error.
+     *
+     * Interrupted reads are recoverable as no data was lost. See also
+     * [`BitstreamError::is_recoverable`].
+     */
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            Self::IoError(error) => error.kind() == embedded_io::ErrorKind::Interrupted,
+            Self::BitstreamError(error) => error.is_recoverable(),
+        }
+    }
+}
+
+impl<E> IoReaderError<E> {
+    /// Returns the layer of the stream where the error comes from.
+    pub fn layer(&self) -> crate::ErrorLayer {
+        match self {
+            Self::IoError(_) => crate::ErrorLayer::Io,
+            Self::BitstreamError(error) => error.layer(),
+        }
+    }
+}
+
+impl<E, D> crate::FramesError<IoReaderError<E>, D> {
+    /// Returns the layer of the stream where the error comes from.
+    pub fn layer(&self) -> crate::ErrorLayer {
+        match self {
+            Self::SourceError(error) => error.layer(),
+            Self::DecoderError(_) => crate::ErrorLayer::Decoder,
+        }
+    }
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for IoReaderError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use IoReaderError::*;
+        match self {
+            IoError(error) => f.write_fmt(format_args!("reading failed: {}", error)),
+            BitstreamError(error) => error.fmt(f),
+        }
+    }
+}
+
+impl<E: core::error::Error + 'static> core::error::Error for IoReaderError<E> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        use IoReaderError::*;
+        match self {
+            IoError(error) => Some(error),
+            BitstreamError(error) => Some(error),
+        }
+    }
+}
+
+impl<E> From<BitstreamError> for IoReaderError<E> {
+    fn from(error: BitstreamError) -> Self {
+        Self::BitstreamError(error)
+    }
+}
+
+impl<E> From<OggError> for IoReaderError<E> {
+    fn from(error: OggError) -> Self {
+        Self::BitstreamError(error.into())
+    }
+}
+
+/**
+ * [`PacketSource`] that reads ogg opus stream from [`embedded_io::Read`].
+ *
+ * Data is read with [`OggPusher`] so only one page and one packet are kept in memory at a time.
+ * `PAGE_SIZE` must be large enough for the largest page and `PACKET_SIZE` for the largest packet
+ * in the stream. Comment header is skipped and may be larger than `PACKET_SIZE`. It can be read in
+ * chunks instead by constructing the reader with
+ * [`new_with_comments`][`IoPacketReader::new_with_comments`].
+ *
+ * ```rust
+ * # use oggopus_embedded::{IoPacketReader, PacketSource, MAX_PAGE_SIZE};
+ * # let file = &include_bytes!("test/mono.opus")[..];
+ * // Anything that implements embedded_io::Read, e.g. a file from a filesystem driver
+ * let mut reader = IoPacketReader::<_, MAX_PAGE_SIZE, 1_024>::new(file).unwrap();
+ * println!("Sample rate is {} Hz", reader.header().sample_rate);
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * ```
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "embedded-io")))]
+#[cfg(feature = "embedded-io")]
+#[derive(Debug)]
+pub struct IoPacketReader<R: embedded_io::Read, const PAGE_SIZE: usize, const PACKET_SIZE: usize> {
+    reader: R,
+    state: PushState<PAGE_SIZE, PACKET_SIZE>,
+    header: OpusHeader,
+}
+
+#[cfg(feature = "embedded-io")]
+impl<R: embedded_io::Read, const PAGE_SIZE: usize, const PACKET_SIZE: usize>
+    IoPacketReader<R, PAGE_SIZE, PACKET_SIZE>
+{
+    /// Construct [`IoPacketReader`] and read the headers of the first stream from the reader.
+    pub fn new(reader: R) -> Result<Self, IoReaderError<R::Error>> {
+        let mut this = Self::new_with_comments(reader)?;
+        while this.next_comment_chunk()?.is_some() {}
+        Ok(this)
+    }
+
+    /**
+     * Construct [`IoPacketReader`] and read only the identification header from the reader.
+     *
+     * The comment header can then be read with
+     * [`next_comment_chunk`][`IoPacketReader::next_comment_chunk`]. Whatever is left of it is
+     * skipped by the first [`next_packet`][`PacketSource::next_packet`].
+     */
+    pub fn new_with_comments(mut reader: R) -> Result<Self, IoReaderError<R::Error>> {
+        let mut state = PushState::new();
+        let found = state.read(&mut reader)?;
+        let header = state.parse_header(found)?;
+        state.pusher.stream_packet();
+        Ok(Self {
+            reader,
+            state,
+            header,
+        })
+    }
+
+    /**
+     * Read the next chunk of the comment header.
+     *
+     * Chunks are the parts of the comment header on each page and they are not collected to the
+     * packet buffer, so the comment header may be of any size. Returns [`None`] after the last
+     * chunk. Concatenated chunks are the packet that [`OpusTags`][`crate::tags::OpusTags`] parses.
+     */
+    pub fn next_comment_chunk(&mut self) -> Result<Option<&[u8]>, IoReaderError<R::Error>> {
+        if self.state.start.is_some() {
+            return Ok(None);
+        }
+        let found = self.state.read(&mut self.reader)?;
+        Ok(Some(self.state.comment_chunk(found)?))
+    }
+
+    /**
+     * Set whether pages are checked against the requirements of RFC7845.
+     *
+     * See [`Bitstream::with_strict`][`crate::Bitstream::with_strict`]. Header pages are always
+     * checked while they are read, and the first violation on them is returned by the next read
+     * after strict mode is turned on. Must be set before the first packet is read.
+     */
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.state.strict = strict;
+        self
+    }
+
+    /**
+     * Set whether common deviations from RFC7845 are tolerated.
+     *
+     * See [`Bitstream::with_lenient`][`crate::Bitstream::with_lenient`]. Data that ends in the
+     * middle of a page ends the stream instead of failing with
+     * [`EndOfStreamError`][`OggError::EndOfStreamError`].
+     */
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.state.lenient = lenient;
+        self
+    }
+
+    /**
+     * Returns the deviations from RFC7845 that were tolerated so far in lenient mode.
+     *
+     * This is always empty if lenient mode is not turned on.
+     */
+    pub fn deviations(&self) -> Deviations {
+        self.state.deviations()
+    }
+
+    /// Returns the bitrate of the audio pages that have been read, or [`None`] if it is not known yet.
+    pub fn bitrate(&self) -> Option<Bitrate> {
+        self.state.bitrate.bitrate()
+    }
+
+    /// Deconstruct [`IoPacketReader`] into the reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<R: embedded_io::Read, const PAGE_SIZE: usize, const PACKET_SIZE: usize> PacketSource
+    for IoPacketReader<R, PAGE_SIZE, PACKET_SIZE>
+{
+    type Error = IoReaderError<R::Error>;
+
+    fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet<'_>>, Self::Error> {
+        while self.next_comment_chunk()?.is_some() {}
+        if self.state.read(&mut self.reader)? {
+            Ok(Some(self.state.pusher.packet()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn end_granule_position(&self) -> Option<u64> {
+        self.state.end_granule_position
+    }
+
+    fn start_granule_position(&self) -> Option<u64> {
+        self.state.start.as_ref().and_then(StartPosition::position)
+    }
+}
+
+/**
+ * [`AsyncPacketSource`] that reads ogg opus stream from [`embedded_io_async::Read`].
+ *
+ * This is the asynchronous counterpart of [`IoPacketReader`]. Reads are awaited so other tasks
+ * may run while waiting for data, e.g. from an SD card.
+ *
+ * ```rust
+ * # use oggopus_embedded::{AsyncIoPacketReader, AsyncPacketSource, MAX_PAGE_SIZE};
+ * # async fn play(file: &[u8]) -> Result<(), oggopus_embedded::IoReaderError<core::convert::Infallible>> {
+ * let mut reader = AsyncIoPacketReader::<_, MAX_PAGE_SIZE, 1_024>::new(file).await?;
+ * while let Some(packet) = reader.next_packet().await? {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * # Ok(())
+ * # }
+ * ```
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncIoPacketReader<
+    R: embedded_io_async::Read,
+    const PAGE_SIZE: usize,
+    const PACKET_SIZE: usize,
+> {
+    reader: R,
+    state: PushState<PAGE_SIZE, PACKET_SIZE>,
+    header: OpusHeader,
+}
+
+#[cfg(feature = "async")]
+impl<R: embedded_io_async::Read, const PAGE_SIZE: usize, const PACKET_SIZE: usize>
+    AsyncIoPacketReader<R, PAGE_SIZE, PACKET_SIZE>
+{
+    /// Construct [`AsyncIoPacketReader`] and read the headers of the first stream from the reader.
+    pub async fn new(reader: R) -> Result<Self, IoReaderError<R::Error>> {
+        let mut this = Self::new_with_comments(reader).await?;
+        while this.next_comment_chunk().await?.is_some() {}
+        Ok(this)
+    }
+
+    /**
+     * Construct [`AsyncIoPacketReader`] and read only the identification header from the reader.
+     *
+     * See [`IoPacketReader::new_with_comments`].
+     */
+    pub async fn new_with_comments(mut reader: R) -> Result<Self, IoReaderError<R::Error>> {
+        let mut state = PushState::new();
+        let found = state.read_async(&mut reader).await?;
+        let header = state.parse_header(found)?;
+        state.pusher.stream_packet();
+        Ok(Self {
+            reader,
+            state,
+            header,
+        })
+    }
+
+    /// Read the next chunk of the comment header. See [`IoPacketReader::next_comment_chunk`].
+    pub async fn next_comment_chunk(&mut self) -> Result<Option<&[u8]>, IoReaderError<R::Error>> {
+        if self.state.start.is_some() {
+            return Ok(None);
+        }
+        let found = self.state.read_async(&mut self.reader).await?;
+        Ok(Some(self.state.comment_chunk(found)?))
+    }
+
+    /**
+     * Set whether pages are checked against the requirements of RFC7845.
+     *
+     * See [`IoPacketReader::with_strict`].
+     */
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.state.strict = strict;
+        self
+    }
+
+    /**
+     * Set whether common deviations from RFC7845 are tolerated.
+     *
+     * See [`IoPacketReader::with_lenient`].
+     */
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.state.lenient = lenient;
+        self
+    }
+
+    /// Returns the deviations that were tolerated so far. See [`IoPacketReader::deviations`].
+    pub fn deviations(&self) -> Deviations {
+        self.state.deviations()
+    }
+
+    /// Returns the bitrate of the audio pages that have been read, or [`None`] if it is not known yet.
+    pub fn bitrate(&self) -> Option<Bitrate> {
+        self.state.bitrate.bitrate()
+    }
+
+    /// Deconstruct [`AsyncIoPacketReader`] into the reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: embedded_io_async::Read, const PAGE_SIZE: usize, const PACKET_SIZE: usize> AsyncPacketSource
+    for AsyncIoPacketReader<R, PAGE_SIZE, PACKET_SIZE>
+{
+    type Error = IoReaderError<R::Error>;
+
+    fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    async fn next_packet(&mut self) -> Result<Option<Packet<'_>>, Self::Error> {
+        while self.next_comment_chunk().await?.is_some() {}
+        if self.state.read_async(&mut self.reader).await? {
+            Ok(Some(self.state.pusher.packet()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn end_granule_position(&self) -> Option<u64> {
+        self.state.end_granule_position
+    }
+
+    fn start_granule_position(&self) -> Option<u64> {
+        self.state.start.as_ref().and_then(StartPosition::position)
+    }
+}
+
+/**
+ * [`PacketSource`] that reads ogg opus stream from [`std::io::Read`] and [`std::io::Seek`].
+ *
+ * Data is read a page at a time like with [`OggPusher`]. The end of the stream is looked up by
+ * seeking when the reader is constructed, so the last frame can be trimmed even if the final page
+ * is not reached, e.g. with chained or truncated files. Interrupted reads are retried.
+ *
+ * ```rust
+ * # use oggopus_embedded::{PacketSource, StdPacketReader, MAX_PAGE_SIZE};
+ * # let file = std::io::Cursor::new(include_bytes!("test/mono.opus"));
+ * // e.g. let file = std::io::BufReader::new(std::fs::File::open("audio.opus")?);
+ * let mut reader = StdPacketReader::<_, MAX_PAGE_SIZE, 1_024>::new(file).unwrap();
+ * println!("Stream ends at {:?}", reader.end_granule_position());
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * ```
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct StdPacketReader<R, const PAGE_SIZE: usize, const PACKET_SIZE: usize>
+where
+    R: std::io::Read + std::io::Seek,
+{
+    reader: R,
+    state: PushState<PAGE_SIZE, PACKET_SIZE>,
+    header: OpusHeader,
+    end_granule_position: Option<u64>,
+    /// Position of the first header page in the reader.
+    header_position: u64,
+    /// Position of the first audio page in the reader.
+    audio_position: u64,
+}
+
+#[cfg(feature = "std")]
+impl<R, const PAGE_SIZE: usize, const PACKET_SIZE: usize> StdPacketReader<R, PAGE_SIZE, PACKET_SIZE>
+where
+    R: std::io::Read + std::io::Seek,
+{
+    /**
+     * Construct [`StdPacketReader`] and read the headers of the first stream from the reader.
+     *
+     * Reading starts from the current position of the reader.
+     */
+    pub fn new(mut reader: R) -> Result<Self, IoReaderError<std::io::Error>> {
+        let header_position = reader.stream_position().map_err(IoReaderError::IoError)?;
+        let mut state = PushState::new();
+        let found = state.read_std(&mut reader)?;
+        let header = state.parse_header(found)?;
+        let found = state.read_std(&mut reader);
+        if matches!(found, Ok(true)) {
+            state.deviations |= vendor_deviation(state.pusher.packet().data);
+        }
+        skip_comments(found)?;
+        state.start = Some(StartPosition::default());
+        let end_granule_position = match state.bitstream_serial_number {
+            Some(serial_number) => Self::find_end(&mut reader, serial_number)?,
+            None => None,
+        };
+        let audio_position = reader.stream_position().map_err(IoReaderError::IoError)?;
+        Ok(Self {
+            reader,
+            state,
+            header,
+            end_granule_position,
+            header_position,
+            audio_position,
+        })
+    }
+
+    /**
+     * Look up [`StreamInfo`] of the stream.
+     *
+     * The start is found from the first audio pages. The end was searched backwards from the end
+     * of data when the reader was constructed and if it was not found then, all pages are read
+     * to find it. The reader is returned to its current position afterwards.
+     */
+    pub fn stream_info(&mut self) -> Result<StreamInfo, IoReaderError<std::io::Error>> {
+        use std::io::SeekFrom;
+        let position = self
+            .reader
+            .stream_position()
+            .map_err(IoReaderError::IoError)?;
+        self.reader
+            .seek(SeekFrom::Start(self.audio_position))
+            .map_err(IoReaderError::IoError)?;
+        let result = self.scan_pages();
+        self.reader
+            .seek(SeekFrom::Start(position))
+            .map_err(IoReaderError::IoError)?;
+        let (first_granule_position, last_granule_position) = result?;
+        Ok(StreamInfo {
+            pre_skip: self.header.pre_skip,
+            first_granule_position,
+            last_granule_position,
+        })
+    }
+
+    /**
+     * Returns the playing time of the stream.
+     *
+     * See [`stream_info`][`StdPacketReader::stream_info`].
+     */
+    pub fn duration(&mut self) -> Result<Duration, IoReaderError<std::io::Error>> {
+        self.stream_info().map(|info| info.duration())
+    }
+
+    /**
+     * Build [`SeekIndex`] of pages at least `interval` apart.
+     *
+     * Reads every page of the stream but does not decode packets. The reader is returned to its
+     * current position afterwards.
+     */
+    pub fn seek_index<const CAPACITY: usize>(
+        &mut self,
+        interval: Duration,
+    ) -> Result<SeekIndex<CAPACITY>, IoReaderError<std::io::Error>> {
+        use std::io::SeekFrom;
+        let position = self
+            .reader
+            .stream_position()
+            .map_err(IoReaderError::IoError)?;
+        let result = self.index_pages(GranulePos::from_duration(interval, 0).get());
+        self.reader
+            .seek(SeekFrom::Start(position))
+            .map_err(IoReaderError::IoError)?;
+        result
+    }
+
+    /**
+     * Set whether pages are checked against the requirements of RFC7845.
+     *
+     * See [`IoPacketReader::with_strict`]. Audio pages are checked from where reading continues
+     * after seeking.
+     */
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.state.strict = strict;
+        self
+    }
+
+    /**
+     * Set whether common deviations from RFC7845 are tolerated.
+     *
+     * See [`IoPacketReader::with_lenient`].
+     */
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.state.lenient = lenient;
+        self
+    }
+
+    /// Returns the deviations that were tolerated so far. See [`IoPacketReader::deviations`].
+    pub fn deviations(&self) -> Deviations {
+        self.state.deviations()
+    }
+
+    /**
+     * Returns the bitrate of the audio pages that have been read, or [`None`] if it is not known
+     * yet.
+     *
+     * Pages that are skipped by seeking are not counted.
+     */
+    pub fn bitrate(&self) -> Option<Bitrate> {
+        self.state.bitrate.bitrate()
+    }
+
+    /// Deconstruct [`StdPacketReader`] into the reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Find granule position of the last page of the stream and return to the current position.
+    fn find_end(
+        reader: &mut R,
+        serial_number: u32,
+    ) -> Result<Option<u64>, IoReaderError<std::io::Error>> {
+        use std::io::SeekFrom;
+        let position = reader.stream_position().map_err(IoReaderError::IoError)?;
+        let length = reader
+            .seek(SeekFrom::End(0))
+            .map_err(IoReaderError::IoError)?;
+        let start = length.saturating_sub(PAGE_SIZE as u64).max(position);
+        reader
+            .seek(SeekFrom::Start(start))
+            .map_err(IoReaderError::IoError)?;
+        let mut data = Vec::with_capacity((length - start) as usize);
+        reader
+            .read_to_end(&mut data)
+            .map_err(IoReaderError::IoError)?;
+        reader
+            .seek(SeekFrom::Start(position))
+            .map_err(IoReaderError::IoError)?;
+        Ok(crate::info::last_page(&data, serial_number).map(|(_, page)| page.granule_position()))
+    }
+
+    /**
+     * Returns the start position of the stream by reading the first audio pages if needed.
+     *
+     * The reader is left in the middle of the audio.
+     */
+    fn find_start(&mut self) -> Result<u64, IoReaderError<std::io::Error>> {
+        if let Some(position) = self.start_granule_position() {
+            return Ok(position);
+        }
+        self.reader
+            .seek(std::io::SeekFrom::Start(self.audio_position))
+            .map_err(IoReaderError::IoError)?;
+        let mut start = StartPosition::default();
+        let mut buffer = Vec::new();
+        let mut position = 0;
+        while let Some(page) = read_page(&mut self.reader, &mut buffer)? {
+            // Invalid start positions are treated as zero
+            if let Some(found) = start.page(&page).unwrap_or(Some(0)) {
+                position = found;
+                break;
+            }
+        }
+        self.state.start = Some(StartPosition::found(position));
+        Ok(position)
+    }
+
+    /**
+     * Continue reading from the page at position of the reader.
+     *
+     * The packet that continues from the previous page is discarded by the pusher.
+     */
+    fn continue_from(&mut self, position: u64) -> Result<(), IoReaderError<std::io::Error>> {
+        use std::io::SeekFrom;
+        self.reader
+            .seek(SeekFrom::Start(position))
+            .map_err(IoReaderError::IoError)?;
+        let mut buffer = Vec::new();
+        let page = read_page(&mut self.reader, &mut buffer)?;
+        if page.map(|page| page.bitstream_serial_number()) != self.state.bitstream_serial_number {
+            return Err(BitstreamError::UnsupportedStream(
+                "bitstream serial number changed unexpectedly",
+            )
+            .into());
+        }
+        self.reader
+            .seek(SeekFrom::Start(position))
+            .map_err(IoReaderError::IoError)?;
+        self.state.pusher.reset();
+        self.state.ended = false;
+        self.state.bitrate.jump();
+        self.state.checker.jump();
+        Ok(())
+    }
+
+    /// Read audio pages to index entries `interval` apart.
+    fn index_pages<const CAPACITY: usize>(
+        &mut self,
+        interval: u64,
+    ) -> Result<SeekIndex<CAPACITY>, IoReaderError<std::io::Error>> {
+        let start = self.find_start()?;
+        self.reader
+            .seek(std::io::SeekFrom::Start(self.audio_position))
+            .map_err(IoReaderError::IoError)?;
+        let mut index = SeekIndex::new(interval);
+        let mut offset = self.audio_position - self.header_position;
+        let mut buffer = Vec::new();
+        while let Some(page) = read_page(&mut self.reader, &mut buffer)? {
+            if Some(page.bitstream_serial_number()) != self.state.bitstream_serial_number {
+                break;
+            }
+            if let Some(position) = first_packet_position(&page) {
+                index.add(SeekEntry {
+                    granule_position: position.saturating_sub(start),
+                    offset,
+                });
+            }
+            offset += page.as_bytes().len() as u64;
+            if page.end_of_stream() {
+                break;
+            }
+        }
+        Ok(index)
+    }
+
+    /// Returns the first page at or after offset on which a packet ends.
+    fn page_at(&mut self, offset: u64) -> Result<Option<SeekPoint>, IoReaderError<std::io::Error>> {
+        use std::io::Read;
+        self.reader
+            .seek(std::io::SeekFrom::Start(offset))
+            .map_err(IoReaderError::IoError)?;
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(2 * PAGE_SIZE as u64)
+            .read_to_end(&mut data)
+            .map_err(IoReaderError::IoError)?;
+        let options = ParseOptions {
+            max_page_size: PAGE_SIZE,
+            ..ParseOptions::new()
+        };
+        let Some(serial_number) = self.state.bitstream_serial_number else {
+            return Ok(None);
+        };
+        let point = seek::find_page(&data, serial_number, &options);
+        Ok(point.map(|point| SeekPoint {
+            offset: point.offset + offset,
+            ..point
+        }))
+    }
+
+    /// Read audio pages until the first and the last granule position are known.
+    fn scan_pages(&mut self) -> Result<(u64, u64), IoReaderError<std::io::Error>> {
+        let mut start = StartPosition::default();
+        let mut first_granule_position = None;
+        let mut last_granule_position = self.end_granule_position.unwrap_or(0);
+        let mut buffer = Vec::new();
+        while let Some(page) = read_page(&mut self.reader, &mut buffer)? {
+            if Some(page.bitstream_serial_number()) != self.state.bitstream_serial_number {
+                break;
+            }
+            if first_granule_position.is_none() {
+                // Invalid start positions are treated as zero
+                first_granule_position = start.page(&page).unwrap_or(Some(0));
+                if first_granule_position.is_some() && self.end_granule_position.is_some() {
+                    break;
+                }
+            }
+            if self.end_granule_position.is_none() && page.granule_position() != u64::MAX {
+                last_granule_position = page.granule_position();
+            }
+            if page.end_of_stream() {
+                break;
+            }
+        }
+        Ok((first_granule_position.unwrap_or(0), last_granule_position))
+    }
+}
+
+/**
+ * Read the next page from [`std::io::Read`] into buffer without checking its checksum.
+ *
+ * Returns [`None`] at the end of data, also if the last page is incomplete.
+ */
+#[cfg(feature = "std")]
+fn read_page<'buffer, R: std::io::Read>(
+    reader: &mut R,
+    buffer: &'buffer mut Vec<u8>,
+) -> Result<Option<RawPage<'buffer>>, IoReaderError<std::io::Error>> {
+    fn read_more<R: std::io::Read>(
+        reader: &mut R,
+        buffer: &mut Vec<u8>,
+        count: usize,
+    ) -> std::io::Result<bool> {
+        let length = buffer.len();
+        buffer.resize(length + count, 0);
+        match reader.read_exact(&mut buffer[length..]) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+    const HEADER_SIZE: usize = 27;
+    buffer.clear();
+    if !read_more(reader, buffer, HEADER_SIZE).map_err(IoReaderError::IoError)? {
+        return Ok(None);
+    }
+    let segments = usize::from(buffer[HEADER_SIZE - 1]);
+    if !read_more(reader, buffer, segments).map_err(IoReaderError::IoError)? {
+        return Ok(None);
+    }
+    let size = buffer[HEADER_SIZE..]
+        .iter()
+        .map(|size| usize::from(*size))
+        .sum();
+    if !read_more(reader, buffer, size).map_err(IoReaderError::IoError)? {
+        return Ok(None);
+    }
+    Ok(Some(RawPage::parse(buffer)?))
+}
+
+#[cfg(feature = "std")]
+impl<R, const PAGE_SIZE: usize, const PACKET_SIZE: usize> SeekablePacketSource
+    for StdPacketReader<R, PAGE_SIZE, PACKET_SIZE>
+where
+    R: std::io::Read + std::io::Seek,
+{
+    /**
+     * Move to the page from which the next packet begins at or before granule position.
+     *
+     * See [`SeekablePacketSource::seek_granule`]. Pages are bisected between the first audio
+     * page and the end of the data by seeking the reader.
+     */
+    fn seek_granule(&mut self, granule_position: u64) -> Result<u64, Self::Error> {
+        use std::io::SeekFrom;
+        let start = self.find_start()?;
+        let end = self
+            .reader
+            .seek(SeekFrom::End(0))
+            .map_err(IoReaderError::IoError)?;
+        let target = start.saturating_add(granule_position);
+        let point = seek::bisect(self.audio_position, end, target, |offset| {
+            self.page_at(offset)
+        })?;
+        let (offset, position) = match point {
+            Some(point) => (point.offset, point.packet_position),
+            None => (self.audio_position, start),
+        };
+        self.reader
+            .seek(SeekFrom::Start(offset))
+            .map_err(IoReaderError::IoError)?;
+        self.continue_from(offset)?;
+        Ok(position.saturating_sub(start))
+    }
+
+    /**
+     * Move to the page of an entry of [`SeekIndex`].
+     *
+     * See [`SeekablePacketSource::seek_entry`]. Entries that do not point to a page of the
+     * stream are rejected with [`UnsupportedStream`][`BitstreamError::UnsupportedStream`].
+     */
+    fn seek_entry(&mut self, entry: SeekEntry) -> Result<u64, Self::Error> {
+        self.find_start()?;
+        let offset = self.header_position.saturating_add(entry.offset);
+        if offset < self.audio_position {
+            return Err(BitstreamError::UnsupportedStream(
+                "seek index entry is not within the stream",
+            )
+            .into());
+        }
+        self.continue_from(offset)?;
+        Ok(entry.granule_position)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R, const PAGE_SIZE: usize, const PACKET_SIZE: usize> PacketSource
+    for StdPacketReader<R, PAGE_SIZE, PACKET_SIZE>
+where
+    R: std::io::Read + std::io::Seek,
+{
+    type Error = IoReaderError<std::io::Error>;
+
+    fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet<'_>>, Self::Error> {
+        if self.state.read_std(&mut self.reader)? {
+            Ok(Some(self.state.pusher.packet()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn end_granule_position(&self) -> Option<u64> {
+        self.end_granule_position
+            .or(self.state.end_granule_position)
+    }
+
+    fn start_granule_position(&self) -> Option<u64> {
+        self.state.start.as_ref().and_then(StartPosition::position)
+    }
+}
+
+/// State of reading packets with [`OggPusher`] independent of how the data is read.
+#[derive(Debug)]
+struct PushState<const PAGE_SIZE: usize, const PACKET_SIZE: usize> {
+    pusher: OggPusher<PAGE_SIZE, PACKET_SIZE>,
+    bitstream_serial_number: Option<u32>,
+    ended: bool,
+    end_granule_position: Option<u64>,
+    /// Start position of audio, which begins on the page after the headers. [`None`] until the
+    /// comment header has been read.
+    start: Option<StartPosition>,
+    bitrate: BitrateMeter,
+    /// Checker of header pages, and of audio pages in strict mode.
+    checker: Checker,
+    /// The first violation on header pages, which is returned when strict mode is turned on.
+    violation: Option<Violation>,
+    /// Whether violations are returned as errors.
+    strict: bool,
+    /// Whether deviations are tolerated and reported.
+    lenient: bool,
+    /// Deviations that were found, which are reported in lenient mode.
+    deviations: Deviations,
+    /// Pre-skip of the identification header.
+    pre_skip: u16,
+    /// Whether the first chunk of the comment header has been read.
+    #[cfg(feature = "embedded-io")]
+    vendor_checked: bool,
+}
+
+impl<const PAGE_SIZE: usize, const PACKET_SIZE: usize> PushState<PAGE_SIZE, PACKET_SIZE> {
+    fn new() -> Self {
+        Self {
+            pusher: OggPusher::new(),
+            bitstream_serial_number: None,
+            ended: false,
+            end_granule_position: None,
+            start: None,
+            bitrate: BitrateMeter::new(),
+            checker: Checker::new(),
+            violation: None,
+            strict: false,
+            lenient: false,
+            deviations: Deviations::empty(),
+            pre_skip: 0,
+            #[cfg(feature = "embedded-io")]
+            vendor_checked: false,
+        }
+    }
+
+    /// Returns the deviations that were found if lenient mode is turned on.
+    fn deviations(&self) -> Deviations {
+        if self.lenient {
+            self.deviations
+        } else {
+            Deviations::empty()
+        }
+    }
+
+    /// Read data until a packet is complete. Returns whether one was completed.
+    #[cfg(feature = "embedded-io")]
+    fn read<R: embedded_io::Read>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<bool, IoReaderError<R::Error>> {
+        loop {
+            if let Some(found) = self.step()? {
+                return Ok(found);
+            }
+            let count = reader
+                .read(self.pusher.space()?)
+                .map_err(IoReaderError::IoError)?;
+            self.advance(count)?;
+        }
+    }
+
+    /// Read data from [`std::io::Read`] until a packet is complete. Returns whether one was completed.
+    #[cfg(feature = "std")]
+    fn read_std<R: std::io::Read>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<bool, IoReaderError<std::io::Error>> {
+        loop {
+            if let Some(found) = self.step()? {
+                return Ok(found);
+            }
+            let count = loop {
+                match reader.read(self.pusher.space()?) {
+                    Ok(count) => break count,
+                    Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(error) => return Err(IoReaderError::IoError(error)),
+                }
+            };
+            self.advance(count)?;
+        }
+    }
+
+    /// Read data asynchronously until a packet is complete. Returns whether one was completed.
+    #[cfg(feature = "async")]
+    async fn read_async<R: embedded_io_async::Read>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<bool, IoReaderError<R::Error>> {
+        loop {
+            if let Some(found) = self.step()? {
+                return Ok(found);
+            }
+            let count = reader
+                .read(self.pusher.space()?)
+                .await
+                .map_err(IoReaderError::IoError)?;
+            self.advance(count)?;
+        }
+    }
+
+    /**
+     * Parse buffered data until a packet is complete.
+     *
+     * Returns whether a packet was completed, or [`None`] if more data must be read first.
+     */
+    fn step(&mut self) -> Result<Option<bool>, BitstreamError> {
+        if let Some(violation) = self.violation.take_if(|_| self.strict) {
+            return Err(BitstreamError::Violation(violation));
+        }
+        if self.pusher.step()? {
+            Ok(Some(true))
+        } else if self.ended {
+            Ok(Some(false))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Mark `count` bytes as read to the space of the pusher. Zero means end of data.
+    fn advance(&mut self, count: usize) -> Result<(), BitstreamError> {
+        if count == 0 {
+            self.ended = true;
+            if self.pusher.has_partial_data() && !self.lenient {
+                return Err(OggError::EndOfStreamError(None).into());
+            }
+            // Data ended without a page with the end of stream flag
+            self.deviations |= Deviations::MissingEndOfStream;
+            return Ok(());
+        }
+        self.pusher.advance(count)?;
+        if self.bitstream_serial_number.is_none() && self.pusher.skipped() > 0 {
+            // Data must begin with a page
+            return Err(OggError::NotOggStream.into());
+        }
+        if let Some(page) = self.pusher.current_page() {
+            let serial_number = *self
+                .bitstream_serial_number
+                .get_or_insert(page.bitstream_serial_number());
+            if page.bitstream_serial_number() != serial_number {
+                return Err(BitstreamError::UnsupportedStream(
+                    "bitstream serial number changed unexpectedly",
+                ));
+            }
+            // Audio pages are checked only in strict mode
+            if self.strict || self.start.is_none() {
+                if let Err(violation) = self.checker.page(&page) {
+                    self.violation.get_or_insert(violation);
+                }
+            }
+            if let Some(start) = &mut self.start {
+                let found = start.position().is_some();
+                // Invalid start positions are treated as zero
+                if start.page(&page) == Err(START_BEFORE_ZERO) {
+                    self.deviations |= Deviations::StartBeforeZero;
+                }
+                if let Some(position) = start.position().filter(|_| !found) {
+                    self.bitrate.start_at(position);
+                }
+                self.bitrate.page(&page);
+            }
+            if page.end_of_stream() {
+                self.ended = true;
+                self.end_granule_position = Some(page.granule_position());
+                let start = self.start.as_ref().and_then(StartPosition::position);
+                if page.granule_position().saturating_sub(start.unwrap_or(0))
+                    < u64::from(self.pre_skip)
+                {
+                    self.deviations |= Deviations::PreSkipPastEnd;
+                }
+            }
+        }
+        match self.violation.take_if(|_| self.strict) {
+            Some(violation) => Err(BitstreamError::Violation(violation)),
+            None => Ok(()),
+        }
+    }
+
+    /// Parse the identification header if it was `found`.
+    fn parse_header(&mut self, found: bool) -> Result<OpusHeader, BitstreamError> {
+        if !found {
+            return Err(BitstreamError::InvalidOpusStream("missing header"));
+        }
+        let header = OpusHeader::parse(self.pusher.packet().data)?;
+        if header.version > 15 {
+            return Err(BitstreamError::UnsupportedOpusVersion(header.version));
+        }
+        self.pre_skip = header.pre_skip;
+        Ok(header)
+    }
+
+    /// Returns the chunk of the comment header if it was `found`.
+    #[cfg(feature = "embedded-io")]
+    fn comment_chunk(&mut self, found: bool) -> Result<&[u8], BitstreamError> {
+        if !found {
+            return Err(BitstreamError::InvalidOpusStream("missing comment header"));
+        }
+        if !self.vendor_checked {
+            self.vendor_checked = true;
+            self.deviations |= vendor_deviation(self.pusher.chunk());
+        }
+        if !self.pusher.is_streaming() {
+            self.start = Some(StartPosition::default());
+        }
+        Ok(self.pusher.chunk())
+    }
+}
+
+/// Returns the deviation of the vendor string at the beginning of comment header data.
+fn vendor_deviation(data: &[u8]) -> Deviations {
+    match tags::vendor(data) {
+        Some(vendor) if vendor.as_str().is_none() => Deviations::InvalidVendor,
+        _ => Deviations::empty(),
+    }
+}
+
+/// Check the result of reading the comment header which is not needed and may be too large.
+#[cfg(feature = "std")]
+fn skip_comments<E>(found: Result<bool, IoReaderError<E>>) -> Result<(), IoReaderError<E>> {
+    match found {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(BitstreamError::InvalidOpusStream("missing comment header").into()),
+        Err(IoReaderError::BitstreamError(BitstreamError::OggError(
+            OggError::BufferTooSmallError(..),
+        ))) => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "async")]
+    use crate::{frames::test::block_on, AsyncFrames, AsyncPacketSource};
+    use crate::{frames::test::CountingDecoder, Bitstream, Frames};
+
+    /// Reader that returns at most `chunk` bytes at a time and fails when data runs out.
+    #[cfg(feature = "embedded-io")]
+    #[derive(Debug)]
+    struct ChunkReader<'data> {
+        data: &'data [u8],
+        chunk: usize,
+        fail: Option<embedded_io::ErrorKind>,
+    }
+
+    #[cfg(feature = "embedded-io")]
+    impl embedded_io::ErrorType for ChunkReader<'_> {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    #[cfg(feature = "embedded-io")]
+    impl embedded_io::Read for ChunkReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.data.is_empty() {
+                if let Some(error) = self.fail.take() {
+                    return Err(error);
+                }
+            }
+            let count = buf.len().min(self.chunk).min(self.data.len());
+            buf[..count].copy_from_slice(&self.data[..count]);
+            self.data = &self.data[count..];
+            Ok(count)
+        }
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn read_packets() {
+        const DATA: &[u8] = include_bytes!("test/mono.opus");
+        let bitstream = Bitstream::new(DATA);
+        let expected = bitstream.packet_reader::<512>().unwrap();
+        for chunk in [1, 7, 4096] {
+            let file = ChunkReader {
+                data: DATA,
+                chunk,
+                fail: None,
+            };
+            let mut reader = IoPacketReader::<_, 512, 512>::new(file).unwrap();
+            assert_eq!(reader.header(), expected.header());
+            assert_eq!(reader.end_granule_position(), None);
+            let mut sizes = [0; 7];
+            let mut count = 0;
+            while let Some(packet) = reader.next_packet().unwrap() {
+                sizes[count] = packet.data.len();
+                count += 1;
+            }
+            assert_eq!(count, 7);
+            assert_eq!(sizes, [13, 13, 13, 13, 14, 16, 14]);
+            assert_eq!(reader.end_granule_position(), Some(7992));
+            assert_eq!(reader.start_granule_position(), Some(7992 - 7 * 960));
+            assert!(reader.next_packet().unwrap().is_none());
+            assert!(reader.into_inner().data.is_empty());
+        }
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn skip_large_comments() {
+        let data = &include_bytes!("test/mono.opus")[..];
+        let mut reader = IoPacketReader::<_, 512, 32>::new(data).unwrap();
+        assert_eq!(reader.header().pre_skip, 312);
+        assert_eq!(reader.next_packet().unwrap().unwrap().data.len(), 13);
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn stream_comments() {
+        const DATA: &[u8] = include_bytes!("test/mono.opus");
+        let tags = Bitstream::new(DATA).tags().unwrap();
+        for chunk in [1, 7, 4096] {
+            let file = ChunkReader {
+                data: DATA,
+                chunk,
+                fail: None,
+            };
+            let mut reader = IoPacketReader::<_, 512, 32>::new_with_comments(file).unwrap();
+            let mut comments = Vec::new();
+            while let Some(chunk) = reader.next_comment_chunk().unwrap() {
+                comments.extend_from_slice(chunk);
+            }
+            assert_eq!(crate::tags::OpusTags::parse(&comments), Ok(tags));
+            assert_eq!(reader.next_comment_chunk(), Ok(None));
+            assert_eq!(reader.next_packet().unwrap().unwrap().data.len(), 13);
+        }
+        // Comments are skipped if they are not read
+        let mut reader = IoPacketReader::<_, 512, 32>::new_with_comments(DATA).unwrap();
+        assert_eq!(reader.next_packet().unwrap().unwrap().data.len(), 13);
+        let result = IoPacketReader::<_, 512, 32>::new_with_comments(&DATA[..47])
+            .unwrap()
+            .next_comment_chunk()
+            .map(|_| ());
+        assert_eq!(
+            result,
+            Err(BitstreamError::InvalidOpusStream("missing comment header").into())
+        );
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn decode_frames() {
+        let data = &include_bytes!("test/mono.opus")[..];
+        let reader = IoPacketReader::<_, 512, 512>::new(data).unwrap();
+        let mut frames = Frames::<_, _, 960>::new(reader, CountingDecoder::default());
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut expected = Frames::<_, _, 960>::new(reader, CountingDecoder::default());
+        while let Some(frame) = frames.next().unwrap() {
+            assert_eq!(Some(frame), expected.next().unwrap());
+        }
+        assert_eq!(expected.next(), Ok(None));
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn read_errors() {
+        const DATA: &[u8] = include_bytes!("test/mono.opus");
+        let file = ChunkReader {
+            data: &DATA[..DATA.len() - 10],
+            chunk: 64,
+            fail: None,
+        };
+        let mut reader = IoPacketReader::<_, 512, 512>::new(file).unwrap();
+        let error = reader.next_packet().unwrap_err();
+        assert_eq!(
+            error,
+            IoReaderError::BitstreamError(BitstreamError::OggError(OggError::EndOfStreamError(
+                None
+            )))
+        );
+        assert!(!error.is_recoverable());
+        assert_eq!(error.layer(), crate::ErrorLayer::Container);
+        let file = ChunkReader {
+            data: &DATA[..100],
+            chunk: 64,
+            fail: Some(embedded_io::ErrorKind::Interrupted),
+        };
+        let error = IoPacketReader::<_, 512, 512>::new(file).unwrap_err();
+        assert_eq!(
+            error,
+            IoReaderError::IoError(embedded_io::ErrorKind::Interrupted)
+        );
+        assert!(error.is_recoverable());
+        assert_eq!(error.layer(), crate::ErrorLayer::Io);
+        let data = &include_bytes!("test/vorbis.ogg")[..];
+        let result = IoPacketReader::<_, 4096, 4096>::new(data);
+        assert_eq!(
+            result.unwrap_err(),
+            IoReaderError::BitstreamError(BitstreamError::NotOpusStream)
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn read_packets_async() {
+        let data = &include_bytes!("test/mono.opus")[..];
+        let mut expected = IoPacketReader::<_, 512, 512>::new(data).unwrap();
+        block_on(async {
+            let mut reader = AsyncIoPacketReader::<_, 512, 512>::new(data).await.unwrap();
+            assert_eq!(reader.header(), expected.header());
+            while let Some(packet) = reader.next_packet().await.unwrap() {
+                assert_eq!(Some(packet), expected.next_packet().unwrap());
+            }
+            assert_eq!(expected.next_packet(), Ok(None));
+            assert_eq!(reader.end_granule_position(), Some(7992));
+        });
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn decode_frames_async() {
+        let data = &include_bytes!("test/mono.opus")[..];
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut expected = Frames::<_, _, 960>::new(reader, CountingDecoder::default());
+        block_on(async {
+            let reader = AsyncIoPacketReader::<_, 512, 512>::new(data).await.unwrap();
+            let mut frames = AsyncFrames::<_, _, 960>::new(reader, CountingDecoder::default());
+            while let Some(frame) = frames.next().await.unwrap() {
+                assert_eq!(Some(frame), expected.next().unwrap());
+            }
+            assert_eq!(expected.next(), Ok(None));
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn read_packets_std() {
+        let data = include_bytes!("test/offset.opus");
+        let file = std::io::Cursor::new(data);
+        let mut reader = StdPacketReader::<_, 512, 512>::new(file).unwrap();
+        // End is known before reaching the last page
+        assert_eq!(reader.end_granule_position(), Some(48_000 + 7 * 960));
+        let bitstream = Bitstream::new(data);
+        let mut expected = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(reader.header(), expected.header());
+        while let Some(packet) = reader.next_packet().unwrap() {
+            assert_eq!(Some(packet), expected.next_packet().unwrap());
+        }
+        assert_eq!(expected.next_packet(), Ok(None));
+        assert_eq!(reader.start_granule_position(), Some(48_000));
+        assert_eq!(expected.start_granule_position(), Some(48_000));
+        assert!(reader.bitrate().is_some());
+        assert_eq!(reader.bitrate(), expected.bitrate());
+        assert_eq!(reader.into_inner().position(), data.len() as u64);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn stream_info_std() {
+        let data = include_bytes!("test/offset.opus");
+        let expected = Bitstream::new(data).stream_info().unwrap();
+        let mut reader = StdPacketReader::<_, 512, 512>::new(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(reader.stream_info().unwrap(), expected);
+        // Reading continues from where it was
+        let mut packets = Bitstream::new(data).packet_reader::<512>().unwrap();
+        assert_eq!(
+            reader.next_packet().unwrap(),
+            packets.next_packet().unwrap()
+        );
+        assert_eq!(reader.duration().unwrap(), expected.duration());
+        assert_eq!(
+            reader.next_packet().unwrap(),
+            packets.next_packet().unwrap()
+        );
+
+        // The last page is not within the last page sized area
+        let mut data = Vec::from(&data[..]);
+        data.extend_from_slice(&[0; 512]);
+        let mut reader = StdPacketReader::<_, 512, 512>::new(std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(reader.end_granule_position(), None);
+        assert_eq!(reader.stream_info().unwrap(), expected);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn seek_std() {
+        let data = include_bytes!("test/offset.opus");
+        let mut reader = StdPacketReader::<_, 512, 512>::new(std::io::Cursor::new(data)).unwrap();
+        let mut expected = Bitstream::new(data).packet_reader::<512>().unwrap();
+        for granule_position in [5_000, 0, 3 * 960, u64::MAX, 100] {
+            assert_eq!(
+                reader.seek_granule(granule_position).unwrap(),
+                expected.seek_granule(granule_position).unwrap()
+            );
+            assert_eq!(reader.start_granule_position(), Some(48_000));
+            assert_eq!(
+                reader.next_packet().unwrap(),
+                expected.next_packet().unwrap()
+            );
+        }
+        while let Some(packet) = reader.next_packet().unwrap() {
+            assert_eq!(Some(packet), expected.next_packet().unwrap());
+        }
+        assert_eq!(expected.next_packet(), Ok(None));
+        // Index is the same with data before the stream
+        let mut file = std::io::Cursor::new([&[0; 10][..], data].concat());
+        file.set_position(10);
+        let mut reader = StdPacketReader::<_, 512, 512>::new(file).unwrap();
+        let index = reader.seek_index::<8>(Duration::ZERO).unwrap();
+        let entries = expected.seek_index::<8>(Duration::ZERO).unwrap();
+        assert_eq!(index.entries(), entries.entries());
+        for entry in index.entries().iter().rev() {
+            let position = expected.seek_entry(*entry).unwrap();
+            assert_eq!(reader.seek_entry(*entry).unwrap(), position);
+            assert_eq!(
+                reader.next_packet().unwrap(),
+                expected.next_packet().unwrap()
+            );
+        }
+    }
+
+    /// Reader that is interrupted on every other read.
+    #[cfg(feature = "std")]
+    struct InterruptingReader<'data> {
+        data: std::io::Cursor<&'data [u8]>,
+        interrupt: bool,
+    }
+
+    #[cfg(feature = "std")]
+    impl std::io::Read for InterruptingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            self.data.read(buf)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::io::Seek for InterruptingReader<'_> {
+        fn seek(&mut self, position: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.data.seek(position)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn decode_frames_std() {
+        let data = &include_bytes!("test/trimmed.opus")[..];
+        let file = InterruptingReader {
+            data: std::io::Cursor::new(data),
+            interrupt: false,
+        };
+        let reader = StdPacketReader::<_, 512, 512>::new(file).unwrap();
+        let mut frames = Frames::<_, _, 960>::new(reader, CountingDecoder::default());
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut expected = Frames::<_, _, 960>::new(reader, CountingDecoder::default());
+        while let Some(frame) = frames.next().unwrap() {
+            assert_eq!(Some(frame), expected.next().unwrap());
+        }
+        assert_eq!(expected.next(), Ok(None));
+    }
+
+    /// Stream with header version 2 and granule position of the second audio page decreasing.
+    fn nonconforming() -> Vec<u8> {
+        use crate::conformance::test::update_checksum;
+        let mut data = Vec::from(include_bytes!("test/offset.opus"));
+        data[28 + 8] = 2;
+        update_checksum(&mut data, 0, 47);
+        data[205 + 6..205 + 14].copy_from_slice(&1_000u64.to_le_bytes());
+        let length = data.len() - 205;
+        update_checksum(&mut data, 205, length);
+        data
+    }
+
+    /// Returns the violation of a failed read.
+    fn violation<T, E>(result: Result<T, IoReaderError<E>>) -> Option<Violation> {
+        match result {
+            Err(IoReaderError::BitstreamError(BitstreamError::Violation(violation))) => {
+                Some(violation)
+            }
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn strict() {
+        let data = nonconforming();
+        let mut reader = IoPacketReader::<_, 512, 512>::new(&data[..]).unwrap();
+        for _ in 0..7 {
+            assert!(reader.next_packet().unwrap().is_some());
+        }
+        assert_eq!(reader.next_packet(), Ok(None));
+        let reader = IoPacketReader::<_, 512, 512>::new(&data[..]).unwrap();
+        let mut reader = reader.with_strict(true);
+        assert_eq!(
+            violation(reader.next_packet()),
+            Some(Violation::HeaderVersion(2))
+        );
+        for _ in 0..3 {
+            assert!(reader.next_packet().unwrap().is_some());
+        }
+        assert_eq!(
+            violation(reader.next_packet()),
+            Some(Violation::GranulePositionDecreased(3))
+        );
+        for _ in 0..4 {
+            assert!(reader.next_packet().unwrap().is_some());
+        }
+        assert_eq!(reader.next_packet(), Ok(None));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn strict_async() {
+        let data = nonconforming();
+        block_on(async {
+            let reader = AsyncIoPacketReader::<_, 512, 512>::new(&data[..]).await;
+            let mut reader = reader.unwrap().with_strict(true);
+            assert_eq!(
+                violation(reader.next_packet().await),
+                Some(Violation::HeaderVersion(2))
+            );
+            for _ in 0..3 {
+                assert!(reader.next_packet().await.unwrap().is_some());
+            }
+            assert_eq!(
+                violation(reader.next_packet().await),
+                Some(Violation::GranulePositionDecreased(3))
+            );
+            for _ in 0..4 {
+                assert!(reader.next_packet().await.unwrap().is_some());
+            }
+            assert_eq!(reader.next_packet().await, Ok(None));
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn strict_std() {
+        let data = nonconforming();
+        let file = std::io::Cursor::new(&data);
+        let mut reader = StdPacketReader::<_, 512, 512>::new(file)
+            .unwrap()
+            .with_strict(true);
+        assert_eq!(
+            violation(reader.next_packet()),
+            Some(Violation::HeaderVersion(2))
+        );
+        for _ in 0..3 {
+            assert!(reader.next_packet().unwrap().is_some());
+        }
+        assert_eq!(
+            violation(reader.next_packet()),
+            Some(Violation::GranulePositionDecreased(3))
+        );
+        for _ in 0..4 {
+            assert!(reader.next_packet().unwrap().is_some());
+        }
+        assert!(reader.next_packet().unwrap().is_none());
+        // Pages are not compared to those before seeking
+        reader.seek_granule(3 * 960).unwrap();
+        while reader.next_packet().unwrap().is_some() {}
+    }
+
+    /**
+     * Stream whose first audio page ends before its three packets, end of stream flag is missing
+     * and vendor string is not UTF-8, and same stream with pre-skip that is a bit longer than its
+     * 6720 samples.
+     */
+    fn deviating() -> (Vec<u8>, Vec<u8>) {
+        use crate::conformance::test::update_checksum;
+        let mut data = Vec::from(include_bytes!("test/offset.opus"));
+        let mut pre_skip = data.clone();
+        pre_skip[28 + 10..28 + 12].copy_from_slice(&6_800u16.to_le_bytes());
+        update_checksum(&mut pre_skip, 0, 47);
+        let vendor = data.windows(4).position(|bytes| bytes == b"Lavf").unwrap();
+        data[vendor] = 0xFF;
+        update_checksum(&mut data, 47, 89);
+        data[136 + 6..136 + 14].copy_from_slice(&1_000u64.to_le_bytes());
+        update_checksum(&mut data, 136, 69);
+        data[205 + 5] = 0;
+        let length = data.len() - 205;
+        update_checksum(&mut data, 205, length);
+        (data, pre_skip)
+    }
+
+    /// Returns the number of packets read from source.
+    fn count<S: PacketSource>(source: &mut S) -> Result<usize, S::Error> {
+        let mut count = 0;
+        while source.next_packet()?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn lenient() {
+        let (data, pre_skip) = deviating();
+        let mut reader = IoPacketReader::<_, 512, 512>::new(&data[..]).unwrap();
+        assert_eq!(count(&mut reader), Ok(7));
+        assert!(reader.deviations().is_empty());
+        let reader = IoPacketReader::<_, 512, 512>::new(&data[..]).unwrap();
+        let mut reader = reader.with_lenient(true);
+        assert_eq!(reader.deviations(), Deviations::InvalidVendor);
+        assert_eq!(count(&mut reader), Ok(7));
+        assert_eq!(
+            reader.deviations(),
+            Deviations::StartBeforeZero
+                | Deviations::MissingEndOfStream
+                | Deviations::InvalidVendor
+        );
+        // Incomplete last page is discarded
+        let mut reader = IoPacketReader::<_, 512, 512>::new(&data[..250]).unwrap();
+        assert_eq!(
+            count(&mut reader),
+            Err(IoReaderError::BitstreamError(
+                OggError::EndOfStreamError(None).into()
+            ))
+        );
+        let reader = IoPacketReader::<_, 512, 512>::new(&data[..250]).unwrap();
+        let mut reader = reader.with_lenient(true);
+        assert_eq!(count(&mut reader), Ok(3));
+        assert!(reader.deviations().contains(Deviations::MissingEndOfStream));
+        let reader = IoPacketReader::<_, 512, 512>::new(&pre_skip[..]).unwrap();
+        let mut reader = reader.with_lenient(true);
+        assert_eq!(count(&mut reader), Ok(7));
+        assert_eq!(reader.deviations(), Deviations::PreSkipPastEnd);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn lenient_async() {
+        let (data, pre_skip) = deviating();
+        block_on(async {
+            for (data, packets, deviations) in [
+                (
+                    &data[..],
+                    7,
+                    Deviations::StartBeforeZero
+                        | Deviations::MissingEndOfStream
+                        | Deviations::InvalidVendor,
+                ),
+                (
+                    &data[..250],
+                    3,
+                    Deviations::StartBeforeZero
+                        | Deviations::MissingEndOfStream
+                        | Deviations::InvalidVendor,
+                ),
+                (&pre_skip[..], 7, Deviations::PreSkipPastEnd),
+            ] {
+                let reader = AsyncIoPacketReader::<_, 512, 512>::new(data).await;
+                let mut reader = reader.unwrap().with_lenient(true);
+                let mut count = 0;
+                while reader.next_packet().await.unwrap().is_some() {
+                    count += 1;
+                }
+                assert_eq!(count, packets);
+                assert_eq!(reader.deviations(), deviations);
+            }
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn lenient_std() {
+        let (data, pre_skip) = deviating();
+        let file = std::io::Cursor::new(&data);
+        let mut reader = StdPacketReader::<_, 512, 512>::new(file).unwrap();
+        assert_eq!(count(&mut reader).unwrap(), 7);
+        assert!(reader.deviations().is_empty());
+        for (data, packets, deviations) in [
+            (
+                &data[..],
+                7,
+                Deviations::StartBeforeZero
+                    | Deviations::MissingEndOfStream
+                    | Deviations::InvalidVendor,
+            ),
+            (
+                &data[..250],
+                3,
+                Deviations::StartBeforeZero
+                    | Deviations::MissingEndOfStream
+                    | Deviations::InvalidVendor,
+            ),
+            (&pre_skip[..], 7, Deviations::PreSkipPastEnd),
+        ] {
+            let file = std::io::Cursor::new(data);
+            let reader = StdPacketReader::<_, 512, 512>::new(file).unwrap();
+            let mut reader = reader.with_lenient(true);
+            assert_eq!(count(&mut reader).unwrap(), packets);
+            assert_eq!(reader.deviations(), deviations);
+        }
+    }
+}
@@ -0,0 +1,615 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Jitter buffer for opus packets received over RTP.
+ *
+ * Packets from network arrive at uneven intervals and sometimes out of order or not at all.
+ * [`JitterBuffer`] holds them for a while and hands them out in order at the pace of the local
+ * clock. It measures interarrival jitter as in
+ * [RFC3550](https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.8) and adapts its delay to
+ * it at the start of every talkspurt, i.e. when the marker bit is set.
+ *
+ * The buffer has a fixed number of slots of fixed size, so it does not allocate. Times are given
+ * in samples at 48 kHz, the clock rate of RTP timestamps of opus, from any local clock that
+ * counts steadily, e.g. a timer.
+ *
+ * ```rust
+ * # use oggopus_embedded::{jitter::{JitterBuffer, Playout}, rtp::RtpHeader};
+ * # let datagram = [0x80, 0x78, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0x08];
+ * # let now = 48_000;
+ * let mut buffer = JitterBuffer::<16, 1_275>::new();
+ * // When a datagram arrives
+ * let (header, payload) = RtpHeader::parse(&datagram).unwrap();
+ * if let Err(error) = buffer.push(header, payload, now) {
+ *     println!("Dropped packet: {}", error);
+ * }
+ * // Every time more audio is needed
+ * match buffer.pop(now + 960) {
+ *     Playout::Waiting => println!("Nothing to play yet"),
+ *     playout => println!("Decode {:?}", playout),
+ * }
+ * ```
+ */
+
+use super::{
+    opus::packet_samples,
+    rtp::{output_length, RtpHeader, CLOCK_RATE, MAX_DROPOUT, MAX_MISORDER},
+    Decode,
+};
+
+/// Default minimum delay in samples at 48 kHz, 20 ms.
+pub const DEFAULT_MIN_DELAY: u32 = CLOCK_RATE / 50;
+
+/// Default maximum delay in samples at 48 kHz, 200 ms.
+pub const DEFAULT_MAX_DELAY: u32 = CLOCK_RATE / 5;
+
+/// Error from adding packet to [`JitterBuffer`].
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum JitterError {
+    /// Packet does not fit in a slot.
+    BufferTooSmallError(usize, usize),
+    /**
+     * Packet arrived after its time to play had passed.
+     *
+     * Contains the sequence number of the packet.
+     */
+    LatePacket(u16),
+    /**
+     * Packet is already in the buffer.
+     *
+     * Contains the sequence number of the packet.
+     */
+    DuplicatePacket(u16),
+}
+
+impl core::fmt::Display for JitterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use JitterError::*;
+        match self {
+            BufferTooSmallError(got, needed) => f.write_fmt(format_args!(
+                "buffer is too small: got {} bytes but needed {} bytes",
+                got, needed
+            )),
+            LatePacket(sequence_number) => {
+                f.write_fmt(format_args!("packet {} arrived late", sequence_number))
+            }
+            DuplicatePacket(sequence_number) => {
+                f.write_fmt(format_args!("packet {} is a duplicate", sequence_number))
+            }
+        }
+    }
+}
+
+impl core::error::Error for JitterError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        None
+    }
+}
+
+/**
+ * Statistics of [`JitterBuffer`].
+ *
+ * Counters wrap around on overflow.
+ */
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JitterStats {
+    /// The number of packets that were played.
+    pub played: u32,
+    /// The number of packets that never arrived.
+    pub lost: u32,
+    /// The number of packets that arrived after their time to play.
+    pub late: u32,
+    /// The number of packets that arrived more than once.
+    pub duplicates: u32,
+    /// The number of packets that were dropped because later packets did not fit otherwise.
+    pub dropped: u32,
+}
+
+/// What to play next from [`JitterBuffer`].
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Playout<'buffer> {
+    /// Nothing is due yet, e.g. because the buffer is still filling.
+    Waiting,
+    /// Packet to decode.
+    Packet {
+        /// Opus packet.
+        payload: &'buffer [u8],
+        /// Whether the stream restarted before this packet, so the decoder must be reset.
+        restarted: bool,
+    },
+    /**
+     * Packet is missing and the samples must be concealed.
+     *
+     * This happens also during silence when the sender uses discontinuous transmission.
+     */
+    Missing {
+        /// The number of samples at 48 kHz to conceal.
+        samples: u32,
+        /// The packet after the missing one if it has arrived, for forward error correction.
+        fec: Option<&'buffer [u8]>,
+    },
+}
+
+impl Playout<'_> {
+    /**
+     * Decode what to play into output buffer.
+     *
+     * Missing samples are recovered with [`Decode::decode_fec`] if the next packet is known and
+     * concealed otherwise. Returns empty slice when waiting.
+     */
+    pub fn decode<'output, D: Decode>(
+        &self,
+        decoder: &mut D,
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], D::Error> {
+        match *self {
+            Self::Waiting => Ok(&output[..0]),
+            Self::Packet { payload, restarted } => {
+                if restarted {
+                    decoder.reset()?;
+                }
+                decoder.decode(payload, output)
+            }
+            Self::Missing { samples, fec } => {
+                let end = output.len().min(output_length(decoder, samples));
+                match fec {
+                    Some(packet) => decoder.decode_fec(packet, &mut output[..end]),
+                    None => decoder.decode(&[], &mut output[..end]),
+                }
+            }
+        }
+    }
+}
+
+/// Packet stored in [`JitterBuffer`].
+#[derive(Clone, Copy, Debug)]
+struct Slot<const PACKET_SIZE: usize> {
+    used: bool,
+    marker: bool,
+    sequence_number: u16,
+    timestamp: u32,
+    /// Arrival time minus timestamp.
+    transit: u32,
+    length: usize,
+    data: [u8; PACKET_SIZE],
+}
+
+impl<const PACKET_SIZE: usize> Slot<PACKET_SIZE> {
+    const EMPTY: Self = Self {
+        used: false,
+        marker: false,
+        sequence_number: 0,
+        timestamp: 0,
+        transit: 0,
+        length: 0,
+        data: [0; PACKET_SIZE],
+    };
+}
+
+/// The next packet to play.
+#[derive(Clone, Copy, Debug)]
+struct Next {
+    ssrc: u32,
+    sequence_number: u16,
+    timestamp: u32,
+}
+
+/**
+ * Reorders packets and hands them out on a steady clock.
+ *
+ * Holds up to `DEPTH` packets of at most `PACKET_SIZE` bytes. `DEPTH` must be a power of two and
+ * large enough to hold packets for the maximum delay, e.g. 16 for 200 ms of 20 ms packets with
+ * some margin.
+ */
+#[derive(Debug)]
+pub struct JitterBuffer<const DEPTH: usize, const PACKET_SIZE: usize> {
+    slots: [Slot<PACKET_SIZE>; DEPTH],
+    next: Option<Next>,
+    /// Local time minus timestamp of playout.
+    offset: u32,
+    /// Transit time of the previous packet.
+    transit: Option<u32>,
+    /// Jitter estimate scaled by 16.
+    jitter: u32,
+    /// Duration of the last played packet.
+    frame: u32,
+    min_delay: u32,
+    max_delay: u32,
+    restarted: bool,
+    stats: JitterStats,
+}
+
+impl<const DEPTH: usize, const PACKET_SIZE: usize> Default for JitterBuffer<DEPTH, PACKET_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const DEPTH: usize, const PACKET_SIZE: usize> JitterBuffer<DEPTH, PACKET_SIZE> {
+    /// Sequence numbers must map to slots without gaps when they wrap around.
+    const POWER_OF_TWO: () = assert!(DEPTH.is_power_of_two(), "DEPTH must be a power of two");
+
+    /// Construct empty [`JitterBuffer`].
+    pub const fn new() -> Self {
+        let () = Self::POWER_OF_TWO;
+        Self {
+            slots: [Slot::EMPTY; DEPTH],
+            next: None,
+            offset: 0,
+            transit: None,
+            jitter: 0,
+            frame: DEFAULT_MIN_DELAY,
+            min_delay: DEFAULT_MIN_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            restarted: false,
+            stats: JitterStats {
+                played: 0,
+                lost: 0,
+                late: 0,
+                duplicates: 0,
+                dropped: 0,
+            },
+        }
+    }
+
+    /**
+     * Set minimum and maximum delay in samples at 48 kHz.
+     *
+     * Delay is three times the jitter estimate within these limits.
+     *
+     * # Panics
+     * Panics if minimum is larger than maximum.
+     */
+    pub const fn with_delay(mut self, min_delay: u32, max_delay: u32) -> Self {
+        assert!(
+            min_delay <= max_delay,
+            "min_delay must not exceed max_delay"
+        );
+        self.min_delay = min_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Returns the interarrival jitter estimate in samples at 48 kHz.
+    pub fn jitter(&self) -> u32 {
+        self.jitter >> 4
+    }
+
+    /// Returns the delay in samples at 48 kHz that will be used from the next talkspurt.
+    pub fn delay(&self) -> u32 {
+        self.jitter()
+            .saturating_mul(3)
+            .clamp(self.min_delay, self.max_delay)
+    }
+
+    /// Returns statistics since the buffer was created.
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    /**
+     * Add packet that arrived at local time `arrival`.
+     *
+     * A packet of another source, or one that is too far from the others, restarts the stream.
+     */
+    pub fn push(
+        &mut self,
+        header: RtpHeader,
+        payload: &[u8],
+        arrival: u32,
+    ) -> Result<(), JitterError> {
+        if payload.len() > PACKET_SIZE {
+            return Err(JitterError::BufferTooSmallError(PACKET_SIZE, payload.len()));
+        }
+        let transit = arrival.wrapping_sub(header.timestamp);
+        let next = match self.next {
+            Some(next) if next.ssrc == header.ssrc => next,
+            _ => {
+                self.restart(header, payload, transit);
+                return Ok(());
+            }
+        };
+        let distance = header.sequence_number.wrapping_sub(next.sequence_number);
+        if distance > u16::MAX - MAX_MISORDER {
+            self.stats.late = self.stats.late.wrapping_add(1);
+            return Err(JitterError::LatePacket(header.sequence_number));
+        } else if distance > MAX_DROPOUT {
+            self.restart(header, payload, transit);
+            return Ok(());
+        } else if before(header.timestamp, next.timestamp) {
+            // Its time has been concealed already
+            self.skip(distance);
+            if let Some(next) = &mut self.next {
+                next.sequence_number = next.sequence_number.wrapping_add(1);
+            }
+            self.stats.late = self.stats.late.wrapping_add(1);
+            return Err(JitterError::LatePacket(header.sequence_number));
+        }
+        if usize::from(distance) >= DEPTH {
+            self.skip(distance - DEPTH as u16 + 1);
+        } else if self.slots[Self::index(header.sequence_number)].used {
+            self.stats.duplicates = self.stats.duplicates.wrapping_add(1);
+            return Err(JitterError::DuplicatePacket(header.sequence_number));
+        }
+        if let Some(previous) = self.transit {
+            let difference = (transit.wrapping_sub(previous) as i32).unsigned_abs();
+            let decay = self.jitter.saturating_add(8) >> 4;
+            self.jitter = self.jitter.saturating_add(difference) - decay;
+        }
+        self.store(header, payload, transit);
+        Ok(())
+    }
+
+    /**
+     * Returns what to play at local time `now`.
+     *
+     * Call this every time output needs more audio, e.g. every 20 ms. Returns
+     * [`Missing`][`Playout::Missing`] when the next packet has not arrived in time.
+     */
+    pub fn pop(&mut self, now: u32) -> Playout<'_> {
+        let Some(next) = self.next else {
+            return Playout::Waiting;
+        };
+        let earliest = (0..DEPTH as u16)
+            .map(|distance| Self::index(next.sequence_number.wrapping_add(distance)))
+            .find(|&index| self.slots[index].used);
+        if let Some(index) = earliest {
+            let slot = &self.slots[index];
+            if !before(next.timestamp, slot.timestamp) {
+                if slot.marker {
+                    self.offset = slot.transit.wrapping_add(self.delay());
+                }
+                if !reached(now, slot.timestamp.wrapping_add(self.offset)) {
+                    return Playout::Waiting;
+                }
+                let lost = slot.sequence_number.wrapping_sub(next.sequence_number);
+                let duration = packet_samples(&slot.data[..slot.length]).unwrap_or(0);
+                if duration > 0 {
+                    self.frame = duration;
+                }
+                self.next = Some(Next {
+                    ssrc: next.ssrc,
+                    sequence_number: slot.sequence_number.wrapping_add(1),
+                    timestamp: slot.timestamp.wrapping_add(duration),
+                });
+                self.stats.lost = self.stats.lost.wrapping_add(u32::from(lost));
+                self.stats.played = self.stats.played.wrapping_add(1);
+                let slot = &mut self.slots[index];
+                slot.used = false;
+                return Playout::Packet {
+                    payload: &slot.data[..slot.length],
+                    restarted: core::mem::take(&mut self.restarted),
+                };
+            }
+        }
+        if !reached(now, next.timestamp.wrapping_add(self.offset)) {
+            return Playout::Waiting;
+        }
+        let mut samples = self.frame;
+        let mut fec = None;
+        if let Some(index) = earliest {
+            let slot = &self.slots[index];
+            let gap = slot.timestamp.wrapping_sub(next.timestamp);
+            samples = samples.min(gap);
+            if gap == samples && slot.sequence_number == next.sequence_number.wrapping_add(1) {
+                fec = Some(&slot.data[..slot.length]);
+            }
+        }
+        self.next = Some(Next {
+            timestamp: next.timestamp.wrapping_add(samples),
+            ..next
+        });
+        Playout::Missing { samples, fec }
+    }
+
+    /// Returns the slot of sequence number.
+    fn index(sequence_number: u16) -> usize {
+        usize::from(sequence_number) & (DEPTH - 1)
+    }
+
+    /// Forget all packets and start over from packet.
+    fn restart(&mut self, header: RtpHeader, payload: &[u8], transit: u32) {
+        for slot in &mut self.slots {
+            slot.used = false;
+        }
+        self.restarted = self.next.is_some();
+        self.next = Some(Next {
+            ssrc: header.ssrc,
+            sequence_number: header.sequence_number,
+            timestamp: header.timestamp,
+        });
+        self.transit = None;
+        self.offset = transit.wrapping_add(self.delay());
+        self.store(header, payload, transit);
+    }
+
+    /// Move the next packet forward by count, dropping packets that are still in the buffer.
+    fn skip(&mut self, count: u16) {
+        let Some(next) = &mut self.next else {
+            return;
+        };
+        let mut dropped = 0;
+        for distance in 0..count.min(DEPTH as u16) {
+            let slot = &mut self.slots[Self::index(next.sequence_number.wrapping_add(distance))];
+            if slot.used {
+                slot.used = false;
+                dropped += 1;
+            }
+        }
+        next.sequence_number = next.sequence_number.wrapping_add(count);
+        self.stats.dropped = self.stats.dropped.wrapping_add(u32::from(dropped));
+        self.stats.lost = self.stats.lost.wrapping_add(u32::from(count - dropped));
+    }
+
+    /// Store packet in its slot.
+    fn store(&mut self, header: RtpHeader, payload: &[u8], transit: u32) {
+        let slot = &mut self.slots[Self::index(header.sequence_number)];
+        slot.used = true;
+        slot.marker = header.marker;
+        slot.sequence_number = header.sequence_number;
+        slot.timestamp = header.timestamp;
+        slot.transit = transit;
+        slot.length = payload.len();
+        slot.data[..payload.len()].copy_from_slice(payload);
+        self.transit = Some(transit);
+    }
+}
+
+/// Returns true if timestamp a is before b.
+fn before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Returns true if local time now has reached time.
+fn reached(now: u32, time: u32) -> bool {
+    !before(now, time)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rtp::test::RecordingDecoder;
+
+    /// Returns header of 20 ms packet with SSRC 1.
+    fn header(sequence_number: u16, timestamp: u32) -> RtpHeader {
+        RtpHeader {
+            marker: false,
+            payload_type: 120,
+            sequence_number,
+            timestamp,
+            ssrc: 1,
+        }
+    }
+
+    fn packet(payload: &[u8]) -> Playout<'_> {
+        Playout::Packet {
+            payload,
+            restarted: false,
+        }
+    }
+
+    #[test]
+    fn reorder() {
+        let mut buffer = JitterBuffer::<8, 16>::new();
+        buffer.push(header(0, 0), &[0x08, 0], 1_000).unwrap();
+        buffer.push(header(2, 1_920), &[0x08, 2], 3_000).unwrap();
+        buffer.push(header(1, 960), &[0x08, 1], 3_100).unwrap();
+        assert_eq!(buffer.jitter(), 70);
+        assert_eq!(buffer.delay(), DEFAULT_MIN_DELAY);
+        assert_eq!(buffer.pop(1_959), Playout::Waiting);
+        assert_eq!(buffer.pop(1_960), packet(&[0x08, 0]));
+        assert_eq!(buffer.pop(1_970), Playout::Waiting);
+        assert_eq!(buffer.pop(2_920), packet(&[0x08, 1]));
+        assert_eq!(buffer.pop(3_880), packet(&[0x08, 2]));
+        assert_eq!(
+            buffer.pop(4_840),
+            Playout::Missing {
+                samples: 960,
+                fec: None
+            }
+        );
+        assert_eq!(buffer.stats().played, 3);
+        assert_eq!(buffer.stats().lost, 0);
+    }
+
+    #[test]
+    fn lost_packet() {
+        let mut buffer = JitterBuffer::<8, 16>::new();
+        buffer.push(header(0, 0), &[0x08, 0], 0).unwrap();
+        buffer.push(header(2, 1_920), &[0x08, 2], 1_920).unwrap();
+        assert_eq!(buffer.pop(960), packet(&[0x08, 0]));
+        let missing = buffer.pop(1_920);
+        assert_eq!(
+            missing,
+            Playout::Missing {
+                samples: 960,
+                fec: Some(&[0x08, 2])
+            }
+        );
+        let mut decoder = RecordingDecoder::default();
+        missing.decode(&mut decoder, &mut [0; 1_920]).unwrap();
+        assert_eq!(decoder.calls, [("fec", 640)]);
+        assert_eq!(buffer.pop(2_880), packet(&[0x08, 2]));
+        assert_eq!(buffer.stats().lost, 1);
+        assert_eq!(
+            buffer.push(header(1, 960), &[0x08, 1], 3_000),
+            Err(JitterError::LatePacket(1))
+        );
+        assert_eq!(buffer.stats().late, 1);
+    }
+
+    #[test]
+    fn late_packet() {
+        let mut buffer = JitterBuffer::<8, 16>::new();
+        buffer.push(header(0, 0), &[0x08, 0], 0).unwrap();
+        assert_eq!(buffer.pop(960), packet(&[0x08, 0]));
+        assert_eq!(
+            buffer.pop(1_920),
+            Playout::Missing {
+                samples: 960,
+                fec: None
+            }
+        );
+        assert_eq!(
+            buffer.push(header(1, 960), &[0x08, 1], 2_000),
+            Err(JitterError::LatePacket(1))
+        );
+        buffer.push(header(2, 1_920), &[0x08, 2], 2_100).unwrap();
+        assert_eq!(buffer.pop(2_880), packet(&[0x08, 2]));
+        let stats = buffer.stats();
+        assert_eq!((stats.played, stats.lost, stats.late), (2, 0, 1));
+    }
+
+    #[test]
+    fn overflow() {
+        let mut buffer = JitterBuffer::<8, 16>::new();
+        buffer.push(header(0, 0), &[0x08, 0], 0).unwrap();
+        assert_eq!(
+            buffer.push(header(0, 0), &[0x08, 0], 0),
+            Err(JitterError::DuplicatePacket(0))
+        );
+        assert_eq!(
+            buffer.push(header(1, 960), &[0; 17], 960),
+            Err(JitterError::BufferTooSmallError(16, 17))
+        );
+        buffer.push(header(10, 9_600), &[0x08, 10], 960).unwrap();
+        let stats = buffer.stats();
+        assert_eq!((stats.duplicates, stats.dropped, stats.lost), (1, 1, 2));
+    }
+
+    #[test]
+    fn restart() {
+        let mut buffer = JitterBuffer::<8, 16>::new();
+        buffer.push(header(0, 0), &[0x08, 0], 0).unwrap();
+        let other = RtpHeader {
+            ssrc: 2,
+            ..header(100, 50_000)
+        };
+        buffer.push(other, &[0x08, 100], 100).unwrap();
+        assert_eq!(buffer.pop(959), Playout::Waiting);
+        let playout = buffer.pop(1_060);
+        assert_eq!(
+            playout,
+            Playout::Packet {
+                payload: &[0x08, 100],
+                restarted: true
+            }
+        );
+        let mut decoder = RecordingDecoder::default();
+        playout.decode(&mut decoder, &mut [0; 640]).unwrap();
+        assert_eq!(decoder.calls, [("reset", 0), ("decode", 640)]);
+        assert_eq!(
+            buffer.pop(2_020),
+            Playout::Missing {
+                samples: 960,
+                fec: None
+            }
+        );
+    }
+}
@@ -14,24 +14,104 @@
  * and [RFC7845](https://datatracker.ietf.org/doc/html/rfc7845).
  *
  * # Limitations
- * - Supports only one logical stream at a time. Grouping is not supported.
- * - Mixing (interleaving or otherwise) other types of streams than opus is not supported.
- * - This parses ID header and ignores comment header.
- * - This does not validate CRC or handle missing packets.
- * - Seeking is not supported.
+ * - Supports only one logical stream at a time. Chained streams can be read one link at a time
+ *   and one stream of grouped streams can be selected with
+ *   [`select_stream`][`Bitstream::select_stream`].
+ * - Comment header is skipped when reading packets. It can be read separately with
+ *   [`tags`][`Bitstream::tags`] if it fits on one page, or with `OggOpusFile` with `std`
+ *   feature.
+ * - This does not handle missing packets.
+ * - Seeking needs random access to the data, i.e. [`PacketReader`] or `StdPacketReader` with
+ *   `std` feature.
  * - Parsing of [RFC8486](https://datatracker.ietf.org/doc/html/rfc8486) family channel mappings is not supported.
  */
 
 #![cfg_attr(docsrs, feature(doc_cfg))]
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![deny(missing_docs)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub mod bitrate;
+#[cfg_attr(docsrs, doc(cfg(feature = "capi")))]
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod conformance;
 mod container;
+pub mod crc;
+pub mod edit;
+#[cfg(feature = "std")]
+mod file;
+mod frames;
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[cfg(feature = "std")]
+pub mod http;
+mod info;
+#[cfg(any(feature = "embedded-io", feature = "std"))]
+mod io;
+pub mod jitter;
+pub mod live;
+pub mod mix;
+#[cfg_attr(docsrs, doc(cfg(feature = "mkv")))]
+#[cfg(feature = "mkv")]
+pub mod mkv;
+#[cfg_attr(docsrs, doc(cfg(feature = "mp4")))]
+#[cfg(feature = "mp4")]
+pub mod mp4;
+mod mux;
 pub mod opus;
+pub mod packetlog;
+pub mod picture;
+#[cfg(feature = "rodio")]
+mod playback;
+mod push;
+pub mod raw;
+#[cfg_attr(docsrs, doc(cfg(feature = "embedded-io")))]
+#[cfg(feature = "embedded-io")]
+pub mod retry;
+pub mod rtp;
+mod seek;
+pub mod serial;
+pub mod sink;
+mod source;
+pub mod tags;
+pub mod time;
+#[cfg(feature = "alloc")]
+mod vec;
+pub mod verify;
+pub mod watchdog;
+#[cfg_attr(docsrs, doc(cfg(feature = "embedded-io")))]
+#[cfg(feature = "embedded-io")]
+pub mod writer;
 
-pub use container::{OggError, Packet, Packets};
+pub use container::{OggError, Packet, Packets, Pages, RawPage, MAX_PAGE_SIZE};
+#[cfg(feature = "std")]
+pub use file::OggOpusFile;
+#[cfg(feature = "async")]
+pub use frames::AsyncFrames;
+pub use frames::{Decode, Frames, FramesError};
+pub use info::{scan_library, ProbeInfo, StreamInfo, Summary};
+#[cfg(feature = "async")]
+pub use io::AsyncIoPacketReader;
+#[cfg(feature = "embedded-io")]
+pub use io::IoPacketReader;
+#[cfg(any(feature = "embedded-io", feature = "std"))]
+pub use io::IoReaderError;
+#[cfg(feature = "std")]
+pub use io::StdPacketReader;
+pub use mux::{LogicalStream, LogicalStreams, SelectedReader};
 pub use opus::ChannelMapping;
+#[cfg(feature = "rodio")]
+pub use playback::RodioSource;
+pub use push::OggPusher;
+pub use seek::{SeekEntry, SeekIndex, SeekablePacketSource};
+#[cfg(feature = "async")]
+pub use source::AsyncPacketSource;
+pub use source::{PacketReader, PacketSource, ReaderStats};
 pub use states::Either;
+#[cfg(feature = "alloc")]
+pub use vec::VecPacketReader;
 
 pub mod prelude {
     /*!
@@ -45,16 +125,18 @@ pub mod prelude {
      * ```
      */
 
-    pub use super::{Bitstream, ChannelMapping, Either};
+    pub use super::{Bitstream, ChannelMapping, Decode, Either, Frames, PacketSource};
 }
 
 /// Error values for formatting.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[doc(hidden)]
 #[non_exhaustive]
 pub enum ErrorValues {
     UnexpectedSequenceNumber(u32),
     SequenceNumberMismatch(u32, u32),
+    MissingContinuation(u32),
 }
 
 impl core::fmt::Display for ErrorValues {
@@ -69,12 +151,40 @@ impl core::fmt::Display for ErrorValues {
                 "page sequence numbers are not sequential, previous: {}, current: {}",
                 previous, current,
             )),
+            MissingContinuation(number) => f.write_fmt(format_args!(
+                "page {} does not continue the packet of the previous page",
+                number
+            )),
         }
     }
 }
 
+/**
+ * Layer of the stream where an error comes from.
+ *
+ * Errors of each layer have their own type, e.g. [`OggError`] and [`OpusError`][`opus::OpusError`],
+ * and errors that wrap them tell which layer the error came from with `layer` method. Together
+ * with `is_recoverable` applications can tell e.g. a corrupted page from a packet that could not
+ * be decoded.
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum ErrorLayer {
+    /// Ogg pages or other container of the packets.
+    Container,
+    /// Opus headers and packets within the container.
+    Packet,
+    /// Decoding opus packets.
+    Decoder,
+    /// Reading data from the underlying reader.
+    Io,
+}
+
 /// Error from parsing bitstream.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum BitstreamError {
     /// Error from parsing ogg container.
     OggError(OggError),
@@ -99,6 +209,47 @@ pub enum BitstreamError {
      * See also [`OpusError::NotOpusStream`][`opus::OpusError::NotOpusStream`].
      */
     NotOpusStream,
+    /**
+     * [`Verifier`][`verify::Verifier`] rejected the stream.
+     *
+     * Contains the sequence number of the rejected page or [`None`] if the stream was rejected
+     * as a whole.
+     */
+    VerificationFailed(Option<u32>),
+    /// Stream does not follow RFC7845 in [strict mode][`Bitstream::with_strict`].
+    Violation(conformance::Violation),
+}
+
+impl BitstreamError {
+    /// Returns the layer of the stream where the error comes from.
+    pub fn layer(&self) -> ErrorLayer {
+        use BitstreamError::*;
+        match self {
+            OpusError(_) | InvalidOpusStream(_) | UnsupportedOpusVersion(_) | NotOpusStream => {
+                ErrorLayer::Packet
+            }
+            _ => ErrorLayer::Container,
+        }
+    }
+
+    /**
+     * Returns whether the stream may continue after the page or packet that caused the error.
+     *
+     * Recoverable errors concern only a part of the stream, e.g. a lost page or a rejected page,
+     * and the application may skip it. Other errors mean that the stream must be aborted.
+     */
+    pub fn is_recoverable(&self) -> bool {
+        use BitstreamError::*;
+        match self {
+            OggError(error) => error.is_recoverable(),
+            OpusError(error) => error.is_recoverable(),
+            InvalidOggStream(
+                ErrorValues::SequenceNumberMismatch(..) | ErrorValues::MissingContinuation(..),
+            ) => true,
+            VerificationFailed(Some(_)) => true,
+            _ => false,
+        }
+    }
 }
 
 impl core::fmt::Display for BitstreamError {
@@ -117,6 +268,14 @@ impl core::fmt::Display for BitstreamError {
             }
             UnsupportedStream(error) => f.write_str(error),
             NotOpusStream => f.write_str("this is not an Opus stream"),
+            VerificationFailed(Some(page)) => {
+                f.write_fmt(format_args!("verification failed for page {}", page))
+            }
+            VerificationFailed(None) => f.write_str("verification failed for stream"),
+            Violation(violation) => {
+                f.write_str("stream violates RFC7845: ")?;
+                violation.fmt(f)
+            }
         }
     }
 }
@@ -127,6 +286,7 @@ impl core::error::Error for BitstreamError {
         match self {
             OggError(error) => Some(error),
             OpusError(error) => Some(error),
+            Violation(violation) => Some(violation),
             _ => None,
         }
     }
@@ -159,6 +319,7 @@ pub type Result<'data, T> = core::result::Result<T, BitstreamError>;
 #[derive(Debug)]
 pub struct Bitstream<'data> {
     data: &'data [u8],
+    options: ParseOptions,
 }
 
 impl<'data> Bitstream<'data> {
@@ -166,7 +327,132 @@ impl<'data> Bitstream<'data> {
      * Construct new [`Bitstream`] for constant data.
      */
     pub const fn new(data: &'data [u8]) -> Self {
-        Self { data }
+        Self {
+            data,
+            options: ParseOptions::new(),
+        }
+    }
+
+    /**
+     * Set the maximum size of ogg pages including their headers.
+     *
+     * Pages that claim to be larger in their segment table are rejected with
+     * [`PageTooLargeError`][`OggError::PageTooLargeError`] before their data is read. Defaults to
+     * [`MAX_PAGE_SIZE`], which allows all valid pages.
+     *
+     * ```rust
+     * # use oggopus_embedded::Bitstream;
+     * # let data = include_bytes!("test/mono.opus");
+     * let stream = Bitstream::new(data).with_max_page_size(4_096);
+     * ```
+     */
+    pub const fn with_max_page_size(mut self, max_page_size: usize) -> Self {
+        self.options.max_page_size = max_page_size;
+        self
+    }
+
+    /**
+     * Set the maximum size of packets that are reassembled from pages.
+     *
+     * Packets may continue over several pages and they are copied together into the buffer of
+     * the reader. Packets larger than this are rejected with
+     * [`PacketTooLarge`][`OggError::PacketTooLarge`] and packets larger than the buffer with
+     * [`BufferTooSmallError`][`OggError::BufferTooSmallError`] before they are copied. By default
+     * only the buffer size limits packets. Comment header is skipped and not limited by this.
+     *
+     * ```rust
+     * # use oggopus_embedded::Bitstream;
+     * # let data = include_bytes!("test/mono.opus");
+     * // Single frame opus packets are at most 1275 bytes
+     * let stream = Bitstream::new(data).with_max_packet_size(1_275);
+     * let reader = stream.packet_reader::<4_096>().unwrap();
+     * ```
+     */
+    pub const fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.options.max_packet_size = max_packet_size;
+        self
+    }
+
+    /**
+     * Set whether CRC checksums of pages are checked.
+     *
+     * Pages whose checksum does not match are rejected with recoverable
+     * [`CrcMismatch`][`OggError::CrcMismatch`] error. Checking costs some CPU time on every page
+     * so it may be turned off for data that comes from trusted storage. Defaults to `true`.
+     *
+     * ```rust
+     * # use oggopus_embedded::Bitstream;
+     * # let data = include_bytes!("test/mono.opus");
+     * // Audio in internal flash was checked when it was written
+     * let stream = Bitstream::new(data).with_crc_check(false);
+     * ```
+     */
+    pub const fn with_crc_check(mut self, check_crc: bool) -> Self {
+        self.options.crc = match (check_crc, self.options.crc) {
+            (false, _) => None,
+            (true, None) => Some(&crc::SoftwareCrc),
+            (true, crc) => crc,
+        };
+        self
+    }
+
+    /**
+     * Set [`Crc32Provider`][`crc::Crc32Provider`] for checking CRC checksums of pages.
+     *
+     * This allows calculating checksums with a hardware CRC peripheral, see [`crc`] module.
+     * Checks are turned on if they were off.
+     */
+    pub const fn with_crc_provider(mut self, provider: &'static dyn crc::Crc32Provider) -> Self {
+        self.options.crc = Some(provider);
+        self
+    }
+
+    /**
+     * Set whether streams are checked against the requirements of RFC7845.
+     *
+     * In strict mode [`PacketReader`] checks every page with
+     * [`Checker`][`conformance::Checker`] before its packets are returned and fails with
+     * [`Violation`][`BitstreamError::Violation`] on the first page that breaks a rule, e.g. for
+     * conformance tests of an encoder. Defaults to `false`, which accepts streams that can be
+     * played. The readers of the `embedded-io`, `async` and `std` features, which do not use
+     * `Bitstream`, are switched with their own `with_strict`.
+     *
+     * ```rust
+     * # use oggopus_embedded::{Bitstream, PacketSource};
+     * # let data = include_bytes!("test/mono.opus");
+     * let mut reader = Bitstream::new(data).with_strict(true).packet_reader::<512>().unwrap();
+     * while let Some(packet) = reader.next_packet().unwrap() {}
+     * ```
+     */
+    pub const fn with_strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /**
+     * Set whether common deviations from RFC7845 are tolerated.
+     *
+     * In lenient mode streams from sloppy muxers are read anyway, e.g. streams whose first
+     * granule position is smaller than its samples and streams that end without the end of
+     * stream flag. [`PacketReader`] reports what it tolerated with
+     * [`deviations`][`PacketReader::deviations`]. See [`Deviations`][`conformance::Deviations`]
+     * for the list. Violations are still reported if strict mode is turned on too. Defaults to
+     * `false`. The readers of the `embedded-io`, `async` and `std` features are switched with
+     * their own `with_lenient` and report with their own `deviations`.
+     *
+     * ```rust
+     * # use oggopus_embedded::{Bitstream, PacketSource};
+     * # let data = include_bytes!("test/mono.opus");
+     * let mut reader = Bitstream::new(data).with_lenient(true).packet_reader::<512>().unwrap();
+     * while let Some(packet) = reader.next_packet().unwrap() {}
+     * if !reader.deviations().is_empty() {
+     *     println!("Stream is broken: {:?}", reader.deviations());
+     * }
+     * ```
+     */
+    pub const fn with_lenient(mut self, lenient: bool) -> Self {
+        self.options.lenient = lenient;
+        self
     }
 
     /**
@@ -177,8 +463,216 @@ impl<'data> Bitstream<'data> {
     pub fn reader<'bs>(&'bs self) -> BitstreamReader<'bs, 'data, states::Beginning> {
         BitstreamReader::<'bs, 'data, states::Beginning>::new(self)
     }
+
+    /**
+     * Iterate over raw ogg pages in [`Bitstream`].
+     *
+     * Pages are returned as they are in the data, which is useful for splitting or filtering
+     * streams without decoding them. Pages larger than the
+     * [maximum page size][`Bitstream::with_max_page_size`] are rejected.
+     *
+     * ```rust
+     * # use oggopus_embedded::Bitstream;
+     * # let data = include_bytes!("test/mono.opus");
+     * // Keep headers and pages that end within the first 100 ms
+     * let stream = Bitstream::new(data);
+     * let mut output = Vec::new();
+     * for page in stream.pages() {
+     *     let page = page.unwrap();
+     *     if page.granule_position() < 312 + 4_800 {
+     *         output.extend_from_slice(page.as_bytes());
+     *     }
+     * }
+     * ```
+     */
+    pub fn pages(&self) -> Pages<'data> {
+        Pages::with_options(self.data, self.options)
+    }
+
+    /**
+     * Returns the comment header of the first stream in [`Bitstream`].
+     *
+     * The comments are borrowed from the data without copying, so the comment header must fit
+     * on one page. Otherwise [`UnsupportedStream`][`BitstreamError::UnsupportedStream`] is
+     * returned.
+     */
+    pub fn tags(&self) -> Result<'data, tags::OpusTags<'data>> {
+        use BitstreamError::*;
+        let mut serial_number = None;
+        let mut pages = self.pages().filter(|page| {
+            page.as_ref().map_or(true, |page| {
+                *serial_number.get_or_insert(page.bitstream_serial_number())
+                    == page.bitstream_serial_number()
+            })
+        });
+        pages.next().ok_or(InvalidOpusStream("missing header"))??;
+        let page = pages
+            .next()
+            .ok_or(InvalidOpusStream("missing comment header"))??;
+        let segments = page.segment_table();
+        let length = segments
+            .iter()
+            .position(|size| *size < 255)
+            .ok_or(UnsupportedStream("comment header does not fit on one page"))?;
+        let size: usize = segments[..=length]
+            .iter()
+            .map(|size| usize::from(*size))
+            .sum();
+        Ok(tags::OpusTags::parse(&page.data()[..size])?)
+    }
+
+    /**
+     * Scan the first stream in [`Bitstream`] for [`StreamInfo`].
+     *
+     * This reads through all pages of the stream without decoding, so it is not free for long
+     * streams.
+     */
+    pub fn stream_info(&self) -> Result<'data, StreamInfo> {
+        StreamInfo::scan(self)
+    }
+
+    /**
+     * Quickly probe the first stream in [`Bitstream`] for its header and length.
+     *
+     * Unlike [`stream_info`][`Bitstream::stream_info`], this looks up the last page from the end
+     * of data instead of reading every page. See also [`scan_library`].
+     */
+    pub fn probe(&self) -> Result<'data, ProbeInfo> {
+        StreamInfo::probe(self).map(|(stream, header)| ProbeInfo { header, stream })
+    }
+
+    /// Returns [`Summary`] of the first stream in [`Bitstream`].
+    pub fn info(&self) -> Result<'data, Summary<'data>> {
+        Summary::read(self)
+    }
+
+    /**
+     * Iterate over logical streams that begin at the beginning of [`Bitstream`].
+     *
+     * Multiplexed streams, e.g. opus with subtitles or Skeleton, begin with the first page of
+     * each of their logical streams. Opus streams have their header parsed so that one of them
+     * can be selected with [`select_stream`][`Bitstream::select_stream`].
+     *
+     * ```rust
+     * # use oggopus_embedded::Bitstream;
+     * # let data = include_bytes!("test/mono.opus");
+     * for stream in Bitstream::new(data).streams() {
+     *     let stream = stream.unwrap();
+     *     if let Some(header) = stream.header {
+     *         println!("Opus stream {:08x} with {} channels", stream.bitstream_serial_number,
+     *             header.channels.get_channel_count());
+     *     }
+     * }
+     * ```
+     */
+    pub fn streams(&self) -> LogicalStreams<'data> {
+        LogicalStreams::new(self)
+    }
+
+    /**
+     * Create [`SelectedReader`] to read opus packets of one logical stream in [`Bitstream`].
+     *
+     * Pages of other multiplexed streams are skipped. The stream is identified by its bitstream
+     * serial number, see [`streams`][`Bitstream::streams`].
+     */
+    pub fn select_stream<const BUFFER_SIZE: usize>(
+        &self,
+        bitstream_serial_number: u32,
+    ) -> Result<'data, SelectedReader<'data, BUFFER_SIZE>> {
+        SelectedReader::new(self, bitstream_serial_number)
+    }
+
+    /**
+     * Create [`SelectedReader`] for the first opus stream in [`Bitstream`].
+     *
+     * Ogg Skeleton and other streams that are not opus are skipped, so this finds the audio in
+     * files from tools that add them. Returns [`NotOpusStream`][`BitstreamError::NotOpusStream`]
+     * if no stream is opus.
+     *
+     * ```rust
+     * # use oggopus_embedded::{Bitstream, PacketSource};
+     * # let data = include_bytes!("test/mono.opus");
+     * let mut reader = Bitstream::new(data).select_opus_stream::<1_024>().unwrap();
+     * while let Some(packet) = reader.next_packet().unwrap() {
+     *     println!("Got {} bytes of opus data", packet.data.len());
+     * }
+     * ```
+     */
+    pub fn select_opus_stream<const BUFFER_SIZE: usize>(
+        &self,
+    ) -> Result<'data, SelectedReader<'data, BUFFER_SIZE>> {
+        let stream = self
+            .streams()
+            .find(|stream| {
+                stream
+                    .as_ref()
+                    .map_or(true, |stream| stream.header.is_some())
+            })
+            .ok_or(BitstreamError::NotOpusStream)??;
+        SelectedReader::new(self, stream.bitstream_serial_number)
+    }
+
+    /**
+     * Create [`PacketReader`] to read opus packets from [`Bitstream`].
+     *
+     * Reads the headers of the first stream and returns [`PacketReader`] that is positioned at
+     * the first opus packet.
+     */
+    pub fn packet_reader<const BUFFER_SIZE: usize>(
+        &self,
+    ) -> Result<'data, PacketReader<'data, BUFFER_SIZE>> {
+        PacketReader::new(self)
+    }
+
+    /**
+     * Create [`VecPacketReader`] to read opus packets of any size from [`Bitstream`].
+     *
+     * Reads the headers of the first stream like [`packet_reader`][`Bitstream::packet_reader`]
+     * but packets are reassembled in heap memory.
+     */
+    #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+    #[cfg(feature = "alloc")]
+    pub fn vec_packet_reader(&self) -> Result<'data, VecPacketReader<'data>> {
+        VecPacketReader::new(self)
+    }
+
+    /**
+     * Decode the first stream with decoder into interleaved samples.
+     *
+     * Pre-skip, end trimming and output gain are applied. Packets may be of any size. This is
+     * meant for tests and tools that want the whole stream at once.
+     *
+     * ```rust
+     * # use oggopus_embedded::{Bitstream, Decode};
+     * # struct Decoder;
+     * # impl Decode for Decoder {
+     * #     type Error = ();
+     * #     fn sample_rate(&self) -> u32 { 48_000 }
+     * #     fn channels(&self) -> u8 { 1 }
+     * #     fn decode<'output>(&mut self, _p: &[u8], output: &'output mut [i16]) -> Result<&'output [i16], ()> {
+     * #         Ok(&output[..960])
+     * #     }
+     * # }
+     * # let data = include_bytes!("test/mono.opus");
+     * let samples = Bitstream::new(data).decode_all(Decoder).unwrap();
+     * assert_eq!(samples.len(), 7 * 960 - 312);
+     * ```
+     */
+    #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+    #[cfg(feature = "alloc")]
+    pub fn decode_all<D: Decode>(
+        &self,
+        decoder: D,
+    ) -> core::result::Result<alloc::vec::Vec<i16>, FramesError<BitstreamError, D::Error>> {
+        let reader = self.vec_packet_reader().map_err(FramesError::SourceError)?;
+        Frames::<_, _, DECODE_ALL_FRAME_SIZE>::new(reader, decoder).decode_all()
+    }
 }
 
+/// Frame buffer size used by [`Bitstream::decode_all`], 120 ms of stereo audio at 48 kHz.
+#[cfg(feature = "alloc")]
+const DECODE_ALL_FRAME_SIZE: usize = 11_520;
+
 pub mod states {
     //! [`BitstreamReader`][`super::BitstreamReader`] states.
 
@@ -216,6 +710,7 @@ pub mod states {
 
     /// Either state may be returned.
     #[derive(Debug, PartialEq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub enum Either<A, B> {
         /// Parsing can continue.
         Continued(A),
@@ -224,6 +719,7 @@ pub mod states {
     }
 }
 
+use container::ParseOptions;
 use states::{Beginning, EndOfStream, InStream, ReaderState};
 
 /// Header with reader for the stream or stream ended.
@@ -243,6 +739,7 @@ pub type EitherPacketsOrEnded<'bs, 'data, const BUFFER_SIZE: usize> = (
 pub struct BitstreamReader<'bs, 'data: 'bs, S: ReaderState> {
     bitstream: core::marker::PhantomData<&'bs Bitstream<'data>>,
     remaining: &'data [u8],
+    options: ParseOptions,
     marker: S,
 }
 
@@ -261,6 +758,7 @@ impl<S: ReaderState> BitstreamReader<'_, '_, S> {
         BitstreamReader {
             bitstream: core::marker::PhantomData::<_>,
             remaining: bitstream.data,
+            options: bitstream.options,
             marker: Beginning,
         }
     }
@@ -291,9 +789,21 @@ impl<'bs, 'data> BitstreamReader<'bs, 'data, Beginning> {
         let BitstreamReader {
             bitstream,
             remaining,
+            options,
             ..
         } = self;
-        let (remaining, mut packets) = Packets::<30>::parse(remaining)?;
+        let (remaining, mut packets) = match Packets::<30>::parse(remaining, &options) {
+            // Large first packet of another codec, e.g. Ogg Skeleton
+            Err(
+                container::OggError::BufferTooSmallError(..)
+                | container::OggError::PacketTooLarge(_),
+            ) if !RawPage::parse(remaining)
+                .is_ok_and(|page| page.data().starts_with(b"OpusHead")) =>
+            {
+                return Err(NotOpusStream);
+            }
+            result => result?,
+        };
         let bitstream_serial_number = packets.bitstream_serial_number();
         let page_sequence_number = packets.current_page_sequence_number();
         if page_sequence_number != 0 {
@@ -309,7 +819,7 @@ impl<'bs, 'data> BitstreamReader<'bs, 'data, Beginning> {
             if packets.next().is_some() {
                 return Err(InvalidOpusStream("unexpected segment after header"));
             }
-            let (remaining, last_page) = container::Page::skip(remaining)?;
+            let (remaining, last_page) = container::Page::skip(remaining, &options)?;
             if last_page.bitstream_serial_number() != bitstream_serial_number {
                 return Err(UnsupportedStream(
                     "bitstream serial number changed unexpectedly",
@@ -319,6 +829,7 @@ impl<'bs, 'data> BitstreamReader<'bs, 'data, Beginning> {
                 Either::Continued(BitstreamReader {
                     bitstream,
                     remaining,
+                    options,
                     marker: InStream {
                         bitstream_serial_number,
                         page_sequence_number: last_page.page_sequence_number(),
@@ -372,13 +883,15 @@ impl<'bs, 'data> BitstreamReader<'bs, 'data, InStream> {
         &self,
     ) -> Result<'data, EitherPacketsOrEnded<'bs, 'data, BUFFER_SIZE>> {
         use BitstreamError::*;
-        let (remaining, packets) = Packets::parse(self.remaining)?;
+        let (remaining, packets) = Packets::parse(self.remaining, &self.options)?;
         if self.marker.bitstream_serial_number != packets.bitstream_serial_number() {
             return Err(UnsupportedStream(
                 "bitstream serial number changed unexpectedly",
             ));
         }
-        if packets.current_page_sequence_number() != self.marker.page_sequence_number + 1 {
+        if packets.current_page_sequence_number()
+            != self.marker.page_sequence_number.wrapping_add(1)
+        {
             return Err(InvalidOggStream(ErrorValues::SequenceNumberMismatch(
                 self.marker.page_sequence_number,
                 packets.current_page_sequence_number(),
@@ -389,6 +902,7 @@ impl<'bs, 'data> BitstreamReader<'bs, 'data, InStream> {
                 Either::Continued(BitstreamReader {
                     bitstream: self.bitstream,
                     remaining,
+                    options: self.options,
                     marker: InStream {
                         bitstream_serial_number: self.marker.bitstream_serial_number,
                         page_sequence_number: packets.last_page_sequence_number(),
@@ -401,6 +915,7 @@ impl<'bs, 'data> BitstreamReader<'bs, 'data, InStream> {
                 Either::Ended(BitstreamReader {
                     bitstream: self.bitstream,
                     remaining,
+                    options: self.options,
                     marker: EndOfStream,
                 }),
                 packets,
@@ -454,6 +969,7 @@ impl<'bs, 'data> BitstreamReader<'bs, 'data, EndOfStream> {
             Some(BitstreamReader {
                 bitstream: core::marker::PhantomData::<_>,
                 remaining: self.remaining,
+                options: self.options,
                 marker: Beginning,
             })
         } else {
@@ -466,6 +982,40 @@ impl<'bs, 'data> BitstreamReader<'bs, 'data, EndOfStream> {
 mod test {
     use super::*;
     use core::error::Error;
+    use core::time::Duration;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    std::thread_local! {
+        /// The number of allocations of the current thread, or [`None`] if they are not counted.
+        static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    /// Allocator that counts allocations of threads that must not allocate.
+    struct GuardAllocator;
+
+    unsafe impl GlobalAlloc for GuardAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get().map(|count| count + 1)));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: GuardAllocator = GuardAllocator;
+
+    /// Run `f` and fail if it allocates with the global allocator.
+    fn without_alloc<T>(f: impl FnOnce() -> T) -> T {
+        ALLOCATIONS.with(|count| count.set(Some(0)));
+        let result = f();
+        let allocations = ALLOCATIONS.with(|count| count.take());
+        assert_eq!(allocations, Some(0), "Allocated memory");
+        result
+    }
 
     #[test]
     fn parse_mono() {
@@ -491,6 +1041,68 @@ mod test {
         }
     }
 
+    #[test]
+    fn error_layers() {
+        let error = BitstreamError::OggError(OggError::CrcMismatch(2));
+        assert_eq!(error.layer(), ErrorLayer::Container);
+        let error = Bitstream::new(include_bytes!("test/vorbis.ogg"))
+            .packet_reader::<512>()
+            .unwrap_err();
+        assert_eq!(error.layer(), ErrorLayer::Packet);
+        let error = FramesError::<BitstreamError, ()>::DecoderError(());
+        assert_eq!(error.layer(), ErrorLayer::Decoder);
+        let error = opus::OpusHeader::parse(b"OpusHead").unwrap_err();
+        let error = FramesError::<_, ()>::SourceError(BitstreamError::OpusError(error));
+        assert_eq!(error.layer(), ErrorLayer::Packet);
+    }
+
+    #[test]
+    fn check_crc() {
+        let mut data = Vec::from(include_bytes!("test/mono.opus"));
+        data[136 + 40] ^= 1;
+        let bitstream = Bitstream::new(&data);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        let result = reader.next_packet();
+        assert_eq!(
+            result,
+            Err(BitstreamError::OggError(OggError::CrcMismatch(2)))
+        );
+        assert!(result.unwrap_err().is_recoverable());
+        let bitstream = Bitstream::new(&data).with_crc_check(false);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        let mut count = 0;
+        while reader.next_packet().unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 7);
+    }
+
+    #[test]
+    fn limit_page_size() {
+        const DATA: &[u8] = include_bytes!("test/mono.opus");
+        let bitstream = Bitstream::new(DATA).with_max_page_size(64);
+        let result = bitstream.reader().read_header();
+        assert_eq!(
+            result,
+            Err(BitstreamError::OggError(OggError::PageTooLargeError(
+                64, 89
+            )))
+        );
+        let bitstream = Bitstream::new(DATA).with_max_page_size(128);
+        let (either, _header) = bitstream.reader().read_header().unwrap();
+        let Either::Continued(reader) = either else {
+            panic!("Unexpected end of stream in test");
+        };
+        let result = reader.next_packets::<512>();
+        assert!(matches!(
+            result,
+            Err(BitstreamError::OggError(OggError::PageTooLargeError(
+                128,
+                _
+            )))
+        ));
+    }
+
     #[test]
     fn parse_stereo() {
         const DATA: &[u8] = include_bytes!("test/stereo.opus");
@@ -512,6 +1124,7 @@ mod test {
         assert_eq!(result, Err(BitstreamError::NotOpusStream));
         let error = result.unwrap_err();
         assert!(error.source().is_none());
+        assert!(!error.is_recoverable());
         assert_eq!(error.to_string(), "this is not an Opus stream");
     }
 
@@ -534,7 +1147,7 @@ mod test {
     fn parse_unexpected_sequence_number() {
         let mut data = Vec::from(include_bytes!("test/mono.opus"));
         data[0x12] = 1;
-        let bitstream = Bitstream::new(&data);
+        let bitstream = Bitstream::new(&data).with_crc_check(false);
         let reader = bitstream.reader();
         let result = reader.read_header();
         assert_eq!(
@@ -544,6 +1157,7 @@ mod test {
             ))
         );
         let error = result.unwrap_err();
+        assert!(!error.is_recoverable());
         assert_eq!(
             error.to_string(),
             "invalid ogg stream: unexpected page sequence number in header: 1"
@@ -565,11 +1179,53 @@ mod test {
         assert_eq!(error.to_string(), "unsupported ogg version: 2");
     }
 
+    #[test]
+    fn read_tags() {
+        let data = include_bytes!("test/mono.opus");
+        let tags = Bitstream::new(data).tags().unwrap();
+        assert_eq!(tags.vendor().as_str(), Some("Lavf61.7.100"));
+        let encoder = tags.get("encoder").unwrap();
+        assert_eq!(encoder.as_str(), Some("Lavc61.19.100 libopus"));
+        assert_eq!(
+            Bitstream::new(&data[..47]).tags(),
+            Err(BitstreamError::InvalidOpusStream("missing comment header"))
+        );
+    }
+
+    #[test]
+    fn no_alloc() {
+        use crate::frames::test::CountingDecoder;
+        let data = include_bytes!("test/offset.opus");
+        let samples = without_alloc(|| {
+            let bitstream = Bitstream::new(data).with_strict(true);
+            let summary = bitstream.info().unwrap();
+            assert_eq!(summary.vendor.unwrap().as_str(), Some("Lavf61.7.100"));
+            bitstream.probe().unwrap();
+            let reader = bitstream.packet_reader::<512>().unwrap();
+            reader.seek_index::<4>(Duration::from_millis(20)).unwrap();
+            let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+            let mut samples = 0;
+            while let Some(frame) = frames.next().unwrap() {
+                samples += frame.len();
+            }
+            frames.seek_to(Duration::from_millis(40)).unwrap();
+            assert!(frames.next().unwrap().is_some());
+            let mut pusher = OggPusher::<512, 512>::new();
+            let mut remaining = &data[..];
+            while !remaining.is_empty() {
+                remaining = &remaining[pusher.push(remaining).unwrap()..];
+                while pusher.next().unwrap().is_some() {}
+            }
+            samples
+        });
+        assert_eq!(samples, 7 * 160 - 312 / 6);
+    }
+
     #[test]
     fn parse_unsupported_opus_version() {
         let mut data = Vec::from(include_bytes!("test/mono.opus"));
         data[0x24] = 0x10;
-        let bitstream = Bitstream::new(&data);
+        let bitstream = Bitstream::new(&data).with_crc_check(false);
         let reader = bitstream.reader();
         let result = reader.read_header();
         assert_eq!(result, Err(BitstreamError::UnsupportedOpusVersion(16)));
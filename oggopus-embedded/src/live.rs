@@ -0,0 +1,448 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Live ogg opus streams, e.g. Icecast web radio.
+ *
+ * Live streams have no known length, the listener may join in the middle of a page, and the
+ * server begins a new link of a chained stream on every track change. [`LiveStream`] handles
+ * these on top of [`OggPusher`]: pages are skipped until the first page that begins a stream,
+ * each link is reported with its headers, and end-of-stream pages do not end the stream.
+ *
+ * ```rust
+ * # use oggopus_embedded::live::{LiveEvent, LiveStream};
+ * # let data = include_bytes!("test/stereo.opus");
+ * let mut stream = LiveStream::<4_096, 512>::new();
+ * for mut chunk in data.chunks(64) {
+ *     while !chunk.is_empty() {
+ *         let consumed = stream.push(chunk).unwrap();
+ *         chunk = &chunk[consumed..];
+ *         while let Some(event) = stream.next().unwrap() {
+ *             match event {
+ *                 LiveEvent::Header(header) => {
+ *                     println!("{} channels", header.channels.get_channel_count())
+ *                 }
+ *                 LiveEvent::Tags(tags) => {
+ *                     if let Some(title) = tags.get("TITLE") {
+ *                         println!("Now playing {}", title.display_lossy());
+ *                     }
+ *                 }
+ *                 LiveEvent::Packet(packet) => println!("Got {} bytes", packet.data.len()),
+ *             }
+ *         }
+ *     }
+ * }
+ * ```
+ */
+
+use crate::{opus::OpusHeader, tags::OpusTags, BitstreamError, OggError, OggPusher, Packet};
+
+/**
+ * Event of [`LiveStream`].
+ *
+ * Every link begins with [`Header`][`LiveEvent::Header`] and [`Tags`][`LiveEvent::Tags`],
+ * followed by its audio packets.
+ */
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LiveEvent<'buffer> {
+    /**
+     * A new link begins.
+     *
+     * The decoder must be reset, or constructed again if the channel count or the sample rate
+     * changed, before decoding the packets of the link.
+     */
+    Header(&'buffer OpusHeader),
+    /**
+     * Comment header of the link, i.e. what is playing now.
+     *
+     * This is not given if the comment header did not fit to the packet buffer or could not be
+     * parsed, the error is returned instead.
+     */
+    Tags(OpusTags<'buffer>),
+    /// Audio packet of the link.
+    Packet(Packet<'buffer>),
+}
+
+/**
+ * Statistics of [`LiveStream`].
+ *
+ * Counters wrap around on overflow.
+ */
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LiveStats {
+    /// The number of links that have begun.
+    pub links: u32,
+    /// The number of pages skipped before a link began or from other logical streams.
+    pub pages_skipped: u32,
+}
+
+/// What the next packet of the link is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Expect {
+    /// Waiting for a page that begins a stream.
+    Sync,
+    Header,
+    Tags,
+    /// The first audio page, which may not follow the header pages directly.
+    FirstAudio,
+    Audio,
+}
+
+/**
+ * Push-based parser for live ogg opus streams.
+ *
+ * Data is given with [`push`][`LiveStream::push`] and [`LiveEvent`]s are taken out with
+ * [`next`][`LiveStream::next`], like with [`OggPusher`]. `PAGE_SIZE` and `PACKET_SIZE` are the
+ * same as for [`OggPusher`] but `PACKET_SIZE` should also fit the comment headers to get
+ * [`Tags`][`LiveEvent::Tags`], which may be much larger than audio packets.
+ *
+ * Nothing is returned until a page that begins a stream is found, errors are not reported
+ * either, so the stream can be joined at any point. Every later beginning of stream begins a new
+ * link, whether or not the previous link ended. Servers usually send the header pages of the
+ * current link to new listeners followed by the live audio pages, so the gap in page sequence
+ * numbers on the first audio page is not reported. Other missing pages are reported with
+ * [`InvalidOggStream`][`BitstreamError::InvalidOggStream`], and parsing can continue after all
+ * errors.
+ *
+ * There is no end to a live stream and thus no duration. Granule positions of packets are passed
+ * as they are, but they are relative to the start of the link on the server, not to when the
+ * listener joined. Pages of other logical streams, e.g. of grouped video streams, are skipped.
+ */
+#[derive(Debug)]
+pub struct LiveStream<const PAGE_SIZE: usize, const PACKET_SIZE: usize> {
+    pusher: OggPusher<PAGE_SIZE, PACKET_SIZE>,
+    expect: Expect,
+    bitstream_serial_number: Option<u32>,
+    header: Option<OpusHeader>,
+    /// Whether the packets of the buffered page are skipped.
+    skipping: bool,
+    /// Whether a gap before the buffered page is expected.
+    gap: bool,
+    /// Whether the buffered page was completed before a link began, so lost sync is expected.
+    joining: bool,
+    stats: LiveStats,
+}
+
+impl<const PAGE_SIZE: usize, const PACKET_SIZE: usize> Default
+    for LiveStream<PAGE_SIZE, PACKET_SIZE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGE_SIZE: usize, const PACKET_SIZE: usize> LiveStream<PAGE_SIZE, PACKET_SIZE> {
+    /// Construct [`LiveStream`] that waits for a link to begin.
+    pub const fn new() -> Self {
+        Self {
+            pusher: OggPusher::new(),
+            expect: Expect::Sync,
+            bitstream_serial_number: None,
+            header: None,
+            skipping: false,
+            gap: false,
+            joining: false,
+            stats: LiveStats {
+                links: 0,
+                pages_skipped: 0,
+            },
+        }
+    }
+
+    /// Set whether CRC checksums of pages are checked, see [`OggPusher::with_crc_check`].
+    pub const fn with_crc_check(mut self, check_crc: bool) -> Self {
+        self.pusher = self.pusher.with_crc_check(check_crc);
+        self
+    }
+
+    /// Returns the opus header of the current link, or [`None`] if no link has begun yet.
+    pub fn header(&self) -> Option<&OpusHeader> {
+        self.header.as_ref()
+    }
+
+    /// Returns [`LiveStats`] of the stream so far.
+    pub fn stats(&self) -> LiveStats {
+        self.stats
+    }
+
+    /**
+     * Feed more data to the parser.
+     *
+     * Returns the number of bytes consumed which is less than given if a page was completed.
+     * Events of that page must be taken with [`next`][`LiveStream::next`] before more data is
+     * consumed. Errors are as for [`OggPusher::push`], except that they are not returned before
+     * a link begins.
+     */
+    pub fn push(&mut self, data: &[u8]) -> Result<usize, BitstreamError> {
+        let buffered = self.pusher.current_page().is_some();
+        let mut consumed = 0;
+        while let Err(error) = self.pusher.push_from(data, &mut consumed) {
+            if self.header.is_some() || self.expect != Expect::Sync {
+                return Err(error.into());
+            }
+        }
+        if !buffered {
+            if let Some(page) = self.pusher.current_page() {
+                self.joining = self.header.is_none() && self.expect == Expect::Sync;
+                let (skipping, gap) =
+                    self.page(page.begin_of_stream(), page.bitstream_serial_number());
+                self.skipping = skipping;
+                self.gap = gap;
+                if skipping {
+                    self.stats.pages_skipped = self.stats.pages_skipped.wrapping_add(1);
+                }
+            }
+        }
+        Ok(consumed)
+    }
+
+    /// Check a page as it is completed. Returns whether it is skipped and whether a gap is ok.
+    fn page(&mut self, begin_of_stream: bool, serial_number: u32) -> (bool, bool) {
+        if begin_of_stream {
+            if matches!(self.expect, Expect::Header | Expect::Tags) {
+                // Another stream of grouped streams
+                return (true, false);
+            }
+            self.expect = Expect::Header;
+            self.bitstream_serial_number = Some(serial_number);
+            self.stats.links = self.stats.links.wrapping_add(1);
+            return (false, true);
+        }
+        if self.expect == Expect::Sync || self.bitstream_serial_number != Some(serial_number) {
+            return (true, false);
+        }
+        if self.expect == Expect::FirstAudio {
+            self.expect = Expect::Audio;
+            return (false, true);
+        }
+        (false, false)
+    }
+
+    /**
+     * Returns the next event, or [`None`] if more data must be pushed first.
+     *
+     * Errors of the header packets are returned after which the stream waits for the next link,
+     * and errors of the comment header are returned after which the audio packets follow. See
+     * [`OggPusher::next`] for the other errors.
+     */
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<LiveEvent<'_>>, BitstreamError> {
+        loop {
+            match self.pusher.step() {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(_) if self.skipping || self.header.is_none() && self.expect == Expect::Sync => {
+                    continue
+                }
+                Err(OggError::InvalidStream(_)) if self.gap => continue,
+                Err(OggError::LostSync(_)) if self.joining => continue,
+                Err(error) => {
+                    if self.expect == Expect::Tags {
+                        self.expect = Expect::FirstAudio;
+                    }
+                    return Err(error.into());
+                }
+            }
+            if !self.skipping && self.expect != Expect::Sync {
+                break;
+            }
+        }
+        let data = self.pusher.packet().data;
+        match self.expect {
+            Expect::Header => {
+                self.expect = Expect::Sync;
+                self.header = None;
+                let header = OpusHeader::parse(data)?;
+                if header.version > 15 {
+                    return Err(BitstreamError::UnsupportedOpusVersion(header.version));
+                }
+                self.expect = Expect::Tags;
+                Ok(Some(LiveEvent::Header(self.header.insert(header))))
+            }
+            Expect::Tags => {
+                self.expect = Expect::FirstAudio;
+                Ok(Some(LiveEvent::Tags(OpusTags::parse(data)?)))
+            }
+            _ => Ok(Some(LiveEvent::Packet(self.pusher.packet()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ErrorValues;
+
+    const MONO: &[u8] = include_bytes!("test/mono.opus");
+    const STEREO: &[u8] = include_bytes!("test/stereo.opus");
+
+    /// What [`LiveEvent`] was pushed.
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Header(u8),
+        Tags(Vec<u8>),
+        Packet(usize),
+        Error(BitstreamError),
+    }
+
+    /// Push data in chunks and collect events.
+    fn collect(stream: &mut LiveStream<4_096, 512>, data: &[u8], chunk_size: usize) -> Vec<Event> {
+        let mut events = Vec::new();
+        for mut chunk in data.chunks(chunk_size) {
+            while !chunk.is_empty() {
+                match stream.push(chunk) {
+                    Ok(consumed) => chunk = &chunk[consumed..],
+                    Err(error) => {
+                        events.push(Event::Error(error));
+                        break;
+                    }
+                }
+                loop {
+                    events.push(match stream.next() {
+                        Ok(Some(LiveEvent::Header(header))) => {
+                            Event::Header(header.channels.get_channel_count())
+                        }
+                        Ok(Some(LiveEvent::Tags(tags))) => {
+                            Event::Tags(tags.vendor().as_bytes().to_vec())
+                        }
+                        Ok(Some(LiveEvent::Packet(packet))) => Event::Packet(packet.data.len()),
+                        Ok(None) => break,
+                        Err(error) => Event::Error(error),
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    /// Returns the number of audio packets in events.
+    fn packets(events: &[Event]) -> usize {
+        events
+            .iter()
+            .filter(|event| matches!(event, Event::Packet(_)))
+            .count()
+    }
+
+    #[test]
+    fn whole_stream() {
+        let mut stream = LiveStream::new();
+        assert_eq!(stream.header(), None);
+        let events = collect(&mut stream, STEREO, 100);
+        assert_eq!(events[0], Event::Header(2));
+        assert!(matches!(&events[1], Event::Tags(vendor) if vendor.starts_with(b"Lavf")));
+        assert_eq!(packets(&events), events.len() - 2);
+        assert!(packets(&events) > 0);
+        assert_eq!(
+            stream
+                .header()
+                .map(|header| header.channels.get_channel_count()),
+            Some(2)
+        );
+        assert_eq!(
+            stream.stats(),
+            LiveStats {
+                links: 1,
+                pages_skipped: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn join_mid_stream() {
+        let data = [&MONO[100..], STEREO].concat();
+        let mut stream = LiveStream::new();
+        let events = collect(&mut stream, &data, 33);
+        assert_eq!(events[0], Event::Header(2));
+        assert_eq!(packets(&events), events.len() - 2);
+        assert_eq!(stream.stats().pages_skipped, 1);
+    }
+
+    #[test]
+    fn chain_links() {
+        let data = [MONO, STEREO, MONO].concat();
+        let mut stream = LiveStream::new();
+        let events = collect(&mut stream, &data, 64);
+        let headers: Vec<_> = events
+            .iter()
+            .filter(|event| matches!(event, Event::Header(_)))
+            .collect();
+        assert_eq!(
+            headers,
+            [&Event::Header(1), &Event::Header(2), &Event::Header(1)]
+        );
+        assert!(!events.iter().any(|event| matches!(event, Event::Error(_))));
+        assert_eq!(stream.stats().links, 3);
+        // Every header is followed by tags
+        for (index, event) in events.iter().enumerate() {
+            if let Event::Header(_) = event {
+                assert!(matches!(events[index + 1], Event::Tags(_)));
+            }
+        }
+    }
+
+    #[test]
+    fn gap_after_headers() {
+        // Server sends the stored header pages followed by live audio pages
+        let mut audio = STEREO[136..].to_vec();
+        audio[18..22].copy_from_slice(&1_000u32.to_le_bytes());
+        let data = [&STEREO[..136], &audio].concat();
+        let mut stream = LiveStream::new().with_crc_check(false);
+        let events = collect(&mut stream, &data, 50);
+        assert!(!events.iter().any(|event| matches!(event, Event::Error(_))));
+        assert!(packets(&events) > 0);
+        // Later gaps are reported
+        let mut stream = LiveStream::new().with_crc_check(false);
+        let page = &STEREO[136..259];
+        let mut lost = page.to_vec();
+        lost[18..22].copy_from_slice(&9u32.to_le_bytes());
+        let data = [STEREO, &lost].concat();
+        let events = collect(&mut stream, &data, 50);
+        assert!(
+            events.contains(&Event::Error(BitstreamError::InvalidOggStream(
+                ErrorValues::SequenceNumberMismatch(2, 9)
+            )))
+        );
+    }
+
+    #[test]
+    fn not_opus_stream() {
+        let data = [include_bytes!("test/vorbis.ogg"), STEREO].concat();
+        let mut stream = LiveStream::new();
+        let events = collect(&mut stream, &data, 64);
+        assert!(matches!(
+            events[0],
+            Event::Error(BitstreamError::OpusError(_) | BitstreamError::NotOpusStream)
+        ));
+        let index = events
+            .iter()
+            .position(|event| *event == Event::Header(2))
+            .unwrap();
+        assert!(events[..index]
+            .iter()
+            .all(|event| matches!(event, Event::Error(_))));
+        assert!(packets(&events) > 0);
+    }
+
+    #[test]
+    fn errors_before_sync() {
+        // Capture pattern with unsupported version, and invalid checksum
+        let mut garbage = b"OggS\x01OggS".to_vec();
+        garbage.extend([0; 23]);
+        let data = [&garbage, STEREO, &garbage].concat();
+        let mut stream = LiveStream::new();
+        let events = collect(&mut stream, &data, 64);
+        assert_eq!(events[0], Event::Header(2));
+        assert_eq!(
+            packets(&events),
+            packets(&collect(&mut LiveStream::new(), STEREO, 64))
+        );
+        // Errors are reported after the link has begun
+        assert_eq!(
+            events.last(),
+            Some(&Event::Error(OggError::UnsupportedVersion(1).into()))
+        );
+    }
+}
@@ -0,0 +1,410 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Mixing of decoded audio.
+
+use super::{Decode, Frames, FramesError, PacketSource};
+
+/// Quarter of sine wave in Q15 format with 64 steps.
+const QUARTER_SINE: [i32; 65] = [
+    0, 804, 1608, 2410, 3212, 4011, 4808, 5602, 6393, 7179, 7962, 8739, 9512, 10278, 11039, 11793,
+    12539, 13279, 14010, 14732, 15446, 16151, 16846, 17530, 18204, 18868, 19519, 20159, 20787,
+    21403, 22005, 22594, 23170, 23731, 24279, 24811, 25329, 25832, 26319, 26790, 27245, 27683,
+    28105, 28510, 28898, 29268, 29621, 29956, 30273, 30571, 30852, 31113, 31356, 31580, 31785,
+    31971, 32137, 32285, 32412, 32521, 32609, 32678, 32728, 32757, 32767,
+];
+
+/// Position in quarter of sine wave that corresponds to 90 degrees.
+const QUARTER: u32 = 1 << 16;
+
+/// Returns sine in Q15 format for angle from 0 to [`QUARTER`].
+fn sine(angle: u32) -> i32 {
+    let index = (angle >> 10) as usize;
+    let fraction = (angle & 0x3FF) as i32;
+    match QUARTER_SINE.get(index + 1) {
+        Some(next) => {
+            let current = QUARTER_SINE[index];
+            current + (((next - current) * fraction) >> 10)
+        }
+        None => QUARTER_SINE[index],
+    }
+}
+
+/// Error from crossfading.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum CrossfadeError<F, T> {
+    /// Error from the stream that fades out.
+    FromError(F),
+    /// Error from the stream that fades in.
+    ToError(T),
+    /// The streams have different sample rates or channel counts.
+    FormatMismatch,
+    /**
+     * Output buffer was too small to contain the crossfade.
+     *
+     * Contains size of the buffer and how many samples would have been actually needed.
+     */
+    BufferTooSmallError(usize, usize),
+}
+
+impl<F: core::fmt::Display, T: core::fmt::Display> core::fmt::Display for CrossfadeError<F, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use CrossfadeError::*;
+        match self {
+            FromError(error) => error.fmt(f),
+            ToError(error) => error.fmt(f),
+            FormatMismatch => f.write_str("streams have different formats"),
+            BufferTooSmallError(got, needed) => f.write_fmt(format_args!(
+                "buffer is too small: got {} but needed {}",
+                got, needed
+            )),
+        }
+    }
+}
+
+impl<F, T> core::error::Error for CrossfadeError<F, T>
+where
+    F: core::error::Error + 'static,
+    T: core::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        use CrossfadeError::*;
+        match self {
+            FromError(error) => Some(error),
+            ToError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Result for crossfading.
+type CrossfadeResult<S1, D1, S2, D2> = Result<
+    usize,
+    CrossfadeError<
+        FramesError<<S1 as PacketSource>::Error, <D1 as Decode>::Error>,
+        FramesError<<S2 as PacketSource>::Error, <D2 as Decode>::Error>,
+    >,
+>;
+
+/**
+ * Mix frames into output with gain that rises or falls along quarter of sine wave.
+ *
+ * Output begins at sample `start` of a crossfade of `length` samples per channel.
+ */
+fn mix<S: PacketSource, D: Decode, const BUFFER_SIZE: usize>(
+    frames: &mut Frames<S, D, BUFFER_SIZE>,
+    output: &mut [i16],
+    channels: usize,
+    (start, length): (u64, u64),
+    fade_in: bool,
+) -> Result<(), FramesError<S::Error, D::Error>> {
+    let mut position = 0;
+    while position < output.len() {
+        let Some(frame) = frames.next()? else {
+            break;
+        };
+        let count = frame.len().min(output.len() - position);
+        for (index, sample) in frame[..count].iter().enumerate() {
+            let index = position + index;
+            let offset = start + (index / channels) as u64;
+            let angle = (offset * u64::from(QUARTER) / length) as u32;
+            let gain = if fade_in {
+                sine(angle)
+            } else {
+                sine(QUARTER - angle)
+            };
+            let sample = ((i32::from(*sample) * gain) >> 15) as i16;
+            output[index] = output[index].saturating_add(sample);
+        }
+        let unused = frame.len() - count;
+        position += count;
+        if unused > 0 {
+            frames.unread(unused);
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Crossfade from one stream to another a block at a time.
+ *
+ * Works like [`crossfade`] but writes the crossfade into output buffers of any size, so that the
+ * whole crossfade does not need to fit in memory at once. Call [`fill`][`Crossfader::fill`]
+ * until it returns zero, and then continue with `to`.
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, Decode, Frames, mix::Crossfader};
+ * # struct Decoder;
+ * # impl Decode for Decoder {
+ * #     type Error = ();
+ * #     fn sample_rate(&self) -> u32 { 8_000 }
+ * #     fn channels(&self) -> u8 { 1 }
+ * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+ * #         Ok(&o[..160])
+ * #     }
+ * # }
+ * # let (music, announcement) = (include_bytes!("test/mono.opus"), include_bytes!("test/mono.opus"));
+ * let music = Bitstream::new(music).packet_reader::<512>().unwrap();
+ * let announcement = Bitstream::new(announcement).packet_reader::<512>().unwrap();
+ * let mut music = Frames::<_, _, 960>::new(music, Decoder);
+ * let mut announcement = Frames::<_, _, 960>::new(announcement, Decoder);
+ * let mut crossfader = Crossfader::new(8_000, 1, 100);
+ * let mut output = [0; 160];
+ * loop {
+ *     let samples = crossfader.fill(&mut music, &mut announcement, &mut output).unwrap();
+ *     if samples == 0 {
+ *         break;
+ *     }
+ *     // Play output[..samples]
+ * }
+ * assert!(crossfader.is_finished());
+ * ```
+ */
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Crossfader {
+    sample_rate: u32,
+    channels: u8,
+    /// Samples per channel that have been written.
+    position: u64,
+    /// Samples per channel in the whole crossfade.
+    length: u64,
+}
+
+impl Crossfader {
+    /// Construct [`Crossfader`] for `duration_ms` of audio with sample rate and channels.
+    pub fn new(sample_rate: u32, channels: u8, duration_ms: u32) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            position: 0,
+            length: u64::from(duration_ms) * u64::from(sample_rate) / 1000,
+        }
+    }
+
+    /**
+     * Mix the next block of the crossfade into output.
+     *
+     * Output is overwritten with as many whole frames of samples as fit in it or remain in the
+     * crossfade. If either stream ends during the crossfade, it is silent for the rest of it.
+     * Both streams must have the sample rate and the number of channels of the crossfader.
+     * Returns the number of samples written to output, which is zero after the crossfade.
+     */
+    pub fn fill<S1, D1, S2, D2, const N1: usize, const N2: usize>(
+        &mut self,
+        from: &mut Frames<S1, D1, N1>,
+        to: &mut Frames<S2, D2, N2>,
+        output: &mut [i16],
+    ) -> CrossfadeResult<S1, D1, S2, D2>
+    where
+        S1: PacketSource,
+        D1: Decode,
+        S2: PacketSource,
+        D2: Decode,
+    {
+        use CrossfadeError::*;
+        let format = (self.sample_rate, self.channels);
+        if (from.decoder().sample_rate(), from.decoder().channels()) != format
+            || (to.decoder().sample_rate(), to.decoder().channels()) != format
+        {
+            return Err(FormatMismatch);
+        }
+        let channels = usize::from(self.channels);
+        let remaining = usize::try_from(self.length - self.position).unwrap_or(usize::MAX);
+        let count = (output.len() / channels).min(remaining);
+        if count == 0 {
+            return Ok(0);
+        }
+        let output = &mut output[..count * channels];
+        output.fill(0);
+        let range = (self.position, self.length);
+        mix(from, output, channels, range, false).map_err(FromError)?;
+        mix(to, output, channels, range, true).map_err(ToError)?;
+        self.position += count as u64;
+        Ok(output.len())
+    }
+
+    /// Returns whether the whole crossfade has been written.
+    pub fn is_finished(&self) -> bool {
+        self.position == self.length
+    }
+}
+
+/**
+ * Crossfade from one stream to another.
+ *
+ * Mixes the next `duration_ms` of audio from both streams into output with equal-power curves,
+ * so that `from` fades out while `to` fades in. Afterwards `to` continues from where the
+ * crossfade ended. If either stream ends during the crossfade, it is silent for the rest of it.
+ *
+ * Both streams must have the same sample rate and number of channels and output must fit the
+ * whole crossfade. Returns the number of samples written to output. See [`Crossfader`] for
+ * crossfading into smaller buffers.
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, Decode, Frames, mix::crossfade};
+ * # struct Decoder;
+ * # impl Decode for Decoder {
+ * #     type Error = ();
+ * #     fn sample_rate(&self) -> u32 { 8_000 }
+ * #     fn channels(&self) -> u8 { 1 }
+ * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+ * #         Ok(&o[..160])
+ * #     }
+ * # }
+ * # let (music, announcement) = (include_bytes!("test/mono.opus"), include_bytes!("test/mono.opus"));
+ * let music = Bitstream::new(music).packet_reader::<512>().unwrap();
+ * let announcement = Bitstream::new(announcement).packet_reader::<512>().unwrap();
+ * let mut music = Frames::<_, _, 960>::new(music, Decoder);
+ * let mut announcement = Frames::<_, _, 960>::new(announcement, Decoder);
+ * let mut output = [0; 400];
+ * let samples = crossfade(&mut music, &mut announcement, 50, &mut output).unwrap();
+ * // Play output[..samples] and continue with the announcement
+ * while let Some(frame) = announcement.next().unwrap() {
+ *     // ...
+ * }
+ * ```
+ */
+pub fn crossfade<S1, D1, S2, D2, const N1: usize, const N2: usize>(
+    from: &mut Frames<S1, D1, N1>,
+    to: &mut Frames<S2, D2, N2>,
+    duration_ms: u32,
+    output: &mut [i16],
+) -> CrossfadeResult<S1, D1, S2, D2>
+where
+    S1: PacketSource,
+    D1: Decode,
+    S2: PacketSource,
+    D2: Decode,
+{
+    let decoder = from.decoder();
+    let mut crossfader = Crossfader::new(decoder.sample_rate(), decoder.channels(), duration_ms);
+    let length = crossfader.length as usize * usize::from(decoder.channels());
+    if output.len() < length {
+        return Err(CrossfadeError::BufferTooSmallError(output.len(), length));
+    }
+    crossfader.fill(from, to, &mut output[..length])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{frames::test::CountingDecoder, Bitstream};
+
+    /// Produces 20 ms frames of mono audio at 8 kHz with constant value.
+    struct ConstantDecoder(i16);
+
+    impl Decode for ConstantDecoder {
+        type Error = &'static str;
+
+        fn sample_rate(&self) -> u32 {
+            8_000
+        }
+
+        fn channels(&self) -> u8 {
+            1
+        }
+
+        fn decode<'output>(
+            &mut self,
+            _packet: &[u8],
+            output: &'output mut [i16],
+        ) -> Result<&'output [i16], &'static str> {
+            output[..160].fill(self.0);
+            Ok(&output[..160])
+        }
+    }
+
+    const DATA: &[u8] = include_bytes!("test/mono.opus");
+
+    #[test]
+    fn sine_curve() {
+        assert_eq!(sine(0), 0);
+        assert_eq!(sine(QUARTER), 32767);
+        assert_eq!(sine(QUARTER / 2), 23170);
+        // sin(30°) is 0.5 with error from interpolation
+        assert!((sine(QUARTER / 3) - 16384).abs() <= 2);
+    }
+
+    #[test]
+    fn equal_power() {
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut from = Frames::<_, _, 160>::new(reader, ConstantDecoder(10_000));
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut to = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        let mut output = [0; 300];
+        // 25 ms at 8 kHz is 200 samples
+        assert_eq!(crossfade(&mut from, &mut to, 25, &mut output), Ok(200));
+        assert_eq!(output[0], 9999);
+        // Power of the middle sample is the same as the power of the streams
+        let gain = sine(QUARTER / 2);
+        assert_eq!(
+            i32::from(output[100]),
+            ((10_000 * gain) >> 15) + ((152 * gain) >> 15)
+        );
+        assert!(output[200..].iter().all(|&sample| sample == 0));
+        // Both streams are left in the middle of a frame after 200 samples
+        assert_eq!(from.unread_samples(), 68);
+        assert_eq!(to.next().unwrap().unwrap()[0], 252);
+    }
+
+    #[test]
+    fn stream_ends() {
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut from = Frames::<_, _, 160>::new(reader, ConstantDecoder(10_000));
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut to = Frames::<_, _, 160>::new(reader, ConstantDecoder(-10_000));
+        let mut output = [1; 1200];
+        // The streams have only 1068 samples
+        assert_eq!(crossfade(&mut from, &mut to, 150, &mut output), Ok(1200));
+        assert!(output[1068..].iter().all(|&sample| sample == 0));
+        assert_eq!(from.next(), Ok(None));
+        assert_eq!(to.next(), Ok(None));
+    }
+
+    #[test]
+    fn blocks() {
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut from = Frames::<_, _, 160>::new(reader, ConstantDecoder(10_000));
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut to = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        let mut expected = [0; 200];
+        crossfade(&mut from, &mut to, 25, &mut expected).unwrap();
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut from = Frames::<_, _, 160>::new(reader, ConstantDecoder(10_000));
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut to = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        let mut crossfader = Crossfader::new(8_000, 1, 25);
+        let mut output = Vec::new();
+        let mut block = [0; 64];
+        while !crossfader.is_finished() {
+            let samples = crossfader.fill(&mut from, &mut to, &mut block).unwrap();
+            output.extend_from_slice(&block[..samples]);
+        }
+        // Blocks give the same crossfade as one buffer
+        assert_eq!(output, expected);
+        assert_eq!(crossfader.fill(&mut from, &mut to, &mut block), Ok(0));
+        assert_eq!(to.next().unwrap().unwrap()[0], 252);
+        let mut crossfader = Crossfader::new(16_000, 1, 25);
+        let result = crossfader.fill(&mut from, &mut to, &mut block);
+        assert_eq!(result, Err(CrossfadeError::FormatMismatch));
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut from = Frames::<_, _, 160>::new(reader, ConstantDecoder(0));
+        let reader = Bitstream::new(DATA).packet_reader::<512>().unwrap();
+        let mut to = Frames::<_, _, 160>::new(reader, ConstantDecoder(0));
+        let mut output = [0; 100];
+        let error = crossfade(&mut from, &mut to, 25, &mut output).unwrap_err();
+        assert_eq!(error, CrossfadeError::BufferTooSmallError(100, 200));
+        assert_eq!(
+            error.to_string(),
+            "buffer is too small: got 100 but needed 200"
+        );
+    }
+}
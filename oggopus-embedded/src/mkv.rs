@@ -0,0 +1,463 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Matroska and WebM parsing code.
+ *
+ * This is a minimal demuxer that finds the first opus track from a Matroska or WebM file and
+ * returns its packets through [`PacketSource`] just like [`PacketReader`][`crate::PacketReader`]
+ * does for ogg files.
+ *
+ * Master elements that are not needed for finding opus packets are skipped as a whole, so in
+ * practice the file must contain `Tracks` element before the first `Cluster` element. This is
+ * always the case for files written by common muxers.
+ *
+ * See also [Matroska specifications](https://www.matroska.org/technical/elements.html) and
+ * [WebM codec mapping for Opus](https://wiki.xiph.org/MatroskaOpus).
+ *
+ * ```rust
+ * # use oggopus_embedded::{mkv::MkvReader, PacketSource};
+ * # let data = include_bytes!("test/mono.webm");
+ * let mut reader = MkvReader::new(data).unwrap();
+ * println!("Sample rate is {} Hz", reader.header().sample_rate);
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * ```
+ */
+
+use super::{
+    opus::{OpusError, OpusHeader},
+    Packet, PacketSource,
+};
+use core::num::NonZeroUsize;
+use nom::{bytes::complete::take, error::ErrorKind, number, Parser};
+
+const EBML: u32 = 0x1A45_DFA3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x1853_8067;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const CLUSTER: u32 = 0x1F43_B675;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+/// Error message for laced blocks which can be skipped.
+const LACED_BLOCKS: &str = "laced blocks are not supported";
+
+/// Error from parsing Matroska container.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum MkvError {
+    /// Parsing error from nom library.
+    ParsingError(#[cfg_attr(feature = "defmt", defmt(Debug2Format))] ErrorKind),
+    /// Stream ended abruptly.
+    EndOfStreamError(Option<NonZeroUsize>),
+    /// Stream did not validate as Matroska stream.
+    InvalidStream(&'static str),
+    /// Stream is not supported, e.g. it uses laced blocks.
+    UnsupportedStream(&'static str),
+    /// Stream is not Matroska or WebM stream.
+    NotMkvStream,
+    /// Stream does not contain any opus tracks.
+    NoOpusTrack,
+    /// Error from parsing opus header of the track.
+    OpusError(OpusError),
+}
+
+impl MkvError {
+    /**
+     * Returns whether the stream may continue after the block that caused the error.
+     *
+     * Laced blocks are not supported but they can be skipped. Other errors mean that the stream
+     * cannot be read further.
+     */
+    pub fn is_recoverable(&self) -> bool {
+        *self == MkvError::UnsupportedStream(LACED_BLOCKS)
+    }
+}
+
+impl core::fmt::Display for MkvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use MkvError::*;
+        match self {
+            ParsingError(kind) => f.write_fmt(format_args!(
+                "parsing error with Matroska: {}",
+                kind.description()
+            ))?,
+            EndOfStreamError(Some(size)) => f.write_fmt(format_args!(
+                "Matroska stream ended abruptly with {} more bytes needed",
+                size
+            ))?,
+            EndOfStreamError(None) => f.write_str("Matroska stream ended abruptly")?,
+            InvalidStream(error) => f.write_fmt(format_args!("invalid stream: {}", error))?,
+            UnsupportedStream(error) => {
+                f.write_fmt(format_args!("unsupported stream: {}", error))?
+            }
+            NotMkvStream => f.write_str("this is not a Matroska stream")?,
+            NoOpusTrack => f.write_str("stream does not contain an Opus track")?,
+            OpusError(error) => error.fmt(f)?,
+        };
+        Ok(())
+    }
+}
+
+impl core::error::Error for MkvError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            MkvError::OpusError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<'data> From<nom::Err<(&'data [u8], ErrorKind)>> for MkvError {
+    fn from(error: nom::Err<(&'data [u8], ErrorKind)>) -> MkvError {
+        use MkvError::*;
+        fn convert(kind: ErrorKind) -> MkvError {
+            if kind == ErrorKind::Eof {
+                EndOfStreamError(None)
+            } else {
+                ParsingError(kind)
+            }
+        }
+        match error {
+            nom::Err::Failure((_, kind)) => convert(kind),
+            nom::Err::Error((_, kind)) => convert(kind),
+            nom::Err::Incomplete(nom::Needed::Size(size)) => EndOfStreamError(Some(size)),
+            nom::Err::Incomplete(nom::Needed::Unknown) => EndOfStreamError(None),
+        }
+    }
+}
+
+impl From<OpusError> for MkvError {
+    fn from(error: OpusError) -> MkvError {
+        MkvError::OpusError(error)
+    }
+}
+
+type Result<'data, O> = core::result::Result<(&'data [u8], O), MkvError>;
+
+/**
+ * Parse EBML variable length integer.
+ *
+ * Returns the value and whether all value bits were set, which means unknown size.
+ */
+fn parse_vint(input: &[u8]) -> Result<'_, (u64, bool)> {
+    let (input, first) = number::u8().parse(input)?;
+    if first == 0 {
+        return Err(MkvError::InvalidStream("invalid variable length integer"));
+    }
+    let length = first.leading_zeros() as usize;
+    let (input, rest) = take(length)(input)?;
+    let mask = 0x7Fu8 >> length;
+    let value = rest.iter().fold(u64::from(first & mask), |value, byte| {
+        value << 8 | u64::from(*byte)
+    });
+    let unknown = value == (1u64 << (7 * (length + 1))) - 1;
+    Ok((input, (value, unknown)))
+}
+
+/// Parse element id. Marker bits are kept as they are a part of the id.
+fn parse_id(input: &[u8]) -> Result<'_, u32> {
+    let (input, first) = number::u8().parse(input)?;
+    let length = first.leading_zeros() as usize;
+    if length > 3 {
+        return Err(MkvError::InvalidStream("invalid element id"));
+    }
+    let (input, rest) = take(length)(input)?;
+    let id = rest
+        .iter()
+        .fold(u32::from(first), |id, byte| id << 8 | u32::from(*byte));
+    Ok((input, id))
+}
+
+/// Parse element header. Size is [`None`] if it is unknown.
+fn parse_element_header(input: &[u8]) -> Result<'_, (u32, Option<usize>)> {
+    let (input, id) = parse_id(input)?;
+    let (input, (size, unknown)) = parse_vint(input)?;
+    if unknown {
+        Ok((input, (id, None)))
+    } else {
+        let size = usize::try_from(size).map_err(|_| MkvError::EndOfStreamError(None))?;
+        Ok((input, (id, Some(size))))
+    }
+}
+
+/// Parse element with known size and return its id and body.
+fn parse_element(input: &[u8]) -> Result<'_, (u32, &[u8])> {
+    let (input, (id, size)) = parse_element_header(input)?;
+    let size = size.ok_or(MkvError::UnsupportedStream("unknown element size"))?;
+    let (input, body) = take(size)(input)?;
+    Ok((input, (id, body)))
+}
+
+/// Parse unsigned integer element body.
+fn parse_uint(body: &[u8]) -> core::result::Result<u64, MkvError> {
+    if body.len() > 8 {
+        return Err(MkvError::InvalidStream("too long unsigned integer"));
+    }
+    Ok(body
+        .iter()
+        .fold(0, |value, byte| value << 8 | u64::from(*byte)))
+}
+
+/// Verify EBML header and return the remaining data.
+fn parse_ebml_header(input: &[u8]) -> core::result::Result<&[u8], MkvError> {
+    let (input, (id, body)) = parse_element(input).map_err(|_| MkvError::NotMkvStream)?;
+    if id != EBML {
+        return Err(MkvError::NotMkvStream);
+    }
+    let mut body = body;
+    while !body.is_empty() {
+        let (remaining, (id, value)) = parse_element(body)?;
+        if id == DOC_TYPE {
+            // Strings may be padded with zeros
+            let end = value
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(value.len());
+            return match &value[..end] {
+                b"webm" | b"matroska" => Ok(input),
+                _ => Err(MkvError::UnsupportedStream("unsupported document type")),
+            };
+        }
+        body = remaining;
+    }
+    Err(MkvError::InvalidStream("missing document type"))
+}
+
+/// Find the first opus track and return its track number and header.
+fn parse_tracks(mut input: &[u8]) -> core::result::Result<(u64, OpusHeader), MkvError> {
+    while !input.is_empty() {
+        let (remaining, (id, mut entry)) = parse_element(input)?;
+        input = remaining;
+        if id != TRACK_ENTRY {
+            continue;
+        }
+        let mut number = None;
+        let mut opus = false;
+        let mut codec_private = None;
+        while !entry.is_empty() {
+            let (remaining, (id, value)) = parse_element(entry)?;
+            match id {
+                TRACK_NUMBER => number = Some(parse_uint(value)?),
+                CODEC_ID => opus = value == b"A_OPUS",
+                CODEC_PRIVATE => codec_private = Some(value),
+                _ => (),
+            }
+            entry = remaining;
+        }
+        if opus {
+            let number = number.ok_or(MkvError::InvalidStream("missing track number"))?;
+            let header = codec_private.ok_or(MkvError::InvalidStream("missing opus header"))?;
+            return Ok((number, OpusHeader::parse(header)?));
+        }
+    }
+    Err(MkvError::NoOpusTrack)
+}
+
+/**
+ * [`PacketSource`] for Matroska and WebM files.
+ *
+ * Packets are returned directly from the input data without copying.
+ */
+#[derive(Debug)]
+pub struct MkvReader<'data> {
+    header: OpusHeader,
+    track: u64,
+    remaining: &'data [u8],
+}
+
+impl<'data> MkvReader<'data> {
+    /**
+     * Construct [`MkvReader`] for data and find the first opus track in it.
+     *
+     * Returns [`NoOpusTrack`][`MkvError::NoOpusTrack`] if the segment does not contain opus
+     * tracks.
+     */
+    pub fn new(data: &'data [u8]) -> core::result::Result<Self, MkvError> {
+        use MkvError::*;
+        let input = parse_ebml_header(data)?;
+        let (mut input, (id, size)) = parse_element_header(input)?;
+        if id != SEGMENT {
+            return Err(InvalidStream("missing segment"));
+        }
+        if let Some(size) = size {
+            (_, input) = take(size)(input)?;
+        }
+        while !input.is_empty() {
+            let (remaining, (id, body)) = parse_element(input)?;
+            match id {
+                TRACKS => {
+                    let (track, header) = parse_tracks(body)?;
+                    return Ok(MkvReader {
+                        header,
+                        track,
+                        remaining,
+                    });
+                }
+                CLUSTER => return Err(InvalidStream("clusters before tracks")),
+                _ => input = remaining,
+            }
+        }
+        Err(InvalidStream("missing tracks"))
+    }
+
+    /// Returns the track number of the opus track that is read.
+    pub fn track_number(&self) -> u64 {
+        self.track
+    }
+
+    /// Parse block and return its frame if it belongs to the track.
+    fn parse_block(
+        &self,
+        block: &'data [u8],
+    ) -> core::result::Result<Option<&'data [u8]>, MkvError> {
+        let (block, (track, _)) = parse_vint(block)?;
+        let (block, _timestamp) = number::be_i16().parse(block)?;
+        let (frame, flags) = number::u8().parse(block)?;
+        if track != self.track {
+            Ok(None)
+        } else if flags & 0x06 != 0 {
+            Err(MkvError::UnsupportedStream(LACED_BLOCKS))
+        } else {
+            Ok(Some(frame))
+        }
+    }
+}
+
+impl PacketSource for MkvReader<'_> {
+    type Error = MkvError;
+
+    fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    fn next_packet(&mut self) -> core::result::Result<Option<Packet<'_>>, MkvError> {
+        while !self.remaining.is_empty() {
+            let (input, (id, size)) = parse_element_header(self.remaining)?;
+            match id {
+                // Children of these follow directly so there is no need to parse them separately
+                CLUSTER | BLOCK_GROUP => self.remaining = input,
+                SIMPLE_BLOCK | BLOCK => {
+                    let size = size.ok_or(MkvError::InvalidStream("unknown block size"))?;
+                    let (input, block) = take(size)(input)?;
+                    self.remaining = input;
+                    if let Some(data) = self.parse_block(block)? {
+                        return Ok(Some(Packet::new(data)));
+                    }
+                }
+                _ => {
+                    let size = size.ok_or(MkvError::UnsupportedStream("unknown element size"))?;
+                    (self.remaining, _) = take(size)(input)?;
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Bitstream;
+    use core::error::Error;
+
+    #[test]
+    fn read_mono() {
+        const DATA: &[u8] = include_bytes!("test/mono.webm");
+        const OGG: &[u8] = include_bytes!("test/mono.opus");
+        let mut reader = MkvReader::new(DATA).unwrap();
+        let bitstream = Bitstream::new(OGG);
+        let mut ogg_reader = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(reader.header(), ogg_reader.header());
+        assert_eq!(reader.track_number(), 1);
+        let mut count = 0;
+        while let Some(packet) = reader.next_packet().unwrap() {
+            let ogg_packet = ogg_reader.next_packet().unwrap().unwrap();
+            assert_eq!(packet.data, ogg_packet.data);
+            count += 1;
+        }
+        assert_eq!(count, 7);
+        assert!(ogg_reader.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn not_mkv_stream() {
+        const DATA: &[u8] = include_bytes!("test/mono.opus");
+        let result = MkvReader::new(DATA);
+        assert_eq!(result.unwrap_err(), MkvError::NotMkvStream);
+    }
+
+    #[test]
+    fn no_opus_track() {
+        let mut data = Vec::from(include_bytes!("test/mono.webm"));
+        let index = data.windows(6).position(|w| w == b"A_OPUS").unwrap();
+        data[index + 2] = b'X';
+        let result = MkvReader::new(&data);
+        assert_eq!(result.unwrap_err(), MkvError::NoOpusTrack);
+    }
+
+    #[test]
+    fn invalid_opus_header() {
+        let mut data = Vec::from(include_bytes!("test/mono.webm"));
+        let index = data.windows(8).position(|w| w == b"OpusHead").unwrap();
+        data[index] = b'X';
+        let result = MkvReader::new(&data);
+        let error = result.unwrap_err();
+        assert_eq!(error, MkvError::OpusError(OpusError::NotOpusStream));
+        assert!(error.source().is_some());
+        assert!(!error.is_recoverable());
+    }
+
+    #[test]
+    fn laced_block() {
+        let mut data = Vec::from(include_bytes!("test/mono.webm"));
+        // Flags of the first simple block
+        let index = data.windows(2).position(|w| w == [0xE7, 0x81]).unwrap() + 8;
+        data[index] |= 0x02;
+        let mut reader = MkvReader::new(&data).unwrap();
+        let error = reader.next_packet().unwrap_err();
+        assert_eq!(
+            error,
+            MkvError::UnsupportedStream("laced blocks are not supported")
+        );
+        assert_eq!(
+            error.to_string(),
+            "unsupported stream: laced blocks are not supported"
+        );
+        assert!(error.is_recoverable());
+        // The laced block is skipped
+        let mut count = 0;
+        while reader.next_packet().unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 6);
+    }
+
+    #[test]
+    fn vint() {
+        assert_eq!(parse_vint(&[0x81]), Ok((&[][..], (1, false))));
+        assert_eq!(parse_vint(&[0x40, 0x02]), Ok((&[][..], (2, false))));
+        assert_eq!(parse_vint(&[0xFF]), Ok((&[][..], (0x7F, true))));
+        assert_eq!(
+            parse_vint(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+            Ok((&[][..], ((1 << 56) - 1, true)))
+        );
+        assert_eq!(
+            parse_vint(&[0x00]),
+            Err(MkvError::InvalidStream("invalid variable length integer"))
+        );
+        assert_eq!(
+            parse_vint(&[0x20, 0x00]),
+            Err(MkvError::EndOfStreamError(None))
+        );
+    }
+}
@@ -0,0 +1,1054 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * MP4 and ISO base media file format parsing code.
+ *
+ * This is a minimal demuxer that finds the first opus track from an MP4 file, e.g. `.m4a` or
+ * CMAF, and returns its packets through [`PacketSource`] just like
+ * [`PacketReader`][`crate::PacketReader`] does for ogg files. Samples are found from the sample
+ * tables of the movie box and from the track runs of movie fragments that follow it. Every packet
+ * has its granule position, i.e. its end time at 48 kHz, and an edit list gives the end of the
+ * stream for trimming.
+ *
+ * The file must contain its movie box before any fragments, which is always the case for
+ * fragmented files. Data offsets of track fragments without base data offset are assumed to be
+ * relative to their movie fragment, as required by CMAF.
+ *
+ * See also [ISO/IEC 14496-12](https://www.iso.org/standard/83102.html) and [Encapsulation of
+ * Opus in ISO Base Media File Format](https://opus-codec.org/docs/opus_in_isobmff.html).
+ *
+ * ```rust
+ * # use oggopus_embedded::{mp4::Mp4Reader, PacketSource};
+ * # fn read(data: &[u8]) -> Result<(), oggopus_embedded::mp4::Mp4Error> {
+ * let mut reader = Mp4Reader::new(data)?;
+ * println!("Sample rate is {} Hz", reader.header().sample_rate);
+ * while let Some(packet) = reader.next_packet()? {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * # Ok(())
+ * # }
+ * ```
+ */
+
+use super::{
+    opus::{OpusError, OpusHeader},
+    Packet, PacketSource,
+};
+use core::num::NonZeroUsize;
+use nom::{bytes::complete::take, error::ErrorKind, number, Parser};
+
+const FTYP: u32 = u32::from_be_bytes(*b"ftyp");
+const MOOV: u32 = u32::from_be_bytes(*b"moov");
+const MVHD: u32 = u32::from_be_bytes(*b"mvhd");
+const TRAK: u32 = u32::from_be_bytes(*b"trak");
+const TKHD: u32 = u32::from_be_bytes(*b"tkhd");
+const EDTS: u32 = u32::from_be_bytes(*b"edts");
+const ELST: u32 = u32::from_be_bytes(*b"elst");
+const MDIA: u32 = u32::from_be_bytes(*b"mdia");
+const MDHD: u32 = u32::from_be_bytes(*b"mdhd");
+const MINF: u32 = u32::from_be_bytes(*b"minf");
+const STBL: u32 = u32::from_be_bytes(*b"stbl");
+const STSD: u32 = u32::from_be_bytes(*b"stsd");
+const OPUS: u32 = u32::from_be_bytes(*b"Opus");
+const DOPS: u32 = u32::from_be_bytes(*b"dOps");
+const STTS: u32 = u32::from_be_bytes(*b"stts");
+const STSC: u32 = u32::from_be_bytes(*b"stsc");
+const STSZ: u32 = u32::from_be_bytes(*b"stsz");
+const STCO: u32 = u32::from_be_bytes(*b"stco");
+const CO64: u32 = u32::from_be_bytes(*b"co64");
+const MVEX: u32 = u32::from_be_bytes(*b"mvex");
+const TREX: u32 = u32::from_be_bytes(*b"trex");
+const MOOF: u32 = u32::from_be_bytes(*b"moof");
+const TRAF: u32 = u32::from_be_bytes(*b"traf");
+const TFHD: u32 = u32::from_be_bytes(*b"tfhd");
+const TFDT: u32 = u32::from_be_bytes(*b"tfdt");
+const TRUN: u32 = u32::from_be_bytes(*b"trun");
+
+/// Size of audio sample entry fields before its child boxes.
+const AUDIO_SAMPLE_ENTRY_SIZE: usize = 28;
+
+/// Error from parsing MP4 container.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Mp4Error {
+    /// Parsing error from nom library.
+    ParsingError(#[cfg_attr(feature = "defmt", defmt(Debug2Format))] ErrorKind),
+    /// Stream ended abruptly.
+    EndOfStreamError(Option<NonZeroUsize>),
+    /// Stream did not validate as MP4 stream.
+    InvalidStream(&'static str),
+    /// Stream is not supported.
+    UnsupportedStream(&'static str),
+    /// Stream is not MP4 stream.
+    NotMp4Stream,
+    /// Stream does not contain any opus tracks.
+    NoOpusTrack,
+    /// Error from parsing opus header of the track.
+    OpusError(OpusError),
+}
+
+impl core::fmt::Display for Mp4Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use Mp4Error::*;
+        match self {
+            ParsingError(kind) => f.write_fmt(format_args!(
+                "parsing error with MP4: {}",
+                kind.description()
+            ))?,
+            EndOfStreamError(Some(size)) => f.write_fmt(format_args!(
+                "MP4 stream ended abruptly with {} more bytes needed",
+                size
+            ))?,
+            EndOfStreamError(None) => f.write_str("MP4 stream ended abruptly")?,
+            InvalidStream(error) => f.write_fmt(format_args!("invalid stream: {}", error))?,
+            UnsupportedStream(error) => {
+                f.write_fmt(format_args!("unsupported stream: {}", error))?
+            }
+            NotMp4Stream => f.write_str("this is not an MP4 stream")?,
+            NoOpusTrack => f.write_str("stream does not contain an Opus track")?,
+            OpusError(error) => error.fmt(f)?,
+        };
+        Ok(())
+    }
+}
+
+impl core::error::Error for Mp4Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Mp4Error::OpusError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<'data> From<nom::Err<(&'data [u8], ErrorKind)>> for Mp4Error {
+    fn from(error: nom::Err<(&'data [u8], ErrorKind)>) -> Mp4Error {
+        use Mp4Error::*;
+        fn convert(kind: ErrorKind) -> Mp4Error {
+            if kind == ErrorKind::Eof {
+                EndOfStreamError(None)
+            } else {
+                ParsingError(kind)
+            }
+        }
+        match error {
+            nom::Err::Failure((_, kind)) => convert(kind),
+            nom::Err::Error((_, kind)) => convert(kind),
+            nom::Err::Incomplete(nom::Needed::Size(size)) => EndOfStreamError(Some(size)),
+            nom::Err::Incomplete(nom::Needed::Unknown) => EndOfStreamError(None),
+        }
+    }
+}
+
+impl From<OpusError> for Mp4Error {
+    fn from(error: OpusError) -> Mp4Error {
+        Mp4Error::OpusError(error)
+    }
+}
+
+type Result<'data, O> = core::result::Result<(&'data [u8], O), Mp4Error>;
+
+/// Parse big-endian 32-bit unsigned integer.
+fn be_u32(input: &[u8]) -> Result<'_, u32> {
+    Ok(number::be_u32().parse(input)?)
+}
+
+/// Parse big-endian 64-bit unsigned integer.
+fn be_u64(input: &[u8]) -> Result<'_, u64> {
+    Ok(number::be_u64().parse(input)?)
+}
+
+/// Skip bytes.
+fn skip(input: &[u8], count: usize) -> core::result::Result<&[u8], Mp4Error> {
+    let (input, _) = take(count)(input)?;
+    Ok(input)
+}
+
+/// Parse box and return its type and body.
+fn parse_box(input: &[u8]) -> Result<'_, (u32, &[u8])> {
+    let (input, size) = be_u32(input)?;
+    let (input, kind) = be_u32(input)?;
+    let (input, size) = match size {
+        0 => (input, input.len()),
+        1 => {
+            let (input, size) = be_u64(input)?;
+            let size = size
+                .checked_sub(16)
+                .ok_or(Mp4Error::InvalidStream("invalid box size"))?;
+            let size = usize::try_from(size).map_err(|_| Mp4Error::EndOfStreamError(None))?;
+            (input, size)
+        }
+        2..8 => return Err(Mp4Error::InvalidStream("invalid box size")),
+        size => (input, size as usize - 8),
+    };
+    let (input, body) = take(size)(input)?;
+    Ok((input, (kind, body)))
+}
+
+/// Parse version and flags of full box.
+fn parse_full_box(input: &[u8]) -> Result<'_, (u8, u32)> {
+    let (input, value) = be_u32(input)?;
+    Ok((input, ((value >> 24) as u8, value & 0x00FF_FFFF)))
+}
+
+/// Find the first child box of type.
+fn find_box(mut input: &[u8], kind: u32) -> core::result::Result<Option<&[u8]>, Mp4Error> {
+    while !input.is_empty() {
+        let (remaining, (id, body)) = parse_box(input)?;
+        if id == kind {
+            return Ok(Some(body));
+        }
+        input = remaining;
+    }
+    Ok(None)
+}
+
+/// Find the first child box of type or fail with error.
+fn require_box<'data>(
+    input: &'data [u8],
+    kind: u32,
+    error: &'static str,
+) -> core::result::Result<&'data [u8], Mp4Error> {
+    find_box(input, kind)?.ok_or(Mp4Error::InvalidStream(error))
+}
+
+/// Parse entries of a table box with entry count.
+fn parse_entries(input: &[u8], size: usize) -> core::result::Result<&[u8], Mp4Error> {
+    let (input, _) = parse_full_box(input)?;
+    let (input, count) = be_u32(input)?;
+    let length = (count as usize)
+        .checked_mul(size)
+        .ok_or(Mp4Error::InvalidStream("too many entries"))?;
+    let (_, entries) = take(length)(input)?;
+    Ok(entries)
+}
+
+/// Convert opus specific box to opus header and parse it.
+fn parse_dops(input: &[u8]) -> core::result::Result<OpusHeader, Mp4Error> {
+    let (table, fields) = take(11usize)(input)?;
+    if fields[0] != 0 {
+        return Err(Mp4Error::UnsupportedStream(
+            "unsupported opus specific box version",
+        ));
+    }
+    // Fields are the same as in identification header but big-endian
+    let mut header = [0; 19 + 2 + 255];
+    header[..8].copy_from_slice(b"OpusHead");
+    header[8] = 1;
+    header[9] = fields[1];
+    header[10..12].copy_from_slice(&[fields[3], fields[2]]);
+    header[12..16].copy_from_slice(&[fields[7], fields[6], fields[5], fields[4]]);
+    header[16..18].copy_from_slice(&[fields[9], fields[8]]);
+    header[18] = fields[10];
+    let length = table.len().min(header.len() - 19);
+    header[19..19 + length].copy_from_slice(&table[..length]);
+    Ok(OpusHeader::parse(&header[..19 + length])?)
+}
+
+/// Find opus sample entry from sample descriptions and return its header.
+fn parse_sample_description(input: &[u8]) -> core::result::Result<Option<OpusHeader>, Mp4Error> {
+    let (input, _) = parse_full_box(input)?;
+    let (mut input, _) = be_u32(input)?;
+    while !input.is_empty() {
+        let (remaining, (kind, entry)) = parse_box(input)?;
+        if kind == OPUS {
+            let children = skip(entry, AUDIO_SAMPLE_ENTRY_SIZE)?;
+            let dops = require_box(children, DOPS, "missing opus specific box")?;
+            return Ok(Some(parse_dops(dops)?));
+        }
+        input = remaining;
+    }
+    Ok(None)
+}
+
+/// Parse the first edit that plays media and return its end in media time.
+fn parse_edit_list(
+    input: &[u8],
+    timescale: u32,
+    movie_timescale: u32,
+) -> core::result::Result<Option<u64>, Mp4Error> {
+    let (input, (version, _)) = parse_full_box(input)?;
+    let (mut input, count) = be_u32(input)?;
+    for _ in 0..count {
+        let (duration, media_time);
+        if version == 1 {
+            (input, duration) = be_u64(input)?;
+            (input, media_time) = number::be_i64().parse(input)?;
+        } else {
+            let (short_duration, short_media_time);
+            (input, short_duration) = be_u32(input)?;
+            (input, short_media_time) = number::be_i32().parse(input)?;
+            (duration, media_time) = (u64::from(short_duration), i64::from(short_media_time));
+        }
+        input = skip(input, 4)?;
+        // Negative media time is an empty edit that only delays the start
+        if let (Ok(media_time), true) = (u64::try_from(media_time), movie_timescale != 0) {
+            let duration =
+                u128::from(duration) * u128::from(timescale) / u128::from(movie_timescale);
+            return Ok(Some(media_time.saturating_add(duration as u64)));
+        }
+    }
+    Ok(None)
+}
+
+/// Sample to read next.
+struct Sample {
+    /// Offset of a new chunk that starts from this sample.
+    offset: Option<u64>,
+    size: u32,
+    duration: u32,
+}
+
+/// Default sample duration and size for track fragments.
+#[derive(Clone, Copy, Debug, Default)]
+struct Defaults {
+    duration: u32,
+    size: u32,
+}
+
+/// Samples of the sample table of a track.
+#[derive(Debug)]
+struct SampleTable<'data> {
+    /// Remaining sample sizes if they are not all the same.
+    sizes: &'data [u8],
+    fixed_size: u32,
+    /// The number of samples remaining.
+    count: u32,
+    /// Remaining sample to chunk entries from the current one.
+    chunks: &'data [u8],
+    /// Remaining chunk offsets.
+    offsets: &'data [u8],
+    large_offsets: bool,
+    /// Number of the current chunk starting from one.
+    chunk: u32,
+    /// The number of samples remaining in the current chunk.
+    in_chunk: u32,
+    /// Remaining time to sample entries.
+    durations: &'data [u8],
+    /// The number of samples remaining with the current duration.
+    with_duration: u32,
+    duration: u32,
+}
+
+impl<'data> SampleTable<'data> {
+    /// Parse sample table box.
+    fn parse(input: &'data [u8]) -> core::result::Result<Self, Mp4Error> {
+        let sizes = require_box(input, STSZ, "missing sample sizes")?;
+        let (sizes, _) = parse_full_box(sizes)?;
+        let (sizes, fixed_size) = be_u32(sizes)?;
+        let (sizes, count) = be_u32(sizes)?;
+        let chunks = require_box(input, STSC, "missing sample to chunk table")?;
+        let (offsets, large_offsets) = match find_box(input, STCO)? {
+            Some(offsets) => (parse_entries(offsets, 4)?, false),
+            None => (
+                parse_entries(require_box(input, CO64, "missing chunk offsets")?, 8)?,
+                true,
+            ),
+        };
+        let durations = require_box(input, STTS, "missing time to sample table")?;
+        Ok(Self {
+            sizes,
+            fixed_size,
+            count,
+            chunks: parse_entries(chunks, 12)?,
+            offsets,
+            large_offsets,
+            chunk: 0,
+            in_chunk: 0,
+            durations: parse_entries(durations, 8)?,
+            with_duration: 0,
+            duration: 0,
+        })
+    }
+
+    /// Returns the next sample.
+    fn next(&mut self) -> core::result::Result<Option<Sample>, Mp4Error> {
+        use Mp4Error::InvalidStream;
+        if self.count == 0 {
+            return Ok(None);
+        }
+        let mut offset = None;
+        while self.in_chunk == 0 {
+            if self.offsets.is_empty() {
+                return Err(InvalidStream("missing chunk offset"));
+            }
+            let chunk_offset;
+            (self.offsets, chunk_offset) = if self.large_offsets {
+                be_u64(self.offsets)?
+            } else {
+                let (offsets, chunk_offset) = be_u32(self.offsets)?;
+                (offsets, u64::from(chunk_offset))
+            };
+            self.chunk += 1;
+            while let Some(&[a, b, c, d]) = self.chunks.get(12..16) {
+                if u32::from_be_bytes([a, b, c, d]) > self.chunk {
+                    break;
+                }
+                self.chunks = &self.chunks[12..];
+            }
+            let Some(&[a, b, c, d]) = self.chunks.get(4..8) else {
+                return Err(InvalidStream("missing sample to chunk entry"));
+            };
+            self.in_chunk = u32::from_be_bytes([a, b, c, d]);
+            offset = Some(chunk_offset);
+        }
+        let size = if self.fixed_size != 0 {
+            self.fixed_size
+        } else {
+            let size;
+            (self.sizes, size) = be_u32(self.sizes)?;
+            size
+        };
+        while self.with_duration == 0 {
+            if self.durations.is_empty() {
+                return Err(InvalidStream("missing sample duration"));
+            }
+            (self.durations, self.with_duration) = be_u32(self.durations)?;
+            (self.durations, self.duration) = be_u32(self.durations)?;
+        }
+        self.count -= 1;
+        self.in_chunk -= 1;
+        self.with_duration -= 1;
+        Ok(Some(Sample {
+            offset,
+            size,
+            duration: self.duration,
+        }))
+    }
+}
+
+/// Samples of a track run in a movie fragment.
+#[derive(Debug)]
+struct TrackRun<'data> {
+    /// Remaining sample entries.
+    entries: &'data [u8],
+    count: u32,
+    flags: u32,
+    defaults: Defaults,
+}
+
+impl<'data> TrackRun<'data> {
+    const DATA_OFFSET: u32 = 0x1;
+    const FIRST_SAMPLE_FLAGS: u32 = 0x4;
+    const SAMPLE_DURATION: u32 = 0x100;
+    const SAMPLE_SIZE: u32 = 0x200;
+    const SAMPLE_FLAGS: u32 = 0x400;
+    const SAMPLE_COMPOSITION_TIME_OFFSET: u32 = 0x800;
+
+    /// Parse track run box and return it with its data offset.
+    fn parse(
+        input: &'data [u8],
+        defaults: Defaults,
+    ) -> core::result::Result<(Self, Option<i32>), Mp4Error> {
+        let (input, (_, flags)) = parse_full_box(input)?;
+        let (mut input, count) = be_u32(input)?;
+        let mut data_offset = None;
+        if flags & Self::DATA_OFFSET != 0 {
+            let offset;
+            (input, offset) = number::be_i32().parse(input)?;
+            data_offset = Some(offset);
+        }
+        if flags & Self::FIRST_SAMPLE_FLAGS != 0 {
+            input = skip(input, 4)?;
+        }
+        let length = (4 * (flags & 0xF00).count_ones() as usize)
+            .checked_mul(count as usize)
+            .ok_or(Mp4Error::InvalidStream("too many samples"))?;
+        let (_, entries) = take(length)(input)?;
+        let run = Self {
+            entries,
+            count,
+            flags,
+            defaults,
+        };
+        Ok((run, data_offset))
+    }
+
+    /// Returns the next sample.
+    fn next(&mut self) -> core::result::Result<Option<Sample>, Mp4Error> {
+        if self.count == 0 {
+            return Ok(None);
+        }
+        let mut field = |flag: u32, default: u32| -> core::result::Result<u32, Mp4Error> {
+            if self.flags & flag != 0 {
+                let value;
+                (self.entries, value) = be_u32(self.entries)?;
+                Ok(value)
+            } else {
+                Ok(default)
+            }
+        };
+        let duration = field(Self::SAMPLE_DURATION, self.defaults.duration)?;
+        let size = field(Self::SAMPLE_SIZE, self.defaults.size)?;
+        field(Self::SAMPLE_FLAGS, 0)?;
+        field(Self::SAMPLE_COMPOSITION_TIME_OFFSET, 0)?;
+        self.count -= 1;
+        Ok(Some(Sample {
+            offset: None,
+            size,
+            duration,
+        }))
+    }
+}
+
+/// Where samples are read from.
+#[derive(Debug)]
+enum Samples<'data> {
+    Table(SampleTable<'data>),
+    Run(TrackRun<'data>),
+}
+
+/// Opus track found from movie box.
+struct Track<'data> {
+    id: u32,
+    timescale: u32,
+    header: OpusHeader,
+    table: SampleTable<'data>,
+    end: Option<u64>,
+}
+
+/// Parse track box and return it if it is an opus track.
+fn parse_track(
+    input: &[u8],
+    movie_timescale: u32,
+) -> core::result::Result<Option<Track<'_>>, Mp4Error> {
+    use Mp4Error::InvalidStream;
+    let media = require_box(input, MDIA, "missing media")?;
+    let media_information = require_box(media, MINF, "missing media information")?;
+    let sample_table = require_box(media_information, STBL, "missing sample table")?;
+    let descriptions = require_box(sample_table, STSD, "missing sample descriptions")?;
+    let Some(header) = parse_sample_description(descriptions)? else {
+        return Ok(None);
+    };
+    let track_header = require_box(input, TKHD, "missing track header")?;
+    let (track_header, (version, _)) = parse_full_box(track_header)?;
+    let (_, id) = be_u32(skip(track_header, if version == 1 { 16 } else { 8 })?)?;
+    let media_header = require_box(media, MDHD, "missing media header")?;
+    let (media_header, (version, _)) = parse_full_box(media_header)?;
+    let (_, timescale) = be_u32(skip(media_header, if version == 1 { 16 } else { 8 })?)?;
+    if timescale == 0 {
+        return Err(InvalidStream("zero timescale"));
+    }
+    let mut end = None;
+    if let Some(edits) = find_box(input, EDTS)? {
+        if let Some(edit_list) = find_box(edits, ELST)? {
+            end = parse_edit_list(edit_list, timescale, movie_timescale)?;
+        }
+    }
+    Ok(Some(Track {
+        id,
+        timescale,
+        header,
+        table: SampleTable::parse(sample_table)?,
+        end,
+    }))
+}
+
+/// Find default sample duration and size of track from movie extends box.
+fn parse_track_extends(input: &[u8], track: u32) -> core::result::Result<Defaults, Mp4Error> {
+    let Some(mut input) = find_box(input, MVEX)? else {
+        return Ok(Defaults::default());
+    };
+    while !input.is_empty() {
+        let (remaining, (kind, body)) = parse_box(input)?;
+        if kind == TREX {
+            let (body, _) = parse_full_box(body)?;
+            let (body, id) = be_u32(body)?;
+            if id == track {
+                let (body, duration) = be_u32(skip(body, 4)?)?;
+                let (_, size) = be_u32(body)?;
+                return Ok(Defaults { duration, size });
+            }
+        }
+        input = remaining;
+    }
+    Ok(Defaults::default())
+}
+
+/**
+ * [`PacketSource`] for MP4 files.
+ *
+ * Packets are returned directly from the input data without copying. The whole file must be in
+ * memory as sample tables point anywhere in it.
+ */
+#[derive(Debug)]
+pub struct Mp4Reader<'data> {
+    data: &'data [u8],
+    header: OpusHeader,
+    track: u32,
+    timescale: u32,
+    /// End of the stream in media time.
+    end: Option<u64>,
+    samples: Samples<'data>,
+    /// Offset of the next sample in data.
+    offset: u64,
+    /// Media time at the end of the last sample.
+    time: u64,
+    /// Top level boxes after movie box.
+    fragments: &'data [u8],
+    /// Remaining boxes of the current movie fragment.
+    fragment: &'data [u8],
+    /// Offset of the current movie fragment in data.
+    fragment_offset: u64,
+    /// Remaining boxes of the current track fragment.
+    track_fragment: &'data [u8],
+    track_extends: Defaults,
+    defaults: Defaults,
+}
+
+impl<'data> Mp4Reader<'data> {
+    /**
+     * Construct [`Mp4Reader`] for data and find the first opus track in it.
+     *
+     * Returns [`NoOpusTrack`][`Mp4Error::NoOpusTrack`] if the movie does not contain opus
+     * tracks.
+     */
+    pub fn new(data: &'data [u8]) -> core::result::Result<Self, Mp4Error> {
+        use Mp4Error::*;
+        match parse_box(data) {
+            Ok((_, (FTYP, _))) => (),
+            _ => return Err(NotMp4Stream),
+        }
+        let mut input = data;
+        let movie = loop {
+            if input.is_empty() {
+                return Err(InvalidStream("missing movie"));
+            }
+            let (remaining, (kind, body)) = parse_box(input)?;
+            input = remaining;
+            match kind {
+                MOOV => break body,
+                MOOF => return Err(InvalidStream("fragments before movie")),
+                _ => (),
+            }
+        };
+        let movie_header = require_box(movie, MVHD, "missing movie header")?;
+        let (movie_header, (version, _)) = parse_full_box(movie_header)?;
+        let (_, movie_timescale) = be_u32(skip(movie_header, if version == 1 { 16 } else { 8 })?)?;
+        let mut boxes = movie;
+        let track = loop {
+            if boxes.is_empty() {
+                return Err(NoOpusTrack);
+            }
+            let (remaining, (kind, body)) = parse_box(boxes)?;
+            boxes = remaining;
+            if kind == TRAK {
+                if let Some(track) = parse_track(body, movie_timescale)? {
+                    break track;
+                }
+            }
+        };
+        Ok(Self {
+            data,
+            header: track.header,
+            track: track.id,
+            timescale: track.timescale,
+            end: track.end,
+            samples: Samples::Table(track.table),
+            offset: 0,
+            time: 0,
+            fragments: input,
+            fragment: &[],
+            fragment_offset: 0,
+            track_fragment: &[],
+            track_extends: parse_track_extends(movie, track.id)?,
+            defaults: Defaults::default(),
+        })
+    }
+
+    /// Returns the track id of the opus track that is read.
+    pub fn track_id(&self) -> u32 {
+        self.track
+    }
+
+    /// Returns the number of media time units per second of the track.
+    pub fn timescale(&self) -> u32 {
+        self.timescale
+    }
+
+    /// Convert media time to granule position.
+    fn granule_position(&self, time: u64) -> u64 {
+        if self.timescale == 48_000 {
+            time
+        } else {
+            (u128::from(time) * 48_000 / u128::from(self.timescale)) as u64
+        }
+    }
+
+    /// Start reading track fragment if it belongs to the track.
+    fn parse_track_fragment(&mut self, input: &'data [u8]) -> core::result::Result<(), Mp4Error> {
+        let (boxes, (kind, header)) = parse_box(input)?;
+        if kind != TFHD {
+            return Err(Mp4Error::InvalidStream("missing track fragment header"));
+        }
+        let (header, (_, flags)) = parse_full_box(header)?;
+        let (mut header, id) = be_u32(header)?;
+        if id != self.track {
+            return Ok(());
+        }
+        self.offset = self.fragment_offset;
+        if flags & 0x01 != 0 {
+            (header, self.offset) = be_u64(header)?;
+        }
+        if flags & 0x02 != 0 {
+            header = skip(header, 4)?;
+        }
+        self.defaults = self.track_extends;
+        if flags & 0x08 != 0 {
+            (header, self.defaults.duration) = be_u32(header)?;
+        }
+        if flags & 0x10 != 0 {
+            (_, self.defaults.size) = be_u32(header)?;
+        }
+        self.track_fragment = boxes;
+        Ok(())
+    }
+
+    /**
+     * Find the next track run of the track from movie fragments.
+     *
+     * Returns false if there are no more track runs.
+     */
+    fn next_track_run(&mut self) -> core::result::Result<bool, Mp4Error> {
+        loop {
+            if !self.track_fragment.is_empty() {
+                let (remaining, (kind, body)) = parse_box(self.track_fragment)?;
+                self.track_fragment = remaining;
+                match kind {
+                    TFDT => {
+                        let (body, (version, _)) = parse_full_box(body)?;
+                        self.time = if version == 1 {
+                            be_u64(body)?.1
+                        } else {
+                            u64::from(be_u32(body)?.1)
+                        };
+                    }
+                    TRUN => {
+                        let (run, data_offset) = TrackRun::parse(body, self.defaults)?;
+                        if let Some(data_offset) = data_offset {
+                            // Data offsets are relative to the base of the track fragment
+                            let base = self.fragment_offset;
+                            self.offset = base
+                                .checked_add_signed(i64::from(data_offset))
+                                .ok_or(Mp4Error::InvalidStream("invalid data offset"))?;
+                        }
+                        self.samples = Samples::Run(run);
+                        return Ok(true);
+                    }
+                    _ => (),
+                }
+            } else if !self.fragment.is_empty() {
+                let (remaining, (kind, body)) = parse_box(self.fragment)?;
+                self.fragment = remaining;
+                if kind == TRAF {
+                    self.parse_track_fragment(body)?;
+                }
+            } else if !self.fragments.is_empty() {
+                let offset = self.data.len() - self.fragments.len();
+                let (remaining, (kind, body)) = parse_box(self.fragments)?;
+                self.fragments = remaining;
+                if kind == MOOF {
+                    self.fragment = body;
+                    self.fragment_offset = offset as u64;
+                }
+            } else {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+impl PacketSource for Mp4Reader<'_> {
+    type Error = Mp4Error;
+
+    fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    fn next_packet(&mut self) -> core::result::Result<Option<Packet<'_>>, Mp4Error> {
+        loop {
+            let sample = match &mut self.samples {
+                Samples::Table(table) => table.next()?,
+                Samples::Run(run) => run.next()?,
+            };
+            let Some(sample) = sample else {
+                if self.next_track_run()? {
+                    continue;
+                }
+                return Ok(None);
+            };
+            if let Some(offset) = sample.offset {
+                self.offset = offset;
+            }
+            let start = usize::try_from(self.offset).unwrap_or(usize::MAX);
+            let end = start.saturating_add(sample.size as usize);
+            let data = self.data.get(start..end).ok_or_else(|| {
+                Mp4Error::EndOfStreamError(NonZeroUsize::new(end - self.data.len()))
+            })?;
+            self.offset += u64::from(sample.size);
+            self.time = self
+                .time
+                .checked_add(u64::from(sample.duration))
+                .ok_or(Mp4Error::InvalidStream("media time overflows"))?;
+            return Ok(Some(Packet {
+                granule_position: Some(self.granule_position(self.time)),
+                ..Packet::new(data)
+            }));
+        }
+    }
+
+    fn end_granule_position(&self) -> Option<u64> {
+        self.end.map(|end| self.granule_position(end))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{opus::packet_samples, Bitstream};
+    use core::error::Error;
+
+    const OGG: &[u8] = include_bytes!("test/mono.opus");
+
+    fn boxed(kind: &[u8; 4], parts: &[&[u8]]) -> Vec<u8> {
+        let size = 8 + parts.iter().map(|part| part.len()).sum::<usize>();
+        let mut data = Vec::from((size as u32).to_be_bytes());
+        data.extend_from_slice(kind);
+        for part in parts {
+            data.extend_from_slice(part);
+        }
+        data
+    }
+
+    fn full(kind: &[u8; 4], version: u8, flags: u32, parts: &[&[u8]]) -> Vec<u8> {
+        let mut body = Vec::from((u32::from(version) << 24 | flags).to_be_bytes());
+        for part in parts {
+            body.extend_from_slice(part);
+        }
+        boxed(kind, &[&body])
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+
+    /// Returns packets of mono.opus.
+    fn packets() -> Vec<Vec<u8>> {
+        let bitstream = Bitstream::new(OGG);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.next_packet().unwrap() {
+            packets.push(packet.data.to_vec());
+        }
+        packets
+    }
+
+    /// Returns track box of mono.opus with sample table boxes.
+    fn track(tables: &[&[u8]], edit: &[u8]) -> Vec<u8> {
+        let head = &OGG[28..47];
+        // Fields of the identification header in big-endian
+        let mut fields = vec![0, head[9], head[11], head[10]];
+        fields.extend(head[12..16].iter().rev());
+        fields.extend([head[17], head[16], head[18]]);
+        let dops = boxed(b"dOps", &[&fields]);
+        let mut entry = vec![0; AUDIO_SAMPLE_ENTRY_SIZE];
+        entry[7] = 1;
+        entry[17] = head[9];
+        entry[19] = 16;
+        entry[24..26].copy_from_slice(&48_000u16.to_be_bytes());
+        let entry = boxed(b"Opus", &[&entry, &dops]);
+        let stsd = full(b"stsd", 0, 0, &[&words(&[1]), &entry]);
+        let mut stbl = stsd;
+        for table in tables {
+            stbl.extend_from_slice(table);
+        }
+        let stbl = boxed(b"stbl", &[&stbl]);
+        let minf = boxed(b"minf", &[&stbl]);
+        let mdhd = full(b"mdhd", 0, 0, &[&words(&[0, 0, 48_000, 0, 0])]);
+        let mdia = boxed(b"mdia", &[&mdhd, &minf]);
+        let tkhd = full(b"tkhd", 0, 7, &[&words(&[0, 0, 1, 0, 0])]);
+        boxed(b"trak", &[&tkhd, edit, &mdia])
+    }
+
+    fn movie(track: &[u8], extends: &[u8]) -> Vec<u8> {
+        let mvhd = full(b"mvhd", 0, 0, &[&words(&[0, 0, 1_000, 0])]);
+        boxed(b"moov", &[&mvhd, track, extends])
+    }
+
+    fn file_type() -> Vec<u8> {
+        boxed(b"ftyp", &[b"iso6", &words(&[0]), b"iso6opus"])
+    }
+
+    /// Returns mono.opus as MP4 file with the samples in two chunks.
+    fn mono() -> Vec<u8> {
+        let packets = packets();
+        let packets: Vec<_> = packets.iter().map(Vec::as_slice).collect();
+        let mut data = file_type();
+        let first = data.len() as u32 + 8;
+        let second = first + packets[..4].iter().map(|p| p.len() as u32).sum::<u32>();
+        data.extend(boxed(b"mdat", &packets));
+        let durations: Vec<_> = packets.iter().map(|p| packet_samples(p).unwrap()).collect();
+        let mut stts = Vec::new();
+        for duration in &durations {
+            stts.extend(words(&[1, *duration]));
+        }
+        let sizes: Vec<_> = packets.iter().map(|p| p.len() as u32).collect();
+        let stts = full(b"stts", 0, 0, &[&words(&[packets.len() as u32]), &stts]);
+        let stsc = full(b"stsc", 0, 0, &[&words(&[2, 1, 4, 1, 2, 3, 1])]);
+        let stsz = full(
+            b"stsz",
+            0,
+            0,
+            &[&words(&[0, sizes.len() as u32]), &words(&sizes)],
+        );
+        let stco = full(b"stco", 0, 0, &[&words(&[2, first, second])]);
+        // 100 ms after pre-skip
+        let elst = full(b"elst", 0, 0, &[&words(&[1, 100, 312, 0x0001_0000])]);
+        let edts = boxed(b"edts", &[&elst]);
+        let track = track(&[&stts, &stsc, &stsz, &stco], &edts);
+        data.extend(movie(&track, &[]));
+        data
+    }
+
+    #[test]
+    fn read_mono() {
+        let data = mono();
+        let mut reader = Mp4Reader::new(&data).unwrap();
+        let bitstream = Bitstream::new(OGG);
+        let mut ogg_reader = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(reader.header(), ogg_reader.header());
+        assert_eq!(reader.track_id(), 1);
+        assert_eq!(reader.timescale(), 48_000);
+        assert_eq!(reader.end_granule_position(), Some(312 + 4_800));
+        let mut time = 0;
+        let mut count = 0;
+        while let Some(packet) = reader.next_packet().unwrap() {
+            let ogg_packet = ogg_reader.next_packet().unwrap().unwrap();
+            assert_eq!(packet.data, ogg_packet.data);
+            time += u64::from(packet_samples(packet.data).unwrap());
+            assert_eq!(packet.granule_position, Some(time));
+            count += 1;
+        }
+        assert_eq!(count, 7);
+        assert!(ogg_reader.next_packet().unwrap().is_none());
+    }
+
+    /// Returns mono.opus as fragmented MP4 file that starts from media time `start`.
+    fn fragmented(start: u64) -> Vec<u8> {
+        let packets = packets();
+        let packets: Vec<_> = packets.iter().map(Vec::as_slice).collect();
+        let empty = [
+            full(b"stts", 0, 0, &[&words(&[0])]),
+            full(b"stsc", 0, 0, &[&words(&[0])]),
+            full(b"stsz", 0, 0, &[&words(&[0, 0])]),
+            full(b"stco", 0, 0, &[&words(&[0])]),
+        ];
+        let tables: Vec<_> = empty.iter().map(|table| &table[..]).collect();
+        let track = track(&tables, &[]);
+        let trex = full(b"trex", 0, 0, &[&words(&[1, 1, 960, 0, 0])]);
+        let mut data = file_type();
+        data.extend(movie(&track, &boxed(b"mvex", &[&trex])));
+        let mut time = start;
+        for (sequence, packets) in packets.chunks(4).enumerate() {
+            let sizes: Vec<_> = packets.iter().map(|p| p.len() as u32).collect();
+            let fragment = |data_offset: u32| {
+                let mfhd = full(b"mfhd", 0, 0, &[&words(&[sequence as u32 + 1])]);
+                let tfhd = full(b"tfhd", 0, 0x02_0000, &[&words(&[1])]);
+                let tfdt = full(b"tfdt", 1, 0, &[&time.to_be_bytes()]);
+                let header = words(&[sizes.len() as u32, data_offset]);
+                let trun = full(b"trun", 0, 0x201, &[&header, &words(&sizes)]);
+                let traf = boxed(b"traf", &[&tfhd, &tfdt, &trun]);
+                boxed(b"moof", &[&mfhd, &traf])
+            };
+            let length = fragment(0).len() as u32;
+            data.extend(fragment(length + 8));
+            data.extend(boxed(b"mdat", packets));
+            time = time.wrapping_add(960 * packets.len() as u64);
+        }
+        data
+    }
+
+    #[test]
+    fn read_fragments() {
+        let packets = packets();
+        let data = fragmented(0);
+        let mut reader = Mp4Reader::new(&data).unwrap();
+        assert_eq!(reader.end_granule_position(), None);
+        let mut count = 0;
+        while let Some(packet) = reader.next_packet().unwrap() {
+            assert_eq!(packet.data, packets[count]);
+            count += 1;
+            assert_eq!(packet.granule_position, Some(960 * count as u64));
+        }
+        assert_eq!(count, 7);
+    }
+
+    #[test]
+    fn time_overflow() {
+        let data = fragmented(u64::MAX - 2 * 960);
+        let mut reader = Mp4Reader::new(&data).unwrap();
+        reader.next_packet().unwrap();
+        reader.next_packet().unwrap();
+        assert_eq!(
+            reader.next_packet(),
+            Err(Mp4Error::InvalidStream("media time overflows"))
+        );
+    }
+
+    #[test]
+    fn not_mp4_stream() {
+        let result = Mp4Reader::new(OGG);
+        assert_eq!(result.unwrap_err(), Mp4Error::NotMp4Stream);
+    }
+
+    #[test]
+    fn no_opus_track() {
+        let mut data = mono();
+        let index = data.windows(4).position(|w| w == b"Opus").unwrap();
+        data[index] = b'X';
+        let result = Mp4Reader::new(&data);
+        assert_eq!(result.unwrap_err(), Mp4Error::NoOpusTrack);
+    }
+
+    #[test]
+    fn invalid_opus_header() {
+        let mut data = mono();
+        // Channel count of opus specific box
+        let index = data.windows(4).position(|w| w == b"dOps").unwrap() + 5;
+        data[index] = 0;
+        let error = Mp4Reader::new(&data).unwrap_err();
+        assert!(matches!(error, Mp4Error::OpusError(_)));
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut data = mono();
+        // Move the second chunk to the end
+        let index = data.windows(4).position(|w| w == b"stco").unwrap() + 16;
+        let offset = data.len() as u32 - 10;
+        data[index..index + 4].copy_from_slice(&offset.to_be_bytes());
+        let mut reader = Mp4Reader::new(&data).unwrap();
+        for _ in 0..4 {
+            reader.next_packet().unwrap();
+        }
+        let size = packets()[4].len() - 10;
+        assert_eq!(
+            reader.next_packet(),
+            Err(Mp4Error::EndOfStreamError(NonZeroUsize::new(size)))
+        );
+        let ftyp = file_type().len();
+        let error = Mp4Reader::new(&data[..ftyp + 4]).unwrap_err();
+        assert_eq!(error, Mp4Error::EndOfStreamError(NonZeroUsize::new(4)));
+        assert_eq!(
+            error.to_string(),
+            "MP4 stream ended abruptly with 4 more bytes needed"
+        );
+    }
+}
@@ -0,0 +1,395 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Selecting one logical stream from multiplexed ogg streams.
+
+use super::{
+    info::StartPosition, opus::OpusHeader, push::Assembler, Bitstream, BitstreamError, OggError,
+    Packet, PacketSource, Pages, RawPage,
+};
+
+/**
+ * Logical stream found at the beginning of [`Bitstream`].
+ *
+ * Multiplexed ogg streams begin with the first page of each of their logical streams.
+ */
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LogicalStream {
+    /// Bitstream serial number of the stream.
+    pub bitstream_serial_number: u32,
+    /// Opus header of the stream, or [`None`] if the stream is not a supported opus stream.
+    pub header: Option<OpusHeader>,
+    /// Whether the stream is Ogg Skeleton, which describes the other streams and has no audio.
+    pub skeleton: bool,
+}
+
+/**
+ * Iterator for logical streams that begin at the beginning of [`Bitstream`].
+ *
+ * Created with [`Bitstream::streams`].
+ */
+#[derive(Debug)]
+pub struct LogicalStreams<'data> {
+    pages: Pages<'data>,
+    ended: bool,
+}
+
+impl<'data> LogicalStreams<'data> {
+    /// Construct [`LogicalStreams`] for the beginning of [`Bitstream`].
+    pub(crate) fn new(bitstream: &Bitstream<'data>) -> Self {
+        Self {
+            pages: bitstream.pages(),
+            ended: false,
+        }
+    }
+}
+
+impl Iterator for LogicalStreams<'_> {
+    type Item = Result<LogicalStream, OggError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ended {
+            return None;
+        }
+        let page = match self.pages.next()? {
+            Ok(page) => page,
+            Err(error) => {
+                self.ended = true;
+                return Some(Err(error));
+            }
+        };
+        if !page.begin_of_stream() {
+            // Streams have begun and data pages follow
+            self.ended = true;
+            return None;
+        }
+        let packet = first_packet(&page);
+        let header = packet.and_then(|packet| OpusHeader::parse(packet).ok());
+        Some(Ok(LogicalStream {
+            bitstream_serial_number: page.bitstream_serial_number(),
+            header,
+            skeleton: packet.is_some_and(|packet| packet.starts_with(SKELETON_MAGIC)),
+        }))
+    }
+}
+
+/// Beginning of the first packet of Ogg Skeleton stream.
+const SKELETON_MAGIC: &[u8] = b"fishead\0";
+
+/// Returns the first packet of page if it ends on the page.
+fn first_packet<'data>(page: &RawPage<'data>) -> Option<&'data [u8]> {
+    let table = page.segment_table();
+    let end = table.iter().position(|size| *size < 255)?;
+    let length = table[..=end].iter().map(|size| usize::from(*size)).sum();
+    Some(&page.data()[..length])
+}
+
+/**
+ * [`PacketSource`] for one logical stream of multiplexed [`Bitstream`].
+ *
+ * Pages of other logical streams are skipped. `BUFFER_SIZE` must be large enough to contain the
+ * largest packet in the stream. Comment header is skipped and may be larger than that.
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, PacketSource};
+ * # let data = include_bytes!("test/mono.opus");
+ * let stream = Bitstream::new(data);
+ * // Select the first opus stream
+ * let serial_number = stream
+ *     .streams()
+ *     .filter_map(|stream| stream.ok())
+ *     .find(|stream| stream.header.is_some())
+ *     .map(|stream| stream.bitstream_serial_number)
+ *     .unwrap();
+ * let mut reader = stream.select_stream::<1_024>(serial_number).unwrap();
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * ```
+ */
+#[derive(Debug)]
+pub struct SelectedReader<'data, const BUFFER_SIZE: usize> {
+    pages: Pages<'data>,
+    page: Option<RawPage<'data>>,
+    assembler: Assembler<BUFFER_SIZE>,
+    header: OpusHeader,
+    bitstream_serial_number: u32,
+    ended: bool,
+    end_granule_position: Option<u64>,
+    start: Option<StartPosition>,
+}
+
+impl<'data, const BUFFER_SIZE: usize> SelectedReader<'data, BUFFER_SIZE> {
+    /// Construct [`SelectedReader`] and read the headers of the stream with serial number.
+    pub(crate) fn new(
+        bitstream: &Bitstream<'data>,
+        bitstream_serial_number: u32,
+    ) -> crate::Result<'data, Self> {
+        use BitstreamError::*;
+        let mut pages = bitstream.pages();
+        let (page, header) = loop {
+            let page = pages.next().ok_or(InvalidOpusStream("missing header"))??;
+            if !page.begin_of_stream() {
+                return Err(UnsupportedStream("stream was not found"));
+            }
+            if page.bitstream_serial_number() == bitstream_serial_number {
+                let packet = first_packet(&page).ok_or(InvalidOpusStream("missing header"))?;
+                let header = OpusHeader::parse(packet)?;
+                if header.version > 15 {
+                    return Err(UnsupportedOpusVersion(header.version));
+                }
+                break (page, header);
+            }
+        };
+        let mut reader = Self {
+            pages,
+            page: Some(page),
+            assembler: Assembler::new(),
+            header,
+            bitstream_serial_number,
+            ended: false,
+            end_granule_position: None,
+            start: None,
+        };
+        // Identification header was parsed already
+        reader.read_packet()?;
+        match reader.read_packet() {
+            Ok(true) => {}
+            Ok(false) => return Err(InvalidOpusStream("missing comment header")),
+            // Comment header is not needed so it may be skipped
+            Err(OggError(crate::OggError::BufferTooSmallError(..))) => {}
+            Err(error) => return Err(error),
+        }
+        // Audio begins on the next page
+        reader.start = Some(StartPosition::default());
+        Ok(reader)
+    }
+
+    /// Read pages until a packet is complete. Returns whether one was completed.
+    fn read_packet(&mut self) -> crate::Result<'data, bool> {
+        loop {
+            if let Some(page) = &self.page {
+                if self.assembler.step(page)? {
+                    return Ok(true);
+                }
+                self.page = None;
+            }
+            if self.ended {
+                return Ok(false);
+            }
+            let Some(page) = self.pages.next() else {
+                self.ended = true;
+                if self.assembler.has_partial_packet() {
+                    return Err(OggError::EndOfStreamError(None).into());
+                }
+                return Ok(false);
+            };
+            let page = page?;
+            if page.bitstream_serial_number() != self.bitstream_serial_number {
+                continue;
+            }
+            if page.end_of_stream() {
+                self.ended = true;
+                self.end_granule_position = Some(page.granule_position());
+            }
+            if let Some(start) = &mut self.start {
+                // Invalid start positions are treated as zero
+                let _ = start.page(&page);
+            }
+            self.page = Some(page);
+        }
+    }
+}
+
+impl<const BUFFER_SIZE: usize> PacketSource for SelectedReader<'_, BUFFER_SIZE> {
+    type Error = BitstreamError;
+
+    fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet<'_>>, BitstreamError> {
+        if self.read_packet()? {
+            Ok(Some(self.assembler.packet()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn end_granule_position(&self) -> Option<u64> {
+        self.end_granule_position
+    }
+
+    fn start_granule_position(&self) -> Option<u64> {
+        self.start.as_ref().and_then(StartPosition::position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Interleave pages of two streams and add the first page of vorbis stream in between.
+    fn multiplexed() -> Vec<u8> {
+        let first: Vec<_> = Bitstream::new(include_bytes!("test/offset.opus"))
+            .pages()
+            .map(|page| page.unwrap().as_bytes())
+            .collect();
+        let second: Vec<_> = Bitstream::new(include_bytes!("test/stereo.opus"))
+            .pages()
+            .map(|page| page.unwrap().as_bytes())
+            .collect();
+        let vorbis = Bitstream::new(include_bytes!("test/vorbis.ogg"))
+            .pages()
+            .next()
+            .unwrap()
+            .unwrap();
+        let mut data = Vec::new();
+        data.extend_from_slice(first[0]);
+        data.extend_from_slice(vorbis.as_bytes());
+        for (index, page) in first.iter().enumerate() {
+            if index > 0 {
+                data.extend_from_slice(page);
+            }
+            if let Some(page) = second.get(index) {
+                data.extend_from_slice(page);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn list_streams() {
+        let data = multiplexed();
+        let streams: Vec<_> = Bitstream::new(&data)
+            .streams()
+            .map(|stream| stream.unwrap())
+            .collect();
+        assert_eq!(streams.len(), 3);
+        assert_eq!(streams[0].bitstream_serial_number, 0x2f0f36f5);
+        assert_eq!(
+            streams[0]
+                .header
+                .as_ref()
+                .unwrap()
+                .channels
+                .get_channel_count(),
+            1
+        );
+        assert_eq!(streams[1].header, None);
+        assert_eq!(streams[2].bitstream_serial_number, 0x783f5825);
+        assert_eq!(
+            streams[2]
+                .header
+                .as_ref()
+                .unwrap()
+                .channels
+                .get_channel_count(),
+            2
+        );
+    }
+
+    #[test]
+    fn select_streams() {
+        let data = multiplexed();
+        let bitstream = Bitstream::new(&data);
+        for (serial_number, file) in [
+            (0x2f0f36f5, &include_bytes!("test/offset.opus")[..]),
+            (0x783f5825, include_bytes!("test/stereo.opus")),
+        ] {
+            let mut reader = bitstream.select_stream::<512>(serial_number).unwrap();
+            let file = Bitstream::new(file);
+            let mut expected = file.packet_reader::<512>().unwrap();
+            assert_eq!(reader.header(), expected.header());
+            while let Some(packet) = reader.next_packet().unwrap() {
+                assert_eq!(Some(packet), expected.next_packet().unwrap());
+            }
+            assert_eq!(expected.next_packet(), Ok(None));
+            assert_eq!(
+                reader.end_granule_position(),
+                expected.end_granule_position()
+            );
+        }
+        let vorbis = bitstream.select_stream::<512>(0x210b5e96);
+        assert_eq!(vorbis.unwrap_err(), BitstreamError::NotOpusStream);
+        let missing = bitstream.select_stream::<512>(1);
+        assert_eq!(
+            missing.unwrap_err(),
+            BitstreamError::UnsupportedStream("stream was not found")
+        );
+    }
+
+    /// Build page of one packet with flags.
+    fn page(flags: u8, sequence_number: u32, packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0".to_vec();
+        page.push(flags);
+        page.extend_from_slice(&0u64.to_le_bytes());
+        page.extend_from_slice(&0x5e1e7001u32.to_le_bytes());
+        page.extend_from_slice(&sequence_number.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend_from_slice(packet);
+        let checksum = crate::crc::page_checksum(&crate::crc::SoftwareCrc, &page);
+        page[22..26].copy_from_slice(&checksum.to_le_bytes());
+        page
+    }
+
+    #[test]
+    fn skip_skeleton() {
+        let opus: Vec<_> = Bitstream::new(include_bytes!("test/offset.opus"))
+            .pages()
+            .map(|page| page.unwrap().as_bytes())
+            .collect();
+        let mut fishead = b"fishead\0\x03\0\0\0".to_vec();
+        fishead.resize(64, 0);
+        let mut fisbone = b"fisbone\0".to_vec();
+        fisbone.resize(52, 0);
+        let mut data = page(0b010, 0, &fishead);
+        data.extend_from_slice(opus[0]);
+        data.extend(page(0, 1, &fisbone));
+        data.extend_from_slice(opus[1]);
+        data.extend(page(0b100, 2, &[]));
+        data.extend(opus[2..].concat());
+        let bitstream = Bitstream::new(&data);
+        let streams: Vec<_> = bitstream.streams().map(Result::unwrap).collect();
+        assert!(streams[0].skeleton && streams[0].header.is_none());
+        assert!(!streams[1].skeleton && streams[1].header.is_some());
+        assert_eq!(
+            bitstream.packet_reader::<512>().unwrap_err(),
+            BitstreamError::NotOpusStream
+        );
+        let mut reader = bitstream.select_opus_stream::<512>().unwrap();
+        let file = Bitstream::new(include_bytes!("test/offset.opus"));
+        let mut expected = file.packet_reader::<512>().unwrap();
+        while let Some(packet) = reader.next_packet().unwrap() {
+            assert_eq!(Some(packet), expected.next_packet().unwrap());
+        }
+        assert_eq!(expected.next_packet(), Ok(None));
+        let vorbis = Bitstream::new(include_bytes!("test/vorbis.ogg"));
+        assert_eq!(
+            vorbis.select_opus_stream::<512>().unwrap_err(),
+            BitstreamError::NotOpusStream
+        );
+    }
+
+    #[test]
+    fn single_stream() {
+        let data = include_bytes!("test/mono.opus");
+        let bitstream = Bitstream::new(data);
+        let streams: Vec<_> = bitstream.streams().collect();
+        assert_eq!(streams.len(), 1);
+        let stream = streams[0].as_ref().unwrap();
+        let mut reader = bitstream
+            .select_stream::<512>(stream.bitstream_serial_number)
+            .unwrap();
+        let mut count = 0;
+        while reader.next_packet().unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 7);
+        assert_eq!(reader.start_granule_position(), Some(7_992 - 7 * 960));
+    }
+}
@@ -9,6 +9,7 @@ use nom::{bytes::complete::tag, error::ErrorKind, number, Parser};
 
 /// Error values for formatting.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[doc(hidden)]
 #[non_exhaustive]
 pub enum ErrorValues {
@@ -23,9 +24,11 @@ pub enum ErrorValues {
 
 /// Errors from parsing opus data.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum OpusError {
     /// Parsing error from nom library.
-    ParsingError(ErrorKind),
+    ParsingError(#[cfg_attr(feature = "defmt", defmt(Debug2Format))] ErrorKind),
     /// Stream ended abruptly.
     EndOfStreamError(Option<NonZeroUsize>),
     /// Stream is not a valid opus stream, e.g. it has a value outside specifications.
@@ -36,6 +39,22 @@ pub enum OpusError {
     NotOpusStream,
 }
 
+impl OpusError {
+    /// Returns the layer of the stream where the error comes from, which is always the packet.
+    pub fn layer(&self) -> crate::ErrorLayer {
+        crate::ErrorLayer::Packet
+    }
+
+    /**
+     * Returns whether the stream may continue after the error.
+     *
+     * Always `false` as these errors come from the headers that are needed for the whole stream.
+     */
+    pub fn is_recoverable(&self) -> bool {
+        false
+    }
+}
+
 impl core::fmt::Display for OpusError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         use OpusError::*;
@@ -110,6 +129,7 @@ pub type Result<'data, O> = core::result::Result<O, OpusError>;
 
 /// Channel mapping of opus stream.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub enum ChannelMapping {
     /**
@@ -150,9 +170,14 @@ pub enum ChannelMapping {
     /**
      * Reserved channel mapping value was used in the stream.
      *
-     * Such mapping may be a future extension to the container format.
+     * Such mapping may be a future extension to the container format. Ambisonics families 2
+     * and 3 from [RFC8486](https://datatracker.ietf.org/doc/html/rfc8486) are also given as
+     * reserved. Family 3 has demixing matrix instead of mapping, so its channels are not mapped
+     * to decoded channels.
      */
     Reserved {
+        /// Channel mapping family number.
+        family: u8,
         /// The number of channels.
         channels: u8,
         /// Channel mapping table.
@@ -161,6 +186,19 @@ pub enum ChannelMapping {
 }
 
 impl ChannelMapping {
+    /// Get channel mapping family number.
+    pub fn get_family(&self) -> u8 {
+        use ChannelMapping::*;
+        match self {
+            Family0 { .. } => 0,
+            Family1 { .. } => 1,
+            #[cfg(feature = "family255")]
+            Family255 { .. } => 255,
+            #[cfg(feature = "family255")]
+            Reserved { family, .. } => *family,
+        }
+    }
+
     /// Get channel count.
     pub fn get_channel_count(&self) -> u8 {
         use ChannelMapping::*;
@@ -200,6 +238,22 @@ impl ChannelMapping {
         }
     }
 
+    /**
+     * Get raw channel mapping table with one entry for each channel.
+     *
+     * Returns [`None`] for family 0 which does not have a table.
+     */
+    pub fn get_mapping_table(&self) -> Option<&[u8]> {
+        use ChannelMapping::*;
+        let channels = usize::from(self.get_channel_count());
+        match self {
+            Family0 { .. } => None,
+            Family1 { table, .. } => Some(&table.mapping[..channels]),
+            #[cfg(feature = "family255")]
+            Family255 { table, .. } | Reserved { table, .. } => Some(&table.mapping[..channels]),
+        }
+    }
+
     /**
      * Get channel mapping for given channel index.
      *
@@ -240,7 +294,10 @@ impl ChannelMapping {
                 Some((Some(speaker_location), index, *coupled_count))
             }
             #[cfg(feature = "family255")]
-            Family255 { channels, table } | Reserved { channels, table } => {
+            Family255 { channels, table }
+            | Reserved {
+                channels, table, ..
+            } => {
                 if channel >= *channels {
                     None
                 } else {
@@ -280,6 +337,7 @@ impl ChannelMapping {
 
 /// Speaker location in audio setup.
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub enum SpeakerLocation {
     /// Mono speaker.
@@ -306,6 +364,7 @@ pub enum SpeakerLocation {
 
 /// Decoded opus channel to use for this audio channel.
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum DecodedChannel {
     /// The opus stream will be mono audio.
     Mono,
@@ -317,6 +376,7 @@ pub enum DecodedChannel {
 
 /// Channel mapping for an audio channel.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Mapping {
     /// Stream index and decoded opus stream channel to use. Silent channel if [`None`].
     pub stream: Option<(u8, DecodedChannel)>,
@@ -326,6 +386,7 @@ pub struct Mapping {
 
 /// Channel mapping table.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct ChannelMappingTable<const MAX_CHANNELS: usize> {
     /// The number of total streams encoded in each Ogg packet.
     stream_count: u8,
@@ -336,11 +397,22 @@ pub struct ChannelMappingTable<const MAX_CHANNELS: usize> {
 }
 
 impl<const MAX_CHANNELS: usize> ChannelMappingTable<MAX_CHANNELS> {
-    fn parse(input: &[u8], channels: u8) -> Result<ChannelMappingTable<MAX_CHANNELS>> {
+    /// Returns the number of streams encoded in each packet.
+    pub fn stream_count(&self) -> u8 {
+        self.stream_count
+    }
+
+    /// Returns the number of streams that are stereo.
+    pub fn coupled_count(&self) -> u8 {
+        self.coupled_count
+    }
+
+    /// Parse stream counts and return the rest of the table.
+    fn parse_counts(input: &[u8]) -> Result<'_, (&[u8], u8, u8)> {
         use OpusError::*;
         let (input, stream_count) = number::u8().parse(input)?;
         let (input, coupled_count) = number::u8().parse(input)?;
-        let total_stream_count = stream_count.checked_add(coupled_count).ok_or_else(|| {
+        stream_count.checked_add(coupled_count).ok_or_else(|| {
             let total = stream_count as u16 + coupled_count as u16;
             InvalidStream(ErrorValues::TotalStreamCountExceeds(total))
         })?;
@@ -351,7 +423,16 @@ impl<const MAX_CHANNELS: usize> ChannelMappingTable<MAX_CHANNELS> {
                 coupled_count,
                 stream_count,
             )))
-        } else if input.len() != channels.into() {
+        } else {
+            Ok((input, stream_count, coupled_count))
+        }
+    }
+
+    fn parse(input: &[u8], channels: u8) -> Result<'_, ChannelMappingTable<MAX_CHANNELS>> {
+        use OpusError::*;
+        let (input, stream_count, coupled_count) = Self::parse_counts(input)?;
+        let total_stream_count = stream_count + coupled_count;
+        if input.len() != channels.into() {
             Err(InvalidStream(ErrorValues::BadTableLength(
                 input.len(),
                 channels,
@@ -376,10 +457,34 @@ impl<const MAX_CHANNELS: usize> ChannelMappingTable<MAX_CHANNELS> {
             })
         }
     }
+
+    /**
+     * Parse table of family 3 which has demixing matrix instead of channel mapping.
+     *
+     * The matrix has 16-bit coefficient for each output channel and decoded channel. Mapping of
+     * each channel is 255 as channels cannot be mapped to a single decoded channel.
+     */
+    #[cfg(feature = "family255")]
+    fn parse_demixing(input: &[u8], channels: u8) -> Result<'_, ChannelMappingTable<MAX_CHANNELS>> {
+        let (matrix, stream_count, coupled_count) = Self::parse_counts(input)?;
+        let decoded = usize::from(stream_count) + usize::from(coupled_count);
+        if matrix.len() != 2 * usize::from(channels) * decoded {
+            return Err(OpusError::InvalidStream(ErrorValues::BadTableLength(
+                matrix.len(),
+                channels,
+            )));
+        }
+        Ok(ChannelMappingTable {
+            stream_count,
+            coupled_count,
+            mapping: [255; MAX_CHANNELS],
+        })
+    }
 }
 
 /// Opus header data.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct OpusHeader {
     /// Opus version.
     pub version: u8,
@@ -389,8 +494,8 @@ pub struct OpusHeader {
     pub pre_skip: u16,
     /// Sample rate used for the original audio.
     pub sample_rate: u32,
-    /// Output gain.
-    pub output_gain: u16,
+    /// Output gain in Q7.8 decibels, i.e. 256 is 1 dB.
+    pub output_gain: i16,
 }
 
 impl OpusHeader {
@@ -400,7 +505,7 @@ impl OpusHeader {
      * May return [`UnsupportedStream`][`OpusError::UnsupportedStream`] if family255 feature has
      * not been enabled and such stream is encountered.
      */
-    pub fn parse(input: &[u8]) -> Result<Self> {
+    pub fn parse(input: &[u8]) -> Result<'_, Self> {
         use OpusError::*;
         let (input, _) = tag(b"OpusHead".as_slice())(input)
             .map_err(|_: nom::Err<(&[u8], ErrorKind)>| NotOpusStream)?;
@@ -408,8 +513,14 @@ impl OpusHeader {
         let (input, channels) = number::u8().parse(input)?;
         let (input, pre_skip) = number::le_u16().parse(input)?;
         let (input, sample_rate) = number::le_u32().parse(input)?;
-        let (input, output_gain) = number::le_u16().parse(input)?;
+        let (input, output_gain) = number::le_i16().parse(input)?;
         let (channel_mapping_table, channel_mapping_family) = number::u8().parse(input)?;
+        if channels == 0 {
+            return Err(InvalidStream(ErrorValues::BadNumberOfChannels(
+                channel_mapping_family,
+                channels,
+            )));
+        }
         let channels = match channel_mapping_family {
             0 => match channels {
                 1..=2 => ChannelMapping::Family0 { channels },
@@ -428,7 +539,14 @@ impl OpusHeader {
                 table: ChannelMappingTable::parse(channel_mapping_table, channels)?,
             },
             #[cfg(feature = "family255")]
-            _ => ChannelMapping::Reserved {
+            3 => ChannelMapping::Reserved {
+                family: 3,
+                channels,
+                table: ChannelMappingTable::parse_demixing(channel_mapping_table, channels)?,
+            },
+            #[cfg(feature = "family255")]
+            family => ChannelMapping::Reserved {
+                family,
                 channels,
                 table: ChannelMappingTable::parse(channel_mapping_table, channels)?,
             },
@@ -449,11 +567,48 @@ impl OpusHeader {
     }
 }
 
+/**
+ * Returns the number of samples in opus packet at 48 kHz.
+ *
+ * The duration is read from the TOC byte and frame count byte of the packet (see
+ * [RFC6716](https://datatracker.ietf.org/doc/html/rfc6716#section-3.1)). Returns [`None`] if the
+ * packet is too short to contain them.
+ */
+pub fn packet_samples(packet: &[u8]) -> Option<u32> {
+    let toc = *packet.first()?;
+    let config = toc >> 3;
+    let frame_size = match config {
+        0..=11 => [480, 960, 1920, 2880][usize::from(config & 0b11)],
+        12..=15 => [480, 960][usize::from(config & 0b1)],
+        _ => [120, 240, 480, 960][usize::from(config & 0b11)],
+    };
+    let frames = match toc & 0b11 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => u32::from(*packet.get(1)? & 0x3F),
+    };
+    Some(frame_size * frames)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use core::error::Error;
 
+    #[test]
+    fn samples_in_packet() {
+        // SILK 20 ms, hybrid 10 ms and CELT 2.5 ms with one frame
+        assert_eq!(packet_samples(&[0x08]), Some(960));
+        assert_eq!(packet_samples(&[0x60]), Some(480));
+        assert_eq!(packet_samples(&[0x80]), Some(120));
+        // SILK 60 ms with two frames
+        assert_eq!(packet_samples(&[0x19, 0x00]), Some(5_760));
+        // CELT 20 ms with arbitrary number of frames
+        assert_eq!(packet_samples(&[0xFB, 0x03]), Some(2_880));
+        assert_eq!(packet_samples(&[0xFB]), None);
+        assert_eq!(packet_samples(&[]), None);
+    }
+
     #[test]
     fn parse_header() {
         let data = include_bytes!("test/opus.data");
@@ -582,12 +737,18 @@ mod test {
     fn family_reserved() {
         let data: [u8; 0x06] = [0x02, 0x01, 0x01, 0x00, 0x02, 0xFF];
         let channels = ChannelMapping::Reserved {
+            family: 2,
             channels: 4,
             table: ChannelMappingTable::parse(&data, 4).unwrap(),
         };
+        assert_eq!(channels.get_family(), 2);
         assert_eq!(channels.get_channel_count(), 4);
         assert_eq!(channels.get_stream_count(), 2);
         assert_eq!(channels.get_coupled_stream_count(), 1);
+        assert_eq!(
+            channels.get_mapping_table(),
+            Some([0x01, 0x00, 0x02, 0xFF].as_slice())
+        );
         let mappings = [
             Some((0, DecodedChannel::Right)),
             Some((0, DecodedChannel::Left)),
@@ -711,6 +872,21 @@ mod test {
         assert!(result.unwrap_err().source().is_none());
     }
 
+    #[test]
+    fn negative_gain() {
+        let mut data = Vec::from(include_bytes!("test/opus.data"));
+        data[0x10..0x12].copy_from_slice(&(-512i16).to_le_bytes());
+        assert_eq!(OpusHeader::parse(&data).unwrap().output_gain, -512);
+    }
+
+    #[test]
+    fn table_counts() {
+        let data: [u8; 0x08] = [0x04, 0x02, 0x00, 0x04, 0x01, 0x02, 0x03, 0x05];
+        let table = ChannelMappingTable::<8>::parse(&data, 6).unwrap();
+        assert_eq!(table.stream_count(), 4);
+        assert_eq!(table.coupled_count(), 2);
+    }
+
     #[test]
     fn invalid_channels() {
         let mut data = Vec::from(include_bytes!("test/opus.data"));
@@ -732,5 +908,13 @@ mod test {
             )))
         );
         assert!(result.unwrap_err().source().is_none());
+        // Tables of other families need at least one channel too
+        data[0x12] = 255;
+        assert_eq!(
+            OpusHeader::parse(&data),
+            Err(OpusError::InvalidStream(ErrorValues::BadNumberOfChannels(
+                255, 0
+            )))
+        );
     }
 }
@@ -0,0 +1,420 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Capture format for logging received opus packets.
+ *
+ * Packet log starts with [`MAGIC`] and [`VERSION`] bytes which are followed by records. Each
+ * record has little-endian 16-bit packet length, 32-bit timestamp and 32-bit CRC, followed by the
+ * packet. The CRC is calculated over the length, the timestamp and the packet with the same
+ * checksum as ogg pages use. Timestamps are in whatever unit the device uses, e.g. milliseconds.
+ * Reader finds the next valid record after a corrupted one by scanning for matching checksum.
+ *
+ * ```rust
+ * # use oggopus_embedded::packetlog::{PacketLogReader, PacketLogWriter};
+ * let mut buffer = [0; 256];
+ * let mut writer = PacketLogWriter::new(&mut buffer).unwrap();
+ * writer.push(10, &[0x08, 0x01, 0x02]).unwrap();
+ * writer.push(30, &[0x08, 0x03]).unwrap();
+ * let log = writer.as_bytes();
+ * // Write log to flash and read it later
+ * for record in PacketLogReader::new(log).unwrap() {
+ *     let record = record.unwrap();
+ *     println!("{}: {} bytes", record.timestamp, record.data.len());
+ * }
+ * ```
+ */
+
+use super::crc;
+
+/// Magic bytes in the beginning of packet log.
+pub const MAGIC: [u8; 4] = *b"OPLG";
+
+/// Version of packet log format.
+pub const VERSION: u8 = 1;
+
+/// Size of packet log header.
+pub const HEADER_SIZE: usize = MAGIC.len() + 1;
+
+/// Size of record header that precedes each packet.
+pub const RECORD_HEADER_SIZE: usize = 2 + 4 + 4;
+
+/// Error from writing or reading packet log.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum PacketLogError {
+    /// Data does not start with [`MAGIC`].
+    NotPacketLog,
+    /// Unsupported packet log version.
+    UnsupportedVersion(u8),
+    /// Log ended in the middle of a record.
+    EndOfLogError,
+    /// Record checksum did not match. Contains offset of the record.
+    ChecksumMismatch(usize),
+    /// Packet is longer than a record can contain. Contains the length of the packet.
+    PacketTooLarge(usize),
+    /**
+     * Buffer was too small to contain record.
+     *
+     * Contains the remaining size of the buffer and how many bytes would have been needed.
+     */
+    BufferTooSmallError(usize, usize),
+}
+
+impl PacketLogError {
+    /**
+     * Returns whether reading may continue after the error.
+     *
+     * A record with mismatching checksum is skipped. When writing, the next packet may still fit
+     * if it is smaller.
+     */
+    pub fn is_recoverable(&self) -> bool {
+        use PacketLogError::*;
+        matches!(
+            self,
+            ChecksumMismatch(_) | PacketTooLarge(_) | BufferTooSmallError(..)
+        )
+    }
+}
+
+impl core::fmt::Display for PacketLogError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use PacketLogError::*;
+        match self {
+            NotPacketLog => f.write_str("this is not a packet log"),
+            UnsupportedVersion(version) => {
+                f.write_fmt(format_args!("unsupported packet log version: {}", version))
+            }
+            EndOfLogError => f.write_str("packet log ended abruptly"),
+            ChecksumMismatch(offset) => {
+                f.write_fmt(format_args!("checksum mismatch in record at {}", offset))
+            }
+            PacketTooLarge(length) => {
+                f.write_fmt(format_args!("packet is too large to log: {} bytes", length))
+            }
+            BufferTooSmallError(got, needed) => f.write_fmt(format_args!(
+                "buffer is too small: got {} but needed {}",
+                got, needed
+            )),
+        }
+    }
+}
+
+impl core::error::Error for PacketLogError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        None
+    }
+}
+
+/// Logged packet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Record<'data> {
+    /// Timestamp given when the packet was logged.
+    pub timestamp: u32,
+    /// The logged packet.
+    pub data: &'data [u8],
+}
+
+/// Calculate checksum of a record.
+fn checksum(length: [u8; 2], timestamp: [u8; 4], data: &[u8]) -> u32 {
+    crc::update(crc::update(crc::update(0, &length), &timestamp), data)
+}
+
+/**
+ * Writer for packet log.
+ *
+ * Writes packet log to a buffer which can then be stored, e.g. to flash.
+ */
+#[derive(Debug)]
+pub struct PacketLogWriter<'buffer> {
+    buffer: &'buffer mut [u8],
+    position: usize,
+}
+
+impl<'buffer> PacketLogWriter<'buffer> {
+    /// Construct [`PacketLogWriter`] and write packet log header to buffer.
+    pub fn new(buffer: &'buffer mut [u8]) -> Result<Self, PacketLogError> {
+        if buffer.len() < HEADER_SIZE {
+            return Err(PacketLogError::BufferTooSmallError(
+                buffer.len(),
+                HEADER_SIZE,
+            ));
+        }
+        buffer[..MAGIC.len()].copy_from_slice(&MAGIC);
+        buffer[MAGIC.len()] = VERSION;
+        Ok(Self {
+            buffer,
+            position: HEADER_SIZE,
+        })
+    }
+
+    /**
+     * Append packet with timestamp to the log.
+     *
+     * Nothing is written if the record does not fit to the remaining buffer.
+     */
+    pub fn push(&mut self, timestamp: u32, packet: &[u8]) -> Result<(), PacketLogError> {
+        use PacketLogError::*;
+        let length = u16::try_from(packet.len()).map_err(|_| PacketTooLarge(packet.len()))?;
+        let remaining = self.buffer.len() - self.position;
+        let size = RECORD_HEADER_SIZE + packet.len();
+        if remaining < size {
+            return Err(BufferTooSmallError(remaining, size));
+        }
+        let length = length.to_le_bytes();
+        let timestamp = timestamp.to_le_bytes();
+        let crc = checksum(length, timestamp, packet).to_le_bytes();
+        let record = &mut self.buffer[self.position..self.position + size];
+        record[0..2].copy_from_slice(&length);
+        record[2..6].copy_from_slice(&timestamp);
+        record[6..10].copy_from_slice(&crc);
+        record[RECORD_HEADER_SIZE..].copy_from_slice(packet);
+        self.position += size;
+        Ok(())
+    }
+
+    /// Returns the log written so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.position]
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.position
+    }
+
+    /// Returns whether no records have been written.
+    pub fn is_empty(&self) -> bool {
+        self.position == HEADER_SIZE
+    }
+
+    /**
+     * Discard written records.
+     *
+     * Useful after the log has been stored and the buffer can be reused.
+     */
+    pub fn clear(&mut self) {
+        self.position = HEADER_SIZE;
+    }
+}
+
+/**
+ * Reader for packet log.
+ *
+ * Iterates over records in the log. Records with mismatching checksum are returned as
+ * [`ChecksumMismatch`][`PacketLogError::ChecksumMismatch`] and reading continues from the next
+ * offset where a record with matching checksum begins, so that a corrupted length does not
+ * misframe the rest of the log. Iteration stops after a truncated record at the end of the log.
+ */
+#[derive(Debug)]
+pub struct PacketLogReader<'data> {
+    data: &'data [u8],
+    offset: usize,
+}
+
+impl<'data> PacketLogReader<'data> {
+    /// Construct [`PacketLogReader`] and check packet log header.
+    pub fn new(data: &'data [u8]) -> Result<Self, PacketLogError> {
+        use PacketLogError::*;
+        if data.len() < HEADER_SIZE {
+            return Err(if MAGIC.starts_with(data) {
+                EndOfLogError
+            } else {
+                NotPacketLog
+            });
+        }
+        if data[..MAGIC.len()] != MAGIC {
+            return Err(NotPacketLog);
+        }
+        if data[MAGIC.len()] != VERSION {
+            return Err(UnsupportedVersion(data[MAGIC.len()]));
+        }
+        Ok(Self {
+            data,
+            offset: HEADER_SIZE,
+        })
+    }
+
+    /**
+     * Parse record in the beginning of data.
+     *
+     * Returns the record and its size, or [`None`] if data does not begin with a complete record
+     * with matching checksum.
+     */
+    fn record(data: &[u8]) -> Option<(Record<'_>, usize)> {
+        let header = data.get(..RECORD_HEADER_SIZE)?;
+        let length = [header[0], header[1]];
+        let timestamp = [header[2], header[3], header[4], header[5]];
+        let crc = u32::from_le_bytes([header[6], header[7], header[8], header[9]]);
+        let size = RECORD_HEADER_SIZE + usize::from(u16::from_le_bytes(length));
+        let packet = data.get(RECORD_HEADER_SIZE..size)?;
+        (checksum(length, timestamp, packet) == crc).then_some((
+            Record {
+                timestamp: u32::from_le_bytes(timestamp),
+                data: packet,
+            },
+            size,
+        ))
+    }
+}
+
+impl<'data> Iterator for PacketLogReader<'data> {
+    type Item = Result<Record<'data>, PacketLogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = &self.data[self.offset..];
+        if remaining.is_empty() {
+            return None;
+        }
+        if let Some((record, size)) = Self::record(remaining) {
+            self.offset += size;
+            return Some(Ok(record));
+        }
+        let offset = self.offset;
+        // Continue from the next valid record in case the length of this record is corrupted
+        match (1..remaining.len()).find(|&skip| Self::record(&remaining[skip..]).is_some()) {
+            Some(skip) => {
+                self.offset += skip;
+                Some(Err(PacketLogError::ChecksumMismatch(offset)))
+            }
+            None => {
+                self.offset = self.data.len();
+                let length = remaining
+                    .get(..2)
+                    .map(|length| usize::from(u16::from_le_bytes([length[0], length[1]])));
+                match length {
+                    Some(length) if remaining.len() >= RECORD_HEADER_SIZE + length => {
+                        Some(Err(PacketLogError::ChecksumMismatch(offset)))
+                    }
+                    _ => Some(Err(PacketLogError::EndOfLogError)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Bitstream, PacketSource};
+
+    #[test]
+    fn roundtrip() {
+        let data = include_bytes!("test/mono.opus");
+        let mut source = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut buffer = [0; 1024];
+        let mut writer = PacketLogWriter::new(&mut buffer).unwrap();
+        assert!(writer.is_empty());
+        let mut timestamp = 0;
+        while let Some(packet) = source.next_packet().unwrap() {
+            writer.push(timestamp, packet.data).unwrap();
+            timestamp += 20;
+        }
+        assert!(!writer.is_empty());
+        let log = Vec::from(writer.as_bytes());
+        assert_eq!(&log[..5], b"OPLG\x01");
+        let mut source = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut count = 0;
+        for record in PacketLogReader::new(&log).unwrap() {
+            let record = record.unwrap();
+            assert_eq!(record.timestamp, count * 20);
+            assert_eq!(record.data, source.next_packet().unwrap().unwrap().data);
+            count += 1;
+        }
+        assert_eq!(count, 7);
+    }
+
+    #[test]
+    fn corrupted_record() {
+        let mut buffer = [0; 64];
+        let mut writer = PacketLogWriter::new(&mut buffer).unwrap();
+        writer.push(1, &[1, 2, 3]).unwrap();
+        writer.push(2, &[4, 5]).unwrap();
+        let mut log = Vec::from(writer.as_bytes());
+        log[HEADER_SIZE + RECORD_HEADER_SIZE] ^= 0xFF;
+        let mut reader = PacketLogReader::new(&log).unwrap();
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(error, PacketLogError::ChecksumMismatch(HEADER_SIZE));
+        assert!(error.is_recoverable());
+        assert_eq!(error.to_string(), "checksum mismatch in record at 5");
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(record.timestamp, 2);
+        assert_eq!(record.data, &[4, 5]);
+        assert_eq!(reader.next(), None);
+    }
+
+    #[test]
+    fn corrupted_length() {
+        let mut buffer = [0; 64];
+        let mut writer = PacketLogWriter::new(&mut buffer).unwrap();
+        writer.push(1, &[1, 2, 3]).unwrap();
+        writer.push(2, &[4, 5]).unwrap();
+        writer.push(3, &[6]).unwrap();
+        let mut log = Vec::from(writer.as_bytes());
+        // Length that covers the next record and one that goes past the end of the log
+        for length in [16, 1_000] {
+            log[HEADER_SIZE..HEADER_SIZE + 2].copy_from_slice(&u16::to_le_bytes(length));
+            let mut reader = PacketLogReader::new(&log).unwrap();
+            let error = reader.next().unwrap().unwrap_err();
+            assert_eq!(error, PacketLogError::ChecksumMismatch(HEADER_SIZE));
+            assert_eq!(reader.next().unwrap().unwrap().data, &[4, 5]);
+            assert_eq!(reader.next().unwrap().unwrap().data, &[6]);
+            assert_eq!(reader.next(), None);
+        }
+        // Corrupted last record is reported as mismatch
+        let length = log.len();
+        log[length - 1] ^= 0xFF;
+        let mut reader = PacketLogReader::new(&log).unwrap();
+        let last = HEADER_SIZE + 2 * RECORD_HEADER_SIZE + 3 + 2;
+        assert_eq!(
+            reader.nth(2).unwrap().unwrap_err(),
+            PacketLogError::ChecksumMismatch(last)
+        );
+        assert_eq!(reader.next(), None);
+    }
+
+    #[test]
+    fn truncated_log() {
+        let mut buffer = [0; 64];
+        let mut writer = PacketLogWriter::new(&mut buffer).unwrap();
+        writer.push(1, &[1, 2, 3]).unwrap();
+        let log = writer.as_bytes();
+        let mut reader = PacketLogReader::new(&log[..log.len() - 1]).unwrap();
+        assert_eq!(reader.next(), Some(Err(PacketLogError::EndOfLogError)));
+        assert_eq!(reader.next(), None);
+        assert_eq!(
+            PacketLogReader::new(&log[..3]).unwrap_err(),
+            PacketLogError::EndOfLogError
+        );
+        assert_eq!(
+            PacketLogReader::new(b"OggS\x00").unwrap_err(),
+            PacketLogError::NotPacketLog
+        );
+        assert_eq!(
+            PacketLogReader::new(b"OPLG\x02").unwrap_err(),
+            PacketLogError::UnsupportedVersion(2)
+        );
+    }
+
+    #[test]
+    fn buffer_full() {
+        let mut buffer = [0; 20];
+        let mut writer = PacketLogWriter::new(&mut buffer).unwrap();
+        writer.push(1, &[1, 2]).unwrap();
+        let error = writer.push(2, &[3, 4]).unwrap_err();
+        assert_eq!(error, PacketLogError::BufferTooSmallError(3, 12));
+        assert_eq!(
+            error.to_string(),
+            "buffer is too small: got 3 but needed 12"
+        );
+        assert_eq!(writer.len(), 17);
+        writer.clear();
+        assert!(writer.is_empty());
+        writer.push(2, &[3, 4]).unwrap();
+        let error = writer.push(3, &[0; 65_536]).unwrap_err();
+        assert_eq!(error, PacketLogError::PacketTooLarge(65_536));
+    }
+}
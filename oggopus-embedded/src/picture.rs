@@ -0,0 +1,386 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Streaming decoder for album art in comment header.
+
+/// Comment key of album art.
+pub const PICTURE_KEY: &str = "METADATA_BLOCK_PICTURE";
+
+/// The maximum length of MIME type that is kept in [`PictureInfo`].
+pub const MAX_MIME_TYPE_LENGTH: usize = 64;
+
+/// Errors from decoding album art.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum PictureError {
+    /// The value has a byte that is not valid base64.
+    InvalidBase64(u8),
+    /// MIME type is longer than [`MAX_MIME_TYPE_LENGTH`].
+    MimeTypeTooLong(u32),
+    /// The value ended before the picture did.
+    Truncated,
+}
+
+impl core::fmt::Display for PictureError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use PictureError::*;
+        match self {
+            InvalidBase64(byte) => {
+                f.write_fmt(format_args!("invalid base64 in picture: {:#04x}", byte))
+            }
+            MimeTypeTooLong(length) => f.write_fmt(format_args!(
+                "picture MIME type is too long: {} bytes",
+                length
+            )),
+            Truncated => f.write_str("picture ended abruptly"),
+        }
+    }
+}
+
+impl core::error::Error for PictureError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        None
+    }
+}
+
+/// Picture metadata that comes before the image data.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PictureInfo {
+    /// Picture type, e.g. 3 is front cover.
+    pub picture_type: u32,
+    mime_type: [u8; MAX_MIME_TYPE_LENGTH],
+    mime_type_length: u8,
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Color depth in bits per pixel.
+    pub depth: u32,
+    /// The number of colors for indexed pictures or zero.
+    pub colors: u32,
+    /// Length of the image data in bytes.
+    pub length: u32,
+}
+
+impl PictureInfo {
+    /// Returns the MIME type of the image, e.g. `image/png`.
+    pub fn mime_type(&self) -> &[u8] {
+        &self.mime_type[..usize::from(self.mime_type_length)]
+    }
+}
+
+/// Field of picture block that is being decoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    PictureType,
+    MimeTypeLength,
+    MimeType,
+    DescriptionLength,
+    Description,
+    Width,
+    Height,
+    Depth,
+    Colors,
+    Length,
+    Data,
+    Done,
+}
+
+/**
+ * Streaming decoder for `METADATA_BLOCK_PICTURE` comment.
+ *
+ * The value is base64 encoded FLAC picture block, which can be far larger than available RAM.
+ * It is decoded as it is pushed in pieces and the image data is given to a callback in small
+ * chunks, so nothing larger than the MIME type is buffered. The description is skipped. Album
+ * art is never decoded unless it is pushed to [`PictureReader`], so it can be skipped by just
+ * ignoring the comment.
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, picture::{PictureReader, PICTURE_KEY}};
+ * # let data = include_bytes!("test/mono.opus");
+ * let tags = Bitstream::new(data).tags().unwrap();
+ * if let Some(picture) = tags.get(PICTURE_KEY) {
+ *     let mut reader = PictureReader::new();
+ *     let mut image = Vec::new();
+ *     reader
+ *         .push(picture.as_bytes(), |_info, chunk| image.extend_from_slice(chunk))
+ *         .unwrap();
+ *     reader.finish().unwrap();
+ * }
+ * ```
+ */
+#[derive(Clone, Debug)]
+pub struct PictureReader {
+    info: PictureInfo,
+    field: Field,
+    /// Big endian value of the current field.
+    value: u32,
+    /// Bytes left in the current field.
+    remaining: u32,
+    /// Base64 bits that have not been decoded into a byte yet.
+    bits: u32,
+    bit_count: u8,
+    padding: bool,
+}
+
+impl Default for PictureReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PictureReader {
+    /// Construct [`PictureReader`] for the beginning of the value.
+    pub const fn new() -> Self {
+        Self {
+            info: PictureInfo {
+                picture_type: 0,
+                mime_type: [0; MAX_MIME_TYPE_LENGTH],
+                mime_type_length: 0,
+                width: 0,
+                height: 0,
+                depth: 0,
+                colors: 0,
+                length: 0,
+            },
+            field: Field::PictureType,
+            value: 0,
+            remaining: 4,
+            bits: 0,
+            bit_count: 0,
+            padding: false,
+        }
+    }
+
+    /**
+     * Decode the next piece of base64 value.
+     *
+     * Image data is given to `data` with the metadata of the picture as soon as it is decoded.
+     * Data after the end of the picture is ignored.
+     */
+    pub fn push(
+        &mut self,
+        base64: &[u8],
+        mut data: impl FnMut(&PictureInfo, &[u8]),
+    ) -> Result<(), PictureError> {
+        let mut chunk = [0; 48];
+        let mut length = 0;
+        for &byte in base64 {
+            if self.field == Field::Done {
+                break;
+            }
+            let sextet = match byte {
+                b'A'..=b'Z' => byte - b'A',
+                b'a'..=b'z' => byte - b'a' + 26,
+                b'0'..=b'9' => byte - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                b'=' => {
+                    self.padding = true;
+                    continue;
+                }
+                _ => return Err(PictureError::InvalidBase64(byte)),
+            };
+            if self.padding {
+                return Err(PictureError::InvalidBase64(byte));
+            }
+            self.bits = (self.bits << 6) | u32::from(sextet);
+            self.bit_count += 6;
+            if self.bit_count < 8 {
+                continue;
+            }
+            self.bit_count -= 8;
+            let byte = (self.bits >> self.bit_count) as u8;
+            self.bits &= (1 << self.bit_count) - 1;
+            if self.field == Field::Data {
+                chunk[length] = byte;
+                length += 1;
+                self.remaining -= 1;
+                if length == chunk.len() || self.remaining == 0 {
+                    data(&self.info, &chunk[..length]);
+                    length = 0;
+                }
+                if self.remaining == 0 {
+                    self.field = Field::Done;
+                }
+            } else {
+                self.header_byte(byte)?;
+            }
+        }
+        if length > 0 {
+            data(&self.info, &chunk[..length]);
+        }
+        Ok(())
+    }
+
+    /// Decode a byte of the fields before image data.
+    fn header_byte(&mut self, byte: u8) -> Result<(), PictureError> {
+        use Field::*;
+        match self.field {
+            MimeType => {
+                let index = usize::from(self.info.mime_type_length);
+                self.info.mime_type[index] = byte;
+                self.info.mime_type_length += 1;
+            }
+            Description => {}
+            _ => self.value = (self.value << 8) | u32::from(byte),
+        }
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return Ok(());
+        }
+        let value = core::mem::take(&mut self.value);
+        let (field, remaining) = match self.field {
+            PictureType => {
+                self.info.picture_type = value;
+                (MimeTypeLength, 4)
+            }
+            MimeTypeLength if value as usize > MAX_MIME_TYPE_LENGTH => {
+                return Err(PictureError::MimeTypeTooLong(value))
+            }
+            MimeTypeLength => (MimeType, value),
+            MimeType | Description => (self.field, 0),
+            DescriptionLength => (Description, value),
+            Width => {
+                self.info.width = value;
+                (Height, 4)
+            }
+            Height => {
+                self.info.height = value;
+                (Depth, 4)
+            }
+            Depth => {
+                self.info.depth = value;
+                (Colors, 4)
+            }
+            Colors => {
+                self.info.colors = value;
+                (Length, 4)
+            }
+            Length => {
+                self.info.length = value;
+                (Data, value)
+            }
+            Data | Done => unreachable!(),
+        };
+        self.field = field;
+        self.remaining = remaining;
+        // Empty fields are finished right away
+        while self.remaining == 0 && self.field != Done {
+            self.field = match self.field {
+                MimeType => DescriptionLength,
+                Description => Width,
+                Data => Done,
+                field => field,
+            };
+            self.remaining = if self.field == Done { 0 } else { 4 };
+        }
+        Ok(())
+    }
+
+    /// Returns the metadata of the picture when it has been decoded.
+    pub fn info(&self) -> Option<&PictureInfo> {
+        matches!(self.field, Field::Data | Field::Done).then_some(&self.info)
+    }
+
+    /// Check that the whole picture was decoded.
+    pub fn finish(&self) -> Result<&PictureInfo, PictureError> {
+        if self.field == Field::Done {
+            Ok(&self.info)
+        } else {
+            Err(PictureError::Truncated)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode(data: &[u8]) -> Vec<u8> {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut output = Vec::new();
+        for chunk in data.chunks(3) {
+            let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+                bits | u32::from(*byte) << (16 - 8 * i)
+            });
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    output.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize]);
+                } else {
+                    output.push(b'=');
+                }
+            }
+        }
+        output
+    }
+
+    fn block(mime_type: &[u8], description: &[u8], image: &[u8]) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend_from_slice(&3u32.to_be_bytes());
+        block.extend_from_slice(&(mime_type.len() as u32).to_be_bytes());
+        block.extend_from_slice(mime_type);
+        block.extend_from_slice(&(description.len() as u32).to_be_bytes());
+        block.extend_from_slice(description);
+        for value in [640u32, 480, 24, 0, image.len() as u32] {
+            block.extend_from_slice(&value.to_be_bytes());
+        }
+        block.extend_from_slice(image);
+        block
+    }
+
+    #[test]
+    fn stream_picture() {
+        let image: Vec<u8> = (0..1_000).map(|i| i as u8).collect();
+        let value = encode(&block(b"image/png", b"Cover", &image));
+        for size in [1, 7, 100, value.len()] {
+            let mut reader = PictureReader::new();
+            let mut decoded = Vec::new();
+            for piece in value.chunks(size) {
+                reader
+                    .push(piece, |info, chunk| {
+                        assert_eq!(info.mime_type(), b"image/png");
+                        assert!(chunk.len() <= 48);
+                        decoded.extend_from_slice(chunk);
+                    })
+                    .unwrap();
+            }
+            assert_eq!(decoded, image);
+            let info = reader.finish().unwrap();
+            assert_eq!(info.picture_type, 3);
+            assert_eq!((info.width, info.height, info.depth), (640, 480, 24));
+            assert_eq!(info.length, 1_000);
+        }
+    }
+
+    #[test]
+    fn empty_fields() {
+        let value = encode(&block(b"", b"", b""));
+        let mut reader = PictureReader::new();
+        reader.push(&value, |_, _| panic!("No data")).unwrap();
+        assert_eq!(reader.finish().unwrap().mime_type(), b"");
+    }
+
+    #[test]
+    fn invalid_picture() {
+        let value = encode(&block(b"image/jpeg", b"", &[1, 2, 3]));
+        let mut reader = PictureReader::new();
+        reader.push(&value[..20], |_, _| {}).unwrap();
+        assert!(reader.info().is_none());
+        assert_eq!(reader.finish(), Err(PictureError::Truncated));
+        assert_eq!(
+            PictureReader::new().push(b"AA*A", |_, _| {}),
+            Err(PictureError::InvalidBase64(b'*'))
+        );
+        let value = encode(&block(&[b'a'; 65], b"", &[]));
+        let result = PictureReader::new().push(&value, |_, _| {});
+        assert_eq!(result, Err(PictureError::MimeTypeTooLong(65)));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "picture MIME type is too long: 65 bytes"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Playback of decoded frames with rodio.
+
+use super::{time::GranulePos, Decode, Frames, FramesError, PacketSource};
+use core::time::Duration;
+use rodio::{ChannelCount, Sample, SampleRate, Source};
+
+/**
+ * Rodio [`Source`] that plays the frames decoded by [`Frames`].
+ *
+ * This lets host-side tools and tests play the same files through the system audio device. Add
+ * the source to the mixer of an output stream, e.g. one opened with
+ * `rodio::DeviceSinkBuilder::open_default_sink` when `playback` feature of rodio is enabled.
+ * Playback needs the source to be `'static`, i.e. to own its data like
+ * [`StdPacketReader`][`crate::StdPacketReader`] and [`VecPacketReader`][`crate::VecPacketReader`].
+ *
+ * The source ends at the end of the stream or at the first error, which is kept for
+ * [`error`][`RodioSource::error`]. Total duration is known once the packet source knows the
+ * final granule position, see [`PacketSource::end_granule_position`].
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, Decode, Frames, RodioSource};
+ * # struct Decoder;
+ * # impl Decode for Decoder {
+ * #     type Error = ();
+ * #     fn sample_rate(&self) -> u32 { 8_000 }
+ * #     fn channels(&self) -> u8 { 1 }
+ * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+ * #         Ok(&o[..160])
+ * #     }
+ * # }
+ * # let decoder = Decoder;
+ * # let data = include_bytes!("test/mono.opus");
+ * use rodio::Source;
+ *
+ * let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+ * let source = RodioSource::new(Frames::<_, _, 960>::new(reader, decoder));
+ * assert_eq!(source.sample_rate().get(), 8_000);
+ * println!("Got {} samples of audio", source.count());
+ * ```
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "rodio")))]
+#[derive(Debug)]
+pub struct RodioSource<S: PacketSource, D: Decode, const BUFFER_SIZE: usize> {
+    frames: Frames<S, D, BUFFER_SIZE>,
+    error: Option<FramesError<S::Error, D::Error>>,
+}
+
+impl<S: PacketSource, D: Decode, const BUFFER_SIZE: usize> RodioSource<S, D, BUFFER_SIZE> {
+    /// Construct [`RodioSource`] that plays frames from the current position of [`Frames`].
+    pub fn new(frames: Frames<S, D, BUFFER_SIZE>) -> Self {
+        Self {
+            frames,
+            error: None,
+        }
+    }
+
+    /// Returns the frames that are played.
+    pub fn frames(&self) -> &Frames<S, D, BUFFER_SIZE> {
+        &self.frames
+    }
+
+    /// Returns the error that ended the source, if any.
+    pub fn error(&self) -> Option<&FramesError<S::Error, D::Error>> {
+        self.error.as_ref()
+    }
+
+    /// Deconstruct [`RodioSource`] into the frames, e.g. for seeking and playing again.
+    pub fn into_inner(self) -> Frames<S, D, BUFFER_SIZE> {
+        self.frames
+    }
+}
+
+impl<S: PacketSource, D: Decode, const BUFFER_SIZE: usize> Iterator
+    for RodioSource<S, D, BUFFER_SIZE>
+{
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.frames.next() {
+            Ok(Some(frame)) => {
+                // Frames are never empty, and the rest is returned again by the next call
+                let (sample, rest) = (frame[0], frame.len() - 1);
+                self.frames.unread(rest);
+                Some(Sample::from(sample) / 32768.0)
+            }
+            Ok(None) => None,
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+}
+
+impl<S: PacketSource, D: Decode, const BUFFER_SIZE: usize> Source
+    for RodioSource<S, D, BUFFER_SIZE>
+{
+    fn current_span_len(&self) -> Option<usize> {
+        // Sample rate and channels do not change within a stream
+        None
+    }
+
+    fn channels(&self) -> ChannelCount {
+        // PANIC: Decoders have at least one channel
+        ChannelCount::new(u16::from(self.frames.decoder().channels())).unwrap()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        // PANIC: Decoders have a sample rate
+        SampleRate::new(self.frames.decoder().sample_rate()).unwrap()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        if self.frames.is_looping() {
+            return None;
+        }
+        let source = self.frames.source();
+        let granules = source
+            .end_granule_position()?
+            .saturating_sub(source.start_granule_position().unwrap_or(0));
+        Some(GranulePos::new(granules).duration(source.header().pre_skip))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frames::test::CountingDecoder;
+    use crate::Bitstream;
+
+    /// Decoder that fails to decode the second packet.
+    struct FailingDecoder(CountingDecoder);
+
+    impl Decode for FailingDecoder {
+        type Error = ();
+
+        fn sample_rate(&self) -> u32 {
+            self.0.sample_rate()
+        }
+
+        fn channels(&self) -> u8 {
+            self.0.channels()
+        }
+
+        fn decode<'output>(
+            &mut self,
+            packet: &[u8],
+            output: &'output mut [i16],
+        ) -> Result<&'output [i16], ()> {
+            if self.0.count > 0 {
+                return Err(());
+            }
+            self.0.decode(packet, output)
+        }
+    }
+
+    #[test]
+    fn samples() {
+        let data = include_bytes!("test/trimmed.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        let mut source = RodioSource::new(frames);
+        assert_eq!(source.channels().get(), 1);
+        assert_eq!(source.sample_rate().get(), 8_000);
+        assert_eq!(source.total_duration(), None);
+        let expected = (52..1052).map(|sample: i16| Sample::from(sample) / 32768.0);
+        assert!(source.by_ref().eq(expected));
+        // Final granule position is 6312, i.e. 1000 samples at 8 kHz after pre-skip
+        assert_eq!(source.total_duration(), Some(Duration::from_millis(125)));
+    }
+
+    #[test]
+    fn error() {
+        let data = include_bytes!("test/mono.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let decoder = FailingDecoder(CountingDecoder::default());
+        let mut source = RodioSource::new(Frames::<_, _, 160>::new(reader, decoder));
+        // Pre-skip discards 52 samples of the first frame
+        assert_eq!(source.by_ref().count(), 160 - 52);
+        assert_eq!(source.error(), Some(&FramesError::DecoderError(())));
+        assert_eq!(source.next(), None);
+    }
+}
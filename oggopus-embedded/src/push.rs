@@ -0,0 +1,652 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Push-based ogg parsing for data that arrives in chunks.
+
+use super::{
+    container::{RawPage, HEADER_SIZE},
+    crc::{self, Crc32Provider, SoftwareCrc},
+    ErrorValues, OggError, Packet,
+};
+
+/**
+ * Incremental ogg parser that is fed with chunks of data.
+ *
+ * Bytes are given with [`push`][`OggPusher::push`] in whatever sizes they arrive, e.g. from an
+ * interrupt handler, and complete packets are taken out with [`next`][`OggPusher::next`]. One
+ * page is buffered at a time so `PAGE_SIZE` must fit the largest page in the stream, see
+ * [`MAX_PAGE_SIZE`][`crate::MAX_PAGE_SIZE`]. Packets that continue over pages are collected to
+ * another buffer so `PACKET_SIZE` must fit the largest packet.
+ *
+ * All packets of the stream are returned, including opus header packets, which can be parsed with
+ * [`OpusHeader::parse`][`crate::opus::OpusHeader::parse`]. CRC checksums of pages are checked
+ * unless turned off with [`with_crc_check`][`OggPusher::with_crc_check`].
+ *
+ * Bytes that do not belong to a page, e.g. after bytes were dropped on a serial line, are skipped
+ * until the next capture pattern and reported with [`LostSync`][`OggError::LostSync`] when the
+ * next page is complete.
+ *
+ * ```rust
+ * # use oggopus_embedded::OggPusher;
+ * # let data = include_bytes!("test/mono.opus");
+ * let mut pusher = OggPusher::<4_096, 512>::new();
+ * for mut chunk in data.chunks(64) {
+ *     while !chunk.is_empty() {
+ *         let consumed = pusher.push(chunk).unwrap();
+ *         chunk = &chunk[consumed..];
+ *         while let Some(packet) = pusher.next().unwrap() {
+ *             println!("Got {} bytes", packet.data.len());
+ *         }
+ *     }
+ * }
+ * ```
+ */
+#[derive(Debug)]
+pub struct OggPusher<const PAGE_SIZE: usize, const PACKET_SIZE: usize> {
+    page: [u8; PAGE_SIZE],
+    filled: usize,
+    skipped: usize,
+    assembler: Assembler<PACKET_SIZE>,
+    crc: Option<&'static dyn Crc32Provider>,
+}
+
+impl<const PAGE_SIZE: usize, const PACKET_SIZE: usize> Default
+    for OggPusher<PAGE_SIZE, PACKET_SIZE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGE_SIZE: usize, const PACKET_SIZE: usize> OggPusher<PAGE_SIZE, PACKET_SIZE> {
+    /// Construct empty [`OggPusher`].
+    pub const fn new() -> Self {
+        Self {
+            page: [0; PAGE_SIZE],
+            filled: 0,
+            skipped: 0,
+            assembler: Assembler::new(),
+            crc: Some(&SoftwareCrc),
+        }
+    }
+
+    /**
+     * Set whether CRC checksums of pages are checked.
+     *
+     * Pages whose checksum does not match are discarded and reported with
+     * [`CrcMismatch`][`OggError::CrcMismatch`]. Defaults to `true`.
+     */
+    pub const fn with_crc_check(mut self, check_crc: bool) -> Self {
+        self.crc = match (check_crc, self.crc) {
+            (false, _) => None,
+            (true, None) => Some(&SoftwareCrc),
+            (true, crc) => crc,
+        };
+        self
+    }
+
+    /// Set [`Crc32Provider`] for checking CRC checksums, e.g. a hardware CRC peripheral.
+    pub const fn with_crc_provider(mut self, provider: &'static dyn Crc32Provider) -> Self {
+        self.crc = Some(provider);
+        self
+    }
+
+    /// Returns the number of bytes the buffered page needs in total as far as it is known.
+    fn wanted(&self) -> usize {
+        if self.filled < HEADER_SIZE {
+            HEADER_SIZE
+        } else {
+            let count = usize::from(self.page[HEADER_SIZE - 1]);
+            let table = &self.page[HEADER_SIZE..self.filled.min(HEADER_SIZE + count)];
+            if table.len() < count {
+                HEADER_SIZE + count
+            } else {
+                HEADER_SIZE + count + table.iter().map(|size| usize::from(*size)).sum::<usize>()
+            }
+        }
+    }
+
+    /// Returns whether a whole page is buffered.
+    fn page_complete(&self) -> bool {
+        self.filled >= HEADER_SIZE && self.filled == self.wanted()
+    }
+
+    /**
+     * Feed more data to the parser.
+     *
+     * Returns the number of bytes consumed which is less than given if a page was completed.
+     * Packets of that page must be taken with [`next`][`OggPusher::next`] before more data is
+     * consumed.
+     *
+     * On error the partial page is discarded and parsing continues from the following data.
+     */
+    pub fn push(&mut self, data: &[u8]) -> Result<usize, OggError> {
+        let mut consumed = 0;
+        self.push_from(data, &mut consumed)?;
+        Ok(consumed)
+    }
+
+    /**
+     * Feed data to the parser from `consumed` bytes onwards and count consumed bytes.
+     *
+     * Unlike with [`push`][`OggPusher::push`], the consumed bytes are known on error too.
+     */
+    pub(crate) fn push_from(&mut self, data: &[u8], consumed: &mut usize) -> Result<(), OggError> {
+        while !self.page_complete() && *consumed < data.len() {
+            let space = self.space()?;
+            let count = space.len().min(data.len() - *consumed);
+            space[..count].copy_from_slice(&data[*consumed..*consumed + count]);
+            *consumed += count;
+            self.advance(count)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Returns the part of the page buffer that is filled next.
+     *
+     * Must not be called when the page is complete. Returned buffer is never empty.
+     */
+    pub(crate) fn space(&mut self) -> Result<&mut [u8], OggError> {
+        let wanted = self.wanted();
+        if wanted > PAGE_SIZE {
+            self.filled = 0;
+            return Err(OggError::PageTooLargeError(PAGE_SIZE, wanted));
+        }
+        Ok(&mut self.page[self.filled..wanted])
+    }
+
+    /// Mark `count` bytes of [`space`][`OggPusher::space`] as filled.
+    pub(crate) fn advance(&mut self, count: usize) -> Result<(), OggError> {
+        self.filled += count;
+        if count == 0 {
+            return Ok(());
+        }
+        // Check capture pattern and version as soon as possible
+        if !b"OggS".starts_with(&self.page[..self.filled.min(4)]) {
+            self.skip(1);
+        }
+        if self.filled > 4 && self.page[4] != 0 {
+            let version = self.page[4];
+            self.skip(1);
+            return Err(OggError::UnsupportedVersion(version));
+        }
+        if let Some(provider) = self.crc.filter(|_| self.page_complete()) {
+            let page = &self.page[..self.filled];
+            let checksum = u32::from_le_bytes([page[22], page[23], page[24], page[25]]);
+            if crc::page_checksum(provider, page) != checksum {
+                self.filled = 0;
+                let page_sequence_number =
+                    u32::from_le_bytes([page[18], page[19], page[20], page[21]]);
+                return Err(OggError::CrcMismatch(page_sequence_number));
+            }
+        }
+        Ok(())
+    }
+
+    /// Discard at least `count` bytes from the beginning of the buffer until it may begin a page.
+    fn skip(&mut self, count: usize) {
+        let filled = self.filled;
+        let start = (count..filled)
+            .find(|&index| {
+                let data = &self.page[index..filled];
+                b"OggS".starts_with(&data[..data.len().min(4)])
+            })
+            .unwrap_or(filled);
+        self.page.copy_within(start..filled, 0);
+        self.filled -= start;
+        self.skipped += start;
+    }
+
+    /// Discard buffered data, e.g. after seeking to another page.
+    #[cfg(feature = "std")]
+    pub(crate) fn reset(&mut self) {
+        self.filled = 0;
+        self.skipped = 0;
+        self.assembler = Assembler::new();
+    }
+
+    /// Returns the number of bytes skipped that have not been reported yet.
+    #[cfg(any(feature = "embedded-io", feature = "std"))]
+    pub(crate) fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Returns the buffered page if it is complete.
+    pub fn current_page(&self) -> Option<RawPage<'_>> {
+        if self.page_complete() {
+            RawPage::parse(&self.page[..self.filled]).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether data is buffered, i.e. a page or a packet is incomplete.
+    pub fn has_partial_data(&self) -> bool {
+        self.filled > 0 || self.assembler.has_partial_packet()
+    }
+
+    /**
+     * Returns the next complete packet, or [`None`] if more data must be pushed first.
+     *
+     * Packets that do not fit to the buffer are skipped and reported with
+     * [`BufferTooSmallError`][`OggError::BufferTooSmallError`]. Missing pages are reported with
+     * [`InvalidStream`][`OggError::InvalidStream`] and the packet that was cut by them is
+     * discarded. Bytes skipped before the page are reported with
+     * [`LostSync`][`OggError::LostSync`]. Parsing can continue after these errors.
+     */
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<Packet<'_>>, OggError> {
+        if self.step()? {
+            Ok(Some(self.packet()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the packet that was completed by the last [`step`][`OggPusher::step`].
+    pub(crate) fn packet(&self) -> Packet<'_> {
+        self.assembler.packet()
+    }
+
+    /**
+     * Return the next packet in chunks instead of collecting it to the packet buffer.
+     *
+     * Each [`step`][`OggPusher::step`] that returns `true` leaves the part of the packet on the
+     * buffered page to [`chunk`][`OggPusher::chunk`]. The packet may be of any size.
+     */
+    #[cfg(feature = "embedded-io")]
+    pub(crate) fn stream_packet(&mut self) {
+        self.assembler.chunked = true;
+    }
+
+    /// Returns whether the packet is still returned in chunks, i.e. its last chunk is not reached.
+    #[cfg(feature = "embedded-io")]
+    pub(crate) fn is_streaming(&self) -> bool {
+        self.assembler.chunked
+    }
+
+    /// Returns the chunk of packet that was found by the last [`step`][`OggPusher::step`].
+    #[cfg(feature = "embedded-io")]
+    pub(crate) fn chunk(&self) -> &[u8] {
+        let start = HEADER_SIZE + usize::from(self.page[HEADER_SIZE - 1]);
+        &self.page[start..][self.assembler.chunk.clone()]
+    }
+
+    /// Parse the buffered page until a packet is complete. Returns whether one was completed.
+    pub(crate) fn step(&mut self) -> Result<bool, OggError> {
+        if !self.page_complete() {
+            self.assembler.release();
+            return Ok(false);
+        }
+        if self.skipped > 0 {
+            return Err(OggError::LostSync(core::mem::take(&mut self.skipped)));
+        }
+        let page = RawPage::parse(&self.page[..self.filled])?;
+        if self.assembler.step(&page)? {
+            Ok(true)
+        } else {
+            self.filled = 0;
+            Ok(false)
+        }
+    }
+}
+
+/**
+ * Collects packets from the segments of pages of one logical stream.
+ *
+ * Pages are given one at a time until all of their segments have been read.
+ */
+#[derive(Debug)]
+pub(crate) struct Assembler<const PACKET_SIZE: usize> {
+    segment: usize,
+    offset: usize,
+    started: bool,
+    packet: [u8; PACKET_SIZE],
+    packet_length: usize,
+    packet_ready: bool,
+    skipping: bool,
+    page_sequence_number: Option<u32>,
+    /// Whether the next packet is returned in chunks without copying.
+    chunked: bool,
+    /// Range of page data of the last chunk.
+    chunk: core::ops::Range<usize>,
+    /// Where the completed packet is in the stream, without its data.
+    position: Packet<'static>,
+}
+
+impl<const PACKET_SIZE: usize> Assembler<PACKET_SIZE> {
+    /// Construct empty [`Assembler`].
+    pub(crate) const fn new() -> Self {
+        Self {
+            segment: 0,
+            offset: 0,
+            started: false,
+            packet: [0; PACKET_SIZE],
+            packet_length: 0,
+            packet_ready: false,
+            skipping: false,
+            page_sequence_number: None,
+            chunked: false,
+            chunk: 0..0,
+            position: Packet::new(&[]),
+        }
+    }
+
+    /// Returns whether a packet has been started but not completed.
+    pub(crate) fn has_partial_packet(&self) -> bool {
+        self.packet_length > 0 && !self.packet_ready
+    }
+
+    /// Returns the packet that was completed by the last [`step`][`Assembler::step`].
+    pub(crate) fn packet(&self) -> Packet<'_> {
+        Packet {
+            data: &self.packet[..self.packet_length],
+            ..self.position
+        }
+    }
+
+    /// Forget the packet that was completed by the last [`step`][`Assembler::step`].
+    fn release(&mut self) {
+        if self.packet_ready {
+            self.packet_ready = false;
+            self.packet_length = 0;
+        }
+    }
+
+    /**
+     * Read segments of page until a packet is complete. Returns whether one was completed.
+     *
+     * The same page must be given until this returns `false`, which means that all of its
+     * segments have been read.
+     */
+    pub(crate) fn step(&mut self, page: &RawPage<'_>) -> Result<bool, OggError> {
+        self.release();
+        if !self.started {
+            self.started = true;
+            let continued = page.continued();
+            let previous = self
+                .page_sequence_number
+                .replace(page.page_sequence_number());
+            if !continued && self.packet_length > 0 {
+                // Packet was not completed on the previous page
+                self.packet_length = 0;
+                self.skipping = false;
+            } else if continued && self.packet_length == 0 {
+                // Beginning of the packet was lost
+                self.skipping = true;
+            }
+            if let Some(previous) = previous {
+                if page.page_sequence_number() != previous.wrapping_add(1) {
+                    if continued {
+                        self.skipping = true;
+                    }
+                    self.packet_length = 0;
+                    return Err(OggError::InvalidStream(
+                        ErrorValues::SequenceNumberMismatch(previous, page.page_sequence_number()),
+                    ));
+                }
+            }
+        }
+        let table = page.segment_table();
+        let mut chunk = self.offset;
+        while self.segment < table.len() {
+            let size = usize::from(table[self.segment]);
+            let data = &page.data()[self.offset..self.offset + size];
+            self.segment += 1;
+            self.offset += size;
+            if !self.skipping {
+                let length = self.packet_length + size;
+                if length <= PACKET_SIZE && !self.chunked {
+                    self.packet[self.packet_length..length].copy_from_slice(data);
+                }
+                self.packet_length = length;
+            }
+            if size < 255 {
+                let length = self.packet_length;
+                if self.skipping {
+                    self.skipping = false;
+                    chunk = self.offset;
+                } else if self.chunked {
+                    self.chunked = false;
+                    self.packet_length = 0;
+                    self.chunk = chunk..self.offset;
+                    return Ok(true);
+                } else if length > PACKET_SIZE {
+                    self.packet_length = 0;
+                    return Err(OggError::BufferTooSmallError(PACKET_SIZE, length));
+                } else {
+                    self.packet_ready = true;
+                    self.position = Packet::on_page(&[], page.page(), &table[self.segment..]);
+                    return Ok(true);
+                }
+            }
+        }
+        if self.chunked && !self.skipping && chunk < self.offset {
+            // The packet continues on the next page
+            self.chunk = chunk..self.offset;
+            return Ok(true);
+        }
+        self.segment = 0;
+        self.offset = 0;
+        self.started = false;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Bitstream, PacketSource, MAX_PAGE_SIZE};
+
+    const DATA: &[u8] = include_bytes!("test/mono.opus");
+
+    /// Push data in chunks and collect packets.
+    fn collect<const PAGE_SIZE: usize, const PACKET_SIZE: usize>(
+        pusher: &mut OggPusher<PAGE_SIZE, PACKET_SIZE>,
+        data: &[u8],
+        chunk_size: usize,
+    ) -> Vec<Result<Vec<u8>, OggError>> {
+        let mut packets = Vec::new();
+        for mut chunk in data.chunks(chunk_size) {
+            while !chunk.is_empty() {
+                let consumed = pusher.push(chunk).unwrap();
+                chunk = &chunk[consumed..];
+                loop {
+                    match pusher.next() {
+                        Ok(Some(packet)) => packets.push(Ok(Vec::from(packet.data))),
+                        Ok(None) => break,
+                        Err(error) => packets.push(Err(error)),
+                    }
+                }
+            }
+        }
+        packets
+    }
+
+    #[test]
+    fn push_chunks() {
+        let bitstream = Bitstream::new(DATA);
+        let mut expected = Vec::new();
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        while let Some(packet) = reader.next_packet().unwrap() {
+            expected.push(Ok(Vec::from(packet.data)));
+        }
+        for chunk_size in [1, 7, 27, 100, DATA.len()] {
+            let mut pusher = OggPusher::<256, 128>::new();
+            let packets = collect(&mut pusher, DATA, chunk_size);
+            assert_eq!(
+                &packets[..2],
+                &[Ok(DATA[28..47].into()), Ok(DATA[75..136].into())]
+            );
+            assert_eq!(&packets[2..], &expected);
+            assert!(!pusher.has_partial_data());
+        }
+    }
+
+    #[test]
+    fn memory_bound() {
+        // Buffers and a small fixed overhead
+        let size = core::mem::size_of::<OggPusher<100, 256>>();
+        assert!(size <= 100 + 256 + 192, "{} bytes", size);
+    }
+
+    #[test]
+    fn packet_positions() {
+        let mut pusher = OggPusher::<MAX_PAGE_SIZE, 128>::new();
+        let mut data = DATA;
+        let mut packets = Vec::new();
+        while !data.is_empty() {
+            data = &data[pusher.push(data).unwrap()..];
+            while let Some(packet) = pusher.next().unwrap() {
+                packets.push((
+                    packet.granule_position,
+                    packet.ends_page,
+                    packet.begin_of_stream,
+                    packet.end_of_stream,
+                ));
+            }
+        }
+        assert_eq!(packets.len(), 9);
+        assert_eq!(packets[0], (Some(0), true, true, false));
+        assert_eq!(packets[1], (Some(0), true, false, false));
+        assert_eq!(packets[2..8], [(None, false, false, false); 6]);
+        assert_eq!(packets[8], (Some(7992), true, false, true));
+    }
+
+    #[test]
+    fn packet_over_pages() {
+        let data = include_bytes!("test/split.ogg");
+        let mut pusher = OggPusher::<512, 512>::new();
+        let packets = collect(&mut pusher, data, 50);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].as_ref().unwrap().len(), 300);
+        let mut pusher = OggPusher::<512, 256>::new();
+        let packets = collect(&mut pusher, data, 50);
+        assert_eq!(packets, [Err(OggError::BufferTooSmallError(256, 300))]);
+    }
+
+    #[test]
+    fn packet_over_full_page() {
+        let data = include_bytes!("test/split.ogg");
+        let mut full = Vec::from(&data[..26]);
+        full.push(255);
+        full.extend_from_slice(&[255; 255]);
+        full.resize(full.len() + 255 * 255, 1);
+        let checksum = crc::page_checksum(&SoftwareCrc, &full);
+        full[22..26].copy_from_slice(&checksum.to_le_bytes());
+        let data = [&full[..], &data[283..]].concat();
+        let mut pusher = OggPusher::<MAX_PAGE_SIZE, 65_536>::new();
+        let packets = collect(&mut pusher, &data, 1_000);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].as_ref().unwrap().len(), 255 * 255 + 45);
+        let mut pusher = OggPusher::<MAX_PAGE_SIZE, 65_024>::new();
+        let packets = collect(&mut pusher, &data, 1_000);
+        assert_eq!(
+            packets,
+            [Err(OggError::BufferTooSmallError(65_024, 255 * 255 + 45))]
+        );
+    }
+
+    #[test]
+    fn lost_page() {
+        let mut data = Vec::from(&DATA[..47]);
+        data.extend_from_slice(&DATA[136..]);
+        let mut pusher = OggPusher::<256, 128>::new();
+        let packets = collect(&mut pusher, &data, 10);
+        assert_eq!(packets.len(), 9);
+        assert_eq!(
+            packets[1],
+            Err(OggError::InvalidStream(
+                ErrorValues::SequenceNumberMismatch(0, 2)
+            ))
+        );
+        assert!(packets[1].as_ref().unwrap_err().is_recoverable());
+        assert!(packets[2..].iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut data = Vec::from(DATA);
+        data[136 + 40] ^= 1;
+        let mut pusher = OggPusher::<256, 128>::new();
+        assert_eq!(pusher.push(&data), Ok(47));
+        assert!(pusher.next().unwrap().is_some());
+        assert_eq!(pusher.next(), Ok(None));
+        assert_eq!(pusher.push(&data[47..]), Ok(89));
+        assert!(pusher.next().unwrap().is_some());
+        assert_eq!(pusher.next(), Ok(None));
+        assert_eq!(pusher.push(&data[136..]), Err(OggError::CrcMismatch(2)));
+        assert!(!pusher.has_partial_data());
+        let mut pusher = OggPusher::<256, 128>::new().with_crc_check(false);
+        let packets = collect(&mut pusher, &data, 64);
+        assert_eq!(packets.len(), 9);
+        assert!(packets.iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn lost_sync() {
+        // Garbage between pages and bytes dropped from the middle of the last page
+        let mut data = Vec::from(&DATA[..47]);
+        data.extend_from_slice(b"OgOggxOgg");
+        data.extend_from_slice(&DATA[47..200]);
+        data.extend_from_slice(&DATA[210..]);
+        data.extend_from_slice(&DATA[136..]);
+        data.extend_from_slice(&DATA[136..]);
+        let mut pusher = OggPusher::<256, 128>::new();
+        let mut packets = Vec::new();
+        for byte in data.chunks(1) {
+            if let Err(error) = pusher.push(byte) {
+                packets.push(Err(error));
+            }
+            while let Some(packet) = pusher.next().transpose() {
+                packets.push(packet.map(|packet| Vec::from(packet.data)));
+            }
+        }
+        assert_eq!(packets.len(), 12);
+        assert_eq!(packets[0], Ok(DATA[28..47].into()));
+        assert_eq!(packets[1], Err(OggError::LostSync(9)));
+        assert!(packets[1].as_ref().unwrap_err().is_recoverable());
+        assert_eq!(packets[2], Ok(DATA[75..136].into()));
+        // The broken page takes the beginning of the next page with it
+        assert_eq!(packets[3], Err(OggError::CrcMismatch(2)));
+        assert_eq!(packets[4], Err(OggError::LostSync(130 - 10)));
+        assert!(packets[5..].iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn invalid_data() {
+        let mut pusher = OggPusher::<256, 128>::new();
+        assert_eq!(pusher.push(&[0; 30]), Ok(30));
+        assert!(!pusher.has_partial_data());
+        let mut page = Vec::from(&DATA[..47]);
+        page[4] = 1;
+        assert_eq!(pusher.push(&page), Err(OggError::UnsupportedVersion(1)));
+        // Parsing continues after the discarded bytes
+        assert!(!pusher.has_partial_data());
+        let packets = collect(&mut pusher, DATA, 64);
+        assert_eq!(packets.len(), 10);
+        assert_eq!(packets[0], Err(OggError::LostSync(30 + 27)));
+        let mut pusher = OggPusher::<64, 128>::new();
+        assert_eq!(pusher.push(DATA), Ok(47));
+        assert_eq!(pusher.next().unwrap().unwrap().data, &DATA[28..47]);
+        assert_eq!(pusher.next(), Ok(None));
+        assert_eq!(
+            pusher.push(&DATA[47..]),
+            Err(OggError::PageTooLargeError(64, 89))
+        );
+    }
+
+    #[test]
+    fn current_page() {
+        let mut pusher = OggPusher::<256, 128>::new();
+        assert_eq!(pusher.push(&DATA[..40]), Ok(40));
+        assert!(pusher.current_page().is_none());
+        assert!(pusher.has_partial_data());
+        assert_eq!(pusher.push(&DATA[40..]), Ok(7));
+        let page = pusher.current_page().unwrap();
+        assert!(page.begin_of_stream());
+        assert_eq!(page.as_bytes(), &DATA[..47]);
+    }
+}
@@ -0,0 +1,205 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Reading opus packets that are stored with length prefixes.
+ *
+ * Some firmware stores bare opus packets without a container, each prefixed with its length as a
+ * 16-bit integer. [`RawReader`] returns them through [`PacketSource`] like
+ * [`PacketReader`][`crate::PacketReader`] does for ogg streams, so that e.g.
+ * [`Frames`][`crate::Frames`] can decode both. Such data has no identification header, so it is
+ * given when constructing the reader.
+ *
+ * ```rust
+ * # use oggopus_embedded::{opus::OpusHeader, raw::RawReader, ChannelMapping, PacketSource};
+ * let data = [3, 0, 0x08, 0x01, 0x02, 2, 0, 0x08, 0x03];
+ * let header = OpusHeader {
+ *     version: 1,
+ *     channels: ChannelMapping::Family0 { channels: 1 },
+ *     pre_skip: 312,
+ *     sample_rate: 48_000,
+ *     output_gain: 0,
+ * };
+ * let mut reader = RawReader::new(&data, header);
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * ```
+ */
+
+use super::{opus::OpusHeader, Packet, PacketSource};
+use core::num::NonZeroUsize;
+
+/// Size of the length prefix of each packet.
+pub const PREFIX_SIZE: usize = 2;
+
+/// Error from reading length-prefixed packets.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum RawError {
+    /**
+     * Data ended in the middle of a length prefix or a packet.
+     *
+     * Contains how many more bytes would have been needed.
+     */
+    EndOfStreamError(NonZeroUsize),
+}
+
+impl core::fmt::Display for RawError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::EndOfStreamError(size) => f.write_fmt(format_args!(
+                "packets ended abruptly with {} more bytes needed",
+                size
+            )),
+        }
+    }
+}
+
+impl core::error::Error for RawError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        None
+    }
+}
+
+/**
+ * [`PacketSource`] for opus packets that are prefixed with their length.
+ *
+ * Lengths are little-endian by default. Packets are returned directly from the input data without
+ * copying.
+ */
+#[derive(Debug)]
+pub struct RawReader<'data> {
+    header: OpusHeader,
+    remaining: &'data [u8],
+    big_endian: bool,
+}
+
+impl<'data> RawReader<'data> {
+    /// Construct [`RawReader`] for data with the header of the stream.
+    pub const fn new(data: &'data [u8], header: OpusHeader) -> Self {
+        Self {
+            header,
+            remaining: data,
+            big_endian: false,
+        }
+    }
+
+    /// Set whether lengths are big-endian.
+    pub const fn with_big_endian(mut self, big_endian: bool) -> Self {
+        self.big_endian = big_endian;
+        self
+    }
+
+    /// Returns the data that has not been read yet.
+    pub fn remaining(&self) -> &'data [u8] {
+        self.remaining
+    }
+}
+
+impl PacketSource for RawReader<'_> {
+    type Error = RawError;
+
+    fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet<'_>>, RawError> {
+        if self.remaining.is_empty() {
+            return Ok(None);
+        }
+        let needed = |size: usize| -> RawError {
+            // PANIC: Only called when there is less data than size
+            RawError::EndOfStreamError(NonZeroUsize::new(size).unwrap())
+        };
+        let Some((prefix, input)) = self.remaining.split_first_chunk::<PREFIX_SIZE>() else {
+            return Err(needed(PREFIX_SIZE - self.remaining.len()));
+        };
+        let length = usize::from(if self.big_endian {
+            u16::from_be_bytes(*prefix)
+        } else {
+            u16::from_le_bytes(*prefix)
+        });
+        if input.len() < length {
+            return Err(needed(length - input.len()));
+        }
+        let (data, remaining) = input.split_at(length);
+        self.remaining = remaining;
+        Ok(Some(Packet::new(data)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{frames::test::CountingDecoder, Bitstream, Frames};
+
+    /// Returns the header and packets of mono.opus with length prefixes.
+    fn mono(big_endian: bool) -> (OpusHeader, Vec<u8>) {
+        let bitstream = Bitstream::new(include_bytes!("test/mono.opus"));
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        let mut data = Vec::new();
+        while let Some(packet) = reader.next_packet().unwrap() {
+            let length = packet.data.len() as u16;
+            if big_endian {
+                data.extend_from_slice(&length.to_be_bytes());
+            } else {
+                data.extend_from_slice(&length.to_le_bytes());
+            }
+            data.extend_from_slice(packet.data);
+        }
+        let header = OpusHeader::parse(&include_bytes!("test/mono.opus")[28..47]).unwrap();
+        (header, data)
+    }
+
+    #[test]
+    fn read_packets() {
+        for big_endian in [false, true] {
+            let (header, data) = mono(big_endian);
+            let bitstream = Bitstream::new(include_bytes!("test/mono.opus"));
+            let mut expected = bitstream.packet_reader::<512>().unwrap();
+            let mut reader = RawReader::new(&data, header).with_big_endian(big_endian);
+            assert_eq!(reader.header(), expected.header());
+            while let Some(packet) = expected.next_packet().unwrap() {
+                assert_eq!(reader.next_packet().unwrap().unwrap().data, packet.data);
+            }
+            assert_eq!(reader.next_packet(), Ok(None));
+        }
+    }
+
+    #[test]
+    fn decode_packets() {
+        let (header, data) = mono(false);
+        let pre_skip = usize::from(header.pre_skip) / 6;
+        let reader = RawReader::new(&data, header);
+        let mut frames = Frames::<_, _, 160>::new(reader, CountingDecoder::default());
+        let mut samples = 0;
+        while let Some(frame) = frames.next().unwrap() {
+            samples += frame.len();
+        }
+        assert_eq!(samples, 7 * 160 - pre_skip);
+    }
+
+    #[test]
+    fn truncated() {
+        let (header, data) = mono(false);
+        let mut reader = RawReader::new(&data[..data.len() - 1], header);
+        for _ in 0..6 {
+            reader.next_packet().unwrap();
+        }
+        let error = reader.next_packet().unwrap_err();
+        assert_eq!(error, RawError::EndOfStreamError(NonZeroUsize::MIN));
+        assert_eq!(
+            error.to_string(),
+            "packets ended abruptly with 1 more bytes needed"
+        );
+        let (header, _) = mono(false);
+        let mut reader = RawReader::new(&[5], header);
+        assert_eq!(
+            reader.next_packet(),
+            Err(RawError::EndOfStreamError(NonZeroUsize::MIN))
+        );
+    }
+}
@@ -0,0 +1,194 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Retrying reads from flaky storage.
+
+use embedded_io::{ErrorType, Read};
+
+/**
+ * Reader wrapper that retries failed reads.
+ *
+ * SPI flash and SD card reads may fail transiently, e.g. because of vibration or interference.
+ * This retries such reads up to the configured number of times before giving up, so that one
+ * failed read does not stop playback. Failed reads have not consumed data so they can be retried
+ * as is. Use it as the reader of [`IoPacketReader`][`crate::IoPacketReader`].
+ *
+ * ```rust
+ * # use oggopus_embedded::{retry::RetryingSource, IoPacketReader, PacketSource, MAX_PAGE_SIZE};
+ * # let file = &include_bytes!("test/mono.opus")[..];
+ * # fn delay_ms(_: u32) {}
+ * // Retry three times and wait longer after each failure
+ * let source = RetryingSource::new(file, 3).with_backoff(|attempt| delay_ms(10 * attempt));
+ * let mut reader = IoPacketReader::<_, MAX_PAGE_SIZE, 1_024>::new(source).unwrap();
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     // Decode packet
+ * }
+ * ```
+ */
+#[derive(Debug)]
+pub struct RetryingSource<R: Read, B: FnMut(u32) = fn(u32)> {
+    reader: R,
+    retries: u32,
+    backoff: B,
+    classify: fn(&R::Error) -> bool,
+    retried: u32,
+}
+
+impl<R: Read> RetryingSource<R> {
+    /**
+     * Construct [`RetryingSource`] that retries failed reads `retries` times.
+     *
+     * All errors are retried without waiting by default.
+     */
+    pub fn new(reader: R, retries: u32) -> Self {
+        Self {
+            reader,
+            retries,
+            backoff: |_| {},
+            classify: |_| true,
+            retried: 0,
+        }
+    }
+}
+
+impl<R: Read, B: FnMut(u32)> RetryingSource<R, B> {
+    /**
+     * Set backoff hook that is called before retrying.
+     *
+     * The hook gets the number of the attempt starting from one and may e.g. wait or reset the
+     * storage peripheral.
+     */
+    pub fn with_backoff<F: FnMut(u32)>(self, backoff: F) -> RetryingSource<R, F> {
+        RetryingSource {
+            reader: self.reader,
+            retries: self.retries,
+            backoff,
+            classify: self.classify,
+            retried: self.retried,
+        }
+    }
+
+    /**
+     * Set function that tells which errors are transient and may be retried.
+     *
+     * Other errors are returned immediately.
+     */
+    pub fn with_classifier(mut self, classify: fn(&R::Error) -> bool) -> Self {
+        self.classify = classify;
+        self
+    }
+
+    /// Returns the number of reads that have been retried in total. Wraps around on overflow.
+    pub fn retried(&self) -> u32 {
+        self.retried
+    }
+
+    /// Returns the wrapped reader.
+    pub fn reader(&self) -> &R {
+        &self.reader
+    }
+
+    /// Deconstruct [`RetryingSource`] into the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read, B: FnMut(u32)> ErrorType for RetryingSource<R, B> {
+    type Error = R::Error;
+}
+
+impl<R: Read, B: FnMut(u32)> Read for RetryingSource<R, B> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let mut attempt = 0;
+        loop {
+            match self.reader.read(buf) {
+                Err(error) if attempt < self.retries && (self.classify)(&error) => {
+                    attempt += 1;
+                    self.retried = self.retried.wrapping_add(1);
+                    (self.backoff)(attempt);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{IoPacketReader, IoReaderError, PacketSource};
+    use embedded_io::ErrorKind;
+
+    /// Reader that fails `failures` times before every successful read.
+    #[derive(Debug)]
+    struct FlakyReader<'data> {
+        data: &'data [u8],
+        failures: u32,
+        failed: u32,
+        error: ErrorKind,
+    }
+
+    impl ErrorType for FlakyReader<'_> {
+        type Error = ErrorKind;
+    }
+
+    impl Read for FlakyReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+            if self.failed < self.failures {
+                self.failed += 1;
+                return Err(self.error);
+            }
+            self.failed = 0;
+            self.data.read(buf).map_err(|error| match error {})
+        }
+    }
+
+    fn flaky(failures: u32, error: ErrorKind) -> FlakyReader<'static> {
+        FlakyReader {
+            data: include_bytes!("test/mono.opus"),
+            failures,
+            failed: 0,
+            error,
+        }
+    }
+
+    #[test]
+    fn retry_reads() {
+        let mut attempts = Vec::new();
+        let source = RetryingSource::new(flaky(2, ErrorKind::Other), 2)
+            .with_backoff(|attempt| attempts.push(attempt));
+        let mut reader = IoPacketReader::<_, 512, 512>::new(source).unwrap();
+        let mut count = 0;
+        while reader.next_packet().unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 7);
+        let source = reader.into_inner();
+        assert!(source.reader().data.is_empty());
+        let retried = source.retried();
+        assert!(retried > 0);
+        assert_eq!(attempts.len(), retried as usize);
+        assert_eq!(attempts[..2], [1, 2]);
+    }
+
+    #[test]
+    fn too_many_failures() {
+        let source = RetryingSource::new(flaky(3, ErrorKind::Other), 2);
+        let result = IoPacketReader::<_, 512, 512>::new(source);
+        assert_eq!(
+            result.unwrap_err(),
+            IoReaderError::IoError(ErrorKind::Other)
+        );
+    }
+
+    #[test]
+    fn permanent_error() {
+        let mut source = RetryingSource::new(flaky(1, ErrorKind::NotFound), 5)
+            .with_classifier(|error| *error != ErrorKind::NotFound);
+        assert_eq!(source.read(&mut [0; 4]), Err(ErrorKind::NotFound));
+        assert_eq!(source.retried(), 0);
+        assert_eq!(source.read(&mut [0; 4]), Ok(4));
+    }
+}
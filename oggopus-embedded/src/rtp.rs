@@ -0,0 +1,494 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Receiving opus packets over RTP.
+ *
+ * [`RtpReceiver`] parses RTP headers of datagrams, e.g. from UDP, and returns their opus payloads
+ * as specified by [RFC7587](https://datatracker.ietf.org/doc/html/rfc7587). It follows sequence
+ * numbers and timestamps of the stream to tell how many packets and samples were lost before each
+ * packet. [`Received::decode`] conceals the gap with packet loss concealment and recovers the last
+ * lost packet from forward error correction data of the packet when the decoder supports it.
+ *
+ * Packets are expected in order. Packets that arrive late are rejected, so for networks that
+ * reorder packets, use a jitter buffer in front of this.
+ *
+ * ```rust
+ * # use oggopus_embedded::{rtp::RtpReceiver, Decode};
+ * # struct Decoder;
+ * # impl Decode for Decoder {
+ * #     type Error = ();
+ * #     fn sample_rate(&self) -> u32 { 48_000 }
+ * #     fn channels(&self) -> u8 { 1 }
+ * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+ * #         Ok(&o[..960])
+ * #     }
+ * # }
+ * # let mut decoder = Decoder;
+ * # let datagrams = [[0x80, 0x78, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0x08]];
+ * # fn play(_: &[i16]) {}
+ * let mut receiver = RtpReceiver::new().with_payload_type(120);
+ * let mut output = [0; 5_760];
+ * for datagram in datagrams {
+ *     match receiver.receive(&datagram) {
+ *         Ok(received) => received.decode(&mut decoder, &mut output, play).unwrap(),
+ *         Err(error) => println!("Dropped packet: {}", error),
+ *     }
+ * }
+ * ```
+ */
+
+use super::{opus::packet_samples, Decode};
+
+/// Clock rate of RTP timestamps of opus, which is always 48 kHz.
+pub const CLOCK_RATE: u32 = 48_000;
+
+/**
+ * The largest jump ahead in sequence numbers that is taken as lost packets.
+ *
+ * Larger jumps restart the stream as recommended by
+ * [RFC3550](https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.1).
+ */
+pub const MAX_DROPOUT: u16 = 3_000;
+
+/// The largest jump back in sequence numbers that is taken as a late packet.
+pub const MAX_MISORDER: u16 = 100;
+
+/// The longest gap in samples at 48 kHz that is concealed, one second.
+pub const MAX_CONCEALMENT: u32 = CLOCK_RATE;
+
+/// Size of fixed RTP header.
+const HEADER_SIZE: usize = 12;
+
+/// Error from receiving RTP packet.
+///
+/// Errors concern only one datagram, so receiving may always continue with the next one.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum RtpError {
+    /// RTP version is not 2.
+    UnsupportedVersion(u8),
+    /// Datagram is shorter than its header, extension or padding claim.
+    TruncatedPacket,
+    /// Payload type is not the one that was configured.
+    UnexpectedPayloadType(u8),
+    /**
+     * Packet arrived after a later packet or it is a duplicate.
+     *
+     * Contains the sequence number of the packet.
+     */
+    LatePacket(u16),
+}
+
+impl core::fmt::Display for RtpError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use RtpError::*;
+        match self {
+            UnsupportedVersion(version) => {
+                f.write_fmt(format_args!("unsupported RTP version: {}", version))
+            }
+            TruncatedPacket => f.write_str("RTP packet is truncated"),
+            UnexpectedPayloadType(payload_type) => {
+                f.write_fmt(format_args!("unexpected payload type: {}", payload_type))
+            }
+            LatePacket(sequence_number) => {
+                f.write_fmt(format_args!("packet {} arrived late", sequence_number))
+            }
+        }
+    }
+}
+
+impl core::error::Error for RtpError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        None
+    }
+}
+
+/// Fixed RTP header fields.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RtpHeader {
+    /// Marker bit, which is set on the first packet after silence for opus.
+    pub marker: bool,
+    /// Payload type, which is dynamically assigned for opus.
+    pub payload_type: u8,
+    /// Sequence number that increments by one for every packet.
+    pub sequence_number: u16,
+    /// Timestamp of the first sample of the packet at 48 kHz.
+    pub timestamp: u32,
+    /// Synchronization source that identifies the stream.
+    pub ssrc: u32,
+}
+
+impl RtpHeader {
+    /**
+     * Parse RTP header from datagram and return it with the payload.
+     *
+     * Contributing sources, header extension and padding are skipped.
+     */
+    pub fn parse(datagram: &[u8]) -> Result<(Self, &[u8]), RtpError> {
+        let Some((header, payload)) = datagram.split_first_chunk::<HEADER_SIZE>() else {
+            return Err(RtpError::TruncatedPacket);
+        };
+        let version = header[0] >> 6;
+        if version != 2 {
+            return Err(RtpError::UnsupportedVersion(version));
+        }
+        let padding = header[0] & 0x20 != 0;
+        let extension = header[0] & 0x10 != 0;
+        let csrc_count = usize::from(header[0] & 0x0F);
+        let mut payload = payload
+            .get(csrc_count * 4..)
+            .ok_or(RtpError::TruncatedPacket)?;
+        if extension {
+            let length = match payload {
+                [_, _, high, low, ..] => usize::from(u16::from_be_bytes([*high, *low])),
+                _ => return Err(RtpError::TruncatedPacket),
+            };
+            payload = payload
+                .get(4 + length * 4..)
+                .ok_or(RtpError::TruncatedPacket)?;
+        }
+        if padding {
+            let length = usize::from(*payload.last().ok_or(RtpError::TruncatedPacket)?);
+            if length == 0 || length > payload.len() {
+                return Err(RtpError::TruncatedPacket);
+            }
+            payload = &payload[..payload.len() - length];
+        }
+        let header = Self {
+            marker: header[1] & 0x80 != 0,
+            payload_type: header[1] & 0x7F,
+            sequence_number: u16::from_be_bytes([header[2], header[3]]),
+            timestamp: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            ssrc: u32::from_be_bytes([header[8], header[9], header[10], header[11]]),
+        };
+        Ok((header, payload))
+    }
+}
+
+/// Opus packet received over RTP.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Received<'data> {
+    /// RTP header of the packet.
+    pub header: RtpHeader,
+    /// Opus packet.
+    pub payload: &'data [u8],
+    /// The number of packets that were lost before this one.
+    pub lost: u16,
+    /**
+     * The number of samples at 48 kHz that are missing before this packet.
+     *
+     * This is nonzero also when no packets were lost but the sender stopped sending during
+     * silence with discontinuous transmission.
+     */
+    pub lost_samples: u32,
+    /**
+     * Whether the stream restarted, e.g. because its source changed, so the decoder must be
+     * reset.
+     */
+    pub restarted: bool,
+}
+
+impl Received<'_> {
+    /**
+     * Decode the packet and conceal the gap before it.
+     *
+     * Frames are given to `frame` in order. Missing samples are concealed in frames of at most
+     * 20 ms. If packets were lost, the last of them is recovered with
+     * [`Decode::decode_fec`] assuming that it was as long as this packet. Output must fit the
+     * decoded packet, and concealed frames are shortened to fit it.
+     */
+    pub fn decode<D: Decode>(
+        &self,
+        decoder: &mut D,
+        output: &mut [i16],
+        mut frame: impl FnMut(&[i16]),
+    ) -> Result<(), D::Error> {
+        if self.restarted {
+            decoder.reset()?;
+        }
+        let duration = packet_samples(self.payload).unwrap_or(0);
+        let recovered = if self.lost > 0 && self.lost_samples >= duration {
+            duration
+        } else {
+            0
+        };
+        let mut concealed = self.lost_samples - recovered;
+        while concealed > 0 {
+            let samples = concealed.min(CLOCK_RATE / 50);
+            concealed -= samples;
+            let end = output.len().min(output_length(decoder, samples));
+            frame(decoder.decode(&[], &mut output[..end])?);
+        }
+        if recovered > 0 {
+            let end = output.len().min(output_length(decoder, recovered));
+            frame(decoder.decode_fec(self.payload, &mut output[..end])?);
+        }
+        frame(decoder.decode(self.payload, output)?);
+        Ok(())
+    }
+}
+
+/// Returns the length of output for samples at 48 kHz from decoder.
+pub(crate) fn output_length<D: Decode>(decoder: &D, samples: u32) -> usize {
+    let length = u64::from(samples) * u64::from(decoder.sample_rate()) / u64::from(CLOCK_RATE)
+        * u64::from(decoder.channels());
+    usize::try_from(length).unwrap_or(usize::MAX)
+}
+
+/// Position of the stream after the last received packet.
+#[derive(Clone, Copy, Debug)]
+struct Expected {
+    ssrc: u32,
+    sequence_number: u16,
+    timestamp: u32,
+}
+
+/**
+ * Receives opus packets from RTP datagrams and detects lost packets.
+ *
+ * Only one stream is followed at a time. When a packet of another source arrives, the stream
+ * restarts from it.
+ */
+#[derive(Debug, Default)]
+pub struct RtpReceiver {
+    payload_type: Option<u8>,
+    expected: Option<Expected>,
+    lost: u32,
+    late: u32,
+}
+
+impl RtpReceiver {
+    /// Construct [`RtpReceiver`] that accepts any payload type.
+    pub const fn new() -> Self {
+        Self {
+            payload_type: None,
+            expected: None,
+            lost: 0,
+            late: 0,
+        }
+    }
+
+    /**
+     * Set payload type that opus packets have.
+     *
+     * Packets of other types, e.g. comfort noise or DTMF, are rejected with
+     * [`UnexpectedPayloadType`][`RtpError::UnexpectedPayloadType`].
+     */
+    pub const fn with_payload_type(mut self, payload_type: u8) -> Self {
+        self.payload_type = Some(payload_type);
+        self
+    }
+
+    /// Returns the number of packets that have been lost.
+    pub fn lost(&self) -> u32 {
+        self.lost
+    }
+
+    /// Returns the number of packets that have been rejected because they arrived late.
+    pub fn late(&self) -> u32 {
+        self.late
+    }
+
+    /// Receive RTP datagram and return the opus packet in it.
+    pub fn receive<'data>(&mut self, datagram: &'data [u8]) -> Result<Received<'data>, RtpError> {
+        let (header, payload) = RtpHeader::parse(datagram)?;
+        if self
+            .payload_type
+            .is_some_and(|payload_type| payload_type != header.payload_type)
+        {
+            return Err(RtpError::UnexpectedPayloadType(header.payload_type));
+        }
+        let (lost, lost_samples, restarted) = match self.expected {
+            None => (0, 0, false),
+            Some(expected) if expected.ssrc != header.ssrc => (0, 0, true),
+            Some(expected) => {
+                let gap = header
+                    .sequence_number
+                    .wrapping_sub(expected.sequence_number);
+                let lost_samples = header.timestamp.wrapping_sub(expected.timestamp);
+                if gap > u16::MAX - MAX_MISORDER {
+                    self.late = self.late.wrapping_add(1);
+                    return Err(RtpError::LatePacket(header.sequence_number));
+                } else if gap > MAX_DROPOUT || lost_samples > MAX_CONCEALMENT {
+                    (0, 0, true)
+                } else {
+                    (gap, lost_samples, false)
+                }
+            }
+        };
+        self.lost = self.lost.wrapping_add(u32::from(lost));
+        let duration = packet_samples(payload).unwrap_or(0);
+        self.expected = Some(Expected {
+            ssrc: header.ssrc,
+            sequence_number: header.sequence_number.wrapping_add(1),
+            timestamp: header.timestamp.wrapping_add(duration),
+        });
+        Ok(Received {
+            header,
+            payload,
+            lost,
+            lost_samples,
+            restarted,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Construct RTP datagram with payload type 120 and SSRC 1.
+    fn datagram(sequence_number: u16, timestamp: u32, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = vec![0x80, 120];
+        datagram.extend_from_slice(&sequence_number.to_be_bytes());
+        datagram.extend_from_slice(&timestamp.to_be_bytes());
+        datagram.extend_from_slice(&1u32.to_be_bytes());
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    /// Records what it was asked to decode.
+    #[derive(Default)]
+    pub(crate) struct RecordingDecoder {
+        pub calls: Vec<(&'static str, usize)>,
+    }
+
+    impl Decode for RecordingDecoder {
+        type Error = ();
+
+        fn sample_rate(&self) -> u32 {
+            16_000
+        }
+
+        fn channels(&self) -> u8 {
+            2
+        }
+
+        fn decode<'output>(
+            &mut self,
+            packet: &[u8],
+            output: &'output mut [i16],
+        ) -> Result<&'output [i16], ()> {
+            let (kind, length) = match packet_samples(packet) {
+                Some(samples) => ("decode", samples as usize / 3 * 2),
+                None => ("conceal", output.len()),
+            };
+            self.calls.push((kind, length));
+            Ok(&output[..length])
+        }
+
+        fn decode_fec<'output>(
+            &mut self,
+            _packet: &[u8],
+            output: &'output mut [i16],
+        ) -> Result<&'output [i16], ()> {
+            self.calls.push(("fec", output.len()));
+            Ok(output)
+        }
+
+        fn reset(&mut self) -> Result<(), ()> {
+            self.calls.push(("reset", 0));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parse_header() {
+        // Marker, two contributing sources, extension of one word and two bytes of padding
+        let mut data = vec![
+            0xB2, 0xF8, 0x12, 0x34, 0, 0, 0x01, 0x00, 0xCA, 0xFE, 0xBA, 0xBE,
+        ];
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&[0xBE, 0xDE, 0x00, 0x01, 1, 2, 3, 4]);
+        data.extend_from_slice(&[0x08, 0xAA, 0, 2]);
+        let (header, payload) = RtpHeader::parse(&data).unwrap();
+        assert_eq!(
+            header,
+            RtpHeader {
+                marker: true,
+                payload_type: 120,
+                sequence_number: 0x1234,
+                timestamp: 256,
+                ssrc: 0xCAFE_BABE,
+            }
+        );
+        assert_eq!(payload, [0x08, 0xAA]);
+        assert_eq!(
+            RtpHeader::parse(&data[..20]),
+            Err(RtpError::TruncatedPacket)
+        );
+        data[0] = 0x40;
+        assert_eq!(
+            RtpHeader::parse(&data),
+            Err(RtpError::UnsupportedVersion(1))
+        );
+    }
+
+    #[test]
+    fn lost_packets() {
+        let mut receiver = RtpReceiver::new().with_payload_type(120);
+        // 20 ms packets
+        let first = datagram(65_535, 0, &[0x08]);
+        let received = receiver.receive(&first).unwrap();
+        assert_eq!((received.lost, received.lost_samples), (0, 0));
+        // Sequence number wraps around and two packets are lost
+        let third = datagram(2, 2_880, &[0x08]);
+        let received = receiver.receive(&third).unwrap();
+        assert_eq!((received.lost, received.lost_samples), (2, 1_920));
+        assert!(!received.restarted);
+        let mut decoder = RecordingDecoder::default();
+        let mut output = [0; 1_920];
+        let mut frames = Vec::new();
+        received
+            .decode(&mut decoder, &mut output, |frame| frames.push(frame.len()))
+            .unwrap();
+        assert_eq!(
+            decoder.calls,
+            [("conceal", 640), ("fec", 640), ("decode", 640)]
+        );
+        assert_eq!(frames, [640, 640, 640]);
+        assert_eq!(
+            receiver.receive(&datagram(1, 1_920, &[0x08])),
+            Err(RtpError::LatePacket(1))
+        );
+        assert_eq!((receiver.lost(), receiver.late()), (2, 1));
+        // Silence with discontinuous transmission is concealed
+        let fourth = datagram(3, 5_760, &[0x08]);
+        let received = receiver.receive(&fourth).unwrap();
+        assert_eq!((received.lost, received.lost_samples), (0, 1_920));
+        let mut decoder = RecordingDecoder::default();
+        received.decode(&mut decoder, &mut output, |_| {}).unwrap();
+        assert_eq!(
+            decoder.calls,
+            [("conceal", 640), ("conceal", 640), ("decode", 640)]
+        );
+        assert_eq!(receiver.receive(&[0x80, 0]), Err(RtpError::TruncatedPacket));
+    }
+
+    #[test]
+    fn restart() {
+        let mut receiver = RtpReceiver::new();
+        receiver.receive(&datagram(10, 0, &[0x08])).unwrap();
+        // Jump far ahead
+        let data = datagram(5_000, 960, &[0x08]);
+        let received = receiver.receive(&data).unwrap();
+        assert!(received.restarted);
+        assert_eq!(received.lost, 0);
+        let mut decoder = RecordingDecoder::default();
+        received
+            .decode(&mut decoder, &mut [0; 640], |_| {})
+            .unwrap();
+        assert_eq!(decoder.calls, [("reset", 0), ("decode", 640)]);
+        // Another source
+        let mut data = datagram(5_001, 1_920, &[0x08]);
+        data[11] = 2;
+        assert!(receiver.receive(&data).unwrap().restarted);
+        let receiver = RtpReceiver::new().with_payload_type(96).receive(&data);
+        assert_eq!(receiver, Err(RtpError::UnexpectedPayloadType(120)));
+    }
+}
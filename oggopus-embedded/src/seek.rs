@@ -0,0 +1,294 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Seeking in ogg opus streams by granule position.
+
+use super::{
+    container::{ParseOptions, RawPage},
+    info::first_packet_position,
+    PacketSource,
+};
+
+/**
+ * The number of samples at 48 kHz that are decoded before the seek target.
+ *
+ * The decoder needs 80 ms of audio to converge after it has been reset as recommended by
+ * [RFC7845](https://datatracker.ietf.org/doc/html/rfc7845#section-4.6).
+ */
+pub(crate) const PRE_ROLL: u64 = 3_840;
+
+/**
+ * [`PacketSource`] that can move to another position in the stream.
+ *
+ * This is implemented by the readers that have random access to the data, i.e.
+ * [`PacketReader`][`crate::PacketReader`] and `StdPacketReader`. [`Frames::seek_to`] uses this
+ * to seek by time.
+ *
+ * [`Frames::seek_to`]: crate::Frames::seek_to
+ */
+pub trait SeekablePacketSource: PacketSource {
+    /**
+     * Move to the page from which the next packet begins at or before granule position.
+     *
+     * Granule positions are counted from the start granule position of the stream, so zero is the
+     * first sample of pre-skip. Returns the granule position where the next packet begins,
+     * counted the same way. Packets are read from the page found by bisection and the start
+     * granule position is known afterwards. Seeking past the end moves to the last page.
+     */
+    fn seek_granule(&mut self, granule_position: u64) -> Result<u64, Self::Error>;
+
+    /**
+     * Move to the page of an entry of [`SeekIndex`] that was built for this stream.
+     *
+     * Returns the granule position of the entry. This does not need to look at other pages, so
+     * the time it takes does not depend on the length of the stream.
+     */
+    fn seek_entry(&mut self, entry: SeekEntry) -> Result<u64, Self::Error>;
+}
+
+/**
+ * Page to continue reading from when seeking.
+ *
+ * Offsets are in bytes from the beginning of the first header page of the stream, and granule
+ * positions are counted from the start of the stream like with
+ * [`SeekablePacketSource::seek_granule`].
+ */
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SeekEntry {
+    /// Granule position where the first packet that begins on the page begins.
+    pub granule_position: u64,
+    /// Offset of the page.
+    pub offset: u64,
+}
+
+impl SeekEntry {
+    /// Size of [`SeekEntry`] in bytes when stored.
+    pub const SIZE: usize = 16;
+
+    /// Returns the entry as bytes for storing it, e.g. to flash.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.granule_position.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.offset.to_le_bytes());
+        bytes
+    }
+
+    /// Construct [`SeekEntry`] from bytes returned by [`to_bytes`][`SeekEntry::to_bytes`].
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        let (granule_position, offset) = bytes.split_at(8);
+        Self {
+            // PANIC: Both halves are eight bytes
+            granule_position: u64::from_le_bytes(granule_position.try_into().unwrap()),
+            offset: u64::from_le_bytes(offset.try_into().unwrap()),
+        }
+    }
+}
+
+/**
+ * Table of pages for seeking without bisection.
+ *
+ * Entries are at least `interval` granule positions apart. If there would be more than
+ * `CAPACITY` entries, the interval is doubled and every other entry is dropped, so the table
+ * stays compact for streams of any length. The entries can be stored by the application and
+ * given to [`Frames::seek_indexed`][`crate::Frames::seek_indexed`] later, which makes seeking on
+ * slow media take only one jump.
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, SeekEntry};
+ * # let data = include_bytes!("test/offset.opus");
+ * let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+ * let index = reader.seek_index::<64>(core::time::Duration::from_secs(1)).unwrap();
+ * let stored: Vec<_> = index.entries().iter().map(SeekEntry::to_bytes).collect();
+ * ```
+ */
+#[derive(Clone, Debug)]
+pub struct SeekIndex<const CAPACITY: usize> {
+    entries: [SeekEntry; CAPACITY],
+    length: usize,
+    interval: u64,
+}
+
+impl<const CAPACITY: usize> SeekIndex<CAPACITY> {
+    /// Construct empty [`SeekIndex`] with entries `interval` granule positions apart.
+    pub(crate) fn new(interval: u64) -> Self {
+        Self {
+            entries: [SeekEntry::default(); CAPACITY],
+            length: 0,
+            interval: interval.max(1),
+        }
+    }
+
+    /// Add entry if it is far enough from the previous one.
+    pub(crate) fn add(&mut self, entry: SeekEntry) {
+        if CAPACITY == 0 {
+            return;
+        }
+        if let Some(last) = self.entries().last() {
+            if entry.granule_position < last.granule_position.saturating_add(self.interval) {
+                return;
+            }
+        }
+        if self.length == CAPACITY {
+            self.thin();
+            return self.add(entry);
+        }
+        self.entries[self.length] = entry;
+        self.length += 1;
+    }
+
+    /// Double the interval and keep the entries that are far enough apart.
+    fn thin(&mut self) {
+        self.interval = self.interval.saturating_mul(2);
+        let mut length: usize = 0;
+        for index in 0..self.length {
+            let entry = self.entries[index];
+            let previous = length.checked_sub(1).map(|last| self.entries[last]);
+            if previous.map_or(true, |previous| {
+                entry.granule_position >= previous.granule_position.saturating_add(self.interval)
+            }) {
+                self.entries[length] = entry;
+                length += 1;
+            }
+        }
+        self.length = length;
+    }
+
+    /// Returns the entries in the order of granule positions.
+    pub fn entries(&self) -> &[SeekEntry] {
+        &self.entries[..self.length]
+    }
+
+    /// Returns the minimum distance of entries in granule positions.
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+}
+
+/// Returns the last entry from which the next packet begins at or before granule position.
+pub(crate) fn lookup(entries: &[SeekEntry], granule_position: u64) -> Option<SeekEntry> {
+    let index = entries.partition_point(|entry| entry.granule_position <= granule_position);
+    index.checked_sub(1).map(|index| entries[index])
+}
+
+/// Page that was found when seeking.
+#[derive(Debug, PartialEq)]
+pub(crate) struct SeekPoint {
+    /// Offset of the page in the data.
+    pub offset: u64,
+    /// Granule position where the first packet that begins on the page begins.
+    pub packet_position: u64,
+}
+
+/**
+ * Find the first page of a stream in data on which a packet ends.
+ *
+ * Pages of other streams and data that does not pass the checks of options are skipped.
+ */
+pub(crate) fn find_page(
+    data: &[u8],
+    serial_number: u32,
+    options: &ParseOptions,
+) -> Option<SeekPoint> {
+    (0..data.len())
+        .filter(|&index| data[index..].starts_with(b"OggS"))
+        .filter_map(|index| Some((index, RawPage::parse_with(&data[index..], options).ok()?)))
+        .filter(|(_, page)| page.bitstream_serial_number() == serial_number)
+        .find_map(|(index, page)| {
+            Some(SeekPoint {
+                offset: index as u64,
+                packet_position: first_packet_position(&page)?,
+            })
+        })
+}
+
+/**
+ * Bisect pages between offsets `start` and `end` for the last one from which the next packet
+ * begins at or before granule position.
+ *
+ * `page_at` returns the first page at or after an offset on which a packet ends. Returns
+ * [`None`] if the packets of all pages that were looked at begin after the granule position, and
+ * then reading should begin from the first audio page.
+ */
+pub(crate) fn bisect<E>(
+    start: u64,
+    end: u64,
+    granule_position: u64,
+    mut page_at: impl FnMut(u64) -> Result<Option<SeekPoint>, E>,
+) -> Result<Option<SeekPoint>, E> {
+    let mut found = None;
+    let (mut low, mut high) = (start, end);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match page_at(middle)? {
+            Some(point) if point.offset < high && point.packet_position <= granule_position => {
+                low = point.offset + 1;
+                found = Some(point);
+            }
+            _ => high = middle,
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_pages() {
+        let data = include_bytes!("test/offset.opus");
+        let serial_number = u32::from_le_bytes(data[14..18].try_into().unwrap());
+        let options = ParseOptions::new();
+        let point = find_page(&data[136..], serial_number, &options).unwrap();
+        assert_eq!(point.offset, 0);
+        assert_eq!(point.packet_position, 48_000);
+        // The second audio page has four packets
+        let point = find_page(&data[137..], serial_number, &options).unwrap();
+        assert_eq!(point.offset, 205 - 137);
+        assert_eq!(point.packet_position, 48_000 + 3 * 960);
+        assert_eq!(find_page(&data[137..], serial_number + 1, &options), None);
+    }
+
+    #[test]
+    fn index() {
+        let mut index = SeekIndex::<3>::new(1_000);
+        for granule_position in (0..10_000).step_by(500) {
+            index.add(SeekEntry {
+                granule_position,
+                offset: granule_position / 10,
+            });
+        }
+        // The interval was doubled twice to fit every 4 000 granule positions
+        assert_eq!(index.interval(), 4_000);
+        let positions: Vec<_> = index.entries().iter().map(|e| e.granule_position).collect();
+        assert_eq!(positions, [0, 4_000, 8_000]);
+        assert_eq!(lookup(index.entries(), 7_999).unwrap().offset, 400);
+        assert_eq!(lookup(index.entries(), u64::MAX).unwrap().offset, 800);
+        assert_eq!(lookup(&index.entries()[1..], 3_999), None);
+        let entry = index.entries()[2];
+        assert_eq!(SeekEntry::from_bytes(entry.to_bytes()), entry);
+        let mut index = SeekIndex::<0>::new(0);
+        index.add(entry);
+        assert!(index.entries().is_empty());
+    }
+
+    #[test]
+    fn bisect_pages() {
+        // Pages at every 100 bytes with packets that begin 960 apart
+        let page_at = |offset: u64| -> Result<_, ()> {
+            let index = offset.div_ceil(100);
+            Ok((index < 10).then(|| SeekPoint {
+                offset: index * 100,
+                packet_position: (index + 1) * 960,
+            }))
+        };
+        let point = bisect(0, 1_000, 3_000, page_at).unwrap().unwrap();
+        assert_eq!(point.offset, 200);
+        assert_eq!(bisect(0, 1_000, 959, page_at), Ok(None));
+        let point = bisect(0, 1_000, u64::MAX, page_at).unwrap().unwrap();
+        assert_eq!(point.offset, 900);
+        assert_eq!(bisect(0, 0, 3_000, page_at), Ok(None));
+    }
+}
@@ -0,0 +1,422 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Framing of opus packets for byte streams such as UART or RS-485.
+ *
+ * Each packet is sent in a frame that begins with [`SYNC`] word, followed by little-endian
+ * 16-bit packet length and 32-bit CRC, and the packet. The CRC is calculated over the length and
+ * the packet with the same checksum as ogg pages use. Bytes that are lost or garbled on the line
+ * make the receiver search for the next sync word, so it recovers from line noise on its own.
+ *
+ * ```rust
+ * # use oggopus_embedded::serial::{encode_frame, FrameDecoder};
+ * let mut line = [0; 64];
+ * let mut length = encode_frame(&[0x08, 0x01, 0x02], &mut line).unwrap();
+ * length += encode_frame(&[0x08, 0x03], &mut line[length..]).unwrap();
+ * // Send the frames over UART and receive them on another device
+ * let mut decoder = FrameDecoder::<1_283>::new();
+ * for mut chunk in line[..length].chunks(4) {
+ *     while !chunk.is_empty() {
+ *         let consumed = decoder.push(chunk);
+ *         chunk = &chunk[consumed..];
+ *         while let Some(packet) = decoder.next().unwrap() {
+ *             println!("Got {} bytes", packet.len());
+ *         }
+ *     }
+ * }
+ * ```
+ */
+
+use super::crc;
+
+/// Sync word in the beginning of each frame.
+pub const SYNC: [u8; 2] = [0xA5, 0x4F];
+
+/// Size of frame header that precedes each packet.
+pub const FRAME_HEADER_SIZE: usize = SYNC.len() + 2 + 4;
+
+/// Error from framing or receiving packets.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum SerialError {
+    /// Bytes were skipped while searching for the next frame. Contains the number of bytes.
+    LostSync(usize),
+    /// Frame checksum did not match and the frame was discarded.
+    ChecksumMismatch,
+    /// Packet is longer than a frame can contain. Contains the length of the packet.
+    PacketTooLarge(usize),
+    /**
+     * Buffer was too small to contain frame.
+     *
+     * Contains the size of the buffer and how many bytes would have been needed.
+     */
+    BufferTooSmallError(usize, usize),
+}
+
+impl SerialError {
+    /**
+     * Returns whether receiving may continue after the error.
+     *
+     * Frames that were lost are skipped. When framing, the next packet may still fit if it is
+     * smaller.
+     */
+    pub fn is_recoverable(&self) -> bool {
+        use SerialError::*;
+        matches!(
+            self,
+            LostSync(_) | ChecksumMismatch | PacketTooLarge(_) | BufferTooSmallError(..)
+        )
+    }
+}
+
+impl core::fmt::Display for SerialError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use SerialError::*;
+        match self {
+            LostSync(count) => f.write_fmt(format_args!("lost sync, skipped {} bytes", count)),
+            ChecksumMismatch => f.write_str("checksum mismatch in frame"),
+            PacketTooLarge(length) => f.write_fmt(format_args!(
+                "packet is too large to frame: {} bytes",
+                length
+            )),
+            BufferTooSmallError(got, needed) => f.write_fmt(format_args!(
+                "buffer is too small: got {} but needed {}",
+                got, needed
+            )),
+        }
+    }
+}
+
+impl core::error::Error for SerialError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        None
+    }
+}
+
+/// Calculate checksum of a frame.
+fn checksum(length: [u8; 2], packet: &[u8]) -> u32 {
+    crc::update(crc::update(0, &length), packet)
+}
+
+/**
+ * Returns the frame header for packet.
+ *
+ * The header and then the packet can be written to the line without copying the packet, e.g.
+ * with DMA. See [`encode_frame`] for writing the whole frame to a buffer.
+ */
+pub fn frame_header(packet: &[u8]) -> Result<[u8; FRAME_HEADER_SIZE], SerialError> {
+    let length = u16::try_from(packet.len())
+        .map_err(|_| SerialError::PacketTooLarge(packet.len()))?
+        .to_le_bytes();
+    let crc = checksum(length, packet).to_le_bytes();
+    let mut header = [0; FRAME_HEADER_SIZE];
+    header[0..2].copy_from_slice(&SYNC);
+    header[2..4].copy_from_slice(&length);
+    header[4..8].copy_from_slice(&crc);
+    Ok(header)
+}
+
+/**
+ * Write packet in a frame to the beginning of buffer.
+ *
+ * Returns the size of the frame. Nothing is written if the frame does not fit to the buffer.
+ */
+pub fn encode_frame(packet: &[u8], buffer: &mut [u8]) -> Result<usize, SerialError> {
+    let header = frame_header(packet)?;
+    let size = FRAME_HEADER_SIZE + packet.len();
+    if buffer.len() < size {
+        return Err(SerialError::BufferTooSmallError(buffer.len(), size));
+    }
+    buffer[..FRAME_HEADER_SIZE].copy_from_slice(&header);
+    buffer[FRAME_HEADER_SIZE..size].copy_from_slice(packet);
+    Ok(size)
+}
+
+/**
+ * Incremental receiver of frames that is fed with bytes from the line.
+ *
+ * Bytes are given with [`push`][`FrameDecoder::push`] in whatever sizes they arrive, e.g. from
+ * a UART interrupt, and packets are taken out with [`next`][`FrameDecoder::next`]. One frame is
+ * buffered at a time so `FRAME_SIZE` must fit the largest packet with [`FRAME_HEADER_SIZE`].
+ * Opus packets are at most 1275 bytes for a single frame.
+ *
+ * Frames that claim to be larger than that or whose checksum does not match are taken as line
+ * noise, and the bytes after their sync word are searched for the next frame. Skipped bytes are
+ * reported with [`LostSync`][`SerialError::LostSync`] before the next packet.
+ */
+#[derive(Debug)]
+pub struct FrameDecoder<const FRAME_SIZE: usize> {
+    buffer: [u8; FRAME_SIZE],
+    filled: usize,
+    skipped: usize,
+    /// Whether a frame was discarded due to checksum mismatch since the last packet.
+    mismatch: bool,
+}
+
+impl<const FRAME_SIZE: usize> Default for FrameDecoder<FRAME_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const FRAME_SIZE: usize> FrameDecoder<FRAME_SIZE> {
+    /// The buffer must fit the frame header and some packet after it.
+    const LARGE_ENOUGH: () = assert!(
+        FRAME_SIZE > FRAME_HEADER_SIZE,
+        "FRAME_SIZE must be larger than FRAME_HEADER_SIZE"
+    );
+
+    /// Construct empty [`FrameDecoder`].
+    pub const fn new() -> Self {
+        let () = Self::LARGE_ENOUGH;
+        Self {
+            buffer: [0; FRAME_SIZE],
+            filled: 0,
+            skipped: 0,
+            mismatch: false,
+        }
+    }
+
+    /// Returns the length of the packet in the buffered frame header.
+    fn length(&self) -> usize {
+        usize::from(u16::from_le_bytes([self.buffer[2], self.buffer[3]]))
+    }
+
+    /// Returns the number of bytes the buffered frame needs in total as far as it is known.
+    fn wanted(&self) -> usize {
+        if self.filled < FRAME_HEADER_SIZE {
+            FRAME_HEADER_SIZE
+        } else {
+            FRAME_HEADER_SIZE + self.length()
+        }
+    }
+
+    /// Returns whether a whole frame is buffered.
+    fn frame_complete(&self) -> bool {
+        self.filled >= FRAME_HEADER_SIZE && self.filled == self.wanted()
+    }
+
+    /**
+     * Feed more bytes to the receiver.
+     *
+     * Returns the number of bytes consumed which is less than given if a frame was completed or
+     * discarded. The packet or the error must be taken with [`next`][`FrameDecoder::next`]
+     * before more bytes are consumed.
+     */
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let mut consumed = 0;
+        while !self.frame_complete() && !self.mismatch && consumed < data.len() {
+            let count = (self.wanted() - self.filled).min(data.len() - consumed);
+            self.buffer[self.filled..self.filled + count]
+                .copy_from_slice(&data[consumed..consumed + count]);
+            self.filled += count;
+            consumed += count;
+            self.check();
+        }
+        consumed
+    }
+
+    /// Discard buffered bytes until they may begin a frame and check the completed frame.
+    fn check(&mut self) {
+        loop {
+            let filled = self.filled;
+            if !SYNC.starts_with(&self.buffer[..filled.min(SYNC.len())])
+                || filled >= 4 && FRAME_HEADER_SIZE + self.length() > FRAME_SIZE
+            {
+                self.skip(1);
+                continue;
+            }
+            if self.frame_complete() {
+                let length = [self.buffer[2], self.buffer[3]];
+                let crc = u32::from_le_bytes([
+                    self.buffer[4],
+                    self.buffer[5],
+                    self.buffer[6],
+                    self.buffer[7],
+                ]);
+                if checksum(length, &self.buffer[FRAME_HEADER_SIZE..filled]) != crc {
+                    // The frame may hide the beginning of the next one
+                    let skipped = self.skipped;
+                    self.skip(1);
+                    self.skipped = skipped;
+                    self.mismatch = true;
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+
+    /// Discard at least `count` bytes from the beginning of the buffer until it may begin a frame.
+    fn skip(&mut self, count: usize) {
+        let filled = self.filled;
+        let start = (count..filled)
+            .find(|&index| {
+                let data = &self.buffer[index..filled];
+                SYNC.starts_with(&data[..data.len().min(SYNC.len())])
+            })
+            .unwrap_or(filled);
+        self.buffer.copy_within(start..filled, 0);
+        self.filled -= start;
+        self.skipped += start;
+    }
+
+    /// Returns whether bytes are buffered, i.e. a frame is incomplete.
+    pub fn has_partial_data(&self) -> bool {
+        self.filled > 0 && !self.frame_complete()
+    }
+
+    /// Discard buffered bytes, e.g. after the line was reopened.
+    pub fn reset(&mut self) {
+        self.filled = 0;
+        self.skipped = 0;
+        self.mismatch = false;
+    }
+
+    /**
+     * Returns the next packet, or [`None`] if more bytes must be pushed first.
+     *
+     * Bytes skipped before the frame are reported with [`LostSync`][`SerialError::LostSync`]
+     * and discarded frames with [`ChecksumMismatch`][`SerialError::ChecksumMismatch`]. Receiving
+     * can continue after these errors.
+     */
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<&[u8]>, SerialError> {
+        let complete = self.frame_complete();
+        if !complete && !self.mismatch {
+            return Ok(None);
+        }
+        if self.skipped > 0 {
+            return Err(SerialError::LostSync(core::mem::take(&mut self.skipped)));
+        }
+        if self.mismatch {
+            self.mismatch = false;
+            return Err(SerialError::ChecksumMismatch);
+        }
+        let filled = core::mem::take(&mut self.filled);
+        Ok(Some(&self.buffer[FRAME_HEADER_SIZE..filled]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PACKETS: [&[u8]; 3] = [&[0x08, 0x01, 0x02], &[0x08], &[0xA5, 0x4F, 0x01, 0x00]];
+
+    /// Frame packets one after another.
+    fn frames(packets: &[&[u8]]) -> Vec<u8> {
+        let mut line = Vec::new();
+        for packet in packets {
+            let mut buffer = [0; 64];
+            let size = encode_frame(packet, &mut buffer).unwrap();
+            line.extend_from_slice(&buffer[..size]);
+        }
+        line
+    }
+
+    /// Push bytes in chunks and collect packets and errors.
+    fn collect(data: &[u8], chunk_size: usize) -> Vec<Result<Vec<u8>, SerialError>> {
+        let mut decoder = FrameDecoder::<32>::new();
+        let mut packets = Vec::new();
+        for mut chunk in data.chunks(chunk_size) {
+            while !chunk.is_empty() {
+                let consumed = decoder.push(chunk);
+                chunk = &chunk[consumed..];
+                while let Some(packet) = decoder.next().transpose() {
+                    packets.push(packet.map(Vec::from));
+                }
+            }
+        }
+        packets
+    }
+
+    #[test]
+    fn encode() {
+        let mut buffer = [0; 16];
+        assert_eq!(encode_frame(&[1, 2, 3], &mut buffer), Ok(11));
+        assert_eq!(buffer[..4], [0xA5, 0x4F, 3, 0]);
+        assert_eq!(buffer[8..11], [1, 2, 3]);
+        assert_eq!(frame_header(&[1, 2, 3]).unwrap(), buffer[..8]);
+        assert_eq!(
+            encode_frame(&[0; 9], &mut buffer),
+            Err(SerialError::BufferTooSmallError(16, 17))
+        );
+        assert_eq!(
+            frame_header(&[0; 65_536]),
+            Err(SerialError::PacketTooLarge(65_536))
+        );
+    }
+
+    #[test]
+    fn receive_chunks() {
+        let line = frames(&PACKETS);
+        for chunk_size in [1, 3, 8, 100] {
+            let packets = collect(&line, chunk_size);
+            assert_eq!(packets, PACKETS.map(|packet| Ok(Vec::from(packet))));
+        }
+    }
+
+    #[test]
+    fn line_noise() {
+        // Noise before, between and after frames, with a partial sync word and a bogus length
+        let mut line = vec![0x00, 0xA5, 0x12, 0xA5, 0x4F, 0xFF, 0xFF];
+        line.extend(frames(&PACKETS[..1]));
+        line.extend([0xA5, 0xA5]);
+        line.extend(frames(&PACKETS[1..]));
+        line.extend([0x55; 3]);
+        let packets = collect(&line, 2);
+        assert_eq!(
+            packets,
+            [
+                Err(SerialError::LostSync(7)),
+                Ok(Vec::from(PACKETS[0])),
+                Err(SerialError::LostSync(2)),
+                Ok(Vec::from(PACKETS[1])),
+                Ok(Vec::from(PACKETS[2])),
+            ]
+        );
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut line = frames(&PACKETS);
+        line[9] ^= 0x10;
+        let packets = collect(&line, 5);
+        assert_eq!(
+            packets,
+            [
+                Err(SerialError::ChecksumMismatch),
+                Ok(Vec::from(PACKETS[1])),
+                Ok(Vec::from(PACKETS[2])),
+            ]
+        );
+        assert!(packets[0].as_ref().unwrap_err().is_recoverable());
+    }
+
+    #[test]
+    fn dropped_bytes() {
+        // A byte dropped from a frame makes it swallow the sync word of the next frame, which is
+        // found again when the discarded frame is searched
+        let line = frames(&PACKETS);
+        let line = [&line[..9], &line[10..]].concat();
+        let packets = collect(&line, 1);
+        assert_eq!(
+            packets,
+            [
+                Err(SerialError::ChecksumMismatch),
+                Ok(Vec::from(PACKETS[1])),
+                Ok(Vec::from(PACKETS[2])),
+            ]
+        );
+        let mut decoder = FrameDecoder::<32>::new();
+        assert_eq!(decoder.push(&line[..5]), 5);
+        assert!(decoder.has_partial_data());
+        decoder.reset();
+        assert!(!decoder.has_partial_data());
+        assert_eq!(decoder.next(), Ok(None));
+    }
+}
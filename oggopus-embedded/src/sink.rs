@@ -0,0 +1,489 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Decoding audio into sample queues and DMA buffers.
+ *
+ * [`SinkWriter`] keeps a [`SampleSink`] filled with decoded audio. This is useful when an
+ * interrupt or DMA driven consumer, e.g. I2S peripheral, drains samples from a queue while the
+ * main loop decodes more. If the sink becomes full, the rest of the frame is kept until the sink
+ * has space again, so no audio is lost.
+ *
+ * With `heapless` feature [`SampleSink`] is implemented for `heapless::spsc::Queue` and its
+ * `Producer`. With `embedded-dma` feature a static [`DoubleBuffer`] can be split into a half
+ * that implements `ReadBuffer` of `embedded_dma` for HAL DMA transfer APIs and a half that fills
+ * it.
+ */
+
+use super::{Decode, Frames, FramesError, PacketSource};
+
+/// Result for filling sinks.
+type FillResult<S, D> =
+    Result<FillStatus, FramesError<<S as PacketSource>::Error, <D as Decode>::Error>>;
+
+/// Destination for decoded samples.
+pub trait SampleSink {
+    /**
+     * Push samples into the sink and return how many were accepted.
+     *
+     * Accepting fewer samples than given means that the sink is full.
+     */
+    fn push_samples(&mut self, samples: &[i16]) -> usize;
+}
+
+/// Status of [`SampleSink`] after [`SinkWriter::fill`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FillStatus {
+    /// Sink is full and there are still samples waiting to be pushed.
+    Full,
+    /// Stream has ended and all samples have been pushed.
+    Ended,
+}
+
+/**
+ * Writes decoded frames into [`SampleSink`].
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, Decode, Frames};
+ * # use oggopus_embedded::sink::{FillStatus, SampleSink, SinkWriter};
+ * # struct Decoder;
+ * # impl Decode for Decoder {
+ * #     type Error = ();
+ * #     fn sample_rate(&self) -> u32 { 8_000 }
+ * #     fn channels(&self) -> u8 { 1 }
+ * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+ * #         Ok(&o[..160])
+ * #     }
+ * # }
+ * # struct Sink;
+ * # impl SampleSink for Sink {
+ * #     fn push_samples(&mut self, samples: &[i16]) -> usize { samples.len() }
+ * # }
+ * # let (decoder, mut sink) = (Decoder, Sink);
+ * # let data = include_bytes!("test/mono.opus");
+ * let stream = Bitstream::new(data);
+ * let reader = stream.packet_reader::<512>().unwrap();
+ * let mut writer = SinkWriter::new(Frames::<_, _, 960>::new(reader, decoder));
+ * while writer.fill(&mut sink).unwrap() == FillStatus::Full {
+ *     // Wait until consumer has drained some samples
+ * }
+ * ```
+ */
+#[derive(Debug)]
+pub struct SinkWriter<S: PacketSource, D: Decode, const BUFFER_SIZE: usize> {
+    frames: Frames<S, D, BUFFER_SIZE>,
+}
+
+impl<S: PacketSource, D: Decode, const BUFFER_SIZE: usize> SinkWriter<S, D, BUFFER_SIZE> {
+    /// Construct [`SinkWriter`] that writes frames into sinks.
+    pub fn new(frames: Frames<S, D, BUFFER_SIZE>) -> Self {
+        Self { frames }
+    }
+
+    /// Returns the frames that are written.
+    pub fn frames(&self) -> &Frames<S, D, BUFFER_SIZE> {
+        &self.frames
+    }
+
+    /**
+     * Deconstruct [`SinkWriter`] into the frames.
+     *
+     * Pending samples are returned first from [`Frames::next`].
+     */
+    pub fn into_inner(self) -> Frames<S, D, BUFFER_SIZE> {
+        self.frames
+    }
+
+    /// Returns the number of decoded samples waiting to be pushed into sink.
+    pub fn pending(&self) -> usize {
+        self.frames.unread_samples()
+    }
+
+    /**
+     * Decode frames and push them into sink until it is full or the stream ends.
+     *
+     * Samples that did not fit are pushed first on the next call.
+     */
+    pub fn fill<K: SampleSink>(&mut self, sink: &mut K) -> FillResult<S, D> {
+        while let Some(frame) = self.frames.next()? {
+            let samples = frame.len();
+            let pushed = sink.push_samples(frame);
+            if pushed < samples {
+                self.frames.unread(samples - pushed);
+                return Ok(FillStatus::Full);
+            }
+        }
+        Ok(FillStatus::Ended)
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "heapless")))]
+#[cfg(feature = "heapless")]
+impl<const N: usize> SampleSink for heapless::spsc::Producer<'_, i16, N> {
+    fn push_samples(&mut self, samples: &[i16]) -> usize {
+        samples
+            .iter()
+            .take_while(|&&sample| self.enqueue(sample).is_ok())
+            .count()
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "heapless")))]
+#[cfg(feature = "heapless")]
+impl<const N: usize> SampleSink for heapless::spsc::Queue<i16, N> {
+    fn push_samples(&mut self, samples: &[i16]) -> usize {
+        samples
+            .iter()
+            .take_while(|&&sample| self.enqueue(sample).is_ok())
+            .count()
+    }
+}
+
+/**
+ * Double buffer for circular DMA transfers.
+ *
+ * The buffer is split into two halves of `N` samples. DMA plays one half while the other half is
+ * filled with decoded audio through [`SampleSink`]. Call [`swap`][`DoubleBuffer::swap`] from
+ * half transfer and transfer complete interrupts, i.e. whenever DMA moves from one half to the
+ * other.
+ *
+ * The first half is written first. Fill it, call [`swap`][`DoubleBuffer::swap`] and start
+ * circular DMA over the buffer so that DMA reads the first half while the second half is being
+ * filled. DMA must not read the buffer while it is borrowed mutably, so with `embedded-dma`
+ * feature a buffer in a static is split with `DoubleBuffer::split` into `DmaBuffer` for DMA
+ * and `DoubleBufferWriter` for writing.
+ *
+ * ```rust
+ * # use oggopus_embedded::sink::{DoubleBuffer, SampleSink};
+ * let mut buffer = DoubleBuffer::<4>::new();
+ * assert_eq!(buffer.push_samples(&[1, 2, 3, 4, 5]), 4);
+ * assert!(buffer.ready());
+ * buffer.swap();
+ * // DMA reads the first half now
+ * assert_eq!(buffer.push_samples(&[5]), 1);
+ * // Underrun if DMA reaches the end of the first half before the second half is ready
+ * assert!(!buffer.swap());
+ * assert_eq!(buffer.as_slice(), &[1, 2, 3, 4, 5, 0, 0, 0]);
+ * ```
+ */
+#[derive(Debug)]
+pub struct DoubleBuffer<const N: usize> {
+    buffer: [[i16; N]; 2],
+    state: SwapState,
+}
+
+/// State of writing into the halves of [`DoubleBuffer`].
+#[derive(Debug)]
+struct SwapState {
+    back: usize,
+    written: usize,
+    underruns: u32,
+}
+
+impl SwapState {
+    /// Push samples into the half that is being written.
+    fn push<const N: usize>(&mut self, half: &mut [i16; N], samples: &[i16]) -> usize {
+        let count = samples.len().min(N - self.written);
+        half[self.written..self.written + count].copy_from_slice(&samples[..count]);
+        self.written += count;
+        count
+    }
+
+    /// Swap halves after silencing the rest of the half that was being written.
+    fn swap<const N: usize>(&mut self, half: &mut [i16; N]) -> bool {
+        let ready = self.written == N;
+        if !ready {
+            half[self.written..].fill(0);
+            self.underruns = self.underruns.wrapping_add(1);
+        }
+        self.back ^= 1;
+        self.written = 0;
+        ready
+    }
+}
+
+impl<const N: usize> Default for DoubleBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> DoubleBuffer<N> {
+    /// Construct silent [`DoubleBuffer`] with the first half ready for writing.
+    pub const fn new() -> Self {
+        Self {
+            buffer: [[0; N]; 2],
+            state: SwapState {
+                back: 0,
+                written: 0,
+                underruns: 0,
+            },
+        }
+    }
+
+    /// Returns the whole buffer of `2 * N` samples.
+    pub fn as_slice(&self) -> &[i16] {
+        self.buffer.as_flattened()
+    }
+
+    /// Returns the index of the half that is being written, 0 or 1.
+    pub fn back_half(&self) -> usize {
+        self.state.back
+    }
+
+    /// Returns whether the half that is being written is full.
+    pub fn ready(&self) -> bool {
+        self.state.written == N
+    }
+
+    /**
+     * Swap halves after DMA has moved to the other half.
+     *
+     * The half that was written is given to DMA and the half that DMA finished becomes writable.
+     * Returns `false` if the written half was not full, in which case the rest of it is silenced
+     * and underrun is counted.
+     */
+    pub fn swap(&mut self) -> bool {
+        self.state.swap(&mut self.buffer[self.state.back])
+    }
+
+    /// Returns the number of underruns, wrapping around on overflow.
+    pub fn underruns(&self) -> u32 {
+        self.state.underruns
+    }
+
+    /**
+     * Split buffer into [`DmaBuffer`] that DMA reads and [`DoubleBufferWriter`] that fills it.
+     *
+     * The writer only accesses the half that DMA is not reading, so DMA can read the buffer for
+     * the whole circular transfer while it is being filled.
+     *
+     * ```rust
+     * # use oggopus_embedded::sink::{DoubleBuffer, SampleSink};
+     * # use embedded_dma::ReadBuffer;
+     * # let buffer = Box::leak(Box::new(DoubleBuffer::<4>::new()));
+     * // e.g. let buffer = static_cell::StaticCell::new().init(DoubleBuffer::<4>::new());
+     * let (dma, mut writer) = buffer.split();
+     * assert_eq!(writer.push_samples(&[1, 2, 3, 4]), 4);
+     * writer.swap();
+     * // Give dma to circular DMA transfer and writer to its interrupt handler
+     * # let (_, length) = unsafe { dma.read_buffer() };
+     * # assert_eq!(length, 8);
+     * ```
+     */
+    #[cfg_attr(docsrs, doc(cfg(feature = "embedded-dma")))]
+    #[cfg(feature = "embedded-dma")]
+    pub fn split(&'static mut self) -> (DmaBuffer<N>, DoubleBufferWriter<N>) {
+        let DoubleBuffer { buffer, state } = self;
+        let buffer = core::ptr::NonNull::from(buffer).cast();
+        (DmaBuffer { buffer }, DoubleBufferWriter { buffer, state })
+    }
+}
+
+impl<const N: usize> SampleSink for DoubleBuffer<N> {
+    fn push_samples(&mut self, samples: &[i16]) -> usize {
+        self.state.push(&mut self.buffer[self.state.back], samples)
+    }
+}
+
+/**
+ * Both halves of [`DoubleBuffer`] for DMA, returned by [`DoubleBuffer::split`].
+ *
+ * Implements [`ReadBuffer`][`embedded_dma::ReadBuffer`] so that it can be given to HAL DMA
+ * transfer APIs.
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "embedded-dma")))]
+#[cfg(feature = "embedded-dma")]
+#[derive(Debug)]
+pub struct DmaBuffer<const N: usize> {
+    /// Pointer to the first half of a static [`DoubleBuffer`].
+    buffer: core::ptr::NonNull<[i16; N]>,
+}
+
+// SAFETY: DMA buffer only gives the pointer of a static buffer and is not used for writing
+#[cfg(feature = "embedded-dma")]
+unsafe impl<const N: usize> Send for DmaBuffer<N> {}
+
+// SAFETY: The pointer and length cover the sample array of a static buffer which never moves,
+// and the writer of the buffer does not create references to the half that DMA is reading
+#[cfg(feature = "embedded-dma")]
+unsafe impl<const N: usize> embedded_dma::ReadBuffer for DmaBuffer<N> {
+    type Word = i16;
+
+    unsafe fn read_buffer(&self) -> (*const i16, usize) {
+        (self.buffer.as_ptr().cast_const().cast(), 2 * N)
+    }
+}
+
+/**
+ * Writing half of [`DoubleBuffer`], returned by [`DoubleBuffer::split`].
+ *
+ * Works like [`DoubleBuffer`] but does not give access to the half that DMA is reading.
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "embedded-dma")))]
+#[cfg(feature = "embedded-dma")]
+#[derive(Debug)]
+pub struct DoubleBufferWriter<const N: usize> {
+    /// Pointer to the first half of a static [`DoubleBuffer`].
+    buffer: core::ptr::NonNull<[i16; N]>,
+    state: &'static mut SwapState,
+}
+
+// SAFETY: Writer has unique access to the half being written and to the state of the buffer
+#[cfg(feature = "embedded-dma")]
+unsafe impl<const N: usize> Send for DoubleBufferWriter<N> {}
+
+#[cfg(feature = "embedded-dma")]
+impl<const N: usize> DoubleBufferWriter<N> {
+    /// Returns the index of the half that is being written, 0 or 1.
+    pub fn back_half(&self) -> usize {
+        self.state.back
+    }
+
+    /// Returns whether the half that is being written is full.
+    pub fn ready(&self) -> bool {
+        self.state.written == N
+    }
+
+    /// Swap halves after DMA has moved to the other half, see [`DoubleBuffer::swap`].
+    pub fn swap(&mut self) -> bool {
+        // SAFETY: The buffer is static and has two halves. DMA only reads the other half, and
+        // the writer has the only reference to this half while it is being written.
+        let half = unsafe { &mut *self.buffer.as_ptr().add(self.state.back) };
+        self.state.swap(half)
+    }
+
+    /// Returns the number of underruns, wrapping around on overflow.
+    pub fn underruns(&self) -> u32 {
+        self.state.underruns
+    }
+}
+
+#[cfg(feature = "embedded-dma")]
+impl<const N: usize> SampleSink for DoubleBufferWriter<N> {
+    fn push_samples(&mut self, samples: &[i16]) -> usize {
+        // SAFETY: The buffer is static and has two halves. DMA only reads the other half, and
+        // the writer has the only reference to this half while it is being written.
+        let half = unsafe { &mut *self.buffer.as_ptr().add(self.state.back) };
+        self.state.push(half, samples)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{frames::test::CountingDecoder, Bitstream, PacketReader};
+
+    /// Sink that accepts only given number of samples at a time.
+    struct LimitedSink {
+        samples: Vec<i16>,
+        room: usize,
+    }
+
+    impl SampleSink for LimitedSink {
+        fn push_samples(&mut self, samples: &[i16]) -> usize {
+            let count = samples.len().min(self.room);
+            self.samples.extend_from_slice(&samples[..count]);
+            self.room -= count;
+            count
+        }
+    }
+
+    fn writer(data: &[u8]) -> SinkWriter<PacketReader<'_, 512>, CountingDecoder, 160> {
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        SinkWriter::new(Frames::new(reader, CountingDecoder { count: 0 }))
+    }
+
+    #[test]
+    fn backpressure() {
+        let mut writer = writer(include_bytes!("test/mono.opus"));
+        let mut sink = LimitedSink {
+            samples: Vec::new(),
+            room: 100,
+        };
+        assert_eq!(writer.fill(&mut sink), Ok(FillStatus::Full));
+        // The first frame has 108 samples after pre-skip
+        assert_eq!(writer.pending(), 8);
+        let mut status = FillStatus::Full;
+        while status == FillStatus::Full {
+            sink.room = 100;
+            status = writer.fill(&mut sink).unwrap();
+        }
+        assert_eq!(writer.pending(), 0);
+        let expected: Vec<i16> = (52..7 * 160).collect();
+        assert_eq!(sink.samples, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "heapless")]
+    fn heapless_queue() {
+        let mut writer = writer(include_bytes!("test/mono.opus"));
+        let mut queue = heapless::spsc::Queue::<i16, 257>::new();
+        let (mut producer, mut consumer) = queue.split();
+        let mut expected = 52;
+        loop {
+            let status = writer.fill(&mut producer).unwrap();
+            assert!(!producer.ready() || status == FillStatus::Ended);
+            while let Some(sample) = consumer.dequeue() {
+                assert_eq!(sample, expected);
+                expected += 1;
+            }
+            if status == FillStatus::Ended {
+                break;
+            }
+        }
+        assert_eq!(expected, 7 * 160);
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-dma")]
+    fn dma_buffer() {
+        use embedded_dma::ReadBuffer;
+        let buffer: &'static mut DoubleBuffer<4> = Box::leak(Box::default());
+        let (dma, mut writer) = buffer.split();
+        assert_eq!(writer.push_samples(&[1, 2, 3, 4, 5, 6]), 4);
+        assert!(writer.ready());
+        assert!(writer.swap());
+        assert_eq!(writer.back_half(), 1);
+        assert_eq!(writer.push_samples(&[5, 6]), 2);
+        // SAFETY: DMA is not used in test
+        let (pointer, length) = unsafe { dma.read_buffer() };
+        assert_eq!(length, 8);
+        // SAFETY: Pointer and length were just returned for the buffer, and the writer is not
+        // used while the samples are borrowed
+        let samples = unsafe { core::slice::from_raw_parts(pointer, length) };
+        assert_eq!(samples, &[1, 2, 3, 4, 5, 6, 0, 0]);
+        assert!(!writer.swap());
+        assert_eq!(writer.underruns(), 1);
+        assert_eq!(writer.push_samples(&[7]), 1);
+        // SAFETY: As above
+        let samples = unsafe { core::slice::from_raw_parts(pointer, length) };
+        assert_eq!(samples, &[7, 2, 3, 4, 5, 6, 0, 0]);
+    }
+
+    #[test]
+    fn double_buffer() {
+        let mut writer = writer(include_bytes!("test/mono.opus"));
+        let mut buffer = DoubleBuffer::<100>::new();
+        assert_eq!(writer.fill(&mut buffer), Ok(FillStatus::Full));
+        assert!(buffer.ready());
+        assert!(buffer.swap());
+        assert_eq!(buffer.back_half(), 1);
+        assert_eq!(writer.fill(&mut buffer), Ok(FillStatus::Full));
+        assert!(buffer.swap());
+        let expected: Vec<i16> = (52..252).collect();
+        assert_eq!(buffer.as_slice(), expected.as_slice());
+        while writer.fill(&mut buffer).unwrap() == FillStatus::Full {
+            assert!(buffer.swap());
+        }
+        // 1068 samples leave 68 samples for the first half
+        assert_eq!(buffer.back_half(), 0);
+        assert!(!buffer.swap());
+        assert_eq!(buffer.underruns(), 1);
+        let expected: Vec<i16> = (1052..1120).collect();
+        assert_eq!(buffer.as_slice()[..68], expected);
+        assert!(buffer.as_slice()[68..100].iter().all(|&sample| sample == 0));
+    }
+}
@@ -0,0 +1,1040 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Container independent access to opus packets.
+
+use super::{
+    container::{Page, ParseOptions, RawPage},
+    info::{first_packet_position, StartPosition, StreamInfo, Summary, START_BEFORE_ZERO},
+    opus::OpusHeader,
+    seek::{self, SeekEntry, SeekIndex, SeekPoint, SeekablePacketSource},
+    time::GranulePos,
+    verify::{NoVerifier, Verifier},
+    Bitstream, BitstreamError, BitstreamReader, EndOfStream, ErrorValues, InStream, OggError,
+    Packet, Packets, Pages,
+};
+use crate::states::Either;
+use crate::{
+    bitrate::{Bitrate, BitrateMeter},
+    conformance::{Checker, Deviations},
+};
+use core::time::Duration;
+
+/**
+ * Source of opus packets.
+ *
+ * This is implemented by the container parsers so that the code consuming opus packets does not
+ * need to care which container the packets were stored in.
+ */
+pub trait PacketSource {
+    /// Error returned when reading from the source fails.
+    type Error;
+
+    /// Returns the opus header of the stream.
+    fn header(&self) -> &OpusHeader;
+
+    /// Returns the next opus packet, or [`None`] if the stream has ended.
+    fn next_packet(&mut self) -> Result<Option<Packet<'_>>, Self::Error>;
+
+    /**
+     * Returns the granule position at the end of the stream if it is known already.
+     *
+     * This is used for trimming the end of the last packet. Sources that cannot tell it return
+     * [`None`], which is the default.
+     */
+    fn end_granule_position(&self) -> Option<u64> {
+        None
+    }
+
+    /**
+     * Returns the granule position of the first sample of the stream if it is known already.
+     *
+     * Streams may start from a granule position other than zero, e.g. when they were cut from a
+     * live stream, and the end is trimmed relative to it. Sources that cannot tell it return
+     * [`None`], which is the default and is treated as zero.
+     */
+    fn start_granule_position(&self) -> Option<u64> {
+        None
+    }
+}
+
+/**
+ * Asynchronous source of opus packets.
+ *
+ * This is the asynchronous counterpart of [`PacketSource`] for sources that must wait for data,
+ * e.g. from an SD card or network.
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait AsyncPacketSource {
+    /// Error returned when reading from the source fails.
+    type Error;
+
+    /// Returns the opus header of the stream.
+    fn header(&self) -> &OpusHeader;
+
+    /// Returns the next opus packet, or [`None`] if the stream has ended.
+    async fn next_packet(&mut self) -> Result<Option<Packet<'_>>, Self::Error>;
+
+    /**
+     * Returns the granule position at the end of the stream if it is known already.
+     *
+     * See [`PacketSource::end_granule_position`].
+     */
+    fn end_granule_position(&self) -> Option<u64> {
+        None
+    }
+
+    /**
+     * Returns the granule position of the first sample of the stream if it is known already.
+     *
+     * See [`PacketSource::start_granule_position`].
+     */
+    fn start_granule_position(&self) -> Option<u64> {
+        None
+    }
+
+    /**
+     * Move to the page from which the next packet begins at or before granule position.
+     *
+     * See [`SeekablePacketSource::seek_granule`][`crate::SeekablePacketSource::seek_granule`].
+     * Returns [`None`] if the source cannot seek, which is the default. `AsyncFrames` uses
+     * this for looping.
+     */
+    async fn seek_granule(&mut self, granule_position: u64) -> Result<Option<u64>, Self::Error> {
+        let _ = granule_position;
+        Ok(None)
+    }
+}
+
+/**
+ * [`PacketSource`] for ogg opus [`Bitstream`].
+ *
+ * Reads the packets of one logical stream across pages. `BUFFER_SIZE` must be large enough to
+ * contain the largest packet in the stream. Packets that continue over several pages are copied
+ * together into the buffer so that is all the memory needed for them regardless of how many pages
+ * they span. Larger packets are rejected with recoverable
+ * [`BufferTooSmallError`][`crate::OggError::BufferTooSmallError`].
+ *
+ * Reading can continue after recoverable errors. Rejected pages are skipped and data that does
+ * not begin with a page is scanned for the next one, which is reported with
+ * [`LostSync`][`crate::OggError::LostSync`].
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, PacketReader, PacketSource};
+ * # let data = include_bytes!("test/mono.opus");
+ * let stream = Bitstream::new(data);
+ * let mut reader = stream.packet_reader::<1_024>().unwrap();
+ * println!("Sample rate is {} Hz", reader.header().sample_rate);
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * ```
+ */
+#[derive(Debug)]
+pub struct PacketReader<'data, const BUFFER_SIZE: usize, V: Verifier = NoVerifier> {
+    header: OpusHeader,
+    reader:
+        Either<BitstreamReader<'data, 'data, InStream>, BitstreamReader<'data, 'data, EndOfStream>>,
+    packets: Option<Packets<'data, BUFFER_SIZE>>,
+    verifier: V,
+    stats: ReaderStats,
+    end_granule_position: Option<u64>,
+    start: StartPosition,
+    resynchronized: bool,
+    bitrate: BitrateMeter,
+    /// Checker of pages in strict mode.
+    checker: Option<Checker>,
+    /// Deviations that were tolerated in lenient mode.
+    deviations: Deviations,
+    /// All data of the link from its first header page.
+    data: &'data [u8],
+    /// Data of the link from its first audio page.
+    audio: &'data [u8],
+}
+
+/**
+ * Statistics of [`PacketReader`].
+ *
+ * Counters wrap around on overflow.
+ */
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReaderStats {
+    /// The number of pages read, including header pages.
+    pub pages_read: u32,
+    /// The number of opus packets returned.
+    pub packets_read: u32,
+    /**
+     * The number of packets rejected because of recoverable errors or discarded after lost
+     * synchronization.
+     *
+     * Reading continues after the page of a rejected packet, and the page is counted once.
+     */
+    pub packets_rejected: u32,
+    /// The number of bytes read from the bitstream.
+    pub bytes_read: usize,
+}
+
+impl<'data, const BUFFER_SIZE: usize> PacketReader<'data, BUFFER_SIZE> {
+    /// Construct [`PacketReader`] and read the headers of the first stream in [`Bitstream`].
+    pub fn new(bitstream: &Bitstream<'data>) -> crate::Result<'data, Self> {
+        Self::with_verifier(bitstream, NoVerifier)
+    }
+}
+
+impl<'data, const BUFFER_SIZE: usize, V: Verifier> PacketReader<'data, BUFFER_SIZE, V> {
+    /**
+     * Construct [`PacketReader`] that verifies pages with [`Verifier`].
+     *
+     * Header pages are verified before this returns. See [`verify`][`crate::verify`] module for
+     * more information.
+     */
+    pub fn with_verifier(bitstream: &Bitstream<'data>, verifier: V) -> crate::Result<'data, Self> {
+        let reader = BitstreamReader {
+            bitstream: core::marker::PhantomData::<_>,
+            remaining: bitstream.data,
+            options: bitstream.options,
+            marker: crate::Beginning,
+        };
+        let (reader, header) = reader.read_header()?;
+        let audio = match &reader {
+            Either::Continued(reader) => reader.remaining,
+            Either::Ended(reader) => reader.remaining,
+        };
+        let mut packet_reader = Self {
+            header,
+            reader,
+            packets: None,
+            verifier,
+            stats: ReaderStats::default(),
+            end_granule_position: None,
+            start: StartPosition::default(),
+            resynchronized: false,
+            bitrate: BitrateMeter::new(),
+            checker: bitstream.options.strict.then(Checker::new),
+            deviations: Deviations::empty(),
+            data: bitstream.data,
+            audio,
+        };
+        if bitstream.options.lenient
+            && bitstream
+                .tags()
+                .is_ok_and(|tags| tags.vendor().as_str().is_none())
+        {
+            packet_reader.deviations |= Deviations::InvalidVendor;
+        }
+        packet_reader.process_pages(bitstream.data)?;
+        Ok(packet_reader)
+    }
+
+    /**
+     * Construct [`PacketReader`] for the next link of a chained stream.
+     *
+     * Chained streams are logical streams that follow each other, e.g. concatenated files or
+     * internet radio rips. Each link has its own headers. Returns [`None`] if this stream has not
+     * ended yet or if there is no more data after it. The verifier is moved to the new reader.
+     *
+     * To make the transition gapless, keep decoding with the same decoder, e.g. by taking it
+     * from [`Frames::into_inner`][`crate::Frames::into_inner`] and constructing new
+     * [`Frames`][`crate::Frames`] for the next link. Links with a different channel count need a
+     * new decoder.
+     *
+     * ```rust
+     * # use oggopus_embedded::{Bitstream, PacketSource};
+     * # let data = [&include_bytes!("test/mono.opus")[..], include_bytes!("test/stereo.opus")].concat();
+     * let stream = Bitstream::new(&data);
+     * let mut reader = stream.packet_reader::<1_024>().unwrap();
+     * loop {
+     *     println!("New link with {} channels", reader.header().channels.get_channel_count());
+     *     while let Some(packet) = reader.next_packet().unwrap() {
+     *         // Decode packets of the link
+     *     }
+     *     match reader.next_link() {
+     *         Some(next) => reader = next.unwrap(),
+     *         None => break,
+     *     }
+     * }
+     * ```
+     */
+    pub fn next_link(self) -> Option<crate::Result<'data, Self>> {
+        let Either::Ended(reader) = &self.reader else {
+            return None;
+        };
+        if !reader.has_more() {
+            return None;
+        }
+        let bitstream = Bitstream {
+            data: reader.remaining,
+            options: reader.options,
+        };
+        Some(Self::with_verifier(&bitstream, self.verifier))
+    }
+
+    /// Returns statistics of reading since the reader was created.
+    pub fn stats(&self) -> ReaderStats {
+        self.stats
+    }
+
+    /**
+     * Returns the bitrate of the audio pages that have been read, or [`None`] if it is not known
+     * yet.
+     *
+     * See [`BitrateMeter`] for how it is estimated. Pages that are skipped by seeking are not
+     * counted.
+     */
+    pub fn bitrate(&self) -> Option<Bitrate> {
+        self.bitrate.bitrate()
+    }
+
+    /**
+     * Returns the deviations from RFC7845 that were tolerated so far in lenient mode.
+     *
+     * See [`Bitstream::with_lenient`]. This is always empty if lenient mode is not turned on.
+     */
+    pub fn deviations(&self) -> Deviations {
+        self.deviations
+    }
+
+    /**
+     * Look up [`StreamInfo`] of the stream, or the current link of a chained stream.
+     *
+     * The last page is searched backwards from the end of the data and every page is read only if
+     * it is not found there. This does not move the reader.
+     */
+    pub fn stream_info(&self) -> crate::Result<'data, StreamInfo> {
+        let bitstream = Bitstream {
+            data: self.data,
+            options: self.options(),
+        };
+        StreamInfo::probe(&bitstream).map(|(info, _)| info)
+    }
+
+    /// Returns [`Summary`] of the stream, or the current link of a chained stream.
+    pub fn info(&self) -> crate::Result<'data, Summary<'data>> {
+        let bitstream = Bitstream {
+            data: self.data,
+            options: self.options(),
+        };
+        Summary::read(&bitstream)
+    }
+
+    /**
+     * Returns the playing time of the stream, or the current link of a chained stream.
+     *
+     * See [`stream_info`][`PacketReader::stream_info`].
+     *
+     * ```rust
+     * # use oggopus_embedded::Bitstream;
+     * # let data = include_bytes!("test/mono.opus");
+     * let reader = Bitstream::new(data).packet_reader::<1_024>().unwrap();
+     * println!("Playing {:?} of audio", reader.duration().unwrap());
+     * ```
+     */
+    pub fn duration(&self) -> crate::Result<'data, Duration> {
+        self.stream_info().map(|info| info.duration())
+    }
+
+    /**
+     * Build [`SeekIndex`] of pages at least `interval` apart from the current link.
+     *
+     * Reads every page of the link but does not decode packets. See [`SeekIndex`] for how the
+     * entries are kept within its capacity.
+     */
+    pub fn seek_index<const CAPACITY: usize>(
+        &self,
+        interval: Duration,
+    ) -> crate::Result<'data, SeekIndex<CAPACITY>> {
+        let start = self.start_position();
+        let serial_number = RawPage::parse(self.data)?.bitstream_serial_number();
+        let mut index = SeekIndex::new(GranulePos::from_duration(interval, 0).get());
+        let mut offset = self.audio_offset();
+        for page in Pages::with_options(self.audio, self.options()) {
+            let page = page?;
+            if page.bitstream_serial_number() != serial_number {
+                break;
+            }
+            if let Some(position) = first_packet_position(&page) {
+                index.add(SeekEntry {
+                    granule_position: position.saturating_sub(start),
+                    offset: offset as u64,
+                });
+            }
+            offset += page.as_bytes().len();
+            if page.end_of_stream() {
+                break;
+            }
+        }
+        Ok(index)
+    }
+
+    /// Returns the offset of the first audio page in the data of the link.
+    fn audio_offset(&self) -> usize {
+        self.data.len() - self.audio.len()
+    }
+
+    /// Returns the start position of the link by reading its first audio pages if needed.
+    fn start_position(&self) -> u64 {
+        if let Some(position) = self.start.position() {
+            return position;
+        }
+        let mut start = StartPosition::default();
+        Pages::with_options(self.audio, self.options())
+            .map_while(Result::ok)
+            // Invalid start positions are treated as zero
+            .find_map(|page| start.page(&page).unwrap_or(Some(0)))
+            .unwrap_or(0)
+    }
+
+    /**
+     * Continue reading from the page at offset in the data of the link.
+     *
+     * The packet that continues from the previous page is not returned.
+     */
+    fn continue_from(&mut self, offset: usize) -> Result<(), BitstreamError> {
+        let remaining = &self.data[offset..];
+        let options = self.options();
+        let page = RawPage::parse_with(remaining, &options)?;
+        let serial_number = RawPage::parse(self.data)?.bitstream_serial_number();
+        if page.bitstream_serial_number() != serial_number {
+            return Err(BitstreamError::UnsupportedStream(
+                "bitstream serial number changed unexpectedly",
+            ));
+        }
+        self.reader = Either::Continued(BitstreamReader {
+            bitstream: core::marker::PhantomData::<_>,
+            remaining,
+            options,
+            marker: InStream {
+                bitstream_serial_number: serial_number,
+                page_sequence_number: page.page_sequence_number().wrapping_sub(1),
+            },
+        });
+        self.packets = None;
+        self.resynchronized = true;
+        self.bitrate.jump();
+        if let Some(checker) = &mut self.checker {
+            checker.jump();
+        }
+        Ok(())
+    }
+
+    /// Returns the parse options of the bitstream.
+    fn options(&self) -> ParseOptions {
+        match &self.reader {
+            Either::Continued(reader) => reader.options,
+            Either::Ended(reader) => reader.options,
+        }
+    }
+
+    /// Returns the data that has not been read yet.
+    fn remaining(&self) -> &'data [u8] {
+        match &self.reader {
+            Either::Continued(reader) => reader.remaining,
+            Either::Ended(reader) => reader.remaining,
+        }
+    }
+
+    /**
+     * Skip the data that caused a recoverable error so that reading continues after it.
+     *
+     * Returns the error to report. Data that does not begin with a page is skipped up to the next
+     * capture pattern and reported with [`LostSync`][`OggError::LostSync`]. Skipped pages are not
+     * verified and the packet that continues from them is discarded.
+     */
+    fn recover(&mut self, error: BitstreamError) -> BitstreamError {
+        let Either::Continued(reader) = &mut self.reader else {
+            return error;
+        };
+        if error == BitstreamError::OggError(OggError::NotOggStream) && !reader.remaining.is_empty()
+        {
+            let skipped = Page::find_next(reader.remaining);
+            reader.remaining = &reader.remaining[skipped..];
+            self.resynchronized = true;
+            return OggError::LostSync(skipped).into();
+        }
+        if !error.is_recoverable() {
+            return error;
+        }
+        match error {
+            BitstreamError::InvalidOggStream(ErrorValues::SequenceNumberMismatch(
+                previous,
+                current,
+            )) if previous == reader.marker.page_sequence_number => {
+                // Earlier pages were lost, continue from this page
+                reader.marker.page_sequence_number = current.wrapping_sub(1);
+            }
+            _ => match Page::skip_rejected(reader.remaining) {
+                Ok((remaining, last)) => {
+                    reader.remaining = remaining;
+                    reader.marker.page_sequence_number = last;
+                    self.stats.packets_rejected = self.stats.packets_rejected.wrapping_add(1);
+                }
+                Err(_) => return error,
+            },
+        }
+        self.resynchronized = true;
+        error
+    }
+
+    /// Returns whether error means that the data ended before the end of stream in lenient mode.
+    fn is_truncated(&self, error: &BitstreamError) -> bool {
+        let Either::Continued(reader) = &self.reader else {
+            return false;
+        };
+        reader.options.lenient
+            && match error {
+                BitstreamError::OggError(OggError::NotOggStream) => reader.remaining.is_empty(),
+                BitstreamError::OggError(OggError::EndOfStreamError(_)) => true,
+                _ => false,
+            }
+    }
+
+    /// End the stream at the end of the data, discarding an incomplete page.
+    fn end_truncated(&mut self) -> crate::Result<'data, ()> {
+        let Either::Continued(reader) = &self.reader else {
+            return Ok(());
+        };
+        self.reader = Either::Ended(BitstreamReader {
+            bitstream: core::marker::PhantomData::<_>,
+            remaining: &reader.remaining[reader.remaining.len()..],
+            options: reader.options,
+            marker: EndOfStream,
+        });
+        self.packets = None;
+        self.deviations |= Deviations::MissingEndOfStream;
+        if !self.verifier.verify_end() {
+            return Err(BitstreamError::VerificationFailed(None));
+        }
+        Ok(())
+    }
+
+    /// Look for the start position in the audio pages that were read since `previous` data.
+    fn find_start(&mut self, previous: &'data [u8]) {
+        if self.start.position().is_some() {
+            return;
+        }
+        let read = &previous[..previous.len() - self.remaining().len()];
+        for page in Page::iter_raw(read).filter_map(|(_, page)| RawPage::parse(page).ok()) {
+            // Invalid start positions are treated as zero
+            if self.start.page(&page) == Err(START_BEFORE_ZERO) && self.options().lenient {
+                self.deviations |= Deviations::StartBeforeZero;
+            }
+        }
+        if let Some(start) = self.start.position() {
+            self.bitrate.start_at(start);
+        }
+    }
+
+    /// Count the audio pages that were read since `previous` data for bitrate.
+    fn count_bitrate(&mut self, previous: &'data [u8]) {
+        let read = &previous[..previous.len() - self.remaining().len()];
+        for page in Page::iter_raw(read).filter_map(|(_, page)| RawPage::parse(page).ok()) {
+            self.bitrate.page(&page);
+        }
+    }
+
+    /// Count and verify pages that were read since `previous` data.
+    fn process_pages(&mut self, previous: &'data [u8]) -> crate::Result<'data, ()> {
+        let read = &previous[..previous.len() - self.remaining().len()];
+        self.stats.bytes_read = self.stats.bytes_read.wrapping_add(read.len());
+        for (sequence_number, page) in Page::iter_raw(read) {
+            self.stats.pages_read = self.stats.pages_read.wrapping_add(1);
+            if !self.verifier.verify_page(sequence_number, page) {
+                return Err(BitstreamError::VerificationFailed(Some(sequence_number)));
+            }
+            if let Some(checker) = &mut self.checker {
+                checker
+                    .page(&RawPage::parse(page)?)
+                    .map_err(BitstreamError::Violation)?;
+            }
+        }
+        if matches!(self.reader, Either::Ended(_)) && !self.verifier.verify_end() {
+            return Err(BitstreamError::VerificationFailed(None));
+        }
+        Ok(())
+    }
+}
+
+impl<const BUFFER_SIZE: usize, V: Verifier> PacketSource for PacketReader<'_, BUFFER_SIZE, V> {
+    type Error = BitstreamError;
+
+    fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet<'_>>, BitstreamError> {
+        while !self
+            .packets
+            .as_ref()
+            .is_some_and(|packets| packets.has_next())
+        {
+            let Either::Continued(reader) = &self.reader else {
+                return Ok(None);
+            };
+            let previous = reader.remaining;
+            let (reader, mut packets) = match reader.next_packets() {
+                Ok(next) => next,
+                Err(error) if self.is_truncated(&error) => {
+                    self.end_truncated()?;
+                    return Ok(None);
+                }
+                Err(error) => return Err(self.recover(error)),
+            };
+            self.reader = reader;
+            self.packets = None;
+            self.process_pages(previous)?;
+            self.find_start(previous);
+            self.count_bitrate(previous);
+            if matches!(self.reader, Either::Ended(_)) {
+                let end = packets.last_granule_position();
+                self.end_granule_position = Some(end);
+                let start = self.start.position().unwrap_or(0);
+                if self.options().lenient && end.saturating_sub(start) < self.header.pre_skip.into()
+                {
+                    self.deviations |= Deviations::PreSkipPastEnd;
+                }
+            }
+            if core::mem::take(&mut self.resynchronized) && packets.skip_continued() {
+                self.stats.packets_rejected = self.stats.packets_rejected.wrapping_add(1);
+            }
+            self.packets = Some(packets);
+        }
+        let packet = self.packets.as_mut().and_then(Packets::next);
+        if packet.is_some() {
+            self.stats.packets_read = self.stats.packets_read.wrapping_add(1);
+        }
+        Ok(packet)
+    }
+
+    fn end_granule_position(&self) -> Option<u64> {
+        self.end_granule_position
+    }
+
+    fn start_granule_position(&self) -> Option<u64> {
+        self.start.position()
+    }
+}
+
+impl<const BUFFER_SIZE: usize, V: Verifier> SeekablePacketSource
+    for PacketReader<'_, BUFFER_SIZE, V>
+{
+    /**
+     * Move to the page from which the next packet begins at or before granule position.
+     *
+     * See [`SeekablePacketSource::seek_granule`]. Chained streams are seeked within the current
+     * link. Pages are verified as they are read after seeking, so verifiers that expect every
+     * page in order fail afterwards.
+     */
+    fn seek_granule(&mut self, granule_position: u64) -> Result<u64, BitstreamError> {
+        let audio = self.audio;
+        let options = self.options();
+        if audio.is_empty() {
+            return Ok(0);
+        }
+        let serial_number = RawPage::parse(self.data)?.bitstream_serial_number();
+        let start = self.start_position();
+        self.start = StartPosition::found(start);
+        let target = start.saturating_add(granule_position);
+        let point = seek::bisect(0, audio.len() as u64, target, |offset| {
+            let point = seek::find_page(&audio[offset as usize..], serial_number, &options);
+            Ok::<_, BitstreamError>(point.map(|point| SeekPoint {
+                offset: point.offset + offset,
+                ..point
+            }))
+        })?;
+        let (offset, position) = match point {
+            Some(point) => (point.offset as usize, point.packet_position),
+            None => (0, start),
+        };
+        self.continue_from(self.audio_offset() + offset)?;
+        Ok(position.saturating_sub(start))
+    }
+
+    /**
+     * Move to the page of an entry of [`SeekIndex`].
+     *
+     * See [`SeekablePacketSource::seek_entry`]. Entries that do not point to a page of the
+     * stream are rejected with [`UnsupportedStream`][`BitstreamError::UnsupportedStream`].
+     */
+    fn seek_entry(&mut self, entry: SeekEntry) -> Result<u64, BitstreamError> {
+        let offset = usize::try_from(entry.offset).unwrap_or(usize::MAX);
+        if offset < self.audio_offset() || offset >= self.data.len() {
+            return Err(BitstreamError::UnsupportedStream(
+                "seek index entry is not within the stream",
+            ));
+        }
+        self.start = StartPosition::found(self.start_position());
+        self.continue_from(offset)?;
+        Ok(entry.granule_position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_packets() {
+        const DATA: &[u8] = include_bytes!("test/mono.opus");
+        let bitstream = Bitstream::new(DATA);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(reader.header().pre_skip, 312);
+        assert_eq!(reader.end_granule_position(), None);
+        let mut sizes = [0; 7];
+        let mut count = 0;
+        while let Some(packet) = reader.next_packet().unwrap() {
+            sizes[count] = packet.data.len();
+            count += 1;
+        }
+        assert_eq!(count, 7);
+        assert_eq!(sizes, [13, 13, 13, 13, 14, 16, 14]);
+        assert!(reader.next_packet().unwrap().is_none());
+        assert_eq!(reader.end_granule_position(), Some(7992));
+        assert_eq!(
+            reader.stats(),
+            ReaderStats {
+                pages_read: 3,
+                packets_read: 7,
+                packets_rejected: 0,
+                bytes_read: DATA.len(),
+            }
+        );
+    }
+
+    /// Collect packet sizes and errors until the end of the stream or an unrecoverable error.
+    fn collect<const BUFFER_SIZE: usize>(
+        reader: &mut PacketReader<'_, BUFFER_SIZE>,
+    ) -> Vec<Result<usize, BitstreamError>> {
+        let mut packets = Vec::new();
+        loop {
+            match reader.next_packet() {
+                Ok(Some(packet)) => packets.push(Ok(packet.data.len())),
+                Ok(None) => break,
+                Err(error) if error.is_recoverable() => packets.push(Err(error)),
+                Err(error) => {
+                    packets.push(Err(error));
+                    break;
+                }
+            }
+        }
+        packets
+    }
+
+    #[test]
+    fn resynchronize() {
+        const DATA: &[u8] = include_bytes!("test/offset.opus");
+        // Garbage before the first audio page and a corrupted first audio page
+        let mut data = Vec::from(&DATA[..136]);
+        data.extend_from_slice(b"OggOgg!");
+        data.extend_from_slice(&DATA[136..]);
+        data[143 + 40] ^= 1;
+        let bitstream = Bitstream::new(&data);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(
+            collect(&mut reader),
+            [
+                Err(OggError::LostSync(7).into()),
+                Err(OggError::CrcMismatch(2).into()),
+                Ok(13),
+                Ok(14),
+                Ok(16),
+                Ok(14),
+            ]
+        );
+        assert_eq!(reader.end_granule_position(), Some(48_000 + 7 * 960));
+        // Corrupted page is rejected
+        assert_eq!(reader.stats().packets_read, 4);
+        assert_eq!(reader.stats().packets_rejected, 1);
+        // Lost audio page
+        let data = [&DATA[..136], &DATA[205..]].concat();
+        let bitstream = Bitstream::new(&data);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(
+            collect(&mut reader),
+            [
+                Err(BitstreamError::InvalidOggStream(
+                    ErrorValues::SequenceNumberMismatch(1, 3)
+                )),
+                Ok(13),
+                Ok(14),
+                Ok(16),
+                Ok(14),
+            ]
+        );
+        // Garbage at the end of data without a page
+        let data = [&DATA[..205], b"garbage"].concat();
+        let bitstream = Bitstream::new(&data);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        let packets = collect(&mut reader);
+        assert_eq!(packets.len(), 5);
+        assert_eq!(packets[3], Err(OggError::LostSync(7).into()));
+        assert!(!packets[4].as_ref().unwrap_err().is_recoverable());
+        // Errors are not counted as packets that were read
+        assert_eq!(reader.stats().packets_read, 3);
+    }
+
+    #[test]
+    fn read_vorbis() {
+        const DATA: &[u8] = include_bytes!("test/vorbis.ogg");
+        let bitstream = Bitstream::new(DATA);
+        let result = bitstream.packet_reader::<512>();
+        assert!(matches!(result, Err(BitstreamError::NotOpusStream)));
+    }
+
+    #[derive(Default)]
+    struct CountingVerifier {
+        pages: u32,
+        ended: bool,
+        reject: Option<u32>,
+    }
+
+    impl Verifier for CountingVerifier {
+        fn verify_page(&mut self, sequence_number: u32, page: &[u8]) -> bool {
+            assert_eq!(sequence_number, self.pages);
+            assert_eq!(&page[..4], b"OggS");
+            self.pages += 1;
+            self.reject != Some(sequence_number)
+        }
+
+        fn verify_end(&mut self) -> bool {
+            self.ended = true;
+            true
+        }
+    }
+
+    #[test]
+    fn verify_pages() {
+        const DATA: &[u8] = include_bytes!("test/mono.opus");
+        let bitstream = Bitstream::new(DATA);
+        let verifier = CountingVerifier::default();
+        let mut reader = PacketReader::<512, _>::with_verifier(&bitstream, verifier).unwrap();
+        assert_eq!(reader.verifier.pages, 2);
+        assert!(reader.next_packet().unwrap().is_some());
+        assert_eq!(reader.verifier.pages, 3);
+        assert!(reader.verifier.ended);
+    }
+
+    #[test]
+    fn reject_page() {
+        const DATA: &[u8] = include_bytes!("test/mono.opus");
+        let bitstream = Bitstream::new(DATA);
+        let verifier = CountingVerifier {
+            reject: Some(2),
+            ..Default::default()
+        };
+        let mut reader = PacketReader::<512, _>::with_verifier(&bitstream, verifier).unwrap();
+        let error = reader.next_packet().unwrap_err();
+        assert_eq!(error, BitstreamError::VerificationFailed(Some(2)));
+        assert_eq!(error.to_string(), "verification failed for page 2");
+        assert!(error.is_recoverable());
+        // Nothing is returned from rejected page
+        assert!(reader.next_packet().unwrap().is_none());
+        let verifier = CountingVerifier {
+            reject: Some(0),
+            ..Default::default()
+        };
+        let result = PacketReader::<512, _>::with_verifier(&bitstream, verifier);
+        assert!(matches!(
+            result,
+            Err(BitstreamError::VerificationFailed(Some(0)))
+        ));
+    }
+
+    #[test]
+    fn strict() {
+        let mut data = Vec::from(include_bytes!("test/offset.opus"));
+        // Granule position of the second audio page before the first one
+        data[205 + 6..205 + 14].copy_from_slice(&1_000u64.to_le_bytes());
+        let bitstream = Bitstream::new(&data).with_crc_check(false);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        while reader.next_packet().unwrap().is_some() {}
+        let mut reader = bitstream.with_strict(true).packet_reader::<512>().unwrap();
+        for _ in 0..3 {
+            assert!(reader.next_packet().unwrap().is_some());
+        }
+        let error = reader.next_packet().unwrap_err();
+        assert_eq!(
+            error,
+            BitstreamError::Violation(crate::conformance::Violation::GranulePositionDecreased(3))
+        );
+        assert_eq!(
+            error.to_string(),
+            "stream violates RFC7845: granule position decreased on page 3"
+        );
+        assert!(!error.is_recoverable());
+        // Seeking back after the end of stream
+        let bitstream = Bitstream::new(include_bytes!("test/offset.opus")).with_strict(true);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        while reader.next_packet().unwrap().is_some() {}
+        assert_eq!(reader.seek_granule(0), Ok(0));
+        assert_eq!(collect(&mut reader).len(), 7);
+    }
+
+    #[test]
+    fn lenient() {
+        use crate::conformance::Deviations;
+        let mut data = Vec::from(include_bytes!("test/offset.opus"));
+        // First audio page ends before its three packets, end of stream flag is missing and the
+        // vendor string is not UTF-8
+        data[136 + 6..136 + 14].copy_from_slice(&1_000u64.to_le_bytes());
+        data[205 + 5] = 0;
+        let vendor = data.windows(4).position(|bytes| bytes == b"Lavf").unwrap();
+        data[vendor] = 0xFF;
+        let bitstream = Bitstream::new(&data).with_crc_check(false);
+        assert_eq!(
+            bitstream.stream_info(),
+            Err(BitstreamError::InvalidOpusStream(
+                "granule position is smaller than the number of samples on the page"
+            ))
+        );
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(
+            collect(&mut reader).pop(),
+            Some(Err(OggError::NotOggStream.into()))
+        );
+        assert!(reader.deviations().is_empty());
+        let bitstream = bitstream.with_lenient(true);
+        assert_eq!(bitstream.stream_info().unwrap().first_granule_position, 0);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(reader.deviations(), Deviations::InvalidVendor);
+        assert_eq!(collect(&mut reader).len(), 7);
+        assert_eq!(
+            reader.deviations(),
+            Deviations::StartBeforeZero
+                | Deviations::MissingEndOfStream
+                | Deviations::InvalidVendor
+        );
+        assert!(reader.next_packet().unwrap().is_none());
+        assert!(reader.next_link().is_none());
+        // Incomplete last page is discarded
+        let bitstream = Bitstream::new(&data[..250]).with_crc_check(false);
+        let mut reader = bitstream.with_lenient(true).packet_reader::<512>().unwrap();
+        assert_eq!(collect(&mut reader).len(), 3);
+        assert!(reader.deviations().contains(Deviations::MissingEndOfStream));
+        // Pre-skip is a bit longer than the 6720 samples of the stream
+        let mut data = Vec::from(include_bytes!("test/offset.opus"));
+        data[28 + 10..28 + 12].copy_from_slice(&6_800u16.to_le_bytes());
+        let bitstream = Bitstream::new(&data).with_crc_check(false);
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(collect(&mut reader).len(), 7);
+        assert!(reader.deviations().is_empty());
+        let mut reader = bitstream.with_lenient(true).packet_reader::<512>().unwrap();
+        assert_eq!(collect(&mut reader).len(), 7);
+        assert_eq!(reader.deviations(), Deviations::PreSkipPastEnd);
+    }
+
+    #[test]
+    fn seek() {
+        // Three packets on the first audio page and four on the second
+        let data = include_bytes!("test/offset.opus");
+        let mut reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.next_packet().unwrap() {
+            packets.push(packet.data.to_vec());
+        }
+        assert_eq!(reader.seek_granule(3 * 960), Ok(3 * 960));
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, packets[3]);
+        assert_eq!(reader.seek_granule(3 * 960 - 1), Ok(0));
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, packets[0]);
+        assert_eq!(reader.seek_granule(u64::MAX), Ok(3 * 960));
+        let mut reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        // Start position is looked up before the first packet is read
+        assert_eq!(reader.seek_granule(5_000), Ok(3 * 960));
+        assert_eq!(reader.start_granule_position(), Some(48_000));
+        for packet in &packets[3..] {
+            assert_eq!(reader.next_packet().unwrap().unwrap().data, packet);
+        }
+        assert_eq!(reader.next_packet(), Ok(None));
+    }
+
+    #[test]
+    fn bitrate() {
+        let data = include_bytes!("test/offset.opus");
+        let mut reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        assert_eq!(reader.bitrate(), None);
+        while reader.next_packet().unwrap().is_some() {}
+        // Both audio pages from the start position
+        let expected = ((data.len() - 136) * 8 * 48_000 / (7 * 960)) as u32;
+        assert_eq!(reader.bitrate().unwrap().mean, expected);
+        // The first page after seeking is only the starting point
+        let mut reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        reader.seek_granule(0).unwrap();
+        reader.next_packet().unwrap();
+        assert_eq!(reader.bitrate(), None);
+        while reader.next_packet().unwrap().is_some() {}
+        let expected = ((data.len() - 205) * 8 * 48_000 / (4 * 960)) as u32;
+        assert_eq!(reader.bitrate().unwrap().instantaneous, expected);
+    }
+
+    #[test]
+    fn seek_index() {
+        let data = include_bytes!("test/offset.opus");
+        let mut reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        let index = reader.seek_index::<8>(Duration::ZERO).unwrap();
+        let second = SeekEntry {
+            granule_position: 3 * 960,
+            offset: 205,
+        };
+        assert_eq!(
+            index.entries(),
+            [
+                SeekEntry {
+                    granule_position: 0,
+                    offset: 136
+                },
+                second
+            ]
+        );
+        let mut packets = Bitstream::new(data).packet_reader::<512>().unwrap();
+        for _ in 0..3 {
+            packets.next_packet().unwrap();
+        }
+        assert_eq!(reader.seek_entry(second), Ok(3 * 960));
+        assert_eq!(reader.next_packet(), packets.next_packet());
+        assert_eq!(reader.start_granule_position(), Some(48_000));
+        // Entries must point to audio pages
+        let entry = SeekEntry {
+            granule_position: 0,
+            offset: 100,
+        };
+        assert!(reader.seek_entry(entry).is_err());
+        let index = reader.seek_index::<1>(Duration::from_millis(10)).unwrap();
+        assert_eq!(index.entries().len(), 1);
+    }
+
+    #[test]
+    fn chained_links() {
+        let data = [
+            &include_bytes!("test/mono.opus")[..],
+            include_bytes!("test/stereo.opus"),
+        ]
+        .concat();
+        let bitstream = Bitstream::new(&data);
+        // The first link has not ended yet
+        let reader = bitstream.packet_reader::<512>().unwrap();
+        assert!(reader.next_link().is_none());
+        let mut reader = bitstream.packet_reader::<512>().unwrap();
+        assert_eq!(reader.header().channels.get_channel_count(), 1);
+        // Duration is of the current link only
+        let first = reader.stream_info().unwrap();
+        assert_eq!(
+            first,
+            Bitstream::new(include_bytes!("test/mono.opus"))
+                .stream_info()
+                .unwrap()
+        );
+        while reader.next_packet().unwrap().is_some() {}
+        assert_eq!(reader.stream_info(), Ok(first));
+        let mut reader = reader.next_link().unwrap().unwrap();
+        assert_eq!(reader.header().channels.get_channel_count(), 2);
+        let expected = Bitstream::new(include_bytes!("test/stereo.opus")).stream_info();
+        assert_eq!(reader.duration(), expected.map(|info| info.duration()));
+        assert_eq!(reader.end_granule_position(), None);
+        while reader.next_packet().unwrap().is_some() {}
+        assert!(reader.end_granule_position().is_some());
+        assert!(reader.next_link().is_none());
+    }
+}
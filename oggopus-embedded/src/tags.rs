@@ -0,0 +1,593 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Opus comment header values.
+
+use super::opus::{OpusError, Result};
+#[cfg(feature = "alloc")]
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt::Write;
+use core::str::{Chars, Utf8Chunks};
+use nom::{bytes::complete::take, number, Parser};
+
+/**
+ * Comment header of opus stream.
+ *
+ * Vendor string and comments are borrowed from the packet without copying, so this can be used
+ * without heap allocation. See
+ * [RFC7845](https://datatracker.ietf.org/doc/html/rfc7845#section-5.2) for the format. Comment
+ * keys are case insensitive and a key may appear several times.
+ *
+ * ```rust
+ * # use oggopus_embedded::Bitstream;
+ * # let data = include_bytes!("test/mono.opus");
+ * let tags = Bitstream::new(data).tags().unwrap();
+ * if let Some(title) = tags.get("TITLE") {
+ *     println!("Playing {}", title.display_lossy());
+ * }
+ * for (key, value) in tags.comments() {
+ *     println!("{key}: {}", value.display_lossy());
+ * }
+ * ```
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OpusTags<'data> {
+    vendor: &'data [u8],
+    count: u32,
+    comments: &'data [u8],
+}
+
+impl<'data> OpusTags<'data> {
+    /**
+     * Parse comment header packet.
+     *
+     * Lengths of all comments are checked so that iterating over them cannot fail later. Data
+     * after the last comment is ignored.
+     */
+    pub fn parse(packet: &'data [u8]) -> Result<'data, Self> {
+        let input = packet
+            .strip_prefix(b"OpusTags")
+            .ok_or(OpusError::NotOpusStream)?;
+        let (input, vendor) = string(input)?;
+        let (comments, count) = number::le_u32().parse(input)?;
+        let mut input = comments;
+        for _ in 0..count {
+            (input, _) = string(input)?;
+        }
+        Ok(Self {
+            vendor,
+            count,
+            comments: &comments[..comments.len() - input.len()],
+        })
+    }
+
+    /// Returns the vendor string, which usually names the encoder.
+    pub fn vendor(&self) -> CommentValue<'data> {
+        CommentValue::new(self.vendor)
+    }
+
+    /**
+     * Returns iterator over comments as key and value pairs.
+     *
+     * Keys are as they are in the stream and they must be printable ASCII. Comments without a
+     * valid key are skipped.
+     */
+    pub fn comments(&self) -> Comments<'data> {
+        Comments {
+            data: self.comments,
+            remaining: self.count,
+        }
+    }
+
+    /// Returns the first value of the key, which is compared case insensitively.
+    pub fn get(&self, key: &str) -> Option<CommentValue<'data>> {
+        self.get_all(key).next()
+    }
+
+    /// Returns all values of the key, which is compared case insensitively.
+    pub fn get_all<'key>(&self, key: &'key str) -> impl Iterator<Item = CommentValue<'data>> + 'key
+    where
+        'data: 'key,
+    {
+        self.comments()
+            .filter(move |(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    /**
+     * Returns R128 gain for normalization mode in Q7.8 decibels.
+     *
+     * The gain is to be added to the output gain of the opus header. Returns [`None`] if the
+     * comment is missing or it is not a decimal integer that fits in [`i16`].
+     */
+    pub fn r128_gain(&self, normalization: Normalization) -> Option<i16> {
+        self.get(normalization.key())?.as_str()?.parse().ok()
+    }
+
+    /**
+     * Copy comments into a map from upper case keys to their values.
+     *
+     * A key may have several values, e.g. for several artists. Invalid UTF-8 is replaced.
+     */
+    #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+    #[cfg(feature = "alloc")]
+    pub fn to_map(&self) -> BTreeMap<String, Vec<String>> {
+        let mut map = BTreeMap::<_, Vec<_>>::new();
+        for (key, value) in self.comments() {
+            map.entry(key.to_ascii_uppercase())
+                .or_default()
+                .push(value.to_string_lossy());
+        }
+        map
+    }
+}
+
+/**
+ * Loudness normalization mode.
+ *
+ * See [`OpusTags::r128_gain`] and [`Frames::with_normalization`][`crate::Frames::with_normalization`].
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Normalization {
+    /// Normalize each track separately with `R128_TRACK_GAIN`.
+    Track,
+    /// Keep the relative loudness of tracks of an album with `R128_ALBUM_GAIN`.
+    Album,
+}
+
+impl Normalization {
+    /// Returns the comment key of the gain.
+    pub(crate) const fn key(&self) -> &'static str {
+        match self {
+            Self::Track => "R128_TRACK_GAIN",
+            Self::Album => "R128_ALBUM_GAIN",
+        }
+    }
+}
+
+/// Error from building comment header.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum TagsError {
+    /// Key is empty or it has other characters than printable ASCII except `=`.
+    InvalidKey,
+    /**
+     * Buffer was too small to contain the comment.
+     *
+     * Contains the remaining size of the buffer and how many bytes would have been needed.
+     */
+    BufferTooSmallError(usize, usize),
+}
+
+impl core::fmt::Display for TagsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use TagsError::*;
+        match self {
+            InvalidKey => f.write_str("invalid comment key"),
+            BufferTooSmallError(got, needed) => f.write_fmt(format_args!(
+                "buffer is too small: got {} but needed {}",
+                got, needed
+            )),
+        }
+    }
+}
+
+impl core::error::Error for TagsError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        None
+    }
+}
+
+/**
+ * Builds comment header packet into a buffer.
+ *
+ * The buffer is the RAM budget of the packet. A comment that does not fit is rejected with
+ * [`BufferTooSmallError`][`TagsError::BufferTooSmallError`] and the packet stays valid without
+ * it, so that the next smaller comment may still fit. The packet can be given to
+ * `OggWriter::new_with_tags` or written by tag editing tools.
+ *
+ * ```rust
+ * # use oggopus_embedded::tags::{Normalization, OpusTags, TagsBuilder};
+ * let mut buffer = [0; 128];
+ * let mut builder = TagsBuilder::new(&mut buffer, "recorder 1.0").unwrap();
+ * builder.push("TITLE", "Morning birds").unwrap();
+ * builder.push_r128_gain(Normalization::Track, -1_280).unwrap();
+ * let tags = OpusTags::parse(builder.as_bytes()).unwrap();
+ * assert_eq!(tags.r128_gain(Normalization::Track), Some(-1_280));
+ * ```
+ */
+#[derive(Debug)]
+pub struct TagsBuilder<'buffer> {
+    buffer: &'buffer mut [u8],
+    length: usize,
+    /// Offset of comment count in the buffer.
+    count_offset: usize,
+    count: u32,
+}
+
+impl<'buffer> TagsBuilder<'buffer> {
+    /// Construct [`TagsBuilder`] and write the header with the vendor string to buffer.
+    pub fn new(buffer: &'buffer mut [u8], vendor: &str) -> core::result::Result<Self, TagsError> {
+        let count_offset = 8 + 4 + vendor.len();
+        let needed = count_offset + 4;
+        if buffer.len() < needed {
+            return Err(TagsError::BufferTooSmallError(buffer.len(), needed));
+        }
+        buffer[..8].copy_from_slice(b"OpusTags");
+        buffer[8..12].copy_from_slice(&(vendor.len() as u32).to_le_bytes());
+        buffer[12..count_offset].copy_from_slice(vendor.as_bytes());
+        buffer[count_offset..needed].fill(0);
+        Ok(Self {
+            buffer,
+            length: needed,
+            count_offset,
+            count: 0,
+        })
+    }
+
+    /// Add comment with the key and value.
+    pub fn push(&mut self, key: &str, value: &str) -> core::result::Result<(), TagsError> {
+        self.push_bytes(key, value.as_bytes())
+    }
+
+    /**
+     * Add comment with the key and raw value.
+     *
+     * Values should be UTF-8, but this allows copying comments as they are, e.g. from
+     * [`CommentValue::as_bytes`].
+     */
+    pub fn push_bytes(&mut self, key: &str, value: &[u8]) -> core::result::Result<(), TagsError> {
+        self.push_parts(key, &[value])
+    }
+
+    /**
+     * Add R128 gain in Q7.8 decibels for normalization mode.
+     *
+     * The gain is relative to the output gain of the opus header, see [`OpusTags::r128_gain`].
+     */
+    pub fn push_r128_gain(
+        &mut self,
+        normalization: Normalization,
+        gain: i16,
+    ) -> core::result::Result<(), TagsError> {
+        let (digits, start) = gain_digits(gain);
+        let sign: &[u8] = if gain < 0 { b"-" } else { b"" };
+        self.push_parts(normalization.key(), &[sign, &digits[start..]])
+    }
+
+    /// Returns the number of comments that have been added.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Returns the comment header packet.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+
+    /// Add comment with value given in parts.
+    fn push_parts(&mut self, key: &str, value: &[&[u8]]) -> core::result::Result<(), TagsError> {
+        if key.is_empty()
+            || !key
+                .bytes()
+                .all(|byte| (0x20..=0x7D).contains(&byte) && byte != b'=')
+        {
+            return Err(TagsError::InvalidKey);
+        }
+        let length = key.len() + 1 + value.iter().map(|part| part.len()).sum::<usize>();
+        let remaining = self.buffer.len() - self.length;
+        if remaining < 4 + length {
+            return Err(TagsError::BufferTooSmallError(remaining, 4 + length));
+        }
+        let parts = [&(length as u32).to_le_bytes()[..], key.as_bytes(), b"="];
+        for part in parts.iter().chain(value) {
+            self.buffer[self.length..self.length + part.len()].copy_from_slice(part);
+            self.length += part.len();
+        }
+        self.count += 1;
+        self.buffer[self.count_offset..self.count_offset + 4]
+            .copy_from_slice(&self.count.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// Format the magnitude of gain as decimal digits. Returns the digits and where they start.
+pub(crate) fn gain_digits(gain: i16) -> ([u8; 5], usize) {
+    let mut digits = [0; 5];
+    let mut start = digits.len();
+    let mut magnitude = gain.unsigned_abs();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (magnitude % 10) as u8;
+        magnitude /= 10;
+        if magnitude == 0 {
+            return (digits, start);
+        }
+    }
+}
+
+/// Returns the vendor string of comment header, or [`None`] if `data` does not contain it all.
+#[cfg(any(feature = "embedded-io", feature = "std"))]
+pub(crate) fn vendor(data: &[u8]) -> Option<CommentValue<'_>> {
+    let input = data.strip_prefix(b"OpusTags")?;
+    let (_, vendor) = string(input).ok()?;
+    Some(CommentValue::new(vendor))
+}
+
+/// Parse string that is prefixed with its length.
+fn string(input: &[u8]) -> Result<'_, (&[u8], &[u8])> {
+    let (input, length) = number::le_u32().parse(input)?;
+    Ok(take(length)(input)?)
+}
+
+/// Iterator over comments of [`OpusTags`].
+#[derive(Clone, Debug)]
+pub struct Comments<'data> {
+    data: &'data [u8],
+    remaining: u32,
+}
+
+impl<'data> Iterator for Comments<'data> {
+    type Item = (&'data str, CommentValue<'data>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            // PANIC: Lengths were checked when parsing
+            let (data, comment) = string(self.data).unwrap();
+            self.data = data;
+            let Some(separator) = comment.iter().position(|byte| *byte == b'=') else {
+                continue;
+            };
+            let (key, value) = (&comment[..separator], &comment[separator + 1..]);
+            if key.iter().all(|byte| (0x20..=0x7D).contains(byte)) {
+                // PANIC: Printable ASCII is valid UTF-8
+                let key = core::str::from_utf8(key).unwrap();
+                return Some((key, CommentValue::new(value)));
+            }
+        }
+        None
+    }
+}
+
+/**
+ * Value of a comment or vendor string.
+ *
+ * Comments should be UTF-8 but that cannot be trusted, so the value is kept as raw bytes. Use
+ * [`as_str`][`CommentValue::as_str`] for valid UTF-8 or
+ * [`display_lossy`][`CommentValue::display_lossy`] to show any value without allocation.
+ *
+ * ```rust
+ * # use oggopus_embedded::tags::CommentValue;
+ * let value = CommentValue::new(b"Caf\xC3\xA9 \xFF");
+ * assert_eq!(value.as_str(), None);
+ * assert_eq!(format!("{}", value.display_lossy()), "Café \u{FFFD}");
+ * ```
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CommentValue<'data> {
+    data: &'data [u8],
+}
+
+impl<'data> CommentValue<'data> {
+    /// Construct [`CommentValue`] from raw bytes.
+    pub const fn new(data: &'data [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Returns the raw bytes of the value.
+    pub const fn as_bytes(&self) -> &'data [u8] {
+        self.data
+    }
+
+    /// Returns the value as string, or [`None`] if it is not valid UTF-8.
+    pub fn as_str(&self) -> Option<&'data str> {
+        core::str::from_utf8(self.data).ok()
+    }
+
+    /**
+     * Returns the value with invalid UTF-8 sequences replaced.
+     *
+     * Each invalid sequence is replaced with [`REPLACEMENT_CHARACTER`][`char::REPLACEMENT_CHARACTER`].
+     * The returned value can be formatted or iterated over as characters.
+     */
+    pub fn display_lossy(&self) -> DisplayLossy<'data> {
+        DisplayLossy {
+            data: self.data,
+            chunks: self.data.utf8_chunks(),
+            chars: "".chars(),
+            replacement: false,
+        }
+    }
+
+    /// Copy the value into [`String`] with invalid UTF-8 sequences replaced.
+    #[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+    #[cfg(feature = "alloc")]
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(self.data).into_owned()
+    }
+}
+
+/// Lossy UTF-8 view of [`CommentValue`]. Implements [`Display`][`core::fmt::Display`].
+#[derive(Clone, Debug)]
+pub struct DisplayLossy<'data> {
+    data: &'data [u8],
+    chunks: Utf8Chunks<'data>,
+    chars: Chars<'data>,
+    replacement: bool,
+}
+
+impl Iterator for DisplayLossy<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        loop {
+            if let Some(c) = self.chars.next() {
+                return Some(c);
+            }
+            if self.replacement {
+                self.replacement = false;
+                return Some(char::REPLACEMENT_CHARACTER);
+            }
+            let chunk = self.chunks.next()?;
+            self.chars = chunk.valid().chars();
+            self.replacement = !chunk.invalid().is_empty();
+        }
+    }
+}
+
+impl core::fmt::Display for DisplayLossy<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for chunk in self.data.utf8_chunks() {
+            f.write_str(chunk.valid())?;
+            if !chunk.invalid().is_empty() {
+                f.write_char(char::REPLACEMENT_CHARACTER)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nom::error::ErrorKind;
+
+    fn comments(comments: &[&[u8]]) -> Vec<u8> {
+        let mut packet = b"OpusTags\x01\x00\x00\x00v".to_vec();
+        packet.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            packet.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            packet.extend_from_slice(comment);
+        }
+        packet
+    }
+
+    #[test]
+    fn parse_tags() {
+        let packet = comments(&[
+            b"Artist=A",
+            b"ARTIST=B",
+            b"title=T=1",
+            b"broken",
+            b"\xC3\x84=x",
+        ]);
+        let tags = OpusTags::parse(&packet).unwrap();
+        assert_eq!(tags.vendor().as_str(), Some("v"));
+        let pairs: Vec<_> = tags
+            .comments()
+            .map(|(key, value)| (key, value.as_bytes()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [("Artist", &b"A"[..]), ("ARTIST", b"B"), ("title", b"T=1")]
+        );
+        assert_eq!(tags.get("title").unwrap().as_str(), Some("T=1"));
+        let artists: Vec<_> = tags.get_all("artist").filter_map(|v| v.as_str()).collect();
+        assert_eq!(artists, ["A", "B"]);
+        assert_eq!(tags.get("ALBUM"), None);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn owned_tags() {
+        let packet = comments(&[b"Artist=A", b"ARTIST=B", b"title=\xFF"]);
+        let map = OpusTags::parse(&packet).unwrap().to_map();
+        assert_eq!(map["ARTIST"], ["A", "B"]);
+        assert_eq!(map["TITLE"], ["\u{FFFD}"]);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn r128_gain() {
+        let packet = comments(&[b"R128_TRACK_GAIN=-1280", b"R128_ALBUM_GAIN=40000"]);
+        let tags = OpusTags::parse(&packet).unwrap();
+        assert_eq!(tags.r128_gain(Normalization::Track), Some(-1_280));
+        assert_eq!(tags.r128_gain(Normalization::Album), None);
+        let packet = comments(&[b"r128_album_gain=0"]);
+        let tags = OpusTags::parse(&packet).unwrap();
+        assert_eq!(tags.r128_gain(Normalization::Album), Some(0));
+        assert_eq!(tags.r128_gain(Normalization::Track), None);
+    }
+
+    #[test]
+    fn build_tags() {
+        let mut buffer = [0; 88];
+        let mut builder = TagsBuilder::new(&mut buffer, "v").unwrap();
+        builder.push("Artist", "A").unwrap();
+        builder.push_bytes("TITLE", b"\xFF").unwrap();
+        assert_eq!(builder.push("", "x"), Err(TagsError::InvalidKey));
+        assert_eq!(builder.push("A=B", "x"), Err(TagsError::InvalidKey));
+        assert_eq!(builder.push("\u{C4}", "x"), Err(TagsError::InvalidKey));
+        builder
+            .push_r128_gain(Normalization::Track, i16::MIN)
+            .unwrap();
+        builder.push_r128_gain(Normalization::Album, 0).unwrap();
+        assert_eq!(
+            builder.as_bytes(),
+            comments(&[
+                b"Artist=A",
+                b"TITLE=\xFF",
+                b"R128_TRACK_GAIN=-32768",
+                b"R128_ALBUM_GAIN=0"
+            ])
+        );
+        // Comment that does not fit is left out
+        assert_eq!(
+            builder.push("COMMENT", "abc"),
+            Err(TagsError::BufferTooSmallError(1, 15))
+        );
+        assert_eq!(builder.count(), 4);
+        let tags = OpusTags::parse(builder.as_bytes()).unwrap();
+        assert_eq!(tags.r128_gain(Normalization::Track), Some(i16::MIN));
+        assert_eq!(tags.r128_gain(Normalization::Album), Some(0));
+        assert_eq!(
+            TagsBuilder::new(&mut [0; 16], "vendor").unwrap_err(),
+            TagsError::BufferTooSmallError(16, 22)
+        );
+    }
+
+    #[test]
+    fn truncated_tags() {
+        let packet = comments(&[b"TITLE=T"]);
+        let result = OpusTags::parse(&packet[..packet.len() - 1]);
+        assert_eq!(result, Err(OpusError::ParsingError(ErrorKind::Eof)));
+        assert_eq!(OpusTags::parse(b"OpusHead"), Err(OpusError::NotOpusStream));
+        // Data after comments is ignored
+        let mut packet = packet;
+        packet.push(1);
+        assert_eq!(OpusTags::parse(&packet).unwrap().comments().count(), 1);
+    }
+
+    #[test]
+    fn valid_value() {
+        let value = CommentValue::new("ÄÖ ♪".as_bytes());
+        assert_eq!(value.as_str(), Some("ÄÖ ♪"));
+        assert_eq!(value.as_bytes(), "ÄÖ ♪".as_bytes());
+        assert_eq!(value.display_lossy().to_string(), "ÄÖ ♪");
+        assert!(value.display_lossy().eq("ÄÖ ♪".chars()));
+    }
+
+    #[test]
+    fn invalid_value() {
+        // Truncated sequence, invalid byte and overlong encoding
+        let value = CommentValue::new(b"a\xE2\x99b\xFFc\xC0\xAF");
+        assert_eq!(value.as_str(), None);
+        let expected = "a\u{FFFD}b\u{FFFD}c\u{FFFD}\u{FFFD}";
+        assert_eq!(value.display_lossy().to_string(), expected);
+        assert!(value.display_lossy().eq(expected.chars()));
+        assert_eq!(String::from_utf8_lossy(value.as_bytes()), expected);
+    }
+
+    #[test]
+    fn empty_value() {
+        let value = CommentValue::new(b"");
+        assert_eq!(value.as_str(), Some(""));
+        assert_eq!(value.display_lossy().next(), None);
+    }
+}
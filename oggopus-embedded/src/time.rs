@@ -0,0 +1,179 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Conversions between granule positions, sample counts and time.
+
+use core::time::Duration;
+
+/// Rate of granule positions of opus streams in samples per second.
+pub const GRANULE_RATE: u32 = 48_000;
+
+/**
+ * Granule position of an opus stream.
+ *
+ * Granule positions count samples at 48 kHz from the beginning of the stream including pre-skip
+ * samples, which are not played. Conversions to and from playable samples and time take the
+ * pre-skip of [`OpusHeader`][`crate::opus::OpusHeader`] into account, so that position zero of a
+ * seek bar is the first audible sample. Arithmetic saturates instead of overflowing.
+ *
+ * ```rust
+ * # use oggopus_embedded::time::GranulePos;
+ * let pre_skip = 312;
+ * let position = GranulePos::from_millis(1_500, pre_skip);
+ * assert_eq!(position.get(), 72_312);
+ * assert_eq!(position.millis(pre_skip), 1_500);
+ * // Samples decoded at 16 kHz
+ * assert_eq!(position.samples(16_000, pre_skip), 24_000);
+ * ```
+ */
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GranulePos(u64);
+
+impl GranulePos {
+    /// Granule position at the beginning of the stream.
+    pub const ZERO: Self = Self(0);
+
+    /// The largest granule position.
+    pub const MAX: Self = Self(u64::MAX);
+
+    /// Construct [`GranulePos`] from raw granule position.
+    pub const fn new(granule_position: u64) -> Self {
+        Self(granule_position)
+    }
+
+    /// Returns the raw granule position.
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    /**
+     * Returns the granule position of playable sample at index `samples` at sample rate.
+     *
+     * # Panics
+     * Panics if the sample rate is zero.
+     */
+    pub const fn from_samples(samples: u64, sample_rate: u32, pre_skip: u16) -> Self {
+        let granules = samples as u128 * GRANULE_RATE as u128 / sample_rate as u128;
+        Self(saturate(granules + pre_skip as u128))
+    }
+
+    /**
+     * Returns the number of playable samples at sample rate before this position.
+     *
+     * Positions within pre-skip are zero.
+     */
+    pub const fn samples(self, sample_rate: u32, pre_skip: u16) -> u64 {
+        let granules = self.0.saturating_sub(pre_skip as u64);
+        saturate(granules as u128 * sample_rate as u128 / GRANULE_RATE as u128)
+    }
+
+    /// Returns the granule position at time in milliseconds from the first playable sample.
+    pub const fn from_millis(milliseconds: u64, pre_skip: u16) -> Self {
+        Self::from_samples(milliseconds, 1_000, pre_skip)
+    }
+
+    /// Returns the time in milliseconds from the first playable sample, rounded down.
+    pub const fn millis(self, pre_skip: u16) -> u64 {
+        self.samples(1_000, pre_skip)
+    }
+
+    /// Returns the granule position at time from the first playable sample, rounded down.
+    pub const fn from_duration(time: Duration, pre_skip: u16) -> Self {
+        let granules = time.as_nanos() * GRANULE_RATE as u128 / 1_000_000_000;
+        Self(saturate(granules + pre_skip as u128))
+    }
+
+    /// Returns the time from the first playable sample.
+    pub const fn duration(self, pre_skip: u16) -> Duration {
+        let granules = self.0.saturating_sub(pre_skip as u64);
+        let rate = GRANULE_RATE as u64;
+        let nanos = (granules % rate) * 1_000_000_000 / rate;
+        Duration::new(granules / rate, nanos as u32)
+    }
+
+    /// Add granule positions, saturating at [`MAX`][`GranulePos::MAX`].
+    pub const fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    /// Subtract granule positions, saturating at [`ZERO`][`GranulePos::ZERO`].
+    pub const fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+/// Convert to `u64` saturating at its maximum value.
+const fn saturate(value: u128) -> u64 {
+    if value > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        value as u64
+    }
+}
+
+impl From<u64> for GranulePos {
+    fn from(granule_position: u64) -> Self {
+        Self(granule_position)
+    }
+}
+
+impl From<GranulePos> for u64 {
+    fn from(position: GranulePos) -> Self {
+        position.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn convert_samples() {
+        let position = GranulePos::from_samples(160, 8_000, 312);
+        assert_eq!(position, GranulePos::new(960 + 312));
+        assert_eq!(position.samples(8_000, 312), 160);
+        assert_eq!(position.samples(48_000, 312), 960);
+        assert_eq!(position.samples(48_000, 0), 960 + 312);
+        // Positions within pre-skip are at the beginning
+        assert_eq!(GranulePos::new(100).samples(48_000, 312), 0);
+        assert_eq!(GranulePos::from_samples(0, 8_000, 312).get(), 312);
+    }
+
+    #[test]
+    fn convert_millis() {
+        assert_eq!(GranulePos::from_millis(20, 0).get(), 960);
+        assert_eq!(GranulePos::new(959 + 312).millis(312), 19);
+        assert_eq!(GranulePos::new(7_992).millis(312), 160);
+    }
+
+    #[test]
+    fn convert_duration() {
+        let position = GranulePos::from_duration(Duration::from_micros(20_500), 312);
+        assert_eq!(position.get(), 984 + 312);
+        assert_eq!(position.duration(312), Duration::from_micros(20_500));
+        assert_eq!(GranulePos::new(1).duration(0).as_nanos(), 20_833);
+        assert_eq!(GranulePos::from_duration(Duration::MAX, 0), GranulePos::MAX);
+    }
+
+    #[test]
+    fn saturate() {
+        assert_eq!(GranulePos::from_millis(u64::MAX, 312), GranulePos::MAX);
+        assert_eq!(GranulePos::MAX.millis(0), u64::MAX / 48);
+        assert_eq!(GranulePos::MAX.samples(96_000, 0), u64::MAX);
+        assert_eq!(
+            GranulePos::MAX.saturating_add(GranulePos::new(1)),
+            GranulePos::MAX
+        );
+        assert_eq!(
+            GranulePos::ZERO.saturating_sub(GranulePos::new(1)),
+            GranulePos::ZERO
+        );
+        assert_eq!(
+            GranulePos::new(1_000).saturating_sub(GranulePos::new(312)),
+            GranulePos::new(688)
+        );
+        assert_eq!(u64::from(GranulePos::from(5)), 5);
+    }
+}
@@ -0,0 +1,222 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Packet reader that reassembles packets in heap memory.
+
+use super::{
+    info::StartPosition, opus::OpusHeader, Bitstream, BitstreamError, ErrorValues, Packet,
+    PacketSource, Pages, RawPage,
+};
+use crate::states::Either;
+use alloc::vec::Vec;
+
+/**
+ * [`PacketSource`] for ogg opus [`Bitstream`] that reassembles packets into a [`Vec`].
+ *
+ * Unlike [`PacketReader`][`crate::PacketReader`], packets are not limited by a buffer size and
+ * the buffer grows to fit the largest packet of the stream. This is meant for tools and tests on
+ * hosts that have a heap. It reads only forwards and skips pages of other logical streams.
+ *
+ * Lost pages are reported with recoverable
+ * [`SequenceNumberMismatch`][`ErrorValues::SequenceNumberMismatch`] and the packet that
+ * continued over them is discarded. Reading stops after other errors.
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, PacketSource};
+ * # let data = include_bytes!("test/mono.opus");
+ * let mut reader = Bitstream::new(data).vec_packet_reader().unwrap();
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     println!("Got {} bytes of opus data", packet.data.len());
+ * }
+ * ```
+ */
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[derive(Debug)]
+pub struct VecPacketReader<'data> {
+    header: OpusHeader,
+    pages: Pages<'data>,
+    bitstream_serial_number: u32,
+    page_sequence_number: u32,
+    /// Segments of the current page that have not been read yet.
+    segments: &'data [u8],
+    /// Data of the current page that has not been read yet.
+    data: &'data [u8],
+    granule_position: u64,
+    begin_of_stream: bool,
+    end_of_stream: bool,
+    /// Whether the packet that continues from the previous page is discarded.
+    skip_continued: bool,
+    /// Whether the buffered packet was returned already.
+    returned: bool,
+    packet: Vec<u8>,
+    ended: bool,
+    start: StartPosition,
+    end_granule_position: Option<u64>,
+}
+
+impl<'data> VecPacketReader<'data> {
+    /// Construct [`VecPacketReader`] and read the headers of the first stream in [`Bitstream`].
+    pub fn new(bitstream: &Bitstream<'data>) -> crate::Result<'data, Self> {
+        let (reader, header) = bitstream.reader().read_header()?;
+        let bitstream_serial_number = RawPage::parse(bitstream.data)?.bitstream_serial_number();
+        let (remaining, page_sequence_number, ended) = match reader {
+            Either::Continued(reader) => {
+                (reader.remaining, reader.marker.page_sequence_number, false)
+            }
+            Either::Ended(reader) => (reader.remaining, 0, true),
+        };
+        Ok(Self {
+            header,
+            pages: Pages::with_options(remaining, bitstream.options),
+            bitstream_serial_number,
+            page_sequence_number,
+            segments: &[],
+            data: &[],
+            granule_position: 0,
+            begin_of_stream: false,
+            end_of_stream: false,
+            skip_continued: false,
+            returned: false,
+            packet: Vec::new(),
+            ended,
+            start: StartPosition::default(),
+            end_granule_position: None,
+        })
+    }
+
+    /// Move to the next page of the stream, or mark the stream ended if there is none.
+    fn next_page(&mut self) -> Result<(), BitstreamError> {
+        let page = match self.pages.next() {
+            Some(page) => page?,
+            None => {
+                self.ended = true;
+                return Ok(());
+            }
+        };
+        if page.bitstream_serial_number() != self.bitstream_serial_number {
+            return Ok(());
+        }
+        let previous = self.page_sequence_number;
+        self.page_sequence_number = page.page_sequence_number();
+        self.segments = page.segment_table();
+        self.data = page.data();
+        self.granule_position = page.granule_position();
+        self.begin_of_stream = page.begin_of_stream();
+        self.end_of_stream = page.end_of_stream();
+        if page.end_of_stream() {
+            self.ended = true;
+            self.end_granule_position = Some(page.granule_position());
+        }
+        // Invalid start positions are treated as zero
+        let _ = self.start.page(&page);
+        if page.page_sequence_number() != previous.wrapping_add(1) {
+            self.packet.clear();
+            self.skip_continued = page.continued();
+            return Err(BitstreamError::InvalidOggStream(
+                ErrorValues::SequenceNumberMismatch(previous, page.page_sequence_number()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl PacketSource for VecPacketReader<'_> {
+    type Error = BitstreamError;
+
+    fn header(&self) -> &OpusHeader {
+        &self.header
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet<'_>>, BitstreamError> {
+        if core::mem::take(&mut self.returned) {
+            self.packet.clear();
+        }
+        loop {
+            while let Some((&size, segments)) = self.segments.split_first() {
+                let (part, data) = self.data.split_at(usize::from(size));
+                (self.segments, self.data) = (segments, data);
+                if !self.skip_continued {
+                    self.packet.extend_from_slice(part);
+                }
+                if size == 255 {
+                    continue;
+                }
+                if core::mem::take(&mut self.skip_continued) {
+                    continue;
+                }
+                let ends_page = self.segments.iter().all(|&size| size == 255);
+                self.returned = true;
+                return Ok(Some(Packet {
+                    data: &self.packet,
+                    granule_position: ends_page.then_some(self.granule_position),
+                    ends_page,
+                    begin_of_stream: self.begin_of_stream,
+                    end_of_stream: ends_page && self.end_of_stream,
+                }));
+            }
+            if self.ended {
+                return Ok(None);
+            }
+            self.next_page()?;
+        }
+    }
+
+    fn end_granule_position(&self) -> Option<u64> {
+        self.end_granule_position
+    }
+
+    fn start_granule_position(&self) -> Option<u64> {
+        self.start.position()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_packets() {
+        for data in [
+            include_bytes!("test/mono.opus").as_slice(),
+            include_bytes!("test/offset.opus"),
+            include_bytes!("test/stereo.opus"),
+            include_bytes!("test/trimmed.opus"),
+        ] {
+            let bitstream = Bitstream::new(data);
+            let mut expected = bitstream.packet_reader::<4_096>().unwrap();
+            let mut reader = bitstream.vec_packet_reader().unwrap();
+            while let Some(packet) = expected.next_packet().unwrap() {
+                assert_eq!(reader.next_packet().unwrap(), Some(packet));
+            }
+            assert_eq!(reader.next_packet(), Ok(None));
+            assert_eq!(
+                reader.end_granule_position(),
+                expected.end_granule_position()
+            );
+            assert_eq!(
+                reader.start_granule_position(),
+                expected.start_granule_position()
+            );
+        }
+    }
+
+    #[test]
+    fn lost_page() {
+        // Drop the first audio page
+        let data = include_bytes!("test/offset.opus");
+        let data = [&data[..136], &data[205..]].concat();
+        let mut reader = Bitstream::new(&data).vec_packet_reader().unwrap();
+        let error = reader.next_packet().unwrap_err();
+        assert_eq!(
+            error,
+            BitstreamError::InvalidOggStream(ErrorValues::SequenceNumberMismatch(1, 3))
+        );
+        assert!(error.is_recoverable());
+        let mut count = 0;
+        while reader.next_packet().unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 4);
+    }
+}
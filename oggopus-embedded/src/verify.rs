@@ -0,0 +1,67 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Verification of ogg pages before their packets are used.
+ *
+ * [`PacketReader`][`crate::PacketReader`] gives each page to [`Verifier`] before any packets of
+ * that page are returned, so tampered audio can be rejected before it reaches the decoder. This
+ * crate does not implement any cryptography itself, instead [`Verifier`] can be implemented with
+ * HMAC, ed25519 or whatever is available on the device.
+ *
+ * ```rust
+ * # use oggopus_embedded::{verify::Verifier, Bitstream, PacketReader, PacketSource};
+ * # fn digest(_page: &[u8]) -> u32 { 0 }
+ * /// Checks pages against digests that were stored alongside the audio.
+ * struct DigestVerifier<'a> {
+ *     digests: &'a [u32],
+ * }
+ *
+ * impl Verifier for DigestVerifier<'_> {
+ *     fn verify_page(&mut self, sequence_number: u32, page: &[u8]) -> bool {
+ *         let index = sequence_number as usize;
+ *         index < self.digests.len() && self.digests[index] == digest(page)
+ *     }
+ * }
+ *
+ * # let data = include_bytes!("test/mono.opus");
+ * let verifier = DigestVerifier { digests: &[0, 0, 0] };
+ * let bitstream = Bitstream::new(data);
+ * let mut reader = PacketReader::<512, _>::with_verifier(&bitstream, verifier).unwrap();
+ * while let Some(packet) = reader.next_packet().unwrap() {
+ *     // Decode packets that have been verified
+ * }
+ * ```
+ */
+
+/// Verifies ogg pages.
+pub trait Verifier {
+    /**
+     * Verify raw ogg page including its header.
+     *
+     * Pages are given in the order they appear in the stream. Returning `false` rejects the page
+     * and reading fails with [`VerificationFailed`][`crate::BitstreamError::VerificationFailed`].
+     */
+    fn verify_page(&mut self, sequence_number: u32, page: &[u8]) -> bool;
+
+    /**
+     * Verify the stream after its last page has been verified.
+     *
+     * Useful for signatures over the whole stream. This is called before the packets of the last
+     * page are returned, but all the other packets have been returned already at this point.
+     */
+    fn verify_end(&mut self) -> bool {
+        true
+    }
+}
+
+/// [`Verifier`] that accepts all pages.
+#[derive(Debug, Default, PartialEq)]
+pub struct NoVerifier;
+
+impl Verifier for NoVerifier {
+    fn verify_page(&mut self, _sequence_number: u32, _page: &[u8]) -> bool {
+        true
+    }
+}
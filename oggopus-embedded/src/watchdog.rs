@@ -0,0 +1,266 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Detection of decoding that takes too long.
+
+use super::Decode;
+
+/**
+ * Hooks that are called before and after decoding a packet.
+ *
+ * Both return a timestamp from the timebase of the application, e.g. a cycle counter or a timer.
+ * Timestamps may wrap around. This is implemented for closures that return the current time.
+ */
+pub trait DecodeHooks {
+    /// Called right before decoding, returns the current time.
+    fn pre_decode(&mut self) -> u32;
+
+    /// Called right after decoding, returns the current time.
+    fn post_decode(&mut self) -> u32;
+}
+
+impl<F: FnMut() -> u32> DecodeHooks for F {
+    fn pre_decode(&mut self) -> u32 {
+        self()
+    }
+
+    fn post_decode(&mut self) -> u32 {
+        self()
+    }
+}
+
+/// Error from [`Watchdog`].
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum WatchdogError<E> {
+    /// Error from the decoder.
+    DecoderError(E),
+    /**
+     * The previous packet took too long to decode.
+     *
+     * Contains the time it took and the configured limit in ticks of the timebase.
+     */
+    Overrun(u32, u32),
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for WatchdogError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use WatchdogError::*;
+        match self {
+            DecoderError(error) => error.fmt(f),
+            Overrun(elapsed, limit) => f.write_fmt(format_args!(
+                "decoding took too long: {} ticks but limit is {}",
+                elapsed, limit
+            )),
+        }
+    }
+}
+
+impl<E: core::error::Error + 'static> core::error::Error for WatchdogError<E> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        use WatchdogError::*;
+        match self {
+            DecoderError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Decoder wrapper that measures how long decoding takes.
+ *
+ * If decoding a packet takes longer than the limit, the next call to
+ * [`decode`][`Decode::decode`] returns [`Overrun`][`WatchdogError::Overrun`] without decoding.
+ * This helps to find out e.g. misconfigured CPU specific code paths when bringing up new hardware.
+ *
+ * ```rust
+ * # use oggopus_embedded::{Bitstream, Decode, Frames, FramesError, watchdog::{Watchdog, WatchdogError}};
+ * # struct Decoder;
+ * # impl Decode for Decoder {
+ * #     type Error = ();
+ * #     fn sample_rate(&self) -> u32 { 8_000 }
+ * #     fn channels(&self) -> u8 { 1 }
+ * #     fn decode<'o>(&mut self, _: &[u8], o: &'o mut [i16]) -> Result<&'o [i16], ()> {
+ * #         Ok(&o[..160])
+ * #     }
+ * # }
+ * # let mut cycles = 0;
+ * # let mut read_cycle_counter = move || { cycles += 1_000; cycles };
+ * # let data = include_bytes!("test/mono.opus");
+ * let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+ * // Allow 10 000 cycles for decoding a packet
+ * let decoder = Watchdog::new(Decoder, read_cycle_counter, 10_000);
+ * let mut frames = Frames::<_, _, 960>::new(reader, decoder);
+ * loop {
+ *     match frames.next() {
+ *         Ok(Some(frame)) => { /* Play frame */ }
+ *         Ok(None) => break,
+ *         Err(FramesError::DecoderError(WatchdogError::Overrun(elapsed, _))) => {
+ *             println!("Decoding took {} cycles", elapsed);
+ *         }
+ *         Err(error) => panic!("{:?}", error),
+ *     }
+ * }
+ * ```
+ */
+#[derive(Debug)]
+pub struct Watchdog<D: Decode, H: DecodeHooks> {
+    decoder: D,
+    hooks: H,
+    limit: u32,
+    overrun: Option<u32>,
+    longest: u32,
+}
+
+impl<D: Decode, H: DecodeHooks> Watchdog<D, H> {
+    /// Construct [`Watchdog`] that allows decoding to take `limit` ticks of the timebase.
+    pub fn new(decoder: D, hooks: H, limit: u32) -> Self {
+        Self {
+            decoder,
+            hooks,
+            limit,
+            overrun: None,
+            longest: 0,
+        }
+    }
+
+    /// Returns the wrapped decoder.
+    pub fn decoder(&self) -> &D {
+        &self.decoder
+    }
+
+    /// Returns the longest time decoding has taken in ticks.
+    pub fn longest(&self) -> u32 {
+        self.longest
+    }
+
+    /// Deconstruct [`Watchdog`] into the decoder and the hooks.
+    pub fn into_inner(self) -> (D, H) {
+        (self.decoder, self.hooks)
+    }
+
+    /// Measure how long `decode` takes with the decoder, unless the previous decoding overran.
+    fn measure<'output>(
+        &mut self,
+        decode: impl FnOnce(&mut D) -> Result<&'output [i16], D::Error>,
+    ) -> Result<&'output [i16], WatchdogError<D::Error>> {
+        if let Some(elapsed) = self.overrun.take() {
+            return Err(WatchdogError::Overrun(elapsed, self.limit));
+        }
+        let start = self.hooks.pre_decode();
+        let result = decode(&mut self.decoder);
+        let elapsed = self.hooks.post_decode().wrapping_sub(start);
+        self.longest = self.longest.max(elapsed);
+        if elapsed > self.limit {
+            self.overrun = Some(elapsed);
+        }
+        result.map_err(WatchdogError::DecoderError)
+    }
+}
+
+impl<D: Decode, H: DecodeHooks> Decode for Watchdog<D, H> {
+    type Error = WatchdogError<D::Error>;
+
+    fn sample_rate(&self) -> u32 {
+        self.decoder.sample_rate()
+    }
+
+    fn channels(&self) -> u8 {
+        self.decoder.channels()
+    }
+
+    fn decode<'output>(
+        &mut self,
+        packet: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], Self::Error> {
+        self.measure(|decoder| decoder.decode(packet, output))
+    }
+
+    fn decode_fec<'output>(
+        &mut self,
+        packet: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], Self::Error> {
+        self.measure(|decoder| decoder.decode_fec(packet, output))
+    }
+
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        self.decoder.reset().map_err(WatchdogError::DecoderError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{frames::test::CountingDecoder, Bitstream, Frames, FramesError};
+
+    #[test]
+    fn overrun() {
+        let data = include_bytes!("test/mono.opus");
+        let reader = Bitstream::new(data).packet_reader::<512>().unwrap();
+        // Every other decoding takes 300 ticks and others take 100 ticks
+        let mut calls = 0u32;
+        let mut time = u32::MAX - 150;
+        let clock = move || {
+            calls += 1;
+            time = time.wrapping_add(if calls % 4 == 0 { 300 } else { 100 });
+            time
+        };
+        let decoder = Watchdog::new(CountingDecoder::default(), clock, 200);
+        let mut frames = Frames::<_, _, 160>::new(reader, decoder);
+        assert!(frames.next().unwrap().is_some());
+        assert!(frames.next().unwrap().is_some());
+        assert_eq!(
+            frames.next(),
+            Err(FramesError::DecoderError(WatchdogError::Overrun(300, 200)))
+        );
+        assert_eq!(frames.decoder().longest(), 300);
+        // Decoding continues after the error
+        assert!(frames.next().unwrap().is_some());
+        assert_eq!(frames.decoder().decoder().count, 480);
+    }
+
+    #[test]
+    fn reset() {
+        let mut decoder = Watchdog::new(CountingDecoder::default(), || 0, 200);
+        let mut output = [0; 160];
+        decoder.decode(&[], &mut output).unwrap();
+        assert_eq!(decoder.decoder().count, 160);
+        assert_eq!(decoder.reset(), Ok(()));
+        assert_eq!(decoder.decoder().count, 0);
+        assert_eq!(decoder.decode(&[], &mut output).unwrap()[0], 0);
+    }
+
+    #[test]
+    fn decode_fec() {
+        let mut time = 0u32;
+        let clock = move || {
+            time += 150;
+            time
+        };
+        let mut decoder = Watchdog::new(CountingDecoder::default(), clock, 100);
+        let mut output = [0; 160];
+        assert_eq!(decoder.decode_fec(&[], &mut output).unwrap()[0], 0);
+        assert_eq!(decoder.decoder().count, 160);
+        assert_eq!(decoder.longest(), 150);
+        assert_eq!(
+            decoder.decode_fec(&[], &mut output),
+            Err(WatchdogError::Overrun(150, 100))
+        );
+        assert_eq!(decoder.decoder().count, 160);
+    }
+
+    #[test]
+    fn overrun_message() {
+        let error = WatchdogError::<&str>::Overrun(300, 200);
+        assert_eq!(
+            error.to_string(),
+            "decoding took too long: 300 ticks but limit is 200"
+        );
+        let error = WatchdogError::DecoderError("invalid packet");
+        assert_eq!(error.to_string(), "invalid packet");
+    }
+}
@@ -0,0 +1,544 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+/*!
+ * Writing ogg opus streams.
+ *
+ * [`OggWriter`] muxes opus packets into pages that follow
+ * [RFC7845](https://datatracker.ietf.org/doc/html/rfc7845) and writes them to
+ * [`embedded_io::Write`], e.g. a file on SD card. Identification and comment headers are written
+ * on their own pages when the writer is constructed. Granule positions are counted from the
+ * durations of packets and the last page ends the stream when the writer is finished.
+ *
+ * ```rust
+ * # use oggopus_embedded::{opus::OpusHeader, writer::OggWriter, ChannelMapping};
+ * # let mut buffer = [0; 1_024];
+ * # let mut file = &mut buffer[..];
+ * # let encoded: [&[u8]; 2] = [&[0x08, 0x01], &[0x08, 0x02]];
+ * let header = OpusHeader {
+ *     version: 1,
+ *     channels: ChannelMapping::Family0 { channels: 1 },
+ *     pre_skip: 312,
+ *     sample_rate: 48_000,
+ *     output_gain: 0,
+ * };
+ * let mut writer = OggWriter::<_, 4_096>::new(&mut file, 0x1234, &header, "encoder").unwrap();
+ * for packet in encoded {
+ *     writer.write_packet(packet).unwrap();
+ * }
+ * writer.finish().unwrap();
+ * ```
+ */
+
+use super::{
+    crc::{Crc32Provider, SoftwareCrc},
+    info::NO_PACKET_ENDS,
+    opus::{packet_samples, ChannelMapping, OpusHeader},
+};
+use embedded_io::Write;
+
+/// Size of page header without segment table.
+const PAGE_HEADER_SIZE: usize = 27;
+
+/// Error from writing ogg opus stream.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum WriterError<E> {
+    /**
+     * Error from the writer.
+     *
+     * The page may have been written partially, so the stream should not be continued.
+     */
+    IoError(E),
+    /// Packet is empty or too short to tell its duration.
+    InvalidPacket,
+    /// More samples were to be discarded from the end than there are on the last page.
+    TrimTooLong(u64),
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for WriterError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use WriterError::*;
+        match self {
+            IoError(error) => f.write_fmt(format_args!("writing failed: {}", error)),
+            InvalidPacket => f.write_str("packet duration cannot be determined"),
+            TrimTooLong(samples) => f.write_fmt(format_args!(
+                "cannot discard {} samples, which is more than on the last page",
+                samples
+            )),
+        }
+    }
+}
+
+impl<E: core::error::Error + 'static> core::error::Error for WriterError<E> {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::IoError(error) => Some(error),
+            Self::InvalidPacket | Self::TrimTooLong(_) => None,
+        }
+    }
+}
+
+/// Result for writing ogg opus streams.
+pub type Result<O, E> = core::result::Result<O, WriterError<E>>;
+
+/**
+ * Writes opus packets to [`embedded_io::Write`] as an ogg opus stream.
+ *
+ * One page is buffered at a time. `PAGE_SIZE` is the most data a page can hold and it must be at
+ * least 255 bytes. A page is written when the next packet does not fit on it anymore or when it
+ * has reached the [target size][`OggWriter::with_page_target`]. Packets that are larger than a
+ * page continue on the following pages.
+ *
+ * The last page is kept until the next packet or [`finish`][`OggWriter::finish`], so that the end
+ * of stream flag can be set on it. If writing is interrupted, e.g. by power loss while recording,
+ * the pages written so far can be read in [lenient mode][`crate::Bitstream::with_lenient`].
+ */
+#[derive(Debug)]
+pub struct OggWriter<W: Write, const PAGE_SIZE: usize> {
+    writer: W,
+    crc: &'static dyn Crc32Provider,
+    bitstream_serial_number: u32,
+    page_sequence_number: u32,
+    /// Granule position after the last packet.
+    granule_position: u64,
+    /// Granule position after the last packet that ended on the current page.
+    page_granule_position: u64,
+    /// Granule position of the last page that has been written.
+    written_granule_position: u64,
+    target: usize,
+    begin_of_stream: bool,
+    /// Whether the current page begins with a packet that continues from the previous page.
+    continued: bool,
+    /// Whether a packet ends on the current page.
+    ended: bool,
+    /// Whether the current page is complete, but it has not been written yet.
+    complete: bool,
+    segments: [u8; 255],
+    segment_count: usize,
+    /// Length of the segment that is being filled.
+    lacing: usize,
+    data: [u8; PAGE_SIZE],
+    data_length: usize,
+}
+
+impl<W: Write, const PAGE_SIZE: usize> OggWriter<W, PAGE_SIZE> {
+    /// Page must fit at least one full segment.
+    const FITS_SEGMENT: () = assert!(PAGE_SIZE >= 255, "PAGE_SIZE must be at least 255");
+
+    /**
+     * Construct [`OggWriter`] and write the headers of the stream.
+     *
+     * The comment header contains only the vendor string. Bitstream serial number should be
+     * chosen randomly if the stream may be multiplexed with others.
+     */
+    pub fn new(
+        writer: W,
+        bitstream_serial_number: u32,
+        header: &OpusHeader,
+        vendor: &str,
+    ) -> Result<Self, W::Error> {
+        let vendor_length = (vendor.len() as u32).to_le_bytes();
+        Self::new_with_parts(
+            writer,
+            bitstream_serial_number,
+            header,
+            &[b"OpusTags", &vendor_length, vendor.as_bytes(), &[0; 4]],
+        )
+    }
+
+    /**
+     * Construct [`OggWriter`] and write the headers of the stream with the given comment header.
+     *
+     * `tags` is the whole comment header packet, starting with `OpusTags`, e.g. from
+     * [`TagsBuilder`][`crate::tags::TagsBuilder`]. It is not checked.
+     */
+    pub fn new_with_tags(
+        writer: W,
+        bitstream_serial_number: u32,
+        header: &OpusHeader,
+        tags: &[u8],
+    ) -> Result<Self, W::Error> {
+        Self::new_with_parts(writer, bitstream_serial_number, header, &[tags])
+    }
+
+    /// Construct [`OggWriter`] and write the headers with comment header given in parts.
+    fn new_with_parts(
+        writer: W,
+        bitstream_serial_number: u32,
+        header: &OpusHeader,
+        tags: &[&[u8]],
+    ) -> Result<Self, W::Error> {
+        let () = Self::FITS_SEGMENT;
+        let mut this = Self {
+            writer,
+            crc: &SoftwareCrc,
+            bitstream_serial_number,
+            page_sequence_number: 0,
+            granule_position: 0,
+            page_granule_position: 0,
+            written_granule_position: 0,
+            target: PAGE_SIZE,
+            begin_of_stream: true,
+            continued: false,
+            ended: false,
+            complete: false,
+            segments: [0; 255],
+            segment_count: 0,
+            lacing: 0,
+            data: [0; PAGE_SIZE],
+            data_length: 0,
+        };
+        this.write_header(header)?;
+        this.write_page(false)?;
+        for part in tags {
+            this.append(part)?;
+        }
+        this.end_packet()?;
+        this.complete = true;
+        Ok(this)
+    }
+
+    /**
+     * Set the size of page data after which pages are written.
+     *
+     * Smaller pages allow seeking more precisely and lose less audio if recording is interrupted,
+     * but add more overhead. Defaults to `PAGE_SIZE`.
+     */
+    pub fn with_page_target(mut self, target: usize) -> Self {
+        self.target = target.clamp(1, PAGE_SIZE);
+        self
+    }
+
+    /**
+     * Set provider for calculating checksums of pages.
+     *
+     * This allows calculating checksums with a hardware CRC peripheral, see [`crc`][`crate::crc`]
+     * module.
+     */
+    pub fn with_crc_provider(mut self, provider: &'static dyn Crc32Provider) -> Self {
+        self.crc = provider;
+        self
+    }
+
+    /// Returns the granule position after the packets that have been given.
+    pub fn granule_position(&self) -> u64 {
+        self.granule_position
+    }
+
+    /**
+     * Write opus packet to the stream.
+     *
+     * Duration of the packet is read from its TOC byte. Returns
+     * [`InvalidPacket`][`WriterError::InvalidPacket`] if it cannot be read, and the packet is not
+     * written then.
+     */
+    pub fn write_packet(&mut self, packet: &[u8]) -> Result<(), W::Error> {
+        let samples = packet_samples(packet).ok_or(WriterError::InvalidPacket)?;
+        let fits = self.data_length + packet.len() <= PAGE_SIZE
+            && self.segment_count + packet.len() / 255 < self.segments.len();
+        if self.complete || (self.data_length > 0 && !fits) {
+            self.write_page(false)?;
+        }
+        self.append(packet)?;
+        self.end_packet()?;
+        self.granule_position += u64::from(samples);
+        self.page_granule_position = self.granule_position;
+        if self.data_length >= self.target {
+            self.complete = true;
+        }
+        Ok(())
+    }
+
+    /**
+     * Write the remaining data with the end of stream flag and return the writer.
+     *
+     * Writer is not flushed.
+     */
+    pub fn finish(self) -> Result<W, W::Error> {
+        self.finish_trimmed(0)
+    }
+
+    /**
+     * Write the remaining data with the end of stream flag and discard `samples` from the end.
+     *
+     * This is used when the encoder had to pad the last packet, so that decoders know how many
+     * samples of it to play. At most the duration of the packets that end on the last page can
+     * be discarded, and [`TrimTooLong`][`WriterError::TrimTooLong`] is returned without ending
+     * the stream otherwise.
+     */
+    pub fn finish_trimmed(mut self, samples: u64) -> Result<W, W::Error> {
+        if samples > self.page_granule_position - self.written_granule_position {
+            return Err(WriterError::TrimTooLong(samples));
+        }
+        self.page_granule_position -= samples;
+        self.write_page(true)?;
+        Ok(self.writer)
+    }
+
+    /// Append opus identification header as a packet to the current page.
+    fn write_header(&mut self, header: &OpusHeader) -> Result<(), W::Error> {
+        let channels = header.channels.get_channel_count();
+        self.append(b"OpusHead")?;
+        self.append(&[header.version, channels])?;
+        self.append(&header.pre_skip.to_le_bytes())?;
+        self.append(&header.sample_rate.to_le_bytes())?;
+        self.append(&header.output_gain.to_le_bytes())?;
+        self.append(&[header.channels.get_family()])?;
+        if !matches!(header.channels, ChannelMapping::Family0 { .. }) {
+            self.append(&[
+                header.channels.get_stream_count(),
+                header.channels.get_coupled_stream_count(),
+            ])?;
+            if let Some(table) = header.channels.get_mapping_table() {
+                self.append(table)?;
+            }
+        }
+        self.end_packet()
+    }
+
+    /// Append data of a packet to the current page and write full pages.
+    fn append(&mut self, mut data: &[u8]) -> Result<(), W::Error> {
+        while !data.is_empty() {
+            if self.data_length == PAGE_SIZE || self.segment_count == self.segments.len() {
+                self.continue_page()?;
+            }
+            let length = data
+                .len()
+                .min(255 - self.lacing)
+                .min(PAGE_SIZE - self.data_length);
+            let end = self.data_length + length;
+            self.data[self.data_length..end].copy_from_slice(&data[..length]);
+            self.data_length = end;
+            self.lacing += length;
+            data = &data[length..];
+            if self.lacing == 255 {
+                self.segments[self.segment_count] = 255;
+                self.segment_count += 1;
+                self.lacing = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// End the packet that is being appended to the current page.
+    fn end_packet(&mut self) -> Result<(), W::Error> {
+        if self.segment_count == self.segments.len() {
+            self.continue_page()?;
+        }
+        self.segments[self.segment_count] = self.lacing as u8;
+        self.segment_count += 1;
+        self.lacing = 0;
+        self.ended = true;
+        if self.segment_count == self.segments.len() {
+            self.complete = true;
+        }
+        Ok(())
+    }
+
+    /// Write the current page and continue the packet that is being appended on the next one.
+    fn continue_page(&mut self) -> Result<(), W::Error> {
+        self.write_page(false)?;
+        self.continued = true;
+        Ok(())
+    }
+
+    /**
+     * Write the current page and start the next one.
+     *
+     * Data of the segment that is being filled is moved to the next page.
+     */
+    fn write_page(&mut self, end_of_stream: bool) -> Result<(), W::Error> {
+        let flags = u8::from(self.continued)
+            | u8::from(self.begin_of_stream) << 1
+            | u8::from(end_of_stream) << 2;
+        let mut header = [0; PAGE_HEADER_SIZE];
+        header[..4].copy_from_slice(b"OggS");
+        header[5] = flags;
+        let granule_position = if self.ended {
+            self.page_granule_position
+        } else {
+            NO_PACKET_ENDS
+        };
+        header[6..14].copy_from_slice(&granule_position.to_le_bytes());
+        header[14..18].copy_from_slice(&self.bitstream_serial_number.to_le_bytes());
+        header[18..22].copy_from_slice(&self.page_sequence_number.to_le_bytes());
+        header[26] = self.segment_count as u8;
+        let segments = &self.segments[..self.segment_count];
+        let length = self.data_length - self.lacing;
+        let data = &self.data[..length];
+        let checksum = [header.as_slice(), segments, data]
+            .into_iter()
+            .fold(0, |crc, part| self.crc.update(crc, part));
+        header[22..26].copy_from_slice(&checksum.to_le_bytes());
+        for part in [header.as_slice(), segments, data] {
+            self.writer.write_all(part).map_err(WriterError::IoError)?;
+        }
+        if self.ended {
+            self.written_granule_position = self.page_granule_position;
+        }
+        self.page_sequence_number = self.page_sequence_number.wrapping_add(1);
+        self.begin_of_stream = false;
+        self.continued = false;
+        self.ended = false;
+        self.complete = false;
+        self.segment_count = 0;
+        self.data.copy_within(length..self.data_length, 0);
+        self.data_length -= length;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        conformance::Checker, tags::TagsBuilder, Bitstream, OggPusher, PacketSource, Pages,
+    };
+
+    /// Returns the identification header of offset.opus.
+    fn header() -> OpusHeader {
+        OpusHeader::parse(&include_bytes!("test/offset.opus")[28..47]).unwrap()
+    }
+
+    /// Write packets with the given page size and target and return the stream.
+    fn write<const PAGE_SIZE: usize>(target: usize, packets: &[&[u8]], trim: u64) -> Vec<u8> {
+        let mut buffer = vec![0; 100_000];
+        let mut output = buffer.as_mut_slice();
+        let mut writer = OggWriter::<_, PAGE_SIZE>::new(&mut output, 7, &header(), "test")
+            .unwrap()
+            .with_page_target(target);
+        for packet in packets {
+            writer.write_packet(packet).unwrap();
+        }
+        writer.finish_trimmed(trim).unwrap();
+        let length = 100_000 - output.len();
+        buffer.truncate(length);
+        buffer
+    }
+
+    /// Returns the audio packets of offset.opus.
+    fn original() -> Vec<Vec<u8>> {
+        let bitstream = Bitstream::new(include_bytes!("test/offset.opus"));
+        let mut reader = bitstream.packet_reader::<1_024>().unwrap();
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.next_packet().unwrap() {
+            packets.push(packet.data.to_vec());
+        }
+        packets
+    }
+
+    /// Check that data conforms to RFC7845 and return its packets.
+    fn read(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut checker = Checker::new();
+        for page in Pages::new(data) {
+            assert_eq!(checker.page(&page.unwrap()), Ok(()));
+        }
+        assert_eq!(checker.finish(), Ok(()));
+        let mut pusher = OggPusher::<{ crate::MAX_PAGE_SIZE }, 70_000>::new();
+        let mut packets = Vec::new();
+        let mut data = data;
+        while !data.is_empty() {
+            data = &data[pusher.push(data).unwrap()..];
+            while let Some(packet) = pusher.next().unwrap() {
+                packets.push(packet.data.to_vec());
+            }
+        }
+        packets
+    }
+
+    #[test]
+    fn write_stream() {
+        let packets = original();
+        let packets: Vec<&[u8]> = packets.iter().map(Vec::as_slice).collect();
+        let data = write::<4_096>(4_096, &packets, 0);
+        assert_eq!(read(&data)[2..], packets);
+        let bitstream = Bitstream::new(&data).with_strict(true);
+        let mut reader = bitstream.packet_reader::<1_024>().unwrap();
+        assert_eq!(reader.header(), &header());
+        assert_eq!(bitstream.tags().unwrap().vendor().as_str(), Some("test"));
+        while reader.next_packet().unwrap().is_some() {}
+        // Granule position counts from zero
+        assert_eq!(reader.end_granule_position(), Some(7 * 960));
+        // Headers and all audio on their own pages
+        assert_eq!(Pages::new(&data).count(), 3);
+    }
+
+    #[test]
+    fn page_target() {
+        let packets = original();
+        let packets: Vec<&[u8]> = packets.iter().map(Vec::as_slice).collect();
+        let data = write::<4_096>(1, &packets, 0);
+        let granule_positions: Vec<_> = Pages::new(&data)
+            .skip(2)
+            .map(|page| page.unwrap().granule_position())
+            .collect();
+        assert_eq!(granule_positions, [960, 1920, 2880, 3840, 4800, 5760, 6720]);
+        assert_eq!(read(&data)[2..], packets);
+    }
+
+    #[test]
+    fn continued_packets() {
+        // Packet of exactly 255 bytes, packet longer than a page and packet after them
+        let long: Vec<u8> = (0..1_000).map(|byte| byte as u8 | 0x08).collect();
+        let packets = [&long[..255], &long, &[0x08, 0x01]];
+        let data = write::<300>(300, &packets, 0);
+        let pages: Vec<_> = Pages::new(&data).map(|page| page.unwrap()).collect();
+        assert!(pages.iter().all(|page| page.data().len() <= 300));
+        assert_eq!(pages[2].granule_position(), 960);
+        assert!(!pages[3].continued());
+        assert_eq!(pages[3].granule_position(), NO_PACKET_ENDS);
+        assert!(pages[4].continued());
+        assert_eq!(read(&data)[2..], packets);
+        // Segment table fills before data
+        let long = vec![0x08; 255 * 255 + 10];
+        let packets = [long.as_slice(), &[0x08]];
+        let data = write::<65_025>(65_025, &packets, 0);
+        assert_eq!(read(&data)[2..], packets);
+    }
+
+    #[test]
+    fn write_tags() {
+        let mut tags = [0; 400];
+        let mut builder = TagsBuilder::new(&mut tags, "test").unwrap();
+        builder.push("TITLE", &"x".repeat(300)).unwrap();
+        let mut buffer = [0; 1_024];
+        let mut output = &mut buffer[..];
+        let writer =
+            OggWriter::<_, 256>::new_with_tags(&mut output, 7, &header(), builder.as_bytes())
+                .unwrap();
+        writer.finish().unwrap();
+        let length = 1_024 - output.len();
+        // Comment header continues over two pages
+        assert_eq!(Pages::new(&buffer[..length]).count(), 3);
+        assert_eq!(read(&buffer[..length])[1..], [builder.as_bytes()]);
+    }
+
+    #[test]
+    fn trim_end() {
+        let data = write::<4_096>(4_096, &[&[0x08], &[0x08, 0x01]], 100);
+        let info = Bitstream::new(&data).stream_info().unwrap();
+        assert_eq!(info.last_granule_position, 1_820);
+        let mut buffer = [0; 256];
+        let mut writer = OggWriter::<_, 256>::new(&mut buffer[..], 7, &header(), "").unwrap();
+        assert_eq!(writer.write_packet(&[]), Err(WriterError::InvalidPacket));
+        writer.write_packet(&[0x08]).unwrap();
+        assert_eq!(writer.granule_position(), 960);
+        // Only the last page can be trimmed
+        let mut buffer = [0; 1_024];
+        let mut writer = OggWriter::<_, 256>::new(&mut buffer[..], 7, &header(), "")
+            .unwrap()
+            .with_page_target(1);
+        writer.write_packet(&[0x08]).unwrap();
+        writer.write_packet(&[0x08]).unwrap();
+        assert_eq!(
+            writer.finish_trimmed(961).err(),
+            Some(WriterError::TrimTooLong(961))
+        );
+        let data = write::<4_096>(1, &[&[0x08], &[0x08]], 960);
+        let info = Bitstream::new(&data).stream_info().unwrap();
+        assert_eq!(info.last_granule_position, 960);
+    }
+}
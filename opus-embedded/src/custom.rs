@@ -0,0 +1,393 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Opus Custom decoder for non-standard frame sizes and sampling rates.
+
+#[cfg(feature = "pseudostack")]
+use super::{with_pseudostack, Scratch};
+use super::{Channels, DecoderError, DecoderStats};
+use az::SaturatingAs;
+use core::ffi::c_int;
+use core::ptr::NonNull;
+use opus_embedded_sys::*;
+
+/**
+ * Mode of Opus Custom, i.e. the sampling rate and frame size that both the encoder and decoder
+ * use.
+ *
+ * Opus Custom streams have no TOC byte, so the mode must be agreed out of band, e.g. fixed in the
+ * firmware of both ends of a wireless link. The mode must outlive the decoders that use it, which
+ * the borrow of [`CustomDecoder`] ensures.
+ */
+#[derive(Debug)]
+pub struct CustomMode {
+    mode: NonNull<OpusCustomMode>,
+    freq: i32,
+    frame_size: usize,
+}
+
+// SAFETY: libopus never modifies a mode after creating it, it is only read by the decoders
+unsafe impl Send for CustomMode {}
+unsafe impl Sync for CustomMode {}
+
+/// Returns whether a number has no other prime factors than 2, 3 and 5.
+const fn is_5_smooth(mut value: usize) -> bool {
+    if value == 0 {
+        return false;
+    }
+    while value % 2 == 0 {
+        value /= 2;
+    }
+    while value % 3 == 0 {
+        value /= 3;
+    }
+    while value % 5 == 0 {
+        value /= 5;
+    }
+    value == 1
+}
+
+impl CustomMode {
+    /**
+     * Sampling rates that modes can be created for.
+     *
+     * libopus accepts any rate from 8 kHz to 96 kHz, but computing the tables of a mode fails an
+     * assertion or corrupts memory with some of them, so only these common rates are allowed.
+     */
+    pub const SAMPLING_RATES: [i32; 12] = [
+        8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 64000, 88200, 96000,
+    ];
+
+    /**
+     * Create mode for a sampling rate and frame size in samples per channel.
+     *
+     * Frame size must be even, from 40 to 1024 samples and no shorter than 1 ms, and it must not
+     * have other prime factors than 2, 3 and 5. Otherwise this fails with `OPUS_BAD_ARG`, as does
+     * a sampling rate that is not in [`CustomMode::SAMPLING_RATES`].
+     *
+     * Modes of 48 kHz with frames of 2.5, 5, 10 or 20 ms, i.e. 120, 240, 480 or 960 samples, are
+     * the built-in modes of libopus. Other modes are allocated with `opus_alloc`, which on targets
+     * without C library needs `rust_allocator` feature, or this fails with `OPUS_ALLOC_FAIL`.
+     * With `pseudostack` feature libopus needs scratch memory for creating any mode, so this
+     * always fails with `OPUS_ALLOC_FAIL` and `CustomMode::with_scratch` must be used instead.
+     *
+     * ```
+     * # use opus_embedded::CustomMode;
+     * // 2.5 ms frames for a low latency link
+     * # #[cfg(not(feature = "pseudostack"))]
+     * let mode = CustomMode::new(48000, 120);
+     * # #[cfg(feature = "pseudostack")]
+     * # let mode = unsafe { CustomMode::with_scratch(48000, 120, &mut Default::default()) };
+     * assert_eq!(mode.unwrap().frame_size(), 120);
+     * ```
+     *
+     * See also [`opus_custom_mode_create`].
+     */
+    pub fn new(freq: i32, frame_size: usize) -> Result<Self, DecoderError> {
+        Self::validate(freq, frame_size)?;
+        if cfg!(feature = "pseudostack") {
+            let error_code = OPUS_ALLOC_FAIL;
+            return Err(DecoderError { error_code });
+        }
+        Self::create(freq, frame_size)
+    }
+
+    /**
+     * Create mode like [`CustomMode::new`] with scratch memory for libopus.
+     *
+     * libopus does not allocate anything from the scratch memory when creating a mode, but
+     * without it libopus fails or allocates its own scratch memory that is never freed. The same
+     * scratch memory can be given to a decoder afterwards.
+     *
+     * # Safety
+     * Modes must not be created at the same time as decoding on different threads or cores, see
+     * [`Decoder::set_scratch`].
+     *
+     * [`Decoder::set_scratch`]: super::Decoder::set_scratch
+     */
+    #[cfg(feature = "pseudostack")]
+    pub unsafe fn with_scratch(
+        freq: i32,
+        frame_size: usize,
+        scratch: &mut Scratch,
+    ) -> Result<Self, DecoderError> {
+        Self::validate(freq, frame_size)?;
+        with_pseudostack(Some(scratch), || Self::create(freq, frame_size))?
+    }
+
+    /// Check that libopus can create mode for sampling rate and frame size safely.
+    fn validate(freq: i32, frame_size: usize) -> Result<(), DecoderError> {
+        // libopus frees uninitialized pointers when it cannot factor the FFT size of the frame
+        if !Self::SAMPLING_RATES.contains(&freq) || !is_5_smooth(frame_size) {
+            let error_code = OPUS_BAD_ARG;
+            Err(DecoderError { error_code })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Create mode that has been validated.
+    fn create(freq: i32, frame_size: usize) -> Result<Self, DecoderError> {
+        let mut error_code = 0;
+        // SAFETY: The sampling rate and frame size are ones that libopus handles, and it checks
+        // their ranges itself. Error code is written to a valid pointer
+        let mode =
+            unsafe { opus_custom_mode_create(freq, frame_size.saturating_as(), &mut error_code) };
+        match NonNull::new(mode) {
+            Some(mode) => Ok(CustomMode {
+                mode,
+                freq,
+                frame_size,
+            }),
+            None => Err(DecoderError { error_code }),
+        }
+    }
+
+    /// Returns the sampling rate of the mode.
+    pub fn sampling_rate(&self) -> i32 {
+        self.freq
+    }
+
+    /// Returns the number of samples per channel in a frame of the mode.
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /**
+     * Returns the size of decoder state for the mode and number of channels in bytes.
+     *
+     * [`CustomDecoder`] must have at least this much memory for a decoder of the mode. It is a
+     * bit over 9 kB per channel for 48 kHz modes, and at most 19.5 kB for stereo decoders.
+     *
+     * See also [`opus_custom_decoder_get_size`].
+     */
+    pub fn decoder_size(&self, channels: Channels) -> usize {
+        // SAFETY: The mode is valid and the number of channels can be only one or two
+        let size =
+            unsafe { opus_custom_decoder_get_size(self.mode.as_ptr(), channels.channels().into()) };
+        size.saturating_as()
+    }
+}
+
+impl Drop for CustomMode {
+    fn drop(&mut self) {
+        // SAFETY: The mode was created by libopus, and the decoders that borrow it are gone.
+        // Built-in modes are not freed by libopus
+        unsafe { opus_custom_mode_destroy(self.mode.as_ptr()) }
+    }
+}
+
+/// Memory for the state of [`CustomDecoder`], aligned for libopus.
+#[repr(C, align(8))]
+struct CustomDecoderState<const SIZE: usize> {
+    memory: [u8; SIZE],
+}
+
+impl<const SIZE: usize> core::fmt::Debug for CustomDecoderState<SIZE> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CustomDecoderState")
+            .field("size", &SIZE)
+            .finish_non_exhaustive()
+    }
+}
+
+/**
+ * Opus Custom decoder with `SIZE` bytes of memory for its state.
+ *
+ * The state is stored in the decoder itself like with [`Decoder`][`super::Decoder`], so its size
+ * must be chosen for the mode, see [`CustomMode::decoder_size`]. Note that stereo decoders cannot
+ * be created if stereo feature has not been enabled.
+ *
+ * ```
+ * # use opus_embedded::{Channels, CustomDecoder, CustomMode};
+ * # #[cfg(not(feature = "pseudostack"))]
+ * let mode = CustomMode::new(48000, 120).unwrap();
+ * # #[cfg(feature = "pseudostack")]
+ * # let mode = unsafe { CustomMode::with_scratch(48000, 120, &mut Default::default()) }.unwrap();
+ * let mut decoder = CustomDecoder::<10240>::new(&mode, Channels::Mono).unwrap();
+ * # #[cfg(feature = "pseudostack")]
+ * # unsafe { decoder.set_scratch(Box::leak(Box::default())) };
+ * let mut output = [0; 120];
+ * # let data = [0x80, 0, 0, 0, 0, 0, 0, 0];
+ * let output = decoder.decode(&data, &mut output).unwrap();
+ * println!("Got {} samples of data in output", output.len());
+ * ```
+ */
+#[derive(Debug)]
+pub struct CustomDecoder<'mode, const SIZE: usize> {
+    state: CustomDecoderState<SIZE>,
+    mode: &'mode CustomMode,
+    channels: Channels,
+    stats: DecoderStats,
+    #[cfg(feature = "pseudostack")]
+    scratch: Option<&'static mut Scratch>,
+}
+
+impl<'mode, const SIZE: usize> CustomDecoder<'mode, SIZE> {
+    /**
+     * Construct decoder for a mode and number of channels.
+     *
+     * Fails with `OPUS_ALLOC_FAIL` if the decoder state of the mode does not fit in `SIZE` bytes.
+     *
+     * See also [`opus_custom_decoder_init`].
+     */
+    pub fn new(mode: &'mode CustomMode, channels: Channels) -> Result<Self, DecoderError> {
+        if !cfg!(feature = "stereo") && channels == Channels::Stereo
+            || mode.decoder_size(channels) > SIZE
+        {
+            let error_code = OPUS_ALLOC_FAIL;
+            return Err(DecoderError { error_code });
+        }
+        let mut decoder = CustomDecoder {
+            state: CustomDecoderState { memory: [0; SIZE] },
+            mode,
+            channels,
+            stats: DecoderStats::default(),
+            #[cfg(feature = "pseudostack")]
+            scratch: None,
+        };
+        decoder.reset()?;
+        Ok(decoder)
+    }
+
+    /// Returns the pointer to the decoder state for libopus.
+    fn state(&mut self) -> *mut OpusCustomDecoder {
+        self.state.memory.as_mut_ptr().cast()
+    }
+
+    /// Returns the mode of the decoder.
+    pub fn mode(&self) -> &'mode CustomMode {
+        self.mode
+    }
+
+    /// Returns the number of channels of the decoder.
+    pub fn channels(&self) -> Channels {
+        self.channels
+    }
+
+    /**
+     * Decode a frame of Opus Custom from data into output buffer.
+     *
+     * Empty data conceals a lost frame. Output buffer must have space for a frame of the mode,
+     * i.e. [`CustomMode::frame_size`] samples per channel, or this fails with
+     * `OPUS_BUFFER_TOO_SMALL`. Returns the decoded frame stored on output buffer.
+     *
+     * The first byte of a frame tells its duration, so a frame can also be shorter than the frame
+     * size of the mode, as the encoder of libopus signals it by default. With 48 kHz modes it is
+     * the TOC byte of a CELT-only Opus packet, and other bytes fail with `OPUS_INVALID_PACKET`.
+     *
+     * See also [`opus_custom_decode`].
+     */
+    pub fn decode<'output>(
+        &mut self,
+        data: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], DecoderError> {
+        let frame_size = self.mode.frame_size;
+        let channels = usize::from(self.channels.channels());
+        if output.len() < frame_size * channels {
+            let error_code = OPUS_BUFFER_TOO_SMALL;
+            return Err(DecoderError { error_code });
+        }
+        // SAFETY: The pointers point to valid slices of data or null if data was empty, and output
+        // has space for a frame. The decoder state has no pointers to itself, so it is valid
+        // even if the decoder has been moved
+        let samples = self.with_scratch(|state| unsafe {
+            let len = data.len().saturating_as();
+            let data = if !data.is_empty() {
+                data.as_ptr()
+            } else {
+                core::ptr::null()
+            };
+            opus_custom_decode(
+                state,
+                data,
+                len,
+                output.as_mut_ptr(),
+                frame_size.saturating_as(),
+            )
+        })?;
+        if samples < 0 {
+            if samples == OPUS_INVALID_PACKET {
+                self.stats.invalid_packets = self.stats.invalid_packets.wrapping_add(1);
+            }
+            Err(DecoderError {
+                error_code: samples,
+            })
+        } else {
+            if data.is_empty() {
+                self.stats.frames_concealed = self.stats.frames_concealed.wrapping_add(1);
+            } else {
+                self.stats.packets_decoded = self.stats.packets_decoded.wrapping_add(1);
+            }
+            self.stats.samples_decoded = self.stats.samples_decoded.wrapping_add(samples as u64);
+            Ok(&output[..samples as usize * channels])
+        }
+    }
+
+    /**
+     * Give scratch memory for the temporary arrays of libopus when decoding.
+     *
+     * Returns the previous scratch memory of the decoder. See [`Decoder::set_scratch`].
+     *
+     * [`Decoder::set_scratch`]: super::Decoder::set_scratch
+     *
+     * # Safety
+     * Decoders must not decode at the same time on different threads or cores.
+     */
+    #[cfg(feature = "pseudostack")]
+    pub unsafe fn set_scratch(
+        &mut self,
+        scratch: &'static mut Scratch,
+    ) -> Option<&'static mut Scratch> {
+        self.scratch.replace(scratch)
+    }
+
+    /// Call libopus with the scratch memory of the decoder as its pseudostack.
+    #[cfg(feature = "pseudostack")]
+    fn with_scratch<R>(
+        &mut self,
+        f: impl FnOnce(*mut OpusCustomDecoder) -> R,
+    ) -> Result<R, DecoderError> {
+        let state = self.state();
+        with_pseudostack(self.scratch.as_deref_mut(), || f(state))
+    }
+
+    /// Call libopus, which uses stack for its temporary arrays.
+    #[cfg(not(feature = "pseudostack"))]
+    fn with_scratch<R>(
+        &mut self,
+        f: impl FnOnce(*mut OpusCustomDecoder) -> R,
+    ) -> Result<R, DecoderError> {
+        Ok(f(self.state()))
+    }
+
+    /**
+     * Reset the decoder state so that the next frame is decoded as if it was the first one.
+     * Statistics are kept.
+     */
+    pub fn reset(&mut self) -> Result<(), DecoderError> {
+        let mode = self.mode.mode.as_ptr();
+        let channels = self.channels.channels().into();
+        // SAFETY: The state has at least the size libopus needs for the mode, checked in new, and
+        // the mode outlives the decoder
+        let error_code: c_int = unsafe { opus_custom_decoder_init(self.state(), mode, channels) };
+        // PANIC: All error codes are small integers
+        if error_code != OPUS_OK.try_into().unwrap() {
+            Err(DecoderError { error_code })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns statistics of decoding since the decoder was created or statistics were reset.
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    /// Reset statistics counters to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = DecoderStats::default();
+    }
+}
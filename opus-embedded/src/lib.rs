@@ -11,11 +11,31 @@
 #![no_std]
 #![deny(missing_docs)]
 
+#[cfg(feature = "std")]
+extern crate std;
+
 use az::SaturatingAs;
 use core::ffi::{c_int, CStr};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use opus_embedded_sys::*;
 
+#[cfg(feature = "custom_modes")]
+mod custom;
+#[cfg(feature = "ogg")]
+mod ogg;
+mod pool;
+#[cfg(feature = "symphonia")]
+mod symphonia;
+
+#[cfg(feature = "custom_modes")]
+pub use custom::{CustomDecoder, CustomMode};
+pub use pool::{DecoderHandle, DecoderPool};
+#[cfg(feature = "symphonia")]
+pub use symphonia::SymphoniaDecoder;
+
+#[cfg(all(feature = "stack_protector", target_os = "none"))]
+pub use opus_embedded_sys::set_stack_guard;
+
 pub mod prelude {
     /*!
      * opus_embedded prelude.
@@ -69,6 +89,18 @@ pub struct DecoderError {
     error_code: c_int,
 }
 
+impl DecoderError {
+    /**
+     * Returns the numeric error code from libopus.
+     *
+     * The codes are defined as `OPUS_*` constants in opus-embedded-sys. Use
+     * [`Display`][`core::fmt::Display`] or [`OpusError::message`] for human-readable message.
+     */
+    pub fn code(&self) -> i32 {
+        self.error_code
+    }
+}
+
 unsafe impl RawOpusError for DecoderError {
     fn numeric(&self) -> c_int {
         // SAFETY: This error code was given by libopus and we trust that it is correct
@@ -135,7 +167,127 @@ impl Channels {
 #[derive(Debug)]
 pub struct Decoder {
     decoder: OpusDecoder,
+    freq: SamplingRate,
     channels: Channels,
+    stats: DecoderStats,
+    #[cfg(feature = "pseudostack")]
+    scratch: Option<&'static mut Scratch>,
+}
+
+#[cfg(all(feature = "pseudostack", not(feature = "custom_modes")))]
+const SCRATCH_SIZE: usize = if cfg!(feature = "stereo") {
+    OPUS_DECODER_SCRATCH_SIZE_CH2
+} else {
+    OPUS_DECODER_SCRATCH_SIZE_CH1
+};
+
+#[cfg(all(feature = "pseudostack", feature = "custom_modes"))]
+const SCRATCH_SIZE: usize = if cfg!(feature = "stereo") {
+    OPUS_CUSTOM_DECODER_SCRATCH_SIZE_CH2
+} else {
+    OPUS_DECODER_SCRATCH_SIZE_CH1
+};
+
+/**
+ * Scratch memory for temporary arrays of libopus when decoding.
+ *
+ * With `pseudostack` feature libopus allocates its temporary arrays from this instead of stack,
+ * which keeps stack usage of decoding small on devices with small task stacks. This is big
+ * enough for any decoder, i.e. [`OPUS_DECODER_SCRATCH_SIZE_CH1`] bytes or with stereo feature
+ * [`OPUS_DECODER_SCRATCH_SIZE_CH2`] bytes. Stereo decoders of Opus Custom need a bit more, so
+ * with `custom_modes` feature this has `OPUS_CUSTOM_DECODER_SCRATCH_SIZE_CH2` bytes instead. See
+ * [`Decoder::set_scratch`].
+ */
+#[cfg(feature = "pseudostack")]
+#[repr(C, align(8))]
+pub struct Scratch {
+    memory: [u8; SCRATCH_SIZE],
+}
+
+#[cfg(feature = "pseudostack")]
+impl Scratch {
+    /// Creates scratch memory, e.g. for a `static`.
+    pub const fn new() -> Self {
+        Scratch {
+            memory: [0; SCRATCH_SIZE],
+        }
+    }
+}
+
+#[cfg(feature = "pseudostack")]
+impl Default for Scratch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "pseudostack")]
+impl core::fmt::Debug for Scratch {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Scratch")
+            .field("size", &self.memory.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Call libopus with scratch memory as its pseudostack, or fail if there is none.
+#[cfg(feature = "pseudostack")]
+fn with_pseudostack<R>(
+    scratch: Option<&mut Scratch>,
+    f: impl FnOnce() -> R,
+) -> Result<R, DecoderError> {
+    let Some(scratch) = scratch else {
+        let error_code = OPUS_ALLOC_FAIL;
+        return Err(DecoderError { error_code });
+    };
+    // SAFETY: The caller of set_scratch ensures that no other thread or core uses the
+    // pseudostack at the same time, and the previous pseudostack is restored
+    unsafe {
+        let previous = global_stack;
+        global_stack = scratch.memory.as_mut_ptr().cast();
+        let result = f();
+        global_stack = previous;
+        Ok(result)
+    }
+}
+
+/**
+ * Statistics of [`Decoder`].
+ *
+ * Counters wrap around on overflow.
+ */
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DecoderStats {
+    /// The number of packets decoded successfully.
+    pub packets_decoded: u32,
+    /// The number of samples per channel produced, including concealed frames.
+    pub samples_decoded: u64,
+    /// The number of frames synthesized by packet loss concealment or recovered with FEC.
+    pub frames_concealed: u32,
+    /// The number of packets rejected as invalid.
+    pub invalid_packets: u32,
+}
+
+/**
+ * Memory used by libopus when decoding a packet, e.g. for sizing the stack of an RTOS task.
+ *
+ * See [`Decoder::memory_usage`].
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemoryUsage {
+    /**
+     * Stack used by libopus in the worst case in bytes, including temporary arrays unless
+     * `pseudostack` feature is enabled.
+     *
+     * This is known only when libopus is built with GCC and `OPUS_EMBEDDED_SYS_STACK_USAGE=1`
+     * environment variable, see [`OPUS_DECODE_STACK_FRAMES`]. Stack used by the caller and by
+     * functions of C library, e.g. memcpy, come on top of this.
+     */
+    pub stack: Option<usize>,
+    /// Scratch memory for temporary arrays in bytes with `pseudostack` feature, or zero.
+    pub scratch: usize,
+    /// Heap allocated in bytes, which is zero as the decoder state is in [`Decoder`] itself.
+    pub heap: usize,
 }
 
 /**
@@ -189,8 +341,61 @@ impl Decoder {
         }
         let mut decoder = Decoder {
             decoder: OpusDecoder::default(),
+            freq,
             channels,
+            stats: DecoderStats::default(),
+            #[cfg(feature = "pseudostack")]
+            scratch: None,
+        };
+        let error_code = Self::init_state(&mut decoder.decoder, freq, channels);
+        // PANIC: All error codes are small integers
+        if error_code != OPUS_OK.try_into().unwrap() {
+            Err(DecoderError { error_code })
+        } else {
+            Ok(decoder)
+        }
+    }
+
+    /**
+     * Initialize decoder in place in the given memory.
+     *
+     * This avoids moving the large decoder struct around on stack, which may be useful on
+     * devices with small stacks. The memory is zeroed before initialization.
+     *
+     * See also [`Decoder::new`].
+     */
+    pub fn init_in_place(
+        slot: &mut core::mem::MaybeUninit<Decoder>,
+        freq: SamplingRate,
+        channels: Channels,
+    ) -> Result<&mut Decoder, DecoderError> {
+        if !cfg!(feature = "stereo") && channels == Channels::Stereo {
+            let error_code = OPUS_ALLOC_FAIL;
+            return Err(DecoderError { error_code });
+        }
+        // SAFETY: All zeroes is a valid value for OpusDecoder as it is only bytes, and the other
+        // fields are written before the reference is created
+        let decoder = unsafe {
+            let pointer = slot.as_mut_ptr();
+            core::ptr::write_bytes(core::ptr::addr_of_mut!((*pointer).decoder), 0, 1);
+            core::ptr::addr_of_mut!((*pointer).freq).write(freq);
+            core::ptr::addr_of_mut!((*pointer).channels).write(channels);
+            core::ptr::addr_of_mut!((*pointer).stats).write(DecoderStats::default());
+            #[cfg(feature = "pseudostack")]
+            core::ptr::addr_of_mut!((*pointer).scratch).write(None);
+            &mut *pointer
         };
+        let error_code = Self::init_state(&mut decoder.decoder, freq, channels);
+        // PANIC: All error codes are small integers
+        if error_code != OPUS_OK.try_into().unwrap() {
+            Err(DecoderError { error_code })
+        } else {
+            Ok(decoder)
+        }
+    }
+
+    /// Initialize libopus decoder state and return the error code.
+    fn init_state(decoder: &mut OpusDecoder, freq: SamplingRate, channels: Channels) -> c_int {
         let channels = channels.channels().into();
         // SAFETY: The number of channels can be only one or two as required
         let size = unsafe { opus_decoder_get_size(channels) };
@@ -198,13 +403,48 @@ impl Decoder {
             core::mem::size_of::<OpusDecoder>() >= size.try_into().unwrap(),
             "OpusDecoder struct is too small!"
         );
-        // SAFETY: decoder.decoder points to a correct sized chunk of memory
-        let error_code = unsafe { opus_decoder_init(&mut decoder.decoder, freq.into(), channels) };
-        // PANIC: All error codes are small integers
-        if error_code != OPUS_OK.try_into().unwrap() {
-            Err(DecoderError { error_code })
-        } else {
-            Ok(decoder)
+        // SAFETY: decoder points to a correct sized chunk of memory
+        unsafe { opus_decoder_init(decoder, freq.into(), channels) }
+    }
+
+    /// Returns the sampling rate of the decoder.
+    pub fn sampling_rate(&self) -> SamplingRate {
+        self.freq
+    }
+
+    /// Returns the number of channels of the decoder.
+    pub fn channels(&self) -> Channels {
+        self.channels
+    }
+
+    /**
+     * Returns the memory that decoding a packet uses in the worst case with a decoder of the
+     * sampling rate and number of channels.
+     *
+     * This can be evaluated at compile time, e.g. for the stack size of a task:
+     *
+     * ```
+     * # use opus_embedded::*;
+     * const USAGE: MemoryUsage = Decoder::memory_usage(SamplingRate::F48k, Channels::Mono);
+     * const STACK_SIZE: usize = match USAGE.stack {
+     *     Some(stack) => stack + 2048,
+     *     None => 32 * 1024,
+     * };
+     * # assert!(STACK_SIZE > 2048 && USAGE.heap == 0);
+     * ```
+     */
+    pub const fn memory_usage(freq: SamplingRate, channels: Channels) -> MemoryUsage {
+        let temporary = opus_decoder_scratch_size(freq as i32, channels as c_int);
+        let (stack, scratch) = match OPUS_DECODE_STACK_FRAMES {
+            Some(frames) if cfg!(feature = "pseudostack") => (Some(frames), temporary),
+            Some(frames) => (Some(frames + temporary), 0),
+            None if cfg!(feature = "pseudostack") => (None, temporary),
+            None => (None, 0),
+        };
+        MemoryUsage {
+            stack,
+            scratch,
+            heap: 0,
         }
     }
 
@@ -256,13 +496,17 @@ impl Decoder {
      * Decode opus packet from data into output buffer.
      *
      * Returns decoded frame stored on output buffer. Its length is total number of samples in a
-     * frame.
+     * frame. Fails with `OPUS_UNIMPLEMENTED` if libopus was built without the parts for the mode
+     * of the packet, see [`Mode::is_supported`].
      *
      * ```
      * # use opus_embedded::{Decoder, SamplingRate, Channels};
-     * # let data = [0, 0, 0, 0, 0, 0, 0];
+     * # let toc = if cfg!(feature = "celt_only") { 0x90 } else { 0 };
+     * # let data = [toc, 0, 0, 0, 0, 0, 0];
      * # let data = data.as_slice();
      * let mut decoder = Decoder::new(SamplingRate::F24k, Channels::Mono).unwrap();
+     * # #[cfg(feature = "pseudostack")]
+     * # unsafe { decoder.set_scratch(Box::leak(Box::default())) };
      * let mut output = Vec::new();
      * output.resize(decoder.get_nb_samples_total(data).unwrap(), 0);
      * let output = decoder.decode(data, &mut output).unwrap();
@@ -276,33 +520,78 @@ impl Decoder {
         data: &[u8],
         output: &'output mut [i16],
     ) -> Result<&'output [i16], DecoderError> {
-        // SAFETY: The pointers point to valid slices of data or null if their respective slice was
-        // empty. Lengths are derived from the respective slices
-        let samples = unsafe {
-            let len: i32 = data.len().saturating_as();
-            let data = if !data.is_empty() {
-                data.as_ptr()
-            } else {
-                core::ptr::null()
-            };
-            // Let's calculate frame_size that will fit in the output buffer
-            let frame_size: i32 = match self.channels {
-                Channels::Mono => output.len(),
-                Channels::Stereo => output.len() / 2,
-            }
-            .saturating_as();
-            let output = if !output.is_empty() {
-                output.as_mut_ptr()
-            } else {
-                core::ptr::null_mut()
-            };
-            opus_decode(&mut self.decoder, data, len, output, frame_size, 0)
+        self.decode_with(data, output, false)
+    }
+
+    /**
+     * Recover the packet before data from its forward error correction data into output buffer.
+     *
+     * This is used when the previous packet was lost and data is the packet after it. Output
+     * buffer must be exactly as long as the lost packet. If data has no FEC data, the frame is
+     * concealed as if data was empty. Data is not decoded itself, so it must be given to
+     * [`decode`][`Decoder::decode`] next.
+     *
+     * See also [`opus_decode`].
+     */
+    pub fn decode_fec<'output>(
+        &mut self,
+        data: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], DecoderError> {
+        self.decode_with(data, output, true)
+    }
+
+    /// Decode data into output buffer, optionally from its forward error correction data.
+    fn decode_with<'output>(
+        &mut self,
+        data: &[u8],
+        output: &'output mut [i16],
+        fec: bool,
+    ) -> Result<&'output [i16], DecoderError> {
+        let channels = self.channels;
+        let samples = if !data.is_empty() && !OpusPacket::new(data).get_mode().is_supported() {
+            // libopus built with celt_only or silk_only feature has only stubs for the other
+            // modes, but malformed packets are still reported as such
+            self.get_nb_samples(data)
+                .map_or_else(|error| error.error_code, |_| OPUS_UNIMPLEMENTED)
+        } else {
+            // SAFETY: The pointers point to valid slices of data or null if their respective slice
+            // was empty. Lengths are derived from the respective slices
+            self.with_scratch(|decoder| unsafe {
+                let len: i32 = data.len().saturating_as();
+                let data = if !data.is_empty() {
+                    data.as_ptr()
+                } else {
+                    core::ptr::null()
+                };
+                // Let's calculate frame_size that will fit in the output buffer
+                let frame_size: i32 = match channels {
+                    Channels::Mono => output.len(),
+                    Channels::Stereo => output.len() / 2,
+                }
+                .saturating_as();
+                let output = if !output.is_empty() {
+                    output.as_mut_ptr()
+                } else {
+                    core::ptr::null_mut()
+                };
+                opus_decode(decoder, data, len, output, frame_size, c_int::from(fec))
+            })?
         };
         if samples < 0 {
+            if samples == OPUS_INVALID_PACKET {
+                self.stats.invalid_packets = self.stats.invalid_packets.wrapping_add(1);
+            }
             Err(DecoderError {
                 error_code: samples,
             })
         } else {
+            if data.is_empty() || fec {
+                self.stats.frames_concealed = self.stats.frames_concealed.wrapping_add(1);
+            } else {
+                self.stats.packets_decoded = self.stats.packets_decoded.wrapping_add(1);
+            }
+            self.stats.samples_decoded = self.stats.samples_decoded.wrapping_add(samples as u64);
             let frame_size = match self.channels {
                 Channels::Mono => samples as usize,
                 Channels::Stereo => samples as usize * 2,
@@ -310,6 +599,80 @@ impl Decoder {
             Ok(&output[..frame_size])
         }
     }
+
+    /**
+     * Give scratch memory for the temporary arrays of libopus when decoding.
+     *
+     * With `pseudostack` feature decoding fails with `OPUS_ALLOC_FAIL` until the decoder has
+     * scratch memory. Returns the previous scratch memory of the decoder.
+     *
+     * ```
+     * # use opus_embedded::{Decoder, SamplingRate, Channels, Scratch};
+     * let mut decoder = Decoder::new(SamplingRate::F24k, Channels::Mono).unwrap();
+     * // SAFETY: This is the only thread that decodes
+     * unsafe { decoder.set_scratch(Box::leak(Box::default())) };
+     * let mut output = [0; 480];
+     * # let toc = if cfg!(feature = "celt_only") { 0x90 } else { 0 };
+     * # let data = [toc, 0, 0, 0, 0, 0, 0];
+     * assert!(decoder.decode(&data, &mut output).is_ok());
+     * ```
+     *
+     * # Safety
+     * libopus keeps the scratch memory in a global variable while decoding, so decoders must not
+     * decode at the same time on different threads or cores. Decoding in an interrupt handler
+     * that interrupts another decoder on the same core is fine, as the scratch memory of the
+     * interrupted decoder is restored afterwards.
+     */
+    #[cfg(feature = "pseudostack")]
+    pub unsafe fn set_scratch(
+        &mut self,
+        scratch: &'static mut Scratch,
+    ) -> Option<&'static mut Scratch> {
+        self.scratch.replace(scratch)
+    }
+
+    /// Call libopus with the scratch memory of the decoder as its pseudostack.
+    #[cfg(feature = "pseudostack")]
+    fn with_scratch<R>(
+        &mut self,
+        f: impl FnOnce(&mut OpusDecoder) -> R,
+    ) -> Result<R, DecoderError> {
+        let decoder = &mut self.decoder;
+        with_pseudostack(self.scratch.as_deref_mut(), || f(decoder))
+    }
+
+    /// Call libopus, which uses stack for its temporary arrays.
+    #[cfg(not(feature = "pseudostack"))]
+    fn with_scratch<R>(
+        &mut self,
+        f: impl FnOnce(&mut OpusDecoder) -> R,
+    ) -> Result<R, DecoderError> {
+        Ok(f(&mut self.decoder))
+    }
+
+    /**
+     * Reset the decoder state, e.g. after seeking, so that the next packet is decoded as if it
+     * was the first one. Statistics are kept.
+     */
+    pub fn reset(&mut self) -> Result<(), DecoderError> {
+        let error_code = Self::init_state(&mut self.decoder, self.freq, self.channels);
+        // PANIC: All error codes are small integers
+        if error_code != OPUS_OK.try_into().unwrap() {
+            Err(DecoderError { error_code })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns statistics of decoding since the decoder was created or statistics were reset.
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    /// Reset statistics counters to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = DecoderStats::default();
+    }
 }
 
 /// Bandwidth in the opus data.
@@ -327,6 +690,33 @@ pub enum Bandwidth {
     Fullband,
 }
 
+/// Coding mode of the opus data.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    /// Speech coded with SILK only.
+    SilkOnly,
+    /// Speech or music coded with SILK for the low band and CELT for the high band.
+    Hybrid,
+    /// Music or other audio coded with CELT only.
+    CeltOnly,
+}
+
+impl Mode {
+    /**
+     * Returns whether libopus was built with the parts needed for decoding this mode.
+     *
+     * With `celt_only` feature only [`Mode::CeltOnly`] and with `silk_only` feature only
+     * [`Mode::SilkOnly`] can be decoded, the other modes fail with `OPUS_UNIMPLEMENTED`.
+     */
+    pub const fn is_supported(self) -> bool {
+        match self {
+            Mode::SilkOnly => !cfg!(feature = "celt_only"),
+            Mode::Hybrid => !cfg!(any(feature = "celt_only", feature = "silk_only")),
+            Mode::CeltOnly => !cfg!(feature = "silk_only"),
+        }
+    }
+}
+
 /// Wraps opus data into a packet type.
 #[derive(Debug)]
 pub struct OpusPacket<'data> {
@@ -414,6 +804,15 @@ impl<'data> OpusPacket<'data> {
         }
     }
 
+    /// Return the coding mode of the packet, which is given by its first byte.
+    pub fn get_mode(&self) -> Mode {
+        match self.data[0] >> 3 {
+            0..=11 => Mode::SilkOnly,
+            12..=15 => Mode::Hybrid,
+            _ => Mode::CeltOnly,
+        }
+    }
+
     /**
      * Return the number of sampels per frame in the packet.
      *
@@ -439,10 +838,65 @@ impl<'data> OpusPacket<'data> {
 #[cfg(test)]
 mod tests {
     extern crate alloc;
+    #[cfg(feature = "pseudostack")]
+    extern crate std;
     use super::*;
+    #[cfg(feature = "pseudostack")]
+    use alloc::boxed::Box;
     use alloc::string::ToString;
     use core::error::Error;
 
+    /// Packet of one 10 ms frame in a mode that libopus was built to decode.
+    const PACKET: [u8; 8] = if cfg!(feature = "celt_only") {
+        [0x90, 0, 0, 0, 0, 0, 0, 0]
+    } else {
+        [0x00; 8]
+    };
+
+    /**
+     * Give scratch memory to the decoder with `pseudostack` feature.
+     *
+     * Returns a guard that keeps the other tests from decoding at the same time, as libopus keeps
+     * its pseudostack in a global variable.
+     */
+    #[cfg(feature = "pseudostack")]
+    type ScratchGuard = std::sync::MutexGuard<'static, ()>;
+
+    #[cfg(not(feature = "pseudostack"))]
+    struct ScratchGuard;
+
+    #[cfg(feature = "pseudostack")]
+    fn lock_scratch() -> ScratchGuard {
+        static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        LOCK.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    #[cfg(feature = "pseudostack")]
+    fn give_scratch(decoder: &mut Decoder) -> ScratchGuard {
+        let guard = lock_scratch();
+        // SAFETY: The guard is held while decoding
+        unsafe { decoder.set_scratch(Box::leak(Box::default())) };
+        guard
+    }
+
+    #[cfg(not(feature = "pseudostack"))]
+    fn give_scratch(_decoder: &mut Decoder) -> ScratchGuard {
+        ScratchGuard
+    }
+
+    #[cfg(all(feature = "custom_modes", feature = "pseudostack"))]
+    fn give_custom_scratch<const SIZE: usize>(decoder: &mut CustomDecoder<SIZE>) -> ScratchGuard {
+        let guard = lock_scratch();
+        // SAFETY: The guard is held while decoding
+        unsafe { decoder.set_scratch(Box::leak(Box::default())) };
+        guard
+    }
+
+    #[cfg(all(feature = "custom_modes", not(feature = "pseudostack")))]
+    fn give_custom_scratch<const SIZE: usize>(_decoder: &mut CustomDecoder<SIZE>) -> ScratchGuard {
+        ScratchGuard
+    }
+
     #[test]
     fn create_decoder() {
         let decoder = Decoder::new(SamplingRate::F8k, Channels::Mono);
@@ -460,6 +914,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn init_decoder_in_place() {
+        let mut slot = core::mem::MaybeUninit::uninit();
+        let decoder = Decoder::init_in_place(&mut slot, SamplingRate::F8k, Channels::Mono).unwrap();
+        assert_eq!(decoder.get_nb_samples(&[0x00u8; 8]), Ok(80));
+    }
+
+    #[test]
+    fn decoder_pool() {
+        let mut pool = DecoderPool::<2>::new();
+        assert_eq!(pool.available(), 2);
+        let first = pool.acquire(SamplingRate::F8k, Channels::Mono).unwrap();
+        let second = pool.acquire(SamplingRate::F8k, Channels::Mono).unwrap();
+        assert_eq!(pool.available(), 0);
+        let result = pool.acquire(SamplingRate::F8k, Channels::Mono);
+        assert_eq!(result.unwrap_err().numeric(), OPUS_ALLOC_FAIL);
+        let mut output = [0i16; 80];
+        let _guard = give_scratch(pool.get(&second));
+        assert_eq!(
+            pool.get(&second)
+                .decode(&PACKET, &mut output)
+                .unwrap()
+                .len(),
+            80
+        );
+        pool.release(first);
+        assert_eq!(pool.available(), 1);
+        let first = pool.acquire(SamplingRate::F16k, Channels::Mono).unwrap();
+        assert_eq!(first.index(), 0);
+        assert_eq!(pool.get(&first).get_nb_samples(&[0x00u8; 8]), Ok(160));
+    }
+
     #[test]
     fn sampling_rate() {
         assert_eq!(SamplingRate::closest(8_000), SamplingRate::F8k);
@@ -495,6 +981,7 @@ mod tests {
         // NB: Error strings depend on libopus internal error messages.
         const DATA: [u8; 0] = [0u8; 0];
         let mut decoder = Decoder::new(SamplingRate::F8k, Channels::Mono).unwrap();
+        let _guard = give_scratch(&mut decoder);
         let result = decoder.get_nb_samples(&DATA);
         assert_eq!(
             result,
@@ -515,7 +1002,7 @@ mod tests {
         }
         // However empty slice for output is not
         let mut output = [0i16; 0];
-        let result = decoder.decode(&[0, 0, 0, 0, 0], &mut output);
+        let result = decoder.decode(&PACKET[..5], &mut output);
         assert_eq!(
             result,
             Err(DecoderError {
@@ -530,8 +1017,9 @@ mod tests {
 
     #[test]
     fn test_decoder_with_zero_packet() {
-        const DATA: [u8; 8] = [0x00u8; 8];
+        const DATA: [u8; 8] = PACKET;
         let mut decoder = Decoder::new(SamplingRate::F8k, Channels::Mono).unwrap();
+        let _guard = give_scratch(&mut decoder);
         assert_eq!(decoder.get_nb_samples(&DATA), Ok(80));
         let mut output = [0i16; 80];
         assert_eq!(decoder.decode(&DATA, &mut output).unwrap().len(), 80);
@@ -541,6 +1029,7 @@ mod tests {
     fn test_decoder_with_0xff_packet() {
         const DATA: [u8; 8] = [0xffu8; 8];
         let mut decoder = Decoder::new(SamplingRate::F8k, Channels::Mono).unwrap();
+        let _guard = give_scratch(&mut decoder);
         let result = decoder.get_nb_samples(&DATA);
         assert_eq!(
             result,
@@ -550,8 +1039,10 @@ mod tests {
         );
         let error = result.unwrap_err();
         assert_eq!(error.numeric(), OPUS_INVALID_PACKET);
+        assert_eq!(error.code(), OPUS_INVALID_PACKET);
         assert!(error.source().is_none());
         assert_eq!(error.to_string(), "corrupted stream");
+        assert_eq!(error.message(), "corrupted stream");
         let mut output = [0i16; 80];
         let result = decoder.decode(&DATA, &mut output);
         assert_eq!(
@@ -564,6 +1055,212 @@ mod tests {
         assert_eq!(error.numeric(), OPUS_INVALID_PACKET);
         assert!(error.source().is_none());
         assert_eq!(error.to_string(), "corrupted stream");
+        assert_eq!(decoder.stats().invalid_packets, 1);
+        assert_eq!(decoder.stats().packets_decoded, 0);
+    }
+
+    #[test]
+    fn test_decoder_stats() {
+        let mut decoder = Decoder::new(SamplingRate::F8k, Channels::Mono).unwrap();
+        let _guard = give_scratch(&mut decoder);
+        let mut output = [0i16; 80];
+        decoder.decode(&PACKET, &mut output).unwrap();
+        decoder.decode(&PACKET, &mut output).unwrap();
+        decoder.decode(&[], &mut output).unwrap();
+        assert_eq!(
+            decoder.stats(),
+            DecoderStats {
+                packets_decoded: 2,
+                samples_decoded: 240,
+                frames_concealed: 1,
+                invalid_packets: 0,
+            }
+        );
+        decoder.reset_stats();
+        assert_eq!(decoder.stats(), DecoderStats::default());
+    }
+
+    #[test]
+    fn test_decoder_unsupported_mode() {
+        let mut decoder = Decoder::new(SamplingRate::F8k, Channels::Mono).unwrap();
+        let _guard = give_scratch(&mut decoder);
+        let mut output = [0i16; 80];
+        for toc in [0x00, 0x60, 0x90] {
+            let result = decoder.decode(&[toc, 0, 0, 0, 0, 0, 0, 0], &mut output);
+            if OpusPacket::new(&[toc]).get_mode().is_supported() {
+                assert_eq!(result.unwrap().len(), 80);
+            } else {
+                assert_eq!(result.unwrap_err().numeric(), OPUS_UNIMPLEMENTED);
+            }
+        }
+        let supported = [Mode::SilkOnly, Mode::Hybrid, Mode::CeltOnly].map(Mode::is_supported);
+        if cfg!(feature = "celt_only") {
+            assert_eq!(supported, [false, false, true]);
+        } else if cfg!(feature = "silk_only") {
+            assert_eq!(supported, [true, false, false]);
+        } else {
+            assert_eq!(supported, [true, true, true]);
+        }
+    }
+
+    #[cfg(feature = "pseudostack")]
+    #[test]
+    fn test_decoder_scratch() {
+        let mut decoder = Decoder::new(SamplingRate::F48k, Channels::Mono).unwrap();
+        let mut output = [0i16; 960];
+        let result = decoder.decode(&PACKET, &mut output);
+        assert_eq!(result.unwrap_err().numeric(), OPUS_ALLOC_FAIL);
+        let _guard = give_scratch(&mut decoder);
+        // SAFETY: Only reads the pointer while the guard is held
+        let previous = unsafe { global_stack };
+        assert_eq!(decoder.decode(&PACKET, &mut output).unwrap().len(), 480);
+        assert_eq!(unsafe { global_stack }, previous);
+        let scratch = unsafe { decoder.set_scratch(Box::leak(Box::default())) };
+        assert!(scratch.is_some());
+    }
+
+    /// Create mode of Opus Custom with scratch memory with `pseudostack` feature.
+    #[cfg(all(feature = "custom_modes", feature = "pseudostack"))]
+    fn custom_mode(freq: i32, frame_size: usize) -> Result<CustomMode, DecoderError> {
+        let _guard = lock_scratch();
+        // SAFETY: The guard is held while creating the mode
+        unsafe { CustomMode::with_scratch(freq, frame_size, &mut Scratch::new()) }
+    }
+
+    #[cfg(all(feature = "custom_modes", not(feature = "pseudostack")))]
+    fn custom_mode(freq: i32, frame_size: usize) -> Result<CustomMode, DecoderError> {
+        CustomMode::new(freq, frame_size)
+    }
+
+    #[cfg(feature = "custom_modes")]
+    #[test]
+    fn create_custom_mode() {
+        let mode = custom_mode(48000, 120).unwrap();
+        assert_eq!(mode.sampling_rate(), 48000);
+        assert_eq!(mode.frame_size(), 120);
+        assert!(mode.decoder_size(Channels::Mono) < mode.decoder_size(Channels::Stereo));
+        assert_eq!(custom_mode(44100, 128).unwrap().frame_size(), 128);
+        let mode = CustomMode::new(48000, 120);
+        if cfg!(feature = "pseudostack") {
+            assert_eq!(mode.unwrap_err().numeric(), OPUS_ALLOC_FAIL);
+        } else {
+            assert!(mode.is_ok());
+        }
+    }
+
+    #[cfg(feature = "custom_modes")]
+    #[test]
+    fn create_custom_mode_invalid() {
+        for (freq, frame_size) in [(48000, 0), (48000, 112), (48000, 2048), (44000, 120)] {
+            let mode = custom_mode(freq, frame_size);
+            assert_eq!(mode.unwrap_err().numeric(), OPUS_BAD_ARG);
+        }
+    }
+
+    #[cfg(feature = "custom_modes")]
+    #[test]
+    fn test_custom_decoder() {
+        let mode = custom_mode(48000, 120).unwrap();
+        let decoder = CustomDecoder::<1024>::new(&mode, Channels::Mono);
+        assert_eq!(decoder.unwrap_err().numeric(), OPUS_ALLOC_FAIL);
+        let mut decoder = CustomDecoder::<10240>::new(&mode, Channels::Mono).unwrap();
+        let _guard = give_custom_scratch(&mut decoder);
+        let mut output = [0i16; 120];
+        // TOC byte of a 2.5 ms CELT frame
+        let packet = [0x80, 0, 0, 0, 0, 0, 0, 0];
+        let result = decoder.decode(&packet, &mut output[..119]);
+        assert_eq!(result.unwrap_err().numeric(), OPUS_BUFFER_TOO_SMALL);
+        let result = decoder.decode(&[0x90, 0, 0, 0], &mut output);
+        assert_eq!(result.unwrap_err().numeric(), OPUS_BUFFER_TOO_SMALL);
+        assert_eq!(decoder.decode(&packet, &mut output).unwrap().len(), 120);
+        let result = decoder.decode(&[0x00; 4], &mut output);
+        assert_eq!(result.unwrap_err().numeric(), OPUS_INVALID_PACKET);
+        assert_eq!(decoder.decode(&[], &mut output).unwrap().len(), 120);
+        let stats = decoder.stats();
+        assert_eq!(stats.packets_decoded, 1);
+        assert_eq!(stats.frames_concealed, 1);
+        assert_eq!(stats.invalid_packets, 1);
+        assert_eq!(stats.samples_decoded, 240);
+        assert!(decoder.reset().is_ok());
+    }
+
+    #[cfg(feature = "custom_modes")]
+    #[test]
+    fn test_custom_decoder_stereo() {
+        let mode = custom_mode(48000, 240).unwrap();
+        let decoder = CustomDecoder::<20480>::new(&mode, Channels::Stereo);
+        if cfg!(feature = "stereo") {
+            let mut decoder = decoder.unwrap();
+            let _guard = give_custom_scratch(&mut decoder);
+            let mut output = [0i16; 480];
+            // TOC byte of a 5 ms stereo CELT frame
+            let packet = [0x8c, 0, 0, 0, 0, 0, 0, 0];
+            assert_eq!(decoder.decode(&packet, &mut output).unwrap().len(), 480);
+        } else {
+            assert_eq!(decoder.unwrap_err().numeric(), OPUS_ALLOC_FAIL);
+        }
+    }
+
+    #[cfg(feature = "symphonia")]
+    #[test]
+    fn test_symphonia_decoder() {
+        use symphonia_core::audio::Channels as Layout;
+        use symphonia_core::codecs::{CodecParameters, Decoder as _, DecoderOptions};
+        use symphonia_core::codecs::{CODEC_TYPE_OPUS, CODEC_TYPE_VORBIS};
+        use symphonia_core::errors::Error;
+        use symphonia_core::formats::Packet;
+
+        let options = DecoderOptions::default();
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_VORBIS)
+            .with_channels(Layout::FRONT_LEFT);
+        let result = SymphoniaDecoder::try_new(&params, &options);
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+        let layout = Layout::FRONT_LEFT | Layout::FRONT_RIGHT;
+        params.for_codec(CODEC_TYPE_OPUS).with_channels(layout);
+        let result = SymphoniaDecoder::try_new(&params, &options);
+        assert_eq!(result.is_ok(), cfg!(feature = "stereo"));
+        // Identification header of a mono stream that was encoded from 44.1 kHz audio
+        let header = b"OpusHead\x01\x01\x38\x01\x44\xac\x00\x00\x00\x00\x00";
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_OPUS)
+            .with_sample_rate(44100)
+            .with_extra_data(header.to_vec().into_boxed_slice());
+        let mut decoder = SymphoniaDecoder::try_new(&params, &options).unwrap();
+        assert_eq!(decoder.codec_params().sample_rate, Some(48000));
+        assert_eq!(decoder.codec_params().channels, Some(Layout::FRONT_LEFT));
+        let result = decoder.decode(&Packet::new_from_slice(0, 0, 480, &PACKET));
+        if cfg!(feature = "pseudostack") {
+            assert!(matches!(result, Err(Error::DecodeError(_))));
+        } else {
+            assert_eq!(result.unwrap().frames(), 480);
+            assert_eq!(decoder.last_decoded().frames(), 480);
+        }
+        let result = decoder.decode(&Packet::new_from_slice(0, 480, 0, &[0x03, 0x00]));
+        assert!(matches!(result, Err(Error::DecodeError(_))));
+        assert_eq!(decoder.last_decoded().frames(), 0);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mono = Decoder::memory_usage(SamplingRate::F48k, Channels::Mono);
+        let stereo = Decoder::memory_usage(SamplingRate::F48k, Channels::Stereo);
+        assert_eq!(mono.heap, 0);
+        if cfg!(feature = "pseudostack") {
+            assert_eq!(mono.stack, OPUS_DECODE_STACK_FRAMES);
+            assert_eq!(mono.scratch, OPUS_DECODER_SCRATCH_SIZE_CH1);
+            assert_eq!(stereo.scratch, OPUS_DECODER_SCRATCH_SIZE_CH2);
+        } else {
+            assert_eq!(mono.scratch, 0);
+            assert!(mono.stack <= stereo.stack);
+            assert_eq!(mono.stack.is_some(), OPUS_DECODE_STACK_FRAMES.is_some());
+        }
+        assert_eq!(
+            Decoder::memory_usage(SamplingRate::F24k, Channels::Stereo),
+            Decoder::memory_usage(SamplingRate::F8k, Channels::Mono)
+        );
     }
 
     #[test]
@@ -622,4 +1319,14 @@ mod tests {
         let packet = OpusPacket::new(&[0xF0]);
         assert_eq!(packet.get_bandwidth(), Ok(Bandwidth::Fullband));
     }
+
+    #[test]
+    fn test_packet_modes() {
+        assert_eq!(OpusPacket::new(&[0x00]).get_mode(), Mode::SilkOnly);
+        assert_eq!(OpusPacket::new(&[0x5F]).get_mode(), Mode::SilkOnly);
+        assert_eq!(OpusPacket::new(&[0x60]).get_mode(), Mode::Hybrid);
+        assert_eq!(OpusPacket::new(&[0x7F]).get_mode(), Mode::Hybrid);
+        assert_eq!(OpusPacket::new(&[0x80]).get_mode(), Mode::CeltOnly);
+        assert_eq!(OpusPacket::new(&[0xFF]).get_mode(), Mode::CeltOnly);
+    }
 }
@@ -0,0 +1,61 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Integration with oggopus-embedded.
+
+use super::{Decoder, DecoderError};
+use oggopus_embedded::Decode;
+
+/**
+ * Allows using [`Decoder`] with [`Frames`][`oggopus_embedded::Frames`].
+ *
+ * ```
+ * # use oggopus_embedded::{Bitstream, Frames, PacketSource};
+ * # use opus_embedded::{Channels, Decoder, SamplingRate};
+ * # let data = include_bytes!("../../oggopus-embedded/src/test/mono.opus");
+ * let stream = Bitstream::new(data);
+ * let reader = stream.packet_reader::<512>().unwrap();
+ * let channels = Channels::try_from(reader.header().channels.get_channel_count()).unwrap();
+ * let mut decoder = Decoder::new(SamplingRate::F8k, channels).unwrap();
+ * # #[cfg(feature = "pseudostack")]
+ * # unsafe { decoder.set_scratch(Box::leak(Box::default())) };
+ * let mut frames = Frames::<_, _, 960>::new(reader, decoder);
+ * # // The test file is in SILK mode, which celt_only feature leaves out
+ * # #[cfg(not(feature = "celt_only"))]
+ * while let Some(frame) = frames.next().unwrap() {
+ *     println!("Got {} samples of audio", frame.len());
+ * }
+ * ```
+ */
+impl Decode for Decoder {
+    type Error = DecoderError;
+
+    fn sample_rate(&self) -> u32 {
+        i32::from(self.freq) as u32
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels.channels()
+    }
+
+    fn decode<'output>(
+        &mut self,
+        packet: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], DecoderError> {
+        Decoder::decode(self, packet, output)
+    }
+
+    fn decode_fec<'output>(
+        &mut self,
+        packet: &[u8],
+        output: &'output mut [i16],
+    ) -> Result<&'output [i16], DecoderError> {
+        Decoder::decode_fec(self, packet, output)
+    }
+
+    fn reset(&mut self) -> Result<(), DecoderError> {
+        Decoder::reset(self)
+    }
+}
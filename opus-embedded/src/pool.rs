@@ -0,0 +1,126 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Fixed size pool of decoders.
+
+use super::{Channels, Decoder, DecoderError, SamplingRate};
+use core::mem::MaybeUninit;
+use opus_embedded_sys::OPUS_ALLOC_FAIL;
+
+/**
+ * Handle to a decoder in [`DecoderPool`].
+ *
+ * Returned by [`DecoderPool::acquire`] and given back with [`DecoderPool::release`]. The handle
+ * is only valid for the pool that created it. Pools do not check where a handle came from, so a
+ * handle of another pool refers to the decoder with the same index in this pool.
+ */
+#[derive(Debug, PartialEq)]
+pub struct DecoderHandle {
+    index: usize,
+}
+
+impl DecoderHandle {
+    /// Returns the index of the decoder in the pool.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/**
+ * Pool of `N` decoders stored in one chunk of memory.
+ *
+ * Decoders are initialized in place when they are acquired so the decoder state is never moved
+ * around on stack. The pool can be placed in a static, e.g. with a mutex or [static_cell], to
+ * keep all decoder states in one statically allocated arena.
+ *
+ * [static_cell]: https://docs.rs/static_cell
+ *
+ * ```
+ * # use opus_embedded::{Channels, DecoderPool, SamplingRate};
+ * let mut pool = DecoderPool::<2>::new();
+ * let first = pool.acquire(SamplingRate::F16k, Channels::Mono).unwrap();
+ * let second = pool.acquire(SamplingRate::F16k, Channels::Mono).unwrap();
+ * assert!(pool.acquire(SamplingRate::F16k, Channels::Mono).is_err());
+ * let mut output = [0i16; 320];
+ * # #[cfg(feature = "pseudostack")]
+ * # unsafe { pool.get(&first).set_scratch(Box::leak(Box::default())) };
+ * let decoded = pool.get(&first).decode(&[], &mut output).unwrap();
+ * println!("Got {} samples of concealed audio", decoded.len());
+ * pool.release(first);
+ * pool.release(second);
+ * ```
+ */
+#[derive(Debug)]
+pub struct DecoderPool<const N: usize> {
+    decoders: [MaybeUninit<Decoder>; N],
+    used: [bool; N],
+}
+
+impl<const N: usize> Default for DecoderPool<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> DecoderPool<N> {
+    /// Construct pool with all decoders available.
+    pub const fn new() -> Self {
+        Self {
+            decoders: [const { MaybeUninit::uninit() }; N],
+            used: [false; N],
+        }
+    }
+
+    /**
+     * Initialize a free decoder from the pool and return a handle to it.
+     *
+     * Returns error with [`OPUS_ALLOC_FAIL`] code if all decoders are already in use.
+     *
+     * See also [`Decoder::new`].
+     */
+    pub fn acquire(
+        &mut self,
+        freq: SamplingRate,
+        channels: Channels,
+    ) -> Result<DecoderHandle, DecoderError> {
+        let index = self
+            .used
+            .iter()
+            .position(|used| !used)
+            .ok_or(DecoderError {
+                error_code: OPUS_ALLOC_FAIL,
+            })?;
+        Decoder::init_in_place(&mut self.decoders[index], freq, channels)?;
+        self.used[index] = true;
+        Ok(DecoderHandle { index })
+    }
+
+    /**
+     * Get decoder for the handle.
+     *
+     * # Panics
+     * Panics if the decoder of the handle is not in use in this pool.
+     */
+    pub fn get(&mut self, handle: &DecoderHandle) -> &mut Decoder {
+        assert!(self.used[handle.index], "Decoder is not in use");
+        // SAFETY: Decoder was initialized when it was marked to be in use
+        unsafe { self.decoders[handle.index].assume_init_mut() }
+    }
+
+    /**
+     * Return decoder back to the pool.
+     *
+     * # Panics
+     * Panics if the decoder of the handle is not in use in this pool.
+     */
+    pub fn release(&mut self, handle: DecoderHandle) {
+        assert!(self.used[handle.index], "Decoder is not in use");
+        self.used[handle.index] = false;
+    }
+
+    /// Returns the number of decoders that can still be acquired.
+    pub fn available(&self) -> usize {
+        self.used.iter().filter(|used| !**used).count()
+    }
+}
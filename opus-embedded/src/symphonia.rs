@@ -0,0 +1,151 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ */
+//! Integration with Symphonia.
+
+use super::{Channels, Decoder, OpusError, SamplingRate};
+use std::boxed::Box;
+use std::vec;
+use std::vec::Vec;
+use symphonia_core::audio::{
+    AsAudioBufferRef, AudioBuffer, AudioBufferRef, Channels as Layout, Signal, SignalSpec,
+};
+use symphonia_core::codecs::{
+    CodecDescriptor, CodecParameters, DecoderOptions, FinalizeResult, CODEC_TYPE_OPUS,
+};
+use symphonia_core::errors::{decode_error, unsupported_error, Result};
+use symphonia_core::formats::Packet;
+use symphonia_core::support_codec;
+
+/// Samples per channel in the longest packet, i.e. 120 ms at 48 kHz.
+const MAX_FRAME_SIZE: usize = 5760;
+
+/**
+ * [`Decoder`] for Symphonia, so that applications decode with exactly the same fixed-point
+ * decoder as devices, e.g. for bit-exact regression comparisons.
+ *
+ * Register it to a `CodecRegistry` and use it with the Ogg format reader of Symphonia or any
+ * other reader that gives Opus packets. Decoding is done at the sampling rate of the codec
+ * parameters if Opus supports it, otherwise at 48 kHz. Only mono and stereo streams are
+ * supported, and stereo streams need stereo feature.
+ *
+ * With `pseudostack` feature decoding fails with `OPUS_ALLOC_FAIL`, as the decoder has no
+ * scratch memory.
+ *
+ * ```
+ * # use opus_embedded::SymphoniaDecoder;
+ * use symphonia_core::audio::Channels;
+ * use symphonia_core::codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_OPUS};
+ * use symphonia_core::formats::Packet;
+ *
+ * let mut params = CodecParameters::new();
+ * params
+ *     .for_codec(CODEC_TYPE_OPUS)
+ *     .with_sample_rate(48000)
+ *     .with_channels(Channels::FRONT_LEFT);
+ * let mut decoder = SymphoniaDecoder::try_new(&params, &DecoderOptions::default()).unwrap();
+ * # let toc = if cfg!(feature = "celt_only") { 0x90 } else { 0 };
+ * # let data = [toc, 0, 0, 0, 0, 0, 0, 0];
+ * let packet = Packet::new_from_slice(0, 0, 480, &data);
+ * # #[cfg(not(feature = "pseudostack"))]
+ * let frame = decoder.decode(&packet).unwrap();
+ * # #[cfg(not(feature = "pseudostack"))]
+ * println!("Got {} samples of audio", frame.frames());
+ * ```
+ */
+pub struct SymphoniaDecoder {
+    decoder: Decoder,
+    params: CodecParameters,
+    output: Vec<i16>,
+    buffer: AudioBuffer<i16>,
+}
+
+impl core::fmt::Debug for SymphoniaDecoder {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SymphoniaDecoder")
+            .field("decoder", &self.decoder)
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+impl symphonia_core::codecs::Decoder for SymphoniaDecoder {
+    fn try_new(params: &CodecParameters, _options: &DecoderOptions) -> Result<Self> {
+        if params.codec != CODEC_TYPE_OPUS {
+            return unsupported_error("opus: invalid codec type");
+        }
+        // Channel count is also in the identification header, which Ogg readers give as extra
+        // data
+        let channels = params
+            .channels
+            .map(|channels| channels.count())
+            .or_else(|| {
+                let header = params.extra_data.as_ref()?;
+                header.get(9).map(|&channels| usize::from(channels))
+            });
+        let (channels, layout) = match channels {
+            Some(1) => (Channels::Mono, Layout::FRONT_LEFT),
+            Some(2) => (Channels::Stereo, Layout::FRONT_LEFT | Layout::FRONT_RIGHT),
+            _ => return unsupported_error("opus: unsupported channel count"),
+        };
+        let freq = params
+            .sample_rate
+            .and_then(|freq| i32::try_from(freq).ok())
+            .and_then(|freq| SamplingRate::try_from(freq).ok())
+            .unwrap_or(SamplingRate::F48k);
+        let decoder = match Decoder::new(freq, channels) {
+            Ok(decoder) => decoder,
+            Err(error) => return unsupported_error(error.message()),
+        };
+        let mut params = params.clone();
+        params
+            .with_sample_rate(i32::from(freq) as u32)
+            .with_channels(layout);
+        let spec = SignalSpec::new(i32::from(freq) as u32, layout);
+        Ok(SymphoniaDecoder {
+            decoder,
+            params,
+            output: vec![0; MAX_FRAME_SIZE * usize::from(channels.channels())],
+            buffer: AudioBuffer::new(MAX_FRAME_SIZE as u64, spec),
+        })
+    }
+
+    fn supported_codecs() -> &'static [CodecDescriptor] {
+        &[support_codec!(CODEC_TYPE_OPUS, "opus", "Opus")]
+    }
+
+    fn reset(&mut self) {
+        // Resetting cannot fail for a decoder that was initialized successfully
+        let _ = self.decoder.reset();
+    }
+
+    fn codec_params(&self) -> &CodecParameters {
+        &self.params
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Result<AudioBufferRef<'_>> {
+        self.buffer.clear();
+        let output = match self.decoder.decode(packet.buf(), &mut self.output) {
+            Ok(output) => output,
+            Err(error) => return decode_error(error.message()),
+        };
+        let channels = usize::from(self.decoder.channels.channels());
+        self.buffer.render_reserved(Some(output.len() / channels));
+        for channel in 0..channels {
+            let samples = output[channel..].iter().step_by(channels);
+            for (sample, decoded) in self.buffer.chan_mut(channel).iter_mut().zip(samples) {
+                *sample = *decoded;
+            }
+        }
+        Ok(self.buffer.as_audio_buffer_ref())
+    }
+
+    fn finalize(&mut self) -> FinalizeResult {
+        FinalizeResult::default()
+    }
+
+    fn last_decoded(&self) -> AudioBufferRef<'_> {
+        self.buffer.as_audio_buffer_ref()
+    }
+}
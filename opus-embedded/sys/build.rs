@@ -5,20 +5,24 @@
  * Builds minimal libopus for decoding with fixed point decoder and no dred.
  */
 
+#[cfg(feature = "bindgen")]
 use bindgen::callbacks::ParseCallbacks;
+#[cfg(feature = "bindgen")]
 use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
-use std::ffi::OsString;
-use std::fs::create_dir_all;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 
+include!("src/scratch_size.rs");
+
+#[cfg(feature = "bindgen")]
 #[derive(Debug)]
 struct ParseCallback {
     cargo_callbacks: bindgen::CargoCallbacks,
     replacements: Vec<(Regex, &'static str)>,
 }
 
+#[cfg(feature = "bindgen")]
 impl ParseCallback {
     fn new() -> Self {
         ParseCallback {
@@ -43,6 +47,7 @@ impl ParseCallback {
     }
 }
 
+#[cfg(feature = "bindgen")]
 impl ParseCallbacks for ParseCallback {
     fn process_comment(&self, comment: &str) -> Option<String> {
         doxygen_bindgen::transform(comment)
@@ -86,20 +91,327 @@ impl ParseCallbacks for ParseCallback {
     }
 }
 
-fn main() {
-    // Make a copy of libopus to OUT_DIR so we can run autoreconf without modifying sources
-    let target = PathBuf::from(env::var("OUT_DIR").unwrap()).join("opus");
-    create_dir_all(&target).unwrap();
-    let inputs: Vec<_> = std::fs::read_dir("src/opus")
+/// Returns whether an executable is found in `PATH`.
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|path| {
+            path.join(format!("{program}{}", env::consts::EXE_SUFFIX))
+                .is_file()
+        })
+    })
+}
+
+/**
+ * Returns GNU toolchain triple for targets whose Rust target names configure does not recognize.
+ *
+ * For Xtensa targets of ESP32 this is the toolchain of Espressif, e.g. `xtensa-esp32s3-elf` for
+ * both `xtensa-esp32s3-none-elf` and `xtensa-esp32s3-espidf`, as [cc] crate does not know their
+ * compilers. For 32-bit RISC-V targets, e.g. `riscv32imac-unknown-none-elf`, this is the first of
+ * the usual bare metal toolchains that is installed, as these are usually built for multiple
+ * architectures.
+ */
+fn gnu_toolchain() -> Option<String> {
+    let target = env::var("TARGET").unwrap();
+    if let Some(rest) = target.strip_prefix("xtensa-") {
+        let chip = rest.split('-').next()?;
+        Some(format!("xtensa-{chip}-elf"))
+    } else if target.starts_with("riscv32") && target.ends_with("-espidf") {
+        Some("riscv32-esp-elf".to_string())
+    } else if target.starts_with("riscv32") && target.ends_with("-none-elf") {
+        let candidates = [
+            "riscv32-unknown-elf",
+            "riscv64-unknown-elf",
+            "riscv-none-elf",
+        ];
+        let toolchain = candidates
+            .iter()
+            .find(|toolchain| in_path(&format!("{toolchain}-gcc")))
+            .unwrap_or(&candidates[0]);
+        Some(toolchain.to_string())
+    } else {
+        None
+    }
+}
+
+/// Returns whether a tool, e.g. `CC`, is set for the target in the variables that [cc] crate reads.
+fn tool_from_env(tool: &str) -> bool {
+    let target = env::var("TARGET").unwrap();
+    [
+        format!("{tool}_{target}"),
+        format!("{tool}_{}", target.replace('-', "_")),
+        format!("TARGET_{tool}"),
+        tool.to_string(),
+    ]
+    .iter()
+    .any(|key| {
+        println!("cargo:rerun-if-env-changed={key}");
+        env::var_os(key).is_some()
+    })
+}
+
+/// Returns whether a target feature, e.g. `-C target-feature=+zbb`, is enabled for the target.
+fn has_target_feature(feature: &str) -> bool {
+    env::var("CARGO_CFG_TARGET_FEATURE")
+        .unwrap_or_default()
+        .split(',')
+        .any(|enabled| enabled == feature)
+}
+
+/// Returns whether the target is WebAssembly, e.g. `wasm32-wasip1` or `wasm32-unknown-emscripten`.
+fn is_wasm() -> bool {
+    env::var("CARGO_CFG_TARGET_FAMILY")
+        .unwrap_or_default()
+        .split(',')
+        .any(|family| family == "wasm")
+}
+
+/// Returns whether the target is WebAssembly without C library, i.e. `wasm32-unknown-unknown`.
+fn is_wasm_without_libc() -> bool {
+    is_wasm() && env::var("CARGO_CFG_TARGET_OS").unwrap() == "unknown"
+}
+
+/**
+ * Returns whether the target has no C library.
+ *
+ * These are the targets without operating system, e.g. `thumbv6m-none-eabi`, and
+ * `wasm32-unknown-unknown`. libopus must not call malloc or print with the C library there, see
+ * [custom_support_cflags] and [bare_metal_cflags].
+ */
+fn is_without_libc() -> bool {
+    env::var("CARGO_CFG_TARGET_OS").unwrap() == "none" || is_wasm_without_libc()
+}
+
+/// Flags needed by the compilers of some targets.
+fn target_cflags() -> Vec<String> {
+    let mut flags = Vec::new();
+    if env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "xtensa" {
+        // Firmware is usually bigger than the range of CALL instructions, ESP-IDF uses this too
+        flags.push("-mlongcalls".to_string());
+    }
+    let target = env::var("TARGET").unwrap();
+    if target.starts_with("thumbv7em-") {
+        // Cortex-M4 and Cortex-M7 (thumbv7em): Inline assembly of libopus for DSP extension,
+        // e.g. SMLAWB and SSAT, is valid in Thumb mode unlike its external assembly. These roughly
+        // double the speed of fixed point decoding.
+        flags.extend(
+            [
+                "-DOPUS_ARM_INLINE_ASM",
+                "-DOPUS_ARM_INLINE_EDSP",
+                "-DOPUS_ARM_INLINE_MEDIA",
+            ]
+            .map(String::from),
+        );
+    }
+    if target.starts_with("riscv32") && has_target_feature("zbb") {
+        // Bit manipulation instructions, e.g. counting leading zeros, when Rust code uses them too.
+        // This replaces -march option given by cc crate, e.g. riscv32imac becomes rv32imac_zbb.
+        let arch = target.split('-').next().unwrap().replacen("riscv", "rv", 1);
+        flags.push(format!("-march={arch}_zbb"));
+    }
+    flags
+}
+
+/// How NEON of Cortex-A and AArch64 is used by libopus.
+#[cfg(any(feature = "cc", not(feature = "cmake")))]
+#[derive(Debug, PartialEq)]
+enum Neon {
+    /// Portable C code only.
+    Disabled,
+    /// NEON intrinsics are always used as NEON is enabled for the target.
+    Presumed,
+    /// NEON intrinsics are used when run-time CPU detection finds NEON.
+    Detected,
+}
+
+/**
+ * Returns how NEON is used on the target.
+ *
+ * NEON is enabled for all AArch64 targets, e.g. `aarch64-unknown-linux-gnu`, and some 32-bit ARM
+ * targets, e.g. `thumbv7neon-unknown-linux-gnueabihf`. Other ARMv7-A targets with Linux, e.g.
+ * `armv7-unknown-linux-gnueabihf`, are used on Cortex-A cores both with and without NEON, so the
+ * NEON code is selected at run-time there.
+ */
+#[cfg(any(feature = "cc", not(feature = "cmake")))]
+fn neon() -> Neon {
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    if (arch == "aarch64" || arch == "arm") && has_target_feature("neon") {
+        Neon::Presumed
+    } else if env::var("TARGET").unwrap().starts_with("armv7-")
+        && (os == "linux" || os == "android")
+    {
+        Neon::Detected
+    } else {
+        Neon::Disabled
+    }
+}
+
+/**
+ * Returns whitespace separated arguments of a user-supplied environment variable.
+ *
+ * E.g. `OPUS_EMBEDDED_SYS_CFLAGS`. These are given after the options of this build script so that
+ * they take precedence.
+ */
+fn env_args(key: &str) -> Vec<String> {
+    println!("cargo:rerun-if-env-changed={key}");
+    env::var(key)
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+/// Flags for building libopus on targets without C library.
+fn bare_metal_cflags() -> Vec<String> {
+    vec![
+        "-D_FORTIFY_SOURCE=0".to_string(),
+        "-DOVERRIDE_celt_fatal".to_string(),
+    ]
+}
+
+/**
+ * Flags for replacing the allocation functions of libopus with the ones in `custom_support.h`.
+ *
+ * On targets without C library libopus must not call malloc, so they return NULL unless
+ * `rust_allocator` feature routes them to the global allocator of Rust on every target.
+ */
+fn custom_support_cflags() -> Vec<String> {
+    let src_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("src");
+    let mut flags = Vec::new();
+    if is_without_libc() || cfg!(feature = "rust_allocator") {
+        flags.push("-DCUSTOM_SUPPORT".to_string());
+        flags.push(format!("-I{}", src_path.to_str().unwrap()));
+    }
+    if cfg!(feature = "rust_allocator") {
+        flags.push("-DOPUS_EMBEDDED_SYS_RUST_ALLOCATOR".to_string());
+    }
+    flags
+}
+
+/**
+ * Flags for placing each function and variable of libopus in its own section with
+ * `optimize_size` feature.
+ *
+ * The linker can then drop the ones that are not used, e.g. the encoder parts of CELT, with
+ * `--gc-sections`. This needs no link arguments from here, as rustc passes `--gc-sections` (or
+ * `/OPT:REF` for MSVC) itself whenever it links an executable, and link arguments of a build
+ * script would not reach the crates that link against this one anyway.
+ */
+fn section_cflags() -> Vec<String> {
+    vec![
+        "-ffunction-sections".to_string(),
+        "-fdata-sections".to_string(),
+    ]
+}
+
+/**
+ * Returns whether stack usage of decoding is analyzed from the call graph of libopus.
+ *
+ * Enabled with `OPUS_EMBEDDED_SYS_STACK_USAGE=1` environment variable as this needs GCC 10 or
+ * newer for `-fcallgraph-info`, see [stack_usage_cflags].
+ */
+fn analyze_stack_usage() -> bool {
+    println!("cargo:rerun-if-env-changed=OPUS_EMBEDDED_SYS_STACK_USAGE");
+    env::var_os("OPUS_EMBEDDED_SYS_STACK_USAGE").is_some_and(|value| value == "1")
+}
+
+/// Flags for writing call graphs with stack usage of each function next to the objects.
+fn stack_usage_cflags() -> Vec<String> {
+    if analyze_stack_usage() && cfg!(feature = "linker_plugin_lto") {
+        println!("cargo:warning=Stack usage cannot be analyzed with clang of linker_plugin_lto");
+        Vec::new()
+    } else if analyze_stack_usage() {
+        vec!["-fcallgraph-info=su".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Copy a directory recursively without hidden files, e.g. `.git`. Works on every host unlike `cp`.
+#[cfg(not(any(feature = "cc", feature = "cmake")))]
+fn copy_dir(source: &Path, target: &Path) {
+    std::fs::create_dir_all(target).unwrap();
+    for entry in std::fs::read_dir(source)
         .unwrap()
         .map(Result::unwrap)
         .filter(|entry| entry.file_name().as_encoded_bytes()[0] != b'.')
-        .map(|entry| entry.path().as_os_str().to_owned())
-        .collect();
-    let mut args = vec![OsString::from("-r"), OsString::from("--")];
-    args.extend(inputs);
-    args.push(OsString::from(&target));
-    Command::new("cp").args(&args).status().unwrap();
+    {
+        let path = target.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &path);
+        } else {
+            std::fs::copy(entry.path(), &path).unwrap();
+        }
+    }
+}
+
+/**
+ * Run `make install` of the autotools build in parallel.
+ *
+ * autotools crate would pass `-j$NUM_JOBS` to make, which makes GNU make leave the jobserver of
+ * cargo and run that many jobs of its own, i.e. only one job with cargo's `-j1` and otherwise as
+ * many as there are cores on top of the rest of the build. Instead make joins the jobserver from
+ * `CARGO_MAKEFLAGS` and compiles as many files at a time as cargo has jobs free. Make on Windows
+ * and BSD hosts cannot use the jobserver of cargo, so they get `-j$NUM_JOBS` as before.
+ */
+#[cfg(not(any(feature = "cc", feature = "cmake")))]
+fn make_install(build: &Path) {
+    println!("cargo:rerun-if-env-changed=MAKE");
+    let make = env::var("MAKE").unwrap_or_else(|_| "make".to_string());
+    let mut command = if env::var("TARGET").unwrap().contains("emscripten") {
+        let mut command = std::process::Command::new("emmake");
+        command.arg(make);
+        command
+    } else {
+        std::process::Command::new(make)
+    };
+    command.arg("install").current_dir(build);
+    let jobserver_host = !cfg!(any(
+        windows,
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ));
+    match env::var_os("CARGO_MAKEFLAGS") {
+        Some(flags) if jobserver_host => {
+            command.env("MAKEFLAGS", flags);
+        }
+        _ => {
+            command.arg(format!("-j{}", env::var("NUM_JOBS").unwrap()));
+        }
+    }
+    println!("running: {command:?}");
+    let status = command.status().expect("Could not run make");
+    if !status.success() {
+        panic!("make install of libopus failed: {status}");
+    }
+}
+
+/// Build libopus with its autotools build system.
+#[cfg(not(any(feature = "cc", feature = "cmake")))]
+fn build_with_autotools(source: &Path) {
+    if env::var("HOST").unwrap().contains("-windows-") {
+        println!(
+            "cargo:warning=Building libopus with autotools needs a POSIX shell, \
+             enable cc or cmake feature to build without one"
+        );
+    }
+
+    // Configure defines VAR_ARRAYS in config.h whenever the compiler supports them, and that
+    // cannot be undefined with flags
+    if cfg!(feature = "pseudostack") {
+        panic!("pseudostack feature needs cc or cmake feature to build libopus");
+    }
+    if is_wasm_without_libc() {
+        panic!(
+            "wasm32-unknown-unknown needs cc feature as configure cannot link without C library"
+        );
+    }
+
+    // Make a copy of libopus to OUT_DIR so we can run autoreconf without modifying sources
+    let target = PathBuf::from(env::var("OUT_DIR").unwrap()).join("opus");
+    copy_dir(source, &target);
 
     // Run autoreconf and configure in the new directory
     let mut builder = autotools::Config::new(target);
@@ -117,10 +429,23 @@ fn main() {
         // However optimizations seem to do a reasonable job here.
         builder.disable("asm", None);
     }
+    if env::var("TARGET").unwrap().starts_with("thumbv8m.base-") {
+        // Cortex-M23 (thumbv8m.base) lacks SMULL like Cortex-M0+, so no assembly for it either.
+        // Also disable rtcd (no OS).
+        builder.disable("asm", None);
+        builder.disable("rtcd", None);
+    }
     if env::var("TARGET").unwrap().starts_with("thumbv7m-") {
         // Fails on Cortex-M3 (thumbv7m), disable CPU detection on embedded
         builder.disable("rtcd", None);
     }
+    if env::var("TARGET").unwrap().starts_with("thumbv7em-") {
+        // Cortex-M4 and Cortex-M7 (thumbv7em): The existing ARM assembly is not valid in Thumb
+        // mode, inline assembly for DSP extension is enabled in target_cflags instead. Also
+        // disable rtcd (no OS).
+        builder.disable("asm", None);
+        builder.disable("rtcd", None);
+    }
     if env::var("TARGET").unwrap().starts_with("thumbv8m.main-") {
         // Cortex-M33 (thumbv8m.main): The existing ARM assembly uses conditional
         // instructions outside IT blocks which is invalid in ARMv8-M Thumb mode.
@@ -128,25 +453,979 @@ fn main() {
         builder.disable("asm", None);
         builder.disable("rtcd", None);
     }
-    if env::var("CARGO_CFG_TARGET_OS").unwrap() == "none" {
-        let src_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("src");
-        builder
-            .cflag("-D_FORTIFY_SOURCE=0")
-            .cflag("-DOVERRIDE_celt_fatal")
-            .cflag("-DCUSTOM_SUPPORT")
-            .cflag(format!("-I{}", src_path.to_str().unwrap()))
-            .ldflag("-nostdlib");
+    let neon = neon();
+    if neon != Neon::Disabled {
+        // Cortex-A and AArch64: NEON intrinsics, and run-time CPU detection unless the target
+        // has NEON. Configure finds the flags for compiling the intrinsics itself.
+        builder.enable("intrinsics", None);
+        if neon == Neon::Detected {
+            builder.enable("rtcd", None);
+        }
+    }
+    if env::var("TARGET").unwrap().starts_with("thumbv7neon-") {
+        // The existing ARM assembly is not valid in Thumb mode, unlike the intrinsics
+        builder.disable("asm", None);
+    }
+    if is_wasm() {
+        // WebAssembly, e.g. wasm32-wasip1 with the clang of WASI SDK or wasm32-unknown-emscripten
+        // with emconfigure: There is neither assembly nor CPU detection for it in libopus.
+        builder.disable("asm", None);
+        builder.disable("rtcd", None);
+    }
+    if cfg!(feature = "custom_modes") {
+        builder.enable("custom-modes", None);
+    }
+    if let Some(toolchain) = gnu_toolchain() {
+        // ESP32 (Xtensa) and RISC-V: Configure would guess the host toolchain. There is neither
+        // assembly nor CPU detection for these in libopus.
+        builder.config_option("host", Some(toolchain.as_str()));
+        builder.disable("asm", None);
+        builder.disable("rtcd", None);
+        if !tool_from_env("CC") {
+            builder.env("CC", format!("{toolchain}-gcc"));
+        }
+    }
+    for flag in target_cflags() {
+        builder.cflag(flag);
+    }
+    for flag in custom_support_cflags() {
+        builder.cflag(flag);
+    }
+    for flag in stack_usage_cflags() {
+        builder.cflag(flag);
     }
-    if cfg!(feature = "optimize_libopus") {
+    if is_without_libc() {
+        for flag in bare_metal_cflags() {
+            builder.cflag(flag);
+        }
+        builder.ldflag("-nostdlib");
+    }
+    if cfg!(feature = "optimize_size") {
+        builder.cflag("-Os");
+        for flag in section_cflags() {
+            builder.cflag(flag);
+        }
+    } else if cfg!(feature = "optimize_libopus") {
         builder.cflag("-O3");
     }
+    if cfg!(feature = "stack_protector") {
+        // Enabled explicitly as configure's own check fails to link on bare metal
+        builder.cflag("-fstack-protector-strong");
+    }
+    for flag in env_args("OPUS_EMBEDDED_SYS_CFLAGS") {
+        builder.cflag(flag);
+    }
+    for arg in env_args("OPUS_EMBEDDED_SYS_CONFIGURE_ARGS") {
+        // E.g. --enable-assertions or --host=arm-none-eabi
+        let arg = arg.strip_prefix("--").unwrap_or(&arg);
+        match arg.split_once('=') {
+            Some((option, value)) => builder.config_option(option, Some(value)),
+            None => builder.config_option(arg, None),
+        };
+    }
+    let dst = builder.configure();
+    make_install(&dst.join("build"));
+    println!(
+        "cargo:rustc-link-search=native={}",
+        dst.join("lib").display()
+    );
+    println!("cargo:rustc-link-lib=static=opus");
+}
+
+/// Returns the major version of LLVM in a version string, e.g. 19 of `LLVM version: 19.1.7`.
+#[cfg(feature = "linker_plugin_lto")]
+fn llvm_major_version(output: &[u8], prefix: &str) -> Option<u32> {
+    let output = String::from_utf8_lossy(output);
+    let version = output.split(prefix).nth(1)?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+/**
+ * Checks that rustc can do cross-language LTO with the bitcode of libopus.
+ *
+ * Only clang writes LLVM bitcode, and the linker of rustc reads it only from the same or older
+ * version of LLVM than its own, so the versions should match. Without `-C linker-plugin-lto` in
+ * RUSTFLAGS rustc does not optimize across the languages, and only linkers that understand
+ * bitcode, e.g. lld, can link libopus then.
+ */
+#[cfg(feature = "linker_plugin_lto")]
+fn check_linker_plugin_lto(builder: &cc::Build) {
+    let compiler = builder.get_compiler();
+    if !compiler.is_like_clang() {
+        panic!(
+            "linker_plugin_lto feature needs clang, not {}",
+            compiler.path().display()
+        );
+    }
+    let clang = compiler
+        .to_command()
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| llvm_major_version(&output.stdout, "clang version "));
+    let rustc = std::process::Command::new(env::var_os("RUSTC").unwrap())
+        .arg("-vV")
+        .output()
+        .ok()
+        .and_then(|output| llvm_major_version(&output.stdout, "LLVM version: "));
+    match (clang, rustc) {
+        (Some(clang), Some(rustc)) if clang != rustc => println!(
+            "cargo:warning=clang uses LLVM {clang} but rustc uses LLVM {rustc}, \
+             set CC to clang of LLVM {rustc} for linker_plugin_lto feature"
+        ),
+        (Some(_), Some(_)) => {}
+        _ => println!("cargo:warning=Could not compare LLVM versions of clang and rustc"),
+    }
+    let rustflags = env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+    if !rustflags.contains("linker-plugin-lto") {
+        println!(
+            "cargo:warning=linker_plugin_lto feature needs -C linker-plugin-lto in RUSTFLAGS \
+             for cross-language LTO"
+        );
+    }
+}
+
+/**
+ * Returns the sources listed in a variable of libopus makefile fragment.
+ *
+ * E.g. `CELT_SOURCES` in `celt_sources.mk`, which lists one file per line with line
+ * continuations.
+ */
+#[cfg(feature = "cc")]
+fn makefile_sources(source: &Path, file: &str, variable: &str) -> Vec<PathBuf> {
+    let path = source.join(file);
+    println!("cargo:rerun-if-changed={}", path.display());
+    let makefile = std::fs::read_to_string(&path).unwrap();
+    let mut lines = makefile
+        .lines()
+        .skip_while(|line| line.split('=').next().map(str::trim) != Some(variable));
+    let mut sources = Vec::new();
+    let mut line = lines
+        .next()
+        .and_then(|line| line.split_once('='))
+        .map(|(_, files)| files);
+    while let Some(files) = line {
+        let continued = files.trim_end().ends_with('\\');
+        sources.extend(
+            files
+                .trim_end()
+                .trim_end_matches('\\')
+                .split_whitespace()
+                .map(|file| source.join(file)),
+        );
+        line = lines.next().filter(|_| continued);
+    }
+    assert!(!sources.is_empty(), "No {variable} in {}", path.display());
+    sources
+}
+
+/**
+ * Returns the decoder half that `celt_only` or `silk_only` feature leaves out.
+ *
+ * Its source file with the entry points called by `opus_decoder.c` is replaced by the stubs in
+ * `codec_stubs.c` enabled by the define, and the linker drops the rest of that half as nothing
+ * refers to it anymore.
+ */
+#[cfg(feature = "cc")]
+fn stubbed_codec() -> Option<(&'static str, &'static str)> {
+    if cfg!(all(feature = "custom_modes", feature = "silk_only")) {
+        panic!("custom_modes feature needs the CELT decoder that silk_only feature leaves out");
+    }
+    match (cfg!(feature = "celt_only"), cfg!(feature = "silk_only")) {
+        (true, true) => panic!("celt_only and silk_only features cannot be enabled together"),
+        (true, false) => Some(("silk/dec_API.c", "OPUS_EMBEDDED_SYS_NO_SILK")),
+        (false, true) => Some(("celt/celt_decoder.c", "OPUS_EMBEDDED_SYS_NO_CELT")),
+        (false, false) => None,
+    }
+}
+
+/**
+ * Build libopus by compiling its C sources with cc crate.
+ *
+ * This needs only a C compiler for the target. Build configuration is written to `config.h` as
+ * configure would do it with the options of the autotools build, without assembly. Run-time CPU
+ * detection is used only for NEON on ARMv7-A, see [neon].
+ */
+#[cfg(feature = "cc")]
+fn build_with_cc(source: &Path) {
+    let neon = neon();
+    let mut config = String::from(
+        "/* Generated by opus-embedded-sys build script */\n\
+         #define OPUS_BUILD 1\n\
+         #define FIXED_POINT 1\n\
+         #define DISABLE_FLOAT_API 1\n\
+         #define ENABLE_HARDENING 1\n\
+         #define HAVE_STDINT_H 1\n\
+         #define HAVE_STRING_H 1\n",
+    );
+    config.push_str(if cfg!(feature = "pseudostack") {
+        // Temporary arrays are allocated from the scratch memory given by the caller
+        "#define NONTHREADSAFE_PSEUDOSTACK 1\n"
+    } else {
+        "#define VAR_ARRAYS 1\n"
+    });
+    let stubbed = stubbed_codec();
+    if let Some((_, define)) = stubbed {
+        config.push_str(&format!("#define {define} 1\n"));
+    }
+    if cfg!(feature = "custom_modes") {
+        config.push_str("#define CUSTOM_MODES 1\n");
+    }
+    match neon {
+        Neon::Disabled => {}
+        Neon::Presumed => {
+            config.push_str(
+                "#define OPUS_ARM_MAY_HAVE_NEON_INTR 1\n\
+                 #define OPUS_ARM_PRESUME_NEON_INTR 1\n",
+            );
+            if env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "aarch64" {
+                config.push_str(
+                    "#define OPUS_ARM_MAY_HAVE_AARCH64_NEON_INTR 1\n\
+                     #define OPUS_ARM_PRESUME_AARCH64_NEON_INTR 1\n",
+                );
+            }
+        }
+        Neon::Detected => config.push_str(
+            "#define OPUS_HAVE_RTCD 1\n\
+             #define OPUS_ARM_MAY_HAVE_NEON_INTR 1\n",
+        ),
+    }
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap()).join("cc");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    std::fs::write(out_dir.join("config.h"), config).unwrap();
+
+    let mut builder = cc::Build::new();
+    builder
+        .include(&out_dir)
+        .include(source.join("include"))
+        .include(source.join("celt"))
+        .include(source.join("silk"))
+        .include(source.join("silk/fixed"))
+        .include(source.join("dnn"))
+        .define("HAVE_CONFIG_H", None)
+        .warnings(false);
+    if cfg!(feature = "linker_plugin_lto") {
+        // LLVM bitcode for the linker of rustc, which needs clang and an archiver that indexes
+        // bitcode, see check_linker_plugin_lto
+        if !tool_from_env("CC") {
+            builder.compiler("clang");
+        }
+        if !tool_from_env("AR") {
+            builder.archiver("llvm-ar");
+        }
+        builder.flag("-flto=thin");
+    } else if let Some(toolchain) = gnu_toolchain() {
+        if !tool_from_env("CC") {
+            builder.compiler(format!("{toolchain}-gcc"));
+        }
+        if !tool_from_env("AR") {
+            builder.archiver(format!("{toolchain}-ar"));
+        }
+    }
+    for flag in target_cflags() {
+        builder.flag(flag);
+    }
+    for flag in custom_support_cflags() {
+        builder.flag(flag);
+    }
+    for flag in stack_usage_cflags() {
+        builder.flag(flag);
+    }
+    if is_without_libc() {
+        for flag in bare_metal_cflags() {
+            builder.flag(flag);
+        }
+    }
+    if is_wasm_without_libc() {
+        // Clang has no C library headers for wasm32-unknown-unknown, and libopus includes some
+        let libc = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("src/libc");
+        println!("cargo:rerun-if-changed={}", libc.display());
+        builder.include(libc);
+    }
+    if cfg!(feature = "optimize_size") {
+        builder.opt_level_str("s");
+        for flag in section_cflags() {
+            builder.flag(flag);
+        }
+    } else if cfg!(feature = "optimize_libopus") {
+        builder.opt_level(3);
+    }
+    if cfg!(feature = "stack_protector") {
+        builder.flag("-fstack-protector-strong");
+    }
+    for flag in env_args("OPUS_EMBEDDED_SYS_CFLAGS") {
+        builder.flag(flag);
+    }
+    if !env_args("OPUS_EMBEDDED_SYS_CONFIGURE_ARGS").is_empty() {
+        println!(
+            "cargo:warning=OPUS_EMBEDDED_SYS_CONFIGURE_ARGS is not used by cc build, \
+             define options of config.h in OPUS_EMBEDDED_SYS_CFLAGS instead"
+        );
+    }
+    #[cfg(feature = "linker_plugin_lto")]
+    check_linker_plugin_lto(&builder);
+
+    let neon_sources = || {
+        [
+            makefile_sources(source, "celt_sources.mk", "CELT_SOURCES_ARM_NEON_INTR"),
+            makefile_sources(source, "silk_sources.mk", "SILK_SOURCES_ARM_NEON_INTR"),
+            makefile_sources(
+                source,
+                "silk_sources.mk",
+                "SILK_SOURCES_FIXED_ARM_NEON_INTR",
+            ),
+        ]
+        .concat()
+    };
+    match neon {
+        Neon::Disabled => {}
+        Neon::Presumed => {
+            builder.files(neon_sources());
+        }
+        Neon::Detected => {
+            // Only the NEON code is compiled for NEON like configure does, the rest must run on
+            // cores without it. Soft float targets need the NEON registers for the intrinsics.
+            let mut neon_builder = builder.clone();
+            neon_builder.files(neon_sources()).flag("-mfpu=neon");
+            if !env::var("TARGET").unwrap().ends_with("hf") {
+                neon_builder.flag("-mfloat-abi=softfp");
+            }
+            builder
+                .objects(neon_builder.compile_intermediates())
+                .files(makefile_sources(
+                    source,
+                    "celt_sources.mk",
+                    "CELT_SOURCES_ARM_RTCD",
+                ))
+                .files(makefile_sources(
+                    source,
+                    "silk_sources.mk",
+                    "SILK_SOURCES_ARM_RTCD",
+                ));
+        }
+    }
+    let mut sources = [
+        makefile_sources(source, "celt_sources.mk", "CELT_SOURCES"),
+        makefile_sources(source, "silk_sources.mk", "SILK_SOURCES"),
+        makefile_sources(source, "silk_sources.mk", "SILK_SOURCES_FIXED"),
+        makefile_sources(source, "opus_sources.mk", "OPUS_SOURCES"),
+    ]
+    .concat();
+    if let Some((stubbed_source, _)) = stubbed {
+        let stubs =
+            PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("src/codec_stubs.c");
+        println!("cargo:rerun-if-changed={}", stubs.display());
+        sources.retain(|path| *path != source.join(stubbed_source));
+        sources.push(stubs);
+    }
+    builder.files(sources).compile("opus");
+}
+
+/**
+ * Build libopus with its CMake build system.
+ *
+ * Cross compilation is configured by [cmake] crate, which also picks up a toolchain file from
+ * `CMAKE_TOOLCHAIN_FILE` environment variable, e.g. the one of Zephyr or ESP-IDF SDK.
+ */
+#[cfg(all(feature = "cmake", not(feature = "cc")))]
+fn build_with_cmake(source: &Path) {
+    if is_wasm_without_libc() {
+        panic!("wasm32-unknown-unknown needs cc feature as CMake cannot build without C library");
+    }
+    let mut builder = cmake::Config::new(source);
+    builder
+        .define("CMAKE_INSTALL_LIBDIR", "lib")
+        .define("OPUS_BUILD_PROGRAMS", "OFF")
+        .define("OPUS_BUILD_SHARED_LIBRARY", "OFF")
+        .define("OPUS_BUILD_TESTING", "OFF")
+        .define("OPUS_DEEP_PLC", "OFF")
+        .define("OPUS_DRED", "OFF")
+        .define("OPUS_ENABLE_FLOAT_API", "OFF")
+        .define("OPUS_FIXED_POINT", "ON")
+        .define("OPUS_INSTALL_CMAKE_CONFIG_MODULE", "OFF")
+        .define("OPUS_INSTALL_PKG_CONFIG_MODULE", "OFF")
+        .define("OPUS_OSCE", "OFF")
+        // Stack protector flag is added explicitly below like with autotools
+        .define("OPUS_STACK_PROTECTOR", "OFF");
+    if cfg!(feature = "pseudostack") {
+        // Temporary arrays are allocated from the scratch memory given by the caller
+        builder
+            .define("OPUS_VAR_ARRAYS", "OFF")
+            .define("OPUS_USE_ALLOCA", "OFF")
+            .define("OPUS_NONTHREADSAFE_PSEUDOSTACK", "ON");
+    }
+    if cfg!(feature = "custom_modes") {
+        builder.define("OPUS_CUSTOM_MODES", "ON");
+    }
+    if is_without_libc() {
+        // Test programs cannot be linked without C library, and there is no CPU detection
+        builder
+            .define("CMAKE_TRY_COMPILE_TARGET_TYPE", "STATIC_LIBRARY")
+            .define("OPUS_DISABLE_INTRINSICS", "ON")
+            .define("OPUS_FORTIFY_SOURCE", "OFF");
+        for flag in bare_metal_cflags() {
+            builder.cflag(flag);
+        }
+    }
+    if let Some(toolchain) = gnu_toolchain() {
+        // Without a toolchain file, e.g. the one of ESP-IDF, cmake crate picks the host compiler
+        if !tool_from_env("CMAKE_TOOLCHAIN_FILE") && !tool_from_env("CC") {
+            builder.define("CMAKE_C_COMPILER", format!("{toolchain}-gcc"));
+        }
+    }
+    for flag in target_cflags() {
+        builder.cflag(flag);
+    }
+    for flag in custom_support_cflags() {
+        builder.cflag(flag);
+    }
+    for flag in stack_usage_cflags() {
+        builder.cflag(flag);
+    }
+    if cfg!(feature = "optimize_size") {
+        builder.profile("MinSizeRel").cflag("-Os");
+        for flag in section_cflags() {
+            builder.cflag(flag);
+        }
+    } else if cfg!(feature = "optimize_libopus") {
+        builder.profile("Release").cflag("-O3");
+    }
+    if cfg!(feature = "stack_protector") {
+        builder.cflag("-fstack-protector-strong");
+    }
+    for flag in env_args("OPUS_EMBEDDED_SYS_CFLAGS") {
+        builder.cflag(flag);
+    }
+    for arg in env_args("OPUS_EMBEDDED_SYS_CONFIGURE_ARGS") {
+        // E.g. -DOPUS_ASSERTIONS=ON
+        let Some((name, value)) = arg
+            .strip_prefix("-D")
+            .and_then(|define| define.split_once('='))
+        else {
+            panic!("Expected -DNAME=VALUE in OPUS_EMBEDDED_SYS_CONFIGURE_ARGS, got {arg}");
+        };
+        builder.define(name, value);
+    }
     let dst = builder.build();
     println!(
         "cargo:rustc-link-search=native={}",
         dst.join("lib").display()
     );
     println!("cargo:rustc-link-lib=static=opus");
+}
+
+/**
+ * Functions of libopus that call themselves, with the maximum number of their frames on stack.
+ *
+ * Recursion depths cannot be seen from the call graph, so these are from the sources.
+ */
+const RECURSION_DEPTHS: [(&str, usize); 3] = [
+    // Packet loss concealment of the frames before a packet with FEC
+    ("opus_decode_native", 2),
+    // Packet loss concealment in 20 ms steps, or of 5 ms for transition between modes
+    ("opus_decode_frame", 2),
+    // Split of a band in halves until LM goes from at most 3 to -1
+    ("quant_partition", 5),
+];
+
+/// Call graph of libopus with the stack frame size of each function, by the names GCC gives.
+#[derive(Debug, Default)]
+struct CallGraph {
+    frames: HashMap<String, usize>,
+    calls: HashMap<String, BTreeSet<String>>,
+}
+
+/// A call chain of functions with how many times each is on stack.
+#[derive(Clone, Debug, Default)]
+struct CallChain {
+    size: usize,
+    functions: Vec<(String, usize)>,
+}
+
+/// Returns the name of a function in the call graph without the file of static functions and
+/// suffixes of clones, e.g. `quant_band` of `/path/to/bands.c:quant_band.isra.0`.
+fn function_name(title: &str) -> &str {
+    let name = title.rsplit(':').next().unwrap();
+    name.split('.').next().unwrap()
+}
+
+impl CallGraph {
+    /**
+     * Reads the call graphs that GCC writes as `.ci` files next to the objects.
+     *
+     * Functions that are defined have their frame size in the label, e.g.
+     * `label: "quant_band\n/path/to/bands.c:1086:17\n160 bytes (dynamic,bounded)"`. Variable
+     * length arrays make the frames dynamic, and they are not included in the size.
+     */
+    fn read(&mut self, dir: &Path) {
+        for entry in std::fs::read_dir(dir).unwrap().map(Result::unwrap) {
+            let path = entry.path();
+            if entry.file_type().unwrap().is_dir() {
+                self.read(&path);
+                continue;
+            } else if path.extension().is_none_or(|extension| extension != "ci") {
+                continue;
+            }
+            for line in std::fs::read_to_string(&path).unwrap().lines() {
+                if let Some(node) = line.strip_prefix("node: { title: \"") {
+                    let (title, label) = node.split_once('"').unwrap();
+                    let Some(size) = label.split("\\n").find_map(|part| {
+                        part.split_once(" bytes (")
+                            .and_then(|(size, _)| size.parse::<usize>().ok())
+                    }) else {
+                        // Declared but not defined in this object
+                        continue;
+                    };
+                    let frame = self.frames.entry(title.to_string()).or_default();
+                    *frame = (*frame).max(size);
+                } else if let Some(edge) = line.strip_prefix("edge: { sourcename: \"") {
+                    let (caller, rest) = edge.split_once('"').unwrap();
+                    let callee = rest
+                        .strip_prefix(" targetname: \"")
+                        .and_then(|rest| rest.split_once('"'))
+                        .unwrap()
+                        .0;
+                    self.calls
+                        .entry(caller.to_string())
+                        .or_default()
+                        .insert(callee.to_string());
+                }
+            }
+        }
+    }
+
+    /**
+     * Returns the call chain from a function that uses the most stack.
+     *
+     * Functions that are not in the call graph, e.g. memcpy of C library, are collected to
+     * `external` and counted as zero. Fails on unknown recursion and calls of function pointers,
+     * whose depth cannot be known.
+     */
+    fn deepest(
+        &self,
+        function: &str,
+        chains: &mut HashMap<String, Option<CallChain>>,
+        external: &mut BTreeSet<String>,
+    ) -> Result<CallChain, String> {
+        match chains.get(function) {
+            Some(Some(chain)) => return Ok(chain.clone()),
+            Some(None) => {
+                return Err(format!("{} is called recursively", function_name(function)));
+            }
+            None => {}
+        }
+        let Some(&frame) = self.frames.get(function) else {
+            external.insert(function_name(function).to_string());
+            return Ok(CallChain::default());
+        };
+        chains.insert(function.to_string(), None);
+        let mut depth = 1;
+        let mut deepest = CallChain::default();
+        for callee in self.calls.get(function).into_iter().flatten() {
+            // Position independent code calls global functions of the same object by local alias
+            let callee = match callee.strip_suffix(".localalias") {
+                Some(alias) => alias.rsplit(':').next().unwrap(),
+                None => callee,
+            };
+            if callee == "__indirect_call" {
+                return Err(format!(
+                    "{} calls a function pointer",
+                    function_name(function)
+                ));
+            } else if callee == function {
+                depth = RECURSION_DEPTHS
+                    .iter()
+                    .find(|(name, _)| *name == function_name(function))
+                    .ok_or_else(|| format!("{} calls itself", function_name(function)))?
+                    .1;
+                continue;
+            }
+            let chain = self.deepest(callee, chains, external)?;
+            if chain.size > deepest.size {
+                deepest = chain;
+            }
+        }
+        let mut chain = CallChain {
+            size: frame * depth + deepest.size,
+            functions: vec![(function_name(function).to_string(), depth)],
+        };
+        chain.functions.extend(deepest.functions);
+        chains.insert(function.to_string(), Some(chain.clone()));
+        Ok(chain)
+    }
+}
+
+/**
+ * Returns the stack used by call frames of `opus_decode` in the worst case.
+ *
+ * This is the deepest call chain in the call graphs of libopus build in OUT_DIR. With
+ * `OPUS_EMBEDDED_SYS_STACK_USAGE=1` environment variable it is shown as a warning together with
+ * the temporary arrays on stack or in scratch memory.
+ */
+fn stack_usage() -> Option<usize> {
+    if !analyze_stack_usage() || cfg!(feature = "linker_plugin_lto") {
+        return None;
+    }
+    let mut graph = CallGraph::default();
+    graph.read(&PathBuf::from(env::var("OUT_DIR").unwrap()));
+    let mut external = BTreeSet::new();
+    let chain = match graph.deepest("opus_decode", &mut HashMap::new(), &mut external) {
+        Ok(chain) if chain.size > 0 => chain,
+        Ok(_) => {
+            println!(
+                "cargo:warning=No call graph of opus_decode, \
+                 is libopus built with GCC 10 or newer?"
+            );
+            return None;
+        }
+        Err(error) => {
+            println!("cargo:warning=Stack usage of opus_decode is not known: {error}");
+            return None;
+        }
+    };
+    let (scratch, decoder) = if cfg!(feature = "stereo") {
+        (OPUS_DECODER_SCRATCH_SIZE_CH2, "a stereo decoder")
+    } else {
+        (OPUS_DECODER_SCRATCH_SIZE_CH1, "a mono decoder")
+    };
+    if cfg!(feature = "pseudostack") {
+        println!(
+            "cargo:warning=opus_decode uses at most {} bytes of stack and {scratch} bytes of \
+             scratch memory with {decoder}, and no heap",
+            chain.size
+        );
+    } else {
+        println!(
+            "cargo:warning=opus_decode uses at most {} bytes of stack with {decoder}, {} for call \
+             frames and {scratch} for temporary arrays, and no heap",
+            chain.size + scratch,
+            chain.size
+        );
+    }
+    let functions = chain
+        .functions
+        .iter()
+        .map(|(function, depth)| match depth {
+            1 => function.clone(),
+            depth => format!("{function} x{depth}"),
+        })
+        .collect::<Vec<_>>();
+    println!(
+        "cargo:warning=Deepest call chain: {}",
+        functions.join(" > ")
+    );
+    if !external.is_empty() {
+        println!(
+            "cargo:warning=Functions outside libopus are not included: {}",
+            external.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    Some(chain.size)
+}
 
+/// Write the stack used by call frames of `opus_decode`, or `None` if it is not known.
+fn write_stack_usage(stack_usage: Option<usize>) {
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("opus_decode_stack.rs");
+    let value = match stack_usage {
+        Some(size) => format!("Some({size})"),
+        None => "None".to_string(),
+    };
+    std::fs::write(out_path, value).expect("Couldn't write stack usage!");
+}
+
+/**
+ * Returns the version of libopus sources.
+ *
+ * Release tarballs have it in `package_version` file, which is missing in git checkouts where it
+ * is described by the tags like configure does. Sources without either, e.g. the vendored ones in
+ * packages of this crate, have no known version.
+ */
+fn source_version(source: &Path) -> Option<String> {
+    std::fs::read_to_string(source.join("package_version"))
+        .ok()
+        .and_then(|file| {
+            file.lines()
+                .find_map(|line| line.strip_prefix("PACKAGE_VERSION="))
+                .map(|version| version.trim().trim_matches('"').to_string())
+        })
+        .or_else(|| {
+            // Git would describe the repository that the sources are in otherwise
+            if !source.join(".git").exists() {
+                return None;
+            }
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(source)
+                .args(["describe", "--tags", "--match", "v*"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|version| version.trim().trim_start_matches('v').to_string())
+        })
+}
+
+/**
+ * Releases of libopus this crate knows, with the size of OpusDecoder for mono and stereo decoders.
+ *
+ * The first one is the vendored release. The sizes are reported by `opus_decoder_get_size` of the
+ * fixed point build without DRED on x86_64, and pre-generated bindings exist for each release.
+ */
+const RELEASES: [(&str, [usize; 2]); 2] = [("1.5.2", [17860, 26580]), ("1.4", [17828, 26548])];
+
+/// Returns whether a version of libopus is of a release, e.g. `1.5.2` is of both `1.5` and `1.5.2`.
+fn is_release(version: &str, release: &str) -> bool {
+    version == release
+        || version
+            .strip_prefix(release)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+/**
+ * Returns the release of libopus that must be built, if it is pinned.
+ *
+ * Pinned with `libopus_1_4` feature or `OPUS_EMBEDDED_SYS_VERSION` environment variable, e.g.
+ * `1.4`, `1.5` or `1.5.2`. Both can be given only if the variable names a release of 1.4.
+ */
+fn pinned_release() -> Option<String> {
+    println!("cargo:rerun-if-env-changed=OPUS_EMBEDDED_SYS_VERSION");
+    let version = env::var("OPUS_EMBEDDED_SYS_VERSION")
+        .ok()
+        .filter(|version| !version.is_empty());
+    match (cfg!(feature = "libopus_1_4"), version) {
+        (true, Some(version)) if !is_release(&version, "1.4") => {
+            panic!("libopus_1_4 feature cannot be used with OPUS_EMBEDDED_SYS_VERSION={version}")
+        }
+        (true, None) => Some("1.4".to_string()),
+        (_, version) => version,
+    }
+}
+
+/**
+ * Returns the known release of a libopus version, or the vendored one for other versions.
+ *
+ * OpusDecoder of another version may be larger than the struct of the vendored release, which is
+ * checked when a decoder is constructed.
+ */
+fn release(version: Option<&str>) -> (&'static str, [usize; 2]) {
+    version
+        .and_then(|version| {
+            RELEASES
+                .into_iter()
+                .find(|(release, _)| is_release(version, release))
+        })
+        .unwrap_or(RELEASES[0])
+}
+
+/**
+ * Export a release of libopus from the git repository of the vendored sources to OUT_DIR.
+ *
+ * The newest tag of the release is exported, e.g. `v1.5.2` for `1.5`, with `package_version` file
+ * like in release tarballs so that every build system finds the version. Packages of this crate
+ * do not have the repository, so there the sources of the release must be given with
+ * `OPUS_EMBEDDED_SYS_SOURCE_DIR` instead.
+ */
+fn export_release(repository: &Path, release: &str) -> PathBuf {
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .arg("-C")
+            .arg(repository)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+    };
+    // Without a checkout of the submodule git would look at the repository of this crate
+    let tags = git(&[
+        "tag",
+        "--list",
+        "--sort=-v:refname",
+        &format!("v{release}"),
+        &format!("v{release}.*"),
+    ])
+    .filter(|_| repository.join(".git").exists());
+    let Some(tag) = tags.as_deref().and_then(|tags| tags.lines().next()) else {
+        panic!(
+            "No libopus {release} in the git repository of {}, give its sources in \
+             OPUS_EMBEDDED_SYS_SOURCE_DIR",
+            repository.display()
+        );
+    };
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let archive = out_dir.join(format!("opus-{tag}.tar"));
+    let target = out_dir.join(format!("opus-{tag}"));
+    git(&["archive", "--output", archive.to_str().unwrap(), tag])
+        .unwrap_or_else(|| panic!("Could not export libopus {tag} from git"));
+    if target.exists() {
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+    std::fs::create_dir_all(&target).unwrap();
+    let status = std::process::Command::new("tar")
+        .arg("-xf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&target)
+        .status()
+        .expect("Could not run tar");
+    if !status.success() {
+        panic!("Could not extract libopus {tag}: {status}");
+    }
+    std::fs::remove_file(archive).unwrap();
+    let version = tag.trim_start_matches('v');
+    std::fs::write(
+        target.join("package_version"),
+        format!("PACKAGE_VERSION=\"{version}\"\n"),
+    )
+    .unwrap();
+    target
+}
+
+/**
+ * Returns the directory of libopus sources to build.
+ *
+ * These are the vendored sources in `src/opus` unless `OPUS_EMBEDDED_SYS_SOURCE_DIR` environment
+ * variable gives another checkout, e.g. one with patches or a newer release. When a release is
+ * pinned, see [pinned_release], that checkout must be of the release, and otherwise the release
+ * is exported from the git repository of the vendored sources unless they are of it already.
+ */
+fn source_dir(pinned: Option<&str>) -> PathBuf {
+    println!("cargo:rerun-if-env-changed=OPUS_EMBEDDED_SYS_SOURCE_DIR");
+    let source = match env::var_os("OPUS_EMBEDDED_SYS_SOURCE_DIR").map(PathBuf::from) {
+        Some(source) => {
+            assert!(
+                source.join("include/opus.h").is_file(),
+                "No libopus sources in OPUS_EMBEDDED_SYS_SOURCE_DIR={}",
+                source.display()
+            );
+            println!("cargo:rerun-if-changed={}", source.display());
+            source
+        }
+        None => {
+            let vendored = PathBuf::from("src/opus");
+            // Packages of this crate have the vendored release without its version
+            let version = source_version(&vendored).unwrap_or_else(|| RELEASES[0].0.to_string());
+            match pinned {
+                Some(pinned) if !is_release(&version, pinned) => export_release(&vendored, pinned),
+                _ => return vendored,
+            }
+        }
+    };
+    let version = source_version(&source);
+    if let Some(pinned) = pinned {
+        assert!(
+            version
+                .as_deref()
+                .is_some_and(|version| is_release(version, pinned)),
+            "libopus {pinned} is pinned but OPUS_EMBEDDED_SYS_SOURCE_DIR={} has {}",
+            source.display(),
+            version.as_deref().unwrap_or("a version that is not known")
+        );
+    }
+    println!(
+        "cargo:warning=Using libopus {} from {}",
+        version.as_deref().unwrap_or("of unknown version"),
+        source.display()
+    );
+    source
+}
+
+/**
+ * Returns whether libopus of the system is used instead of building the vendored sources.
+ *
+ * Enabled with `system_libopus` feature or `OPUS_EMBEDDED_SYS_NO_VENDOR=1` environment variable.
+ */
+fn use_system_libopus() -> bool {
+    println!("cargo:rerun-if-env-changed=OPUS_EMBEDDED_SYS_NO_VENDOR");
+    cfg!(feature = "system_libopus")
+        || env::var_os("OPUS_EMBEDDED_SYS_NO_VENDOR").is_some_and(|value| value == "1")
+}
+
+/**
+ * Find libopus of the system with pkg-config and link against it.
+ *
+ * Returns the include paths of its headers and its version, which must be of the pinned release
+ * if there is one. Static linking can be requested with `OPUS_STATIC` environment variable, see
+ * [pkg_config] crate.
+ */
+fn find_system_libopus(pinned: Option<&str>) -> (Vec<PathBuf>, String) {
+    // Distributions build libopus with variable length arrays
+    if cfg!(feature = "pseudostack") {
+        panic!("pseudostack feature cannot be used with libopus of the system");
+    }
+    if cfg!(feature = "linker_plugin_lto") {
+        println!("cargo:warning=linker_plugin_lto feature has no effect on libopus of the system");
+    }
+    if cfg!(any(feature = "celt_only", feature = "silk_only")) {
+        println!(
+            "cargo:warning=celt_only and silk_only features do not leave anything out of libopus \
+             of the system"
+        );
+    }
+    if cfg!(feature = "custom_modes") {
+        println!(
+            "cargo:warning=custom_modes feature needs libopus of the system to be built with \
+             --enable-custom-modes, or linking fails"
+        );
+    }
+    let library = pkg_config::Config::new()
+        .atleast_version("1.1")
+        .probe("opus")
+        .expect("Could not find libopus with pkg-config");
+    if let Some(pinned) = pinned {
+        assert!(
+            is_release(&library.version, pinned),
+            "libopus {pinned} is pinned but the system has {}",
+            library.version
+        );
+    }
+    println!(
+        "cargo:warning=Using libopus {} of the system",
+        library.version
+    );
+    (library.include_paths, library.version)
+}
+
+/**
+ * Returns the path of pre-generated bindings for this configuration of a release in [RELEASES].
+ *
+ * Bindings differ by the size of OpusDecoder struct and by whether functions that allocate are
+ * included, which they are not on targets without C library, see [is_without_libc].
+ */
+fn pregenerated_bindings(release: &str) -> PathBuf {
+    let mut name = String::from("opus_decoder");
+    name.push_str(if cfg!(feature = "stereo") {
+        "_stereo"
+    } else {
+        "_mono"
+    });
+    if is_without_libc() {
+        name.push_str("_os_none");
+    }
+    name.push_str(".rs");
+    PathBuf::from("src/bindings").join(release).join(name)
+}
+
+/// Use bindings from the source tree so that bindgen and libclang are not needed.
+fn copy_pregenerated_bindings(release: &str, out_path: &Path) {
+    let bindings = pregenerated_bindings(release);
+    println!("cargo:rerun-if-changed={}", bindings.display());
+    std::fs::copy(&bindings, out_path).expect("Couldn't copy pre-generated bindings!");
+}
+
+/**
+ * Generate bindings with bindgen for OpusDecoder of the sizes of a release.
+ *
+ * With `OPUS_EMBEDDED_SYS_UPDATE_BINDINGS=1` environment variable the pre-generated bindings of
+ * this configuration are updated as well.
+ */
+#[cfg(feature = "bindgen")]
+fn generate_bindings(
+    source: &Path,
+    include_paths: &[PathBuf],
+    (release, sizes): (&str, [usize; 2]),
+    out_path: &Path,
+) {
     let mut builder = bindgen::Builder::default()
         .header("src/decoder.h")
         .allowlist_type("OpusDecoder")
@@ -171,24 +1450,172 @@ fn main() {
         .clang_arg("-DDISABLE_FLOAT_API=1")
         .clang_arg("-DFIXED_POINT=1")
         .clang_arg("-DFLOAT_APPROX=1")
-        .clang_arg("-Isrc/opus/celt")
-        .clang_arg("-Isrc/opus/dnn")
-        .clang_arg("-Isrc/opus/include")
-        .clang_arg("-Isrc/opus/silk")
         .derive_default(true)
+        .clang_args(
+            ["celt", "dnn", "include", "silk"]
+                .iter()
+                .map(|dir| source.join(dir))
+                .chain(include_paths.iter().cloned())
+                .map(|path| format!("-I{}", path.display())),
+        )
         .parse_callbacks(Box::new(ParseCallback::new()));
-    if env::var("CARGO_CFG_TARGET_OS").unwrap() != "none" {
+    if !is_without_libc() {
         builder = builder
             .allowlist_function("opus_decoder_create")
             .allowlist_function("opus_decoder_destroy");
     }
-    if cfg!(feature = "stereo") {
-        builder = builder.clang_arg("-DOPUS_EMBEDDED_SYS_STEREO");
+    let size = if cfg!(feature = "stereo") {
+        sizes[1]
+    } else {
+        sizes[0]
+    };
+    builder = builder.clang_arg(format!("-DOPUS_EMBEDDED_SYS_DECODER_SIZE={size}"));
+    let bindings = builder.generate().expect("Unable to generate bindings");
+
+    bindings
+        .write_to_file(out_path)
+        .expect("Couldn't write bindings!");
+    println!("cargo:rerun-if-env-changed=OPUS_EMBEDDED_SYS_UPDATE_BINDINGS");
+    if env::var_os("OPUS_EMBEDDED_SYS_UPDATE_BINDINGS").is_some_and(|value| value == "1") {
+        bindings
+            .write_to_file(pregenerated_bindings(release))
+            .expect("Couldn't update pre-generated bindings!");
+    }
+}
+
+/**
+ * Returns the path of pre-generated bindings of Opus Custom for this configuration.
+ *
+ * `opus_custom.h` is the same in every release in [RELEASES], so these differ only by whether
+ * functions that allocate decoders are included, see [pregenerated_bindings].
+ */
+#[cfg(feature = "custom_modes")]
+fn pregenerated_custom_bindings() -> PathBuf {
+    PathBuf::from(if is_without_libc() {
+        "src/bindings/opus_custom_os_none.rs"
+    } else {
+        "src/bindings/opus_custom.rs"
+    })
+}
+
+/// Use bindings of Opus Custom from the source tree so that bindgen and libclang are not needed.
+#[cfg(feature = "custom_modes")]
+fn copy_pregenerated_custom_bindings(out_path: &Path) {
+    let bindings = pregenerated_custom_bindings();
+    println!("cargo:rerun-if-changed={}", bindings.display());
+    std::fs::copy(&bindings, out_path).expect("Couldn't copy pre-generated bindings!");
+}
+
+/**
+ * Generate bindings of the decoder functions of Opus Custom with bindgen.
+ *
+ * These are included next to the other bindings, so the integer types are left out. Pre-generated
+ * bindings are updated like in [generate_bindings].
+ */
+#[cfg(all(feature = "bindgen", feature = "custom_modes"))]
+fn generate_custom_bindings(source: &Path, include_paths: &[PathBuf], out_path: &Path) {
+    let mut builder = bindgen::Builder::default()
+        .header("src/custom.h")
+        .allowlist_type("OpusCustomDecoder")
+        .allowlist_type("OpusCustomMode")
+        .allowlist_function("opus_custom_decode")
+        .allowlist_function("opus_custom_decoder_get_size")
+        .allowlist_function("opus_custom_decoder_init")
+        .allowlist_function("opus_custom_mode_create")
+        .allowlist_function("opus_custom_mode_destroy")
+        .blocklist_type("opus_int16")
+        .blocklist_type("opus_int32")
+        .use_core()
+        .clang_arg("-DDISABLE_FLOAT_API=1")
+        .clang_arg("-DFIXED_POINT=1")
+        .clang_args(
+            std::iter::once(source.join("include"))
+                .chain(include_paths.iter().cloned())
+                .map(|path| format!("-I{}", path.display())),
+        )
+        .parse_callbacks(Box::new(ParseCallback::new()));
+    if !is_without_libc() {
+        builder = builder
+            .allowlist_function("opus_custom_decoder_create")
+            .allowlist_function("opus_custom_decoder_destroy");
     }
     let bindings = builder.generate().expect("Unable to generate bindings");
 
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
     bindings
-        .write_to_file(out_path.join("opus_decoder_gen.rs"))
+        .write_to_file(out_path)
         .expect("Couldn't write bindings!");
+    if env::var_os("OPUS_EMBEDDED_SYS_UPDATE_BINDINGS").is_some_and(|value| value == "1") {
+        bindings
+            .write_to_file(pregenerated_custom_bindings())
+            .expect("Couldn't update pre-generated bindings!");
+    }
+}
+
+/// Write the sizes of OpusDecoder for mono and stereo decoders of the release that is built.
+fn write_decoder_sizes(sizes: [usize; 2]) {
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("opus_decoder_size.rs");
+    std::fs::write(out_path, format!("{sizes:?}")).expect("Couldn't write decoder sizes!");
+}
+
+/**
+ * Returns whether the crate is built for documentation on docs.rs.
+ *
+ * The sandbox there has neither C toolchains for cross targets nor pkg-config packages, and
+ * rustdoc does not link anything.
+ */
+fn is_docs_rs() -> bool {
+    println!("cargo:rerun-if-env-changed=DOCS_RS");
+    env::var_os("DOCS_RS").is_some()
+}
+
+fn main() {
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("opus_decoder_gen.rs");
+    #[cfg(feature = "custom_modes")]
+    let custom_out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("opus_custom_gen.rs");
+    let pinned = pinned_release();
+    if is_docs_rs() {
+        // Skip building libopus, pre-generated bindings are enough for documentation
+        let (release, sizes) = release(pinned.as_deref());
+        copy_pregenerated_bindings(release, &out_path);
+        #[cfg(feature = "custom_modes")]
+        copy_pregenerated_custom_bindings(&custom_out_path);
+        write_decoder_sizes(sizes);
+        write_stack_usage(None);
+        return;
+    }
+
+    let (source, include_paths, version) = if use_system_libopus() {
+        // Call graph of a library built elsewhere is not known
+        write_stack_usage(None);
+        let (include_paths, version) = find_system_libopus(pinned.as_deref());
+        (source_dir(None), include_paths, Some(version))
+    } else {
+        let source = source_dir(pinned.as_deref());
+        #[cfg(feature = "cc")]
+        build_with_cc(&source);
+        #[cfg(all(feature = "cmake", not(feature = "cc")))]
+        build_with_cmake(&source);
+        #[cfg(not(any(feature = "cc", feature = "cmake")))]
+        build_with_autotools(&source);
+        write_stack_usage(stack_usage());
+        let version = source_version(&source);
+        (source, Vec::new(), version)
+    };
+    let release = release(version.as_deref());
+    write_decoder_sizes(release.1);
+
+    #[cfg(feature = "bindgen")]
+    {
+        generate_bindings(&source, &include_paths, release, &out_path);
+        #[cfg(feature = "custom_modes")]
+        generate_custom_bindings(&source, &include_paths, &custom_out_path);
+    }
+    #[cfg(not(feature = "bindgen"))]
+    {
+        // Pre-generated bindings do not need the headers
+        let _ = (source, include_paths);
+        copy_pregenerated_bindings(release.0, &out_path);
+        #[cfg(feature = "custom_modes")]
+        copy_pregenerated_custom_bindings(&custom_out_path);
+    }
 }
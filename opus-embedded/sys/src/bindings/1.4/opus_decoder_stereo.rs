@@ -0,0 +1,117 @@
+/* automatically generated by rust-bindgen 0.71.1 */
+
+pub type opus_int16 = i16;
+pub type opus_int32 = i32;
+pub const OPUS_OK: u32 = 0;
+pub const OPUS_BAD_ARG: i32 = -1;
+pub const OPUS_BUFFER_TOO_SMALL: i32 = -2;
+pub const OPUS_INTERNAL_ERROR: i32 = -3;
+pub const OPUS_INVALID_PACKET: i32 = -4;
+pub const OPUS_UNIMPLEMENTED: i32 = -5;
+pub const OPUS_INVALID_STATE: i32 = -6;
+pub const OPUS_ALLOC_FAIL: i32 = -7;
+pub const OPUS_BANDWIDTH_NARROWBAND: u32 = 1101;
+pub const OPUS_BANDWIDTH_MEDIUMBAND: u32 = 1102;
+pub const OPUS_BANDWIDTH_WIDEBAND: u32 = 1103;
+pub const OPUS_BANDWIDTH_SUPERWIDEBAND: u32 = 1104;
+pub const OPUS_BANDWIDTH_FULLBAND: u32 = 1105;
+unsafe extern "C" {
+    #[doc = "Converts an opus error code into a human readable string.\n\n# Arguments\n\n* `error` \\[in\\] - <tt>int</tt>: Error number\n\n# Returns\n\n* Error string"]
+    pub fn opus_strerror(error: ::core::ffi::c_int) -> *const ::core::ffi::c_char;
+}
+#[doc = "Struct big enough to contain OpusDecoder of the selected libopus release so it\ncan be reserved on stack.\n<div rustbindgen replaces=\"OpusDecoder\"></div>"]
+#[repr(C)]
+#[repr(align(4))]
+#[derive(Debug, Copy, Clone)]
+pub struct OpusDecoder {
+    _unused: [::core::ffi::c_char; 26548usize],
+}
+#[allow(clippy::unnecessary_operation, clippy::identity_op)]
+const _: () = {
+    ["Size of OpusDecoder"][::core::mem::size_of::<OpusDecoder>() - 26548usize];
+    ["Alignment of OpusDecoder"][::core::mem::align_of::<OpusDecoder>() - 4usize];
+    ["Offset of field: OpusDecoder::_unused"]
+        [::core::mem::offset_of!(OpusDecoder, _unused) - 0usize];
+};
+impl Default for OpusDecoder {
+    fn default() -> Self {
+        let mut s = ::core::mem::MaybeUninit::<Self>::uninit();
+        unsafe {
+            ::core::ptr::write_bytes(s.as_mut_ptr(), 0, 1);
+            s.assume_init()
+        }
+    }
+}
+unsafe extern "C" {
+    #[doc = "Gets the size of an <code>OpusDecoder</code> structure.\n\n# Arguments\n\n* `channels` \\[in\\] - <tt>int</tt>: Number of channels.\nThis must be 1 or 2.\n\n# Returns\n\n* The size in bytes."]
+    pub fn opus_decoder_get_size(channels: ::core::ffi::c_int) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Allocates and initializes a decoder state.\n\n# Arguments\n\n* `Fs` \\[in\\] - <tt>opus_int32</tt>: Sample rate to decode at (Hz).\nThis must be one of 8000, 12000, 16000,\n24000, or 48000.\n* `channels` \\[in\\] - <tt>int</tt>: Number of channels (1 or 2) to decode\n* `error` \\[out\\] - <tt>int*</tt>: [`OPUS_OK`] Success or opus error codes\n\n\nInternally Opus stores data at 48000 Hz, so that should be the default\nvalue for Fs. However, the decoder can efficiently decode to buffers\nat 8, 12, 16, and 24 kHz so if for some reason the caller cannot use\ndata at the full sample rate, or knows the compressed data doesn't\nuse the full frequency range, it can request decoding at a reduced\nrate. Likewise, the decoder is capable of filling in either mono or\ninterleaved stereo pcm buffers, at the caller's request."]
+    pub fn opus_decoder_create(
+        Fs: opus_int32,
+        channels: ::core::ffi::c_int,
+        error: *mut ::core::ffi::c_int,
+    ) -> *mut OpusDecoder;
+}
+unsafe extern "C" {
+    #[doc = "Initializes a previously allocated decoder state.\nThe state must be at least the size returned by opus_decoder_get_size().\nThis is intended for applications which use their own allocator instead of malloc.\nSee also [`opus_decoder_create`] and [`opus_decoder_get_size`].\nTo reset a previously initialized state, use the `OPUS_RESET_STATE` CTL.\n\n# Arguments\n\n* `st` \\[in\\] - <tt>OpusDecoder*</tt>: Decoder state.\n* `Fs` \\[in\\] - <tt>opus_int32</tt>: Sampling rate to decode to (Hz).\nThis must be one of 8000, 12000, 16000,\n24000, or 48000.\n* `channels` \\[in\\] - <tt>int</tt>: Number of channels (1 or 2) to decode\n\n\n\n# Returns\n\n\n\n* [`OPUS_OK`] Success or opus error codes"]
+    pub fn opus_decoder_init(
+        st: *mut OpusDecoder,
+        Fs: opus_int32,
+        channels: ::core::ffi::c_int,
+    ) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Decode an Opus packet.\n\n# Arguments\n\n* `st` \\[in\\] - <tt>OpusDecoder*</tt>: Decoder state\n* `data` \\[in\\] - <tt>char*</tt>: Input payload. Use a NULL pointer to indicate packet loss\n* `len` \\[in\\] - <tt>opus_int32</tt>: Number of bytes in payload*\n* `pcm` \\[out\\] - <tt>opus_int16*</tt>: Output signal (interleaved if 2 channels). length\nis frame_size*channels*sizeof(opus_int16)\n* `frame_size` \\[in\\] - Number of samples per channel of available space in _pcm._\nIf this is less than the maximum packet duration (120ms; 5760 for 48kHz), this function will\nnot be capable of decoding some packets. In the case of PLC (data==NULL) or FEC (decode_fec=1),\nthen frame_size needs to be exactly the duration of audio that is missing, otherwise the\ndecoder will not be in the optimal state to decode the next incoming packet. For the PLC and\nFEC cases, frame_size <b>must</b> be a multiple of 2.5 ms.\n* `decode_fec` \\[in\\] - <tt>int</tt>: Flag (0 or 1) to request that any in-band forward error correction data be\ndecoded. If no such data is available, the frame is decoded as if it were lost.\n\n# Returns\n\n* Number of decoded samples or opus error codes"]
+    pub fn opus_decode(
+        st: *mut OpusDecoder,
+        data: *const ::core::ffi::c_uchar,
+        len: opus_int32,
+        pcm: *mut opus_int16,
+        frame_size: ::core::ffi::c_int,
+        decode_fec: ::core::ffi::c_int,
+    ) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Frees an <code>OpusDecoder</code> allocated by opus_decoder_create().\n\n# Arguments\n\n* `st` \\[in\\] - <tt>OpusDecoder*</tt>: State to be freed."]
+    pub fn opus_decoder_destroy(st: *mut OpusDecoder);
+}
+unsafe extern "C" {
+    #[doc = "Gets the bandwidth of an Opus packet.\n\n# Arguments\n\n* `data` \\[in\\] - <tt>char*</tt>: Opus packet\n\n\n# Returns\n\n\n\n* [`OPUS_BANDWIDTH_NARROWBAND`] Narrowband (4kHz bandpass)\n\n\n* [`OPUS_BANDWIDTH_MEDIUMBAND`] Mediumband (6kHz bandpass)\n\n\n* [`OPUS_BANDWIDTH_WIDEBAND`] Wideband (8kHz bandpass)\n\n\n* [`OPUS_BANDWIDTH_SUPERWIDEBAND`] Superwideband (12kHz bandpass)\n\n\n* [`OPUS_BANDWIDTH_FULLBAND`] Fullband (20kHz bandpass)\n\n\n* [`OPUS_INVALID_PACKET`] The compressed data passed is corrupted or of an unsupported type"]
+    pub fn opus_packet_get_bandwidth(data: *const ::core::ffi::c_uchar) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Gets the number of samples per frame from an Opus packet.\n\n# Arguments\n\n* `data` \\[in\\] - <tt>char*</tt>: Opus packet.\nThis must contain at least one byte of\ndata.\n* `Fs` \\[in\\] - <tt>opus_int32</tt>: Sampling rate in Hz.\nThis must be a multiple of 400, or\ninaccurate results will be returned.\n\n# Returns\n\n* Number of samples per frame."]
+    pub fn opus_packet_get_samples_per_frame(
+        data: *const ::core::ffi::c_uchar,
+        Fs: opus_int32,
+    ) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Gets the number of channels from an Opus packet.\n\n# Arguments\n\n* `data` \\[in\\] - <tt>char*</tt>: Opus packet\n\n# Returns\n\n* Number of channels\n\n\n* [`OPUS_INVALID_PACKET`] The compressed data passed is corrupted or of an unsupported type"]
+    pub fn opus_packet_get_nb_channels(data: *const ::core::ffi::c_uchar) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Gets the number of frames in an Opus packet.\n\n# Arguments\n\n* `packet` \\[in\\] - <tt>char*</tt>: Opus packet\n* `len` \\[in\\] - <tt>opus_int32</tt>: Length of packet\n\n# Returns\n\n* Number of frames\n\n\n* [`OPUS_BAD_ARG`] Insufficient data was passed to the function\n\n\n* [`OPUS_INVALID_PACKET`] The compressed data passed is corrupted or of an unsupported type"]
+    pub fn opus_packet_get_nb_frames(
+        packet: *const ::core::ffi::c_uchar,
+        len: opus_int32,
+    ) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Gets the number of samples of an Opus packet.\n\n# Arguments\n\n* `packet` \\[in\\] - <tt>char*</tt>: Opus packet\n* `len` \\[in\\] - <tt>opus_int32</tt>: Length of packet\n* `Fs` \\[in\\] - <tt>opus_int32</tt>: Sampling rate in Hz.\nThis must be a multiple of 400, or\ninaccurate results will be returned.\n\n# Returns\n\n* Number of samples\n\n\n* [`OPUS_BAD_ARG`] Insufficient data was passed to the function\n\n\n* [`OPUS_INVALID_PACKET`] The compressed data passed is corrupted or of an unsupported type"]
+    pub fn opus_packet_get_nb_samples(
+        packet: *const ::core::ffi::c_uchar,
+        len: opus_int32,
+        Fs: opus_int32,
+    ) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Gets the number of samples of an Opus packet.\n\n# Arguments\n\n* `dec` \\[in\\] - <tt>OpusDecoder*</tt>: Decoder state\n* `packet` \\[in\\] - <tt>char*</tt>: Opus packet\n* `len` \\[in\\] - <tt>opus_int32</tt>: Length of packet\n\n# Returns\n\n* Number of samples\n\n\n* [`OPUS_BAD_ARG`] Insufficient data was passed to the function\n\n\n* [`OPUS_INVALID_PACKET`] The compressed data passed is corrupted or of an unsupported type"]
+    pub fn opus_decoder_get_nb_samples(
+        dec: *const OpusDecoder,
+        packet: *const ::core::ffi::c_uchar,
+        len: opus_int32,
+    ) -> ::core::ffi::c_int;
+}
@@ -0,0 +1,63 @@
+/* automatically generated by rust-bindgen 0.71.1 */
+
+#[doc = "State of the decoder. One decoder state is needed for each stream.\nIt is initialized once at the beginning of the stream. Do *not*\nre-initialize the state for every frame.\nDecoder state"]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct OpusCustomDecoder {
+    _unused: [u8; 0],
+}
+#[doc = "The mode contains all the information necessary to create an\nencoder. Both the encoder and decoder need to be initialized\nwith exactly the same mode, otherwise the output will be\ncorrupted. The mode MUST NOT BE DESTROYED until the encoders and\ndecoders that use it are destroyed as well.\nMode configuration"]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct OpusCustomMode {
+    _unused: [u8; 0],
+}
+unsafe extern "C" {
+    #[doc = "Creates a new mode struct. This will be passed to an encoder or\ndecoder. The mode MUST NOT BE DESTROYED until the encoders and\ndecoders that use it are destroyed as well.\n\n# Arguments\n\n* `Fs` \\[in\\] - <tt>int</tt>: Sampling rate (8000 to 96000 Hz)\n* `frame_size` \\[in\\] - <tt>int</tt>: Number of samples (per channel) to encode in each\npacket (64 - 1024, prime factorization must contain zero or more 2s, 3s, or 5s and no other primes)\n* `error` \\[out\\] - <tt>int*</tt>: Returned error code (if NULL, no error will be returned)\n\n# Returns\n\n* A newly created mode"]
+    pub fn opus_custom_mode_create(
+        Fs: opus_int32,
+        frame_size: ::core::ffi::c_int,
+        error: *mut ::core::ffi::c_int,
+    ) -> *mut OpusCustomMode;
+}
+unsafe extern "C" {
+    #[doc = "Destroys a mode struct. Only call this after all encoders and\ndecoders using this mode are destroyed as well.\n\n# Arguments\n\n* `mode` \\[in\\] - <tt>OpusCustomMode*</tt>: Mode to be freed."]
+    pub fn opus_custom_mode_destroy(mode: *mut OpusCustomMode);
+}
+unsafe extern "C" {
+    #[doc = "Gets the size of an OpusCustomDecoder structure.\n\n# Arguments\n\n* `mode` \\[in\\] - <tt>OpusCustomMode *</tt>: Mode configuration\n* `channels` \\[in\\] - <tt>int</tt>: Number of channels\n\n# Returns\n\n* size"]
+    pub fn opus_custom_decoder_get_size(
+        mode: *const OpusCustomMode,
+        channels: ::core::ffi::c_int,
+    ) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Initializes a previously allocated decoder state\nThe memory pointed to by st must be the size returned by opus_custom_decoder_get_size.\nThis is intended for applications which use their own allocator instead of malloc.\nSee also [`opus_custom_decoder_create`] and [`opus_custom_decoder_get_size`].\nTo reset a previously initialized state use the `OPUS_RESET_STATE` CTL.\n\n# Arguments\n\n* `st` \\[in\\] - <tt>OpusCustomDecoder*</tt>: Decoder state\n* `mode` \\[in\\] - <tt>OpusCustomMode *</tt>: Contains all the information about the characteristics of\nthe stream (must be the same characteristics as used for the\nencoder)\n* `channels` \\[in\\] - <tt>int</tt>: Number of channels\n\n# Returns\n\n* OPUS_OK Success or opus error codes"]
+    pub fn opus_custom_decoder_init(
+        st: *mut OpusCustomDecoder,
+        mode: *const OpusCustomMode,
+        channels: ::core::ffi::c_int,
+    ) -> ::core::ffi::c_int;
+}
+unsafe extern "C" {
+    #[doc = "Creates a new decoder state. Each stream needs its own decoder state (can't\nbe shared across simultaneous streams).\n\n# Arguments\n\n* `mode` \\[in\\] - <tt>OpusCustomMode</tt>: Contains all the information about the characteristics of the\nstream (must be the same characteristics as used for the encoder)\n* `channels` \\[in\\] - <tt>int</tt>: Number of channels\n* `error` \\[out\\] - <tt>int*</tt>: Returns an error code\n\n# Returns\n\n* Newly created decoder state."]
+    pub fn opus_custom_decoder_create(
+        mode: *const OpusCustomMode,
+        channels: ::core::ffi::c_int,
+        error: *mut ::core::ffi::c_int,
+    ) -> *mut OpusCustomDecoder;
+}
+unsafe extern "C" {
+    #[doc = "Destroys a decoder state.\n\n# Arguments\n\n* `st` \\[in\\] - <tt>OpusCustomDecoder*</tt>: State to be freed."]
+    pub fn opus_custom_decoder_destroy(st: *mut OpusCustomDecoder);
+}
+unsafe extern "C" {
+    #[doc = "Decode an opus custom frame\n\n# Arguments\n\n* `st` \\[in\\] - <tt>OpusCustomDecoder*</tt>: Decoder state\n* `data` \\[in\\] - <tt>char*</tt>: Input payload. Use a NULL pointer to indicate packet loss\n* `len` \\[in\\] - <tt>int</tt>: Number of bytes in payload\n* `pcm` \\[out\\] - <tt>opus_int16*</tt>: Output signal (interleaved if 2 channels). length\nis frame_size*channels*sizeof(opus_int16)\n* `frame_size` \\[in\\] - Number of samples per channel of available space in *pcm.\n\n# Returns\n\n* Number of decoded samples or opus error codes"]
+    pub fn opus_custom_decode(
+        st: *mut OpusCustomDecoder,
+        data: *const ::core::ffi::c_uchar,
+        len: ::core::ffi::c_int,
+        pcm: *mut opus_int16,
+        frame_size: ::core::ffi::c_int,
+    ) -> ::core::ffi::c_int;
+}
@@ -0,0 +1,130 @@
+// Stubs for the half of the codec left out with celt_only or silk_only feature
+// Nothing special, free to use for any purpose
+//
+// These replace the entry points of SILK or CELT decoder that opus_decoder.c
+// calls, so that the linker drops the rest of that half. The Rust decoder
+// rejects packets of the unsupported modes before they get here, but the stubs
+// fail gracefully if libopus is used directly.
+
+#ifdef HAVE_CONFIG_H
+#include "config.h"
+#endif
+
+#include <stdarg.h>
+#include <string.h>
+
+#include "opus_defines.h"
+
+#ifdef OPUS_EMBEDDED_SYS_NO_SILK
+#include "API.h"
+
+opus_int silk_Get_Decoder_Size(opus_int *decSizeBytes)
+{
+    *decSizeBytes = 0;
+    return SILK_NO_ERROR;
+}
+
+opus_int silk_ResetDecoder(void *decState)
+{
+    (void)decState;
+    return SILK_NO_ERROR;
+}
+
+opus_int silk_InitDecoder(void *decState)
+{
+    (void)decState;
+    return SILK_NO_ERROR;
+}
+
+// opus_decode_frame conceals lost frames with silence and fails otherwise
+opus_int silk_Decode(void *decState, silk_DecControlStruct *decControl, opus_int lostFlag,
+                     opus_int newPacketFlag, ec_dec *psRangeDec, opus_int16 *samplesOut,
+                     opus_int32 *nSamplesOut, int arch)
+{
+    (void)decState;
+    (void)decControl;
+    (void)lostFlag;
+    (void)newPacketFlag;
+    (void)psRangeDec;
+    (void)samplesOut;
+    (void)arch;
+    *nSamplesOut = 0;
+    return SILK_DEC_INVALID_FRAME_SIZE;
+}
+#endif
+
+#ifdef OPUS_EMBEDDED_SYS_NO_CELT
+#include "celt.h"
+#include "modes.h"
+
+// Window for fading the redundant CELT frames of SILK packets, which decode to
+// silence without CELT. Its overlap of 2.5 ms at 48 kHz is the same as in
+// the static mode of libopus.
+static const opus_val16 stub_window[120] = {0};
+
+static const CELTMode stub_mode = {
+    .Fs = 48000,
+    .overlap = 120,
+    .window = stub_window,
+};
+
+// Only the number of channels is needed for writing silence
+struct OpusCustomDecoder {
+    int channels;
+};
+
+int celt_decoder_get_size(int channels)
+{
+    (void)channels;
+    return sizeof(CELTDecoder);
+}
+
+int celt_decoder_init(CELTDecoder *st, opus_int32 sampling_rate, int channels)
+{
+    (void)sampling_rate;
+    st->channels = channels;
+    return OPUS_OK;
+}
+
+int opus_custom_decoder_ctl(CELTDecoder *OPUS_RESTRICT st, int request, ...)
+{
+    va_list ap;
+    (void)st;
+    va_start(ap, request);
+    switch (request) {
+    case CELT_GET_MODE_REQUEST:
+        *va_arg(ap, const CELTMode **) = &stub_mode;
+        break;
+    case OPUS_GET_FINAL_RANGE_REQUEST:
+        *va_arg(ap, opus_uint32 *) = 0;
+        break;
+    case OPUS_GET_PITCH_REQUEST:
+    case OPUS_GET_PHASE_INVERSION_DISABLED_REQUEST:
+        *va_arg(ap, opus_int32 *) = 0;
+        break;
+    default:
+        break;
+    }
+    va_end(ap);
+    return OPUS_OK;
+}
+
+int celt_decode_with_ec_dred(CELTDecoder *OPUS_RESTRICT st, const unsigned char *data, int len,
+                             opus_val16 *OPUS_RESTRICT pcm, int frame_size, ec_dec *dec,
+                             int accum)
+{
+    (void)data;
+    (void)len;
+    (void)dec;
+    if (!accum) {
+        memset(pcm, 0, (size_t)frame_size * st->channels * sizeof(*pcm));
+    }
+    return OPUS_UNIMPLEMENTED;
+}
+
+int celt_decode_with_ec(CELTDecoder *OPUS_RESTRICT st, const unsigned char *data, int len,
+                        opus_val16 *OPUS_RESTRICT pcm, int frame_size, ec_dec *dec, int accum)
+{
+    return celt_decode_with_ec_dred(st, data, len, pcm, frame_size, dec, accum);
+}
+#endif
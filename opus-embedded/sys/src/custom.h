@@ -0,0 +1 @@
+#include <opus_custom.h>
@@ -3,6 +3,30 @@
 
 #include <stddef.h>
 
+#ifdef OPUS_EMBEDDED_SYS_RUST_ALLOCATOR
+// Implemented in Rust with the global allocator
+void *opus_embedded_sys_alloc(size_t size);
+void *opus_embedded_sys_realloc(void *ptr, size_t size);
+void opus_embedded_sys_free(void *ptr);
+
+#define OVERRIDE_OPUS_ALLOC
+static inline void *opus_alloc (size_t size)
+{
+    return opus_embedded_sys_alloc(size);
+}
+
+#define OVERRIDE_OPUS_REALLOC
+static inline void *opus_realloc (void *ptr, size_t size)
+{
+    return opus_embedded_sys_realloc(ptr, size);
+}
+
+#define OVERRIDE_OPUS_FREE
+static inline void opus_free (void *ptr)
+{
+    opus_embedded_sys_free(ptr);
+}
+#else
 #define OVERRIDE_OPUS_ALLOC
 static inline void *opus_alloc (size_t size)
 {
@@ -23,3 +47,4 @@ static inline void opus_free (void *ptr)
 {
     (void)ptr;
 }
+#endif
@@ -1,14 +1,10 @@
 #include <opus.h>
 
 /**
- * Struct big enough to contain OpusDecoder of opus-1.5.2 so it can be reserved
- * on stack.
+ * Struct big enough to contain OpusDecoder of the selected libopus release so it
+ * can be reserved on stack.
  * <div rustbindgen replaces="OpusDecoder"></div>
  */
 struct OpusDecoder {
-#ifdef OPUS_EMBEDDED_SYS_STEREO
-    char _unused[26580] __attribute__((aligned(4)));
-#else
-    char _unused[17860] __attribute__((aligned(4)));
-#endif
+    char _unused[OPUS_EMBEDDED_SYS_DECODER_SIZE] __attribute__((aligned(4)));
 };
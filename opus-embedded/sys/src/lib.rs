@@ -17,19 +17,86 @@
 #![allow(non_snake_case)]
 #![no_std]
 
-#[cfg(target_os = "none")]
-use core::ffi::{c_char, c_int, CStr};
+#[cfg(feature = "rust_allocator")]
+extern crate alloc;
 
-pub const OPUS_DECODER_SIZE_CH1: usize = 17860;
-pub const OPUS_DECODER_SIZE_CH2: usize = 26580;
+#[cfg(feature = "rust_allocator")]
+use alloc::alloc::Layout;
+use core::ffi::c_int;
+#[cfg(feature = "rust_allocator")]
+use core::ffi::c_void;
+#[cfg(any(target_os = "none", all(target_family = "wasm", target_os = "unknown")))]
+use core::ffi::{c_char, CStr};
+
+/// Sizes of OpusDecoder for mono and stereo decoders of the libopus release that is built.
+const OPUS_DECODER_SIZES: [usize; 2] = include!(concat!(env!("OUT_DIR"), "/opus_decoder_size.rs"));
+
+pub const OPUS_DECODER_SIZE_CH1: usize = OPUS_DECODER_SIZES[0];
+pub const OPUS_DECODER_SIZE_CH2: usize = OPUS_DECODER_SIZES[1];
+
+include!("scratch_size.rs");
 
 include!(concat!(env!("OUT_DIR"), "/opus_decoder_gen.rs"));
 
-#[cfg(target_os = "none")]
+#[cfg(feature = "custom_modes")]
+include!(concat!(env!("OUT_DIR"), "/opus_custom_gen.rs"));
+
+/**
+ * Temporary arrays allocated by libopus when decoding with a stereo Opus Custom decoder.
+ *
+ * This is the worst case measured with opus-1.4 and opus-1.5.2 for the common sampling rates from
+ * 8 kHz to 96 kHz and all valid frame sizes, including packet loss concealment, with some margin.
+ * Mono decoders need at most [`OPUS_DECODER_SCRATCH_SIZE_CH1`] bytes, and decoders of 48 kHz
+ * modes at most [`OPUS_DECODER_SCRATCH_SIZE_CH2`] bytes for frames of up to 960 samples.
+ */
+#[cfg(feature = "custom_modes")]
+pub const OPUS_CUSTOM_DECODER_SCRATCH_SIZE_CH2: usize = 10240;
+
+/**
+ * Returns the size of temporary arrays allocated by libopus when decoding with a decoder of the
+ * sampling rate and number of channels.
+ *
+ * Only stereo decoders at 48 kHz need more than [`OPUS_DECODER_SCRATCH_SIZE_CH1`] bytes.
+ */
+pub const fn opus_decoder_scratch_size(Fs: opus_int32, channels: c_int) -> usize {
+    if channels == 2 && Fs > 24000 {
+        OPUS_DECODER_SCRATCH_SIZE_CH2
+    } else {
+        OPUS_DECODER_SCRATCH_SIZE_CH1
+    }
+}
+
+/**
+ * Stack used by call frames of [`opus_decode`] in the worst case, in bytes.
+ *
+ * This is the deepest call chain in the call graph that GCC writes when libopus is built with
+ * `OPUS_EMBEDDED_SYS_STACK_USAGE=1` environment variable, and `None` otherwise. Temporary arrays
+ * are on stack too unless `pseudostack` feature is enabled, see [`opus_decoder_scratch_size`].
+ * Functions outside libopus, e.g. memcpy of C library, are not included.
+ */
+pub const OPUS_DECODE_STACK_FRAMES: Option<usize> =
+    include!(concat!(env!("OUT_DIR"), "/opus_decode_stack.rs"));
+
+#[cfg(feature = "pseudostack")]
+extern "C" {
+    /**
+     * Pseudostack of libopus built with `pseudostack` feature.
+     *
+     * libopus allocates temporary arrays from here and restores it when returning. This must point
+     * to scratch memory of at least [`OPUS_DECODER_SCRATCH_SIZE_CH1`] or
+     * [`OPUS_DECODER_SCRATCH_SIZE_CH2`] bytes while decoding, or libopus tries to allocate it
+     * with `opus_alloc`. As this is a global variable, only one thread or core may decode at a
+     * time.
+     */
+    pub static mut global_stack: *mut core::ffi::c_char;
+}
+
+#[cfg(any(target_os = "none", all(target_family = "wasm", target_os = "unknown")))]
 #[no_mangle]
 pub unsafe extern "C" fn celt_fatal(str_: *const c_char, file: *const c_char, line: c_int) {
     /*!
-     * Celt fatal implementation that doesn't need C stdlib.
+     * Celt fatal implementation that doesn't need C stdlib, for targets without operating system
+     * and `wasm32-unknown-unknown`.
      *
      * # Panics
      * Always.
@@ -56,10 +123,139 @@ pub unsafe extern "C" fn celt_fatal(str_: *const c_char, file: *const c_char, li
     }
 }
 
+/**
+ * Stack canary for libopus built with stack protector.
+ *
+ * This has a fixed default value, set a random value with [`set_stack_guard`] as early as
+ * possible.
+ */
+#[cfg(all(feature = "stack_protector", target_os = "none"))]
+#[no_mangle]
+pub static mut __stack_chk_guard: usize = 0x595e_9fbd_94fd_a766_u64 as usize;
+
+/**
+ * Set stack canary for libopus built with stack protector.
+ *
+ * # Safety
+ * This must not be called while any libopus function is running, i.e. from an interrupt handler
+ * or another core, because those would see a different canary when they return.
+ */
+#[cfg(all(feature = "stack_protector", target_os = "none"))]
+pub unsafe fn set_stack_guard(value: usize) {
+    unsafe {
+        __stack_chk_guard = value;
+    }
+}
+
+#[cfg(all(feature = "stack_protector", target_os = "none"))]
+#[no_mangle]
+pub extern "C" fn __stack_chk_fail() -> ! {
+    /*!
+     * Stack protector failure handler that doesn't need C stdlib.
+     *
+     * # Panics
+     * Always.
+     */
+    panic!("stack smashing detected in libopus");
+}
+
+/**
+ * Alignment of allocations for libopus, which is the same as malloc has on most platforms.
+ *
+ * Allocated size is stored before the returned pointer as Rust's allocator needs it for
+ * deallocation.
+ */
+#[cfg(feature = "rust_allocator")]
+const ALLOC_ALIGN: usize = 2 * core::mem::size_of::<usize>();
+
+/// Layout of an allocation for libopus with space for its size.
+#[cfg(feature = "rust_allocator")]
+fn alloc_layout(size: usize) -> Option<Layout> {
+    Layout::from_size_align(size.checked_add(ALLOC_ALIGN)?, ALLOC_ALIGN).ok()
+}
+
+#[cfg(feature = "rust_allocator")]
+#[no_mangle]
+pub unsafe extern "C" fn opus_embedded_sys_alloc(size: usize) -> *mut c_void {
+    /*!
+     * Allocation for libopus with the global allocator of Rust.
+     *
+     * Returns null pointer if allocation fails like malloc does.
+     *
+     * # Safety
+     * The returned pointer must be freed with [`opus_embedded_sys_free`].
+     */
+    let Some(layout) = alloc_layout(size) else {
+        return core::ptr::null_mut();
+    };
+    unsafe {
+        let ptr = alloc::alloc::alloc(layout);
+        if ptr.is_null() {
+            return core::ptr::null_mut();
+        }
+        ptr.cast::<usize>().write(size);
+        ptr.add(ALLOC_ALIGN).cast()
+    }
+}
+
+#[cfg(feature = "rust_allocator")]
+#[no_mangle]
+pub unsafe extern "C" fn opus_embedded_sys_realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    /*!
+     * Reallocation for libopus with the global allocator of Rust.
+     *
+     * Allocates if the pointer is null and returns null pointer if reallocation fails, in which
+     * case the old allocation is left as it was, like realloc does.
+     *
+     * # Safety
+     * The pointer must be null or returned by [`opus_embedded_sys_alloc`] or this function, and
+     * not freed yet.
+     */
+    if ptr.is_null() {
+        return unsafe { opus_embedded_sys_alloc(size) };
+    }
+    let Some(new_layout) = alloc_layout(size) else {
+        return core::ptr::null_mut();
+    };
+    unsafe {
+        let ptr = ptr.cast::<u8>().sub(ALLOC_ALIGN);
+        let layout = alloc_layout(ptr.cast::<usize>().read()).unwrap();
+        let ptr = alloc::alloc::realloc(ptr, layout, new_layout.size());
+        if ptr.is_null() {
+            return core::ptr::null_mut();
+        }
+        ptr.cast::<usize>().write(size);
+        ptr.add(ALLOC_ALIGN).cast()
+    }
+}
+
+#[cfg(feature = "rust_allocator")]
+#[no_mangle]
+pub unsafe extern "C" fn opus_embedded_sys_free(ptr: *mut c_void) {
+    /*!
+     * Deallocation for libopus with the global allocator of Rust.
+     *
+     * Does nothing if the pointer is null like free does.
+     *
+     * # Safety
+     * The pointer must be null or returned by [`opus_embedded_sys_alloc`] or
+     * [`opus_embedded_sys_realloc`], and not freed yet.
+     */
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let ptr = ptr.cast::<u8>().sub(ALLOC_ALIGN);
+        let layout = alloc_layout(ptr.cast::<usize>().read()).unwrap();
+        alloc::alloc::dealloc(ptr, layout);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(not(any(feature = "celt_only", feature = "silk_only")))]
     #[test]
     fn check_reported_size() {
         let size = unsafe { opus_decoder_get_size(1) };
@@ -68,6 +264,15 @@ mod tests {
         assert_eq!(size, OPUS_DECODER_SIZE_CH2.try_into().unwrap());
     }
 
+    #[cfg(any(feature = "celt_only", feature = "silk_only"))]
+    #[test]
+    fn check_reported_size_without_stubbed_codec() {
+        let size = unsafe { opus_decoder_get_size(1) };
+        assert!(size < OPUS_DECODER_SIZE_CH1.try_into().unwrap());
+        let size = unsafe { opus_decoder_get_size(2) };
+        assert!(size < OPUS_DECODER_SIZE_CH2.try_into().unwrap());
+    }
+
     #[test]
     fn check_struct_size() {
         assert_eq!(
@@ -79,4 +284,49 @@ mod tests {
             }
         );
     }
+
+    #[cfg(feature = "custom_modes")]
+    #[test]
+    fn check_custom_decoder_size() {
+        let mut error = 0;
+        unsafe {
+            let mode = opus_custom_mode_create(48000, 960, &mut error);
+            assert_eq!(error, OPUS_OK as core::ffi::c_int);
+            let size = opus_custom_decoder_get_size(mode, 1);
+            assert!(size > 0 && size < OPUS_DECODER_SIZE_CH1.try_into().unwrap());
+            opus_custom_mode_destroy(mode);
+        }
+    }
+
+    #[cfg(feature = "rust_allocator")]
+    #[test]
+    fn rust_allocator() {
+        unsafe {
+            let ptr = opus_embedded_sys_alloc(3).cast::<u8>();
+            assert!(!ptr.is_null());
+            assert_eq!(ptr.align_offset(ALLOC_ALIGN), 0);
+            ptr.copy_from_nonoverlapping([1, 2, 3].as_ptr(), 3);
+            let ptr = opus_embedded_sys_realloc(ptr.cast(), 1000).cast::<u8>();
+            assert!(!ptr.is_null());
+            assert_eq!(core::slice::from_raw_parts(ptr, 3), [1, 2, 3]);
+            opus_embedded_sys_free(ptr.cast());
+            opus_embedded_sys_free(core::ptr::null_mut());
+            assert!(opus_embedded_sys_alloc(usize::MAX).is_null());
+        }
+    }
+
+    #[cfg(all(
+        feature = "rust_allocator",
+        not(any(target_os = "none", all(target_family = "wasm", target_os = "unknown")))
+    ))]
+    #[test]
+    fn create_decoder_with_rust_allocator() {
+        let mut error = 0;
+        unsafe {
+            let decoder = opus_decoder_create(48000, 1, &mut error);
+            assert_eq!(error, OPUS_OK as core::ffi::c_int);
+            assert!(!decoder.is_null());
+            opus_decoder_destroy(decoder);
+        }
+    }
 }
@@ -0,0 +1,14 @@
+// Minimal C library headers for targets without one, e.g. wasm32-unknown-unknown
+// Nothing special, free to use for any purpose
+//
+// Included by libopus, but the fixed point decoder uses nothing of it with OVERRIDE_celt_fatal.
+// Opus Custom computes the tables of its modes with these, which Rust provides like the functions
+// of string.h.
+
+#ifndef OPUS_EMBEDDED_SYS_MATH_H
+#define OPUS_EMBEDDED_SYS_MATH_H
+
+double floor(double x);
+double sin(double x);
+
+#endif
@@ -0,0 +1,8 @@
+// Minimal C library headers for targets without one, e.g. wasm32-unknown-unknown
+// Nothing special, free to use for any purpose
+//
+// Included by libopus, but the fixed point decoder uses nothing of it with OVERRIDE_celt_fatal.
+
+#ifndef OPUS_EMBEDDED_SYS_STDIO_H
+#define OPUS_EMBEDDED_SYS_STDIO_H
+#endif
@@ -0,0 +1,14 @@
+// Minimal C library headers for targets without one, e.g. wasm32-unknown-unknown
+// Nothing special, free to use for any purpose
+//
+// Allocation goes through custom_support.h, and abs is expanded by the compiler so that nothing
+// is imported from the host.
+
+#ifndef OPUS_EMBEDDED_SYS_STDLIB_H
+#define OPUS_EMBEDDED_SYS_STDLIB_H
+
+#include <stddef.h>
+
+#define abs(x) __builtin_abs(x)
+
+#endif
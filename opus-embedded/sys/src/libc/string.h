@@ -0,0 +1,15 @@
+// Minimal C library headers for targets without one, e.g. wasm32-unknown-unknown
+// Nothing special, free to use for any purpose
+//
+// Rust provides these functions, i.e. compiler_builtins of the target.
+
+#ifndef OPUS_EMBEDDED_SYS_STRING_H
+#define OPUS_EMBEDDED_SYS_STRING_H
+
+#include <stddef.h>
+
+void *memcpy(void *dest, const void *src, size_t n);
+void *memmove(void *dest, const void *src, size_t n);
+void *memset(void *dest, int c, size_t n);
+
+#endif
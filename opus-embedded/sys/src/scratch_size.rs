@@ -0,0 +1,20 @@
+/*
+ * Copyright (c) 2025 Tomi Leppänen
+ * SPDX-License-Identifier: BSD-3-Clause
+ *
+ * Included by both the crate and its build script, which reports stack usage with these.
+ */
+
+/**
+ * Temporary arrays allocated by libopus when decoding with a mono decoder.
+ *
+ * With `pseudostack` feature these are allocated from scratch memory pointed by `global_stack`,
+ * otherwise from stack as variable length arrays.
+ *
+ * This is the worst case measured with opus-1.4 and opus-1.5.2 for all sampling rates, modes,
+ * frame sizes and malformed packets, including packet loss concealment and FEC, with some margin.
+ * libopus does not check the bounds of its pseudostack, so scratch memory must not be any smaller.
+ */
+pub const OPUS_DECODER_SCRATCH_SIZE_CH1: usize = 9216;
+/// Temporary arrays allocated when decoding with a stereo decoder, see [`OPUS_DECODER_SCRATCH_SIZE_CH1`].
+pub const OPUS_DECODER_SCRATCH_SIZE_CH2: usize = 9728;